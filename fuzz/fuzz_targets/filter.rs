@@ -0,0 +1,16 @@
+#![no_main]
+
+use aprsserver::filter::{FilterContext, FilterSet, parse_filter_list};
+use aprsserver::parser;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(s) = std::str::from_utf8(data) else {
+        return;
+    };
+    // Filters that parse are also matched, which runs the grid and area code
+    if let Ok(filters) = parse_filter_list(s) {
+        let packet = parser::parse("N0CALL>APRS:;LEADER   *092345z4903.50N/07201.75W>").unwrap();
+        FilterSet::new(filters).matches(&packet, &FilterContext::default());
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use aprsserver::login::parse_login;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(line) = std::str::from_utf8(data)
+        && let Ok(login) = parse_login(line)
+    {
+        let _ = login.verified();
+    }
+});
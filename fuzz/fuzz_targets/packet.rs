@@ -0,0 +1,11 @@
+#![no_main]
+
+use aprsserver::parser;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(packet) = parser::parse_bytes(data) {
+        let _ = packet.position();
+        let _ = packet.weather();
+    }
+});
@@ -0,0 +1,305 @@
+use crate::bans::Bans;
+use crate::config::S2SPeerConfig;
+use crate::login_limit::LoginLimitStats;
+use crate::web::AppState;
+use axum::extract::ws::{Message, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use std::net::IpAddr;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info};
+
+/// Authenticated admin endpoints, merged into the web UI router.
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/clients/:id/kick", post(kick_client))
+        .route("/admin/bans", get(list_bans))
+        .route("/admin/bans/callsigns/:callsign", post(ban_callsign).delete(unban_callsign))
+        .route("/admin/bans/ips/:ip", post(ban_ip).delete(unban_ip))
+        .route("/admin/logins", get(login_blocks))
+        .route("/admin/logins/:ip", delete(unblock_login))
+        .route("/admin/log-level", get(get_log_level).put(set_log_level))
+        .route("/admin/inject", post(inject))
+        .route("/admin/peers", post(add_peer))
+        .route("/admin/peers/:name", delete(remove_peer))
+        .route("/admin/watch", get(list_watched))
+        .route("/admin/watch/:callsign", post(watch).delete(unwatch))
+        .route("/admin/watch/ws", get(watch_ws))
+}
+
+/// Checks the `Authorization: Bearer` header against the configured token.
+/// Without a configured token the admin API is disabled.
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let Some(token) = &state.admin_token else {
+        return Err(StatusCode::FORBIDDEN);
+    };
+    let supplied = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if supplied.is_some_and(|supplied| token_matches(supplied, token)) {
+        Ok(())
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Compares tokens in constant time, so response times do not tell a
+/// guesser how much of the token was right. Both are reduced to MACs first,
+/// which also hides the token's length.
+fn token_matches(supplied: &str, token: &str) -> bool {
+    let mac = |key: &str| Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC takes keys of any length").chain_update(b"admin token");
+    mac(supplied).verify_slice(&mac(token).finalize().into_bytes()).is_ok()
+}
+
+async fn kick_client(State(state): State<AppState>, headers: HeaderMap, Path(id): Path<usize>) -> StatusCode {
+    if let Err(status) = authorize(&state, &headers) {
+        return status;
+    }
+    if state.hub.kick_client(id, "Disconnected by server operator") {
+        info!(client = id, "client kicked via admin API");
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn list_bans(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<Bans>, StatusCode> {
+    authorize(&state, &headers)?;
+    Ok(Json(state.hub.bans.read().unwrap().clone()))
+}
+
+/// Applies a change to the ban list. The change is made to a copy, which
+/// replaces the list in use once it is saved, so a failed save changes
+/// nothing. Adding a ban also disconnects matching clients; removing one
+/// that does not exist gives 404.
+fn change_bans(
+    state: &AppState,
+    headers: &HeaderMap,
+    change: impl FnOnce(&mut Bans) -> Result<bool, String>,
+    adding: bool,
+) -> StatusCode {
+    if let Err(status) = authorize(state, headers) {
+        return status;
+    }
+    let _edit = state.hub.ban_edits.lock().unwrap();
+    let mut bans = state.hub.bans.read().unwrap().clone();
+    match change(&mut bans) {
+        Ok(changed) => {
+            *state.hub.bans.write().unwrap() = bans;
+            if adding {
+                state.hub.kick_banned();
+            }
+            if changed || adding { StatusCode::NO_CONTENT } else { StatusCode::NOT_FOUND }
+        }
+        Err(e) => {
+            error!(error = %e, "failed to save ban file");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn ban_callsign(State(state): State<AppState>, headers: HeaderMap, Path(callsign): Path<String>) -> StatusCode {
+    change_bans(&state, &headers, |bans| bans.add_callsign(&callsign), true)
+}
+
+async fn unban_callsign(State(state): State<AppState>, headers: HeaderMap, Path(callsign): Path<String>) -> StatusCode {
+    change_bans(&state, &headers, |bans| bans.remove_callsign(&callsign), false)
+}
+
+async fn ban_ip(State(state): State<AppState>, headers: HeaderMap, Path(ip): Path<IpAddr>) -> StatusCode {
+    change_bans(&state, &headers, |bans| bans.add_ip(ip), true)
+}
+
+async fn unban_ip(State(state): State<AppState>, headers: HeaderMap, Path(ip): Path<IpAddr>) -> StatusCode {
+    change_bans(&state, &headers, |bans| bans.remove_ip(ip), false)
+}
+
+/// Failed login counters and the addresses blocked for them; 404 without
+/// a `[login_limit]` section.
+async fn login_blocks(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<LoginLimitStats>, StatusCode> {
+    authorize(&state, &headers)?;
+    let limit = state.hub.login_limit.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(limit.stats(state.hub.clock.now())))
+}
+
+async fn unblock_login(State(state): State<AppState>, headers: HeaderMap, Path(ip): Path<IpAddr>) -> StatusCode {
+    if let Err(status) = authorize(&state, &headers) {
+        return status;
+    }
+    match &state.hub.login_limit {
+        Some(limit) if limit.unblock(ip, state.hub.clock.now()) => {
+            info!(%ip, "login block lifted via admin API");
+            StatusCode::NO_CONTENT
+        }
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+
+async fn get_log_level(State(state): State<AppState>, headers: HeaderMap) -> Result<String, StatusCode> {
+    authorize(&state, &headers)?;
+    crate::logging::level().ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Replaces the log level directives with the request body.
+async fn set_log_level(State(state): State<AppState>, headers: HeaderMap, body: String) -> (StatusCode, String) {
+    if let Err(status) = authorize(&state, &headers) {
+        return (status, String::new());
+    }
+    let directives = body.trim();
+    match crate::logging::set_level(directives) {
+        Ok(()) => {
+            info!(%directives, "log level changed via admin API");
+            (StatusCode::NO_CONTENT, String::new())
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e),
+    }
+}
+
+/// Injects the raw packet in the request body, answering with the line as
+/// tagged and distributed.
+async fn inject(State(state): State<AppState>, headers: HeaderMap, body: String) -> (StatusCode, String) {
+    if let Err(status) = authorize(&state, &headers) {
+        return (status, String::new());
+    }
+    match crate::pipeline::inject(&state.hub, &body) {
+        Ok(line) => {
+            info!(%line, "packet injected via admin API");
+            (StatusCode::OK, line)
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e),
+    }
+}
+
+/// Starts the TCP S2S peer described by the JSON body, in the config's
+/// `[[s2s_peers]]` format.
+async fn add_peer(State(state): State<AppState>, headers: HeaderMap, Json(cfg): Json<S2SPeerConfig>) -> (StatusCode, String) {
+    if let Err(status) = authorize(&state, &headers) {
+        return (status, String::new());
+    }
+    let key = crate::links::peer_key(&cfg);
+    match crate::links::add_peer(&state.hub, cfg) {
+        Ok(()) => {
+            info!(peer = %key, "S2S peer added via admin API");
+            (StatusCode::NO_CONTENT, String::new())
+        }
+        Err(e) => (StatusCode::CONFLICT, e),
+    }
+}
+
+/// Tears down a TCP peer by `peer_name`, or `host:port` if it has none.
+async fn remove_peer(State(state): State<AppState>, headers: HeaderMap, Path(name): Path<String>) -> StatusCode {
+    if let Err(status) = authorize(&state, &headers) {
+        return status;
+    }
+    if crate::links::remove_peer(&state.hub, &name) {
+        info!(peer = %name, "S2S peer removed via admin API");
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn list_watched(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<Vec<String>>, StatusCode> {
+    authorize(&state, &headers)?;
+    Ok(Json(state.hub.watch.list()))
+}
+
+async fn watch(State(state): State<AppState>, headers: HeaderMap, Path(callsign): Path<String>) -> StatusCode {
+    if let Err(status) = authorize(&state, &headers) {
+        return status;
+    }
+    if state.hub.watch.add(&callsign) {
+        info!(%callsign, "watch started via admin API");
+    }
+    StatusCode::NO_CONTENT
+}
+
+async fn unwatch(State(state): State<AppState>, headers: HeaderMap, Path(callsign): Path<String>) -> StatusCode {
+    if let Err(status) = authorize(&state, &headers) {
+        return status;
+    }
+    if state.hub.watch.remove(&callsign) {
+        info!(%callsign, "watch stopped via admin API");
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Streams the decisions about watched stations' packets as JSON text
+/// messages, until the socket closes.
+async fn watch_ws(ws: WebSocketUpgrade, State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(status) = authorize(&state, &headers) {
+        return status.into_response();
+    }
+    let mut events = state.hub.watch.subscribe();
+    ws.on_upgrade(move |mut socket| async move {
+        loop {
+            tokio::select! {
+                msg = socket.recv() => match msg {
+                    Some(Ok(_)) => {}
+                    _ => break,
+                },
+                event = events.recv() => {
+                    let text = match event {
+                        Ok(event) => serde_json::to_string(&event).unwrap_or_default(),
+                        Err(RecvError::Lagged(n)) => serde_json::json!({"skipped": n}).to_string(),
+                        Err(RecvError::Closed) => break,
+                    };
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UplinkConfig;
+    use crate::hub::Hub;
+    use crate::uplink::UplinkStatus;
+    use std::sync::{Arc, Mutex};
+
+    fn state(token: Option<&str>) -> AppState {
+        let cfg = UplinkConfig { host: "dummy".to_string(), port: 0, callsign: "dummy".to_string(), passcode: 0, ..Default::default() };
+        AppState::new(Arc::new(Hub::new()), Arc::new(Mutex::new(UplinkStatus::new(&cfg))), token.map(|t| t.to_string()))
+    }
+
+    #[test]
+    fn test_authorize() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert_eq!(authorize(&state(None), &headers), Err(StatusCode::FORBIDDEN));
+        assert_eq!(authorize(&state(Some("secret")), &headers), Ok(()));
+        assert_eq!(authorize(&state(Some("other")), &headers), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(authorize(&state(Some("secret2")), &headers), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(authorize(&state(Some("secre")), &headers), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(authorize(&state(Some("secret")), &HeaderMap::new()), Err(StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn test_change_bans() {
+        let state = state(Some("secret"));
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert_eq!(change_bans(&state, &headers, |b| b.add_callsign("N0CALL"), true), StatusCode::NO_CONTENT);
+        assert!(state.hub.bans.read().unwrap().is_callsign_banned("N0CALL-1"));
+        assert_eq!(change_bans(&state, &headers, |b| b.remove_callsign("N0CALL"), false), StatusCode::NO_CONTENT);
+        assert_eq!(change_bans(&state, &headers, |b| b.remove_callsign("N0CALL"), false), StatusCode::NOT_FOUND);
+        assert_eq!(change_bans(&state, &HeaderMap::new(), |b| b.add_callsign("X"), true), StatusCode::UNAUTHORIZED);
+        // A ban that cannot be saved is not applied either
+        state.hub.bans.write().unwrap().file = Some(std::env::temp_dir().to_string_lossy().into_owned());
+        assert_eq!(change_bans(&state, &headers, |b| b.add_callsign("N1CALL"), true), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!state.hub.bans.read().unwrap().is_callsign_banned("N1CALL"));
+    }
+}
@@ -0,0 +1,88 @@
+//! The AGWPE TCP API, spoken by AGW Packet Engine and UZ7HO SoundModem.
+//! Every frame is a 36-byte header followed by `data_len` bytes of data.
+//! With raw monitoring enabled (`k`) the engine sends each frame heard as
+//! a `K` frame: one KISS command byte and the AX.25 frame without its FCS.
+
+const HEADER_LEN: usize = 36;
+/// Anything longer is not an AGWPE stream
+const MAX_DATA: usize = 64 * 1024;
+/// Toggles raw AX.25 monitoring
+pub const KIND_RAW_MONITOR: u8 = b'k';
+/// A raw AX.25 frame, received or to be sent
+pub const KIND_RAW: u8 = b'K';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgwFrame {
+    pub port: u8,
+    pub kind: u8,
+    pub data: Vec<u8>,
+}
+
+impl AgwFrame {
+    /// The AX.25 frame carried by a raw `K` frame.
+    pub fn raw_ax25(&self) -> Option<&[u8]> {
+        match (self.kind, self.data.split_first()) {
+            (KIND_RAW, Some((_command, frame))) => Some(frame),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes a frame without callsigns, as used for requests and raw frames.
+pub fn encode(port: u8, kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; HEADER_LEN];
+    out[0] = port;
+    out[4] = kind;
+    out[28..32].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+/// Splits an AGWPE byte stream into frames.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+}
+
+impl Decoder {
+    /// Feeds `bytes` and returns the frames completed by them. A data length
+    /// over 64 KiB means the stream is not AGWPE and is an error.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<AgwFrame>, String> {
+        self.buf.extend_from_slice(bytes);
+        let mut frames = Vec::new();
+        while self.buf.len() >= HEADER_LEN {
+            let data_len = u32::from_le_bytes(self.buf[28..32].try_into().unwrap()) as usize;
+            if data_len > MAX_DATA {
+                return Err(format!("AGWPE frame of {} bytes", data_len));
+            }
+            if self.buf.len() < HEADER_LEN + data_len {
+                break;
+            }
+            let rest = self.buf.split_off(HEADER_LEN + data_len);
+            frames.push(AgwFrame { port: self.buf[0], kind: self.buf[4], data: self.buf[HEADER_LEN..].to_vec() });
+            self.buf = rest;
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let mut bytes = encode(1, KIND_RAW, &[0x10, 0x82, 0x84]);
+        bytes.extend(encode(0, KIND_RAW_MONITOR, &[]));
+        let mut decoder = Decoder::default();
+        assert!(decoder.push(&bytes[..30]).unwrap().is_empty());
+        let frames = decoder.push(&bytes[30..]).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].port, frames[0].raw_ax25()), (1, Some(&[0x82, 0x84][..])));
+        assert_eq!((frames[1].kind, frames[1].raw_ax25()), (KIND_RAW_MONITOR, None));
+
+        let mut huge = encode(0, KIND_RAW, &[]);
+        huge[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Decoder::default().push(&huge).is_err());
+    }
+}
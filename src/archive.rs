@@ -0,0 +1,193 @@
+use crate::config::ArchiveConfig;
+use crate::hook::PacketHook;
+use crate::parser::ParsedPacket;
+use crate::sink::SinkQueue;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::Receiver;
+use tokio_postgres::{Client, NoTls};
+use tracing::error;
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS packets (
+    id BIGSERIAL PRIMARY KEY,
+    received TIMESTAMPTZ NOT NULL,
+    source TEXT NOT NULL,
+    destination TEXT NOT NULL,
+    packet_type TEXT NOT NULL,
+    raw TEXT NOT NULL,
+    position GEOGRAPHY(POINT, 4326)
+)";
+const INSERT: &str = "INSERT INTO packets (received, source, destination, packet_type, raw, position)
+    VALUES ($1, $2, $3, $4, $5,
+        CASE WHEN $6::float8 IS NULL THEN NULL ELSE ST_SetSRID(ST_MakePoint($7::float8, $6::float8), 4326)::geography END)";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArchiveStatus {
+    pub connected: bool,
+    pub written: u64,
+    pub dropped: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivedPacket {
+    pub received: SystemTime,
+    pub source: String,
+    pub destination: String,
+    pub packet_type: &'static str,
+    pub raw: String,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+}
+
+impl ArchivedPacket {
+    pub fn new(line: &str, packet: &ParsedPacket) -> Self {
+        let position = packet.position();
+        ArchivedPacket {
+            received: SystemTime::now(),
+            source: packet.source.clone(),
+            destination: packet.destination.clone(),
+            packet_type: packet.data.type_name(),
+            raw: line.to_string(),
+            lat: position.map(|p| p.lat),
+            lon: position.map(|p| p.lon),
+        }
+    }
+}
+
+/// Packet hook queueing accepted packets for the database writer. The queue
+/// is bounded; when the database falls behind, packets are dropped rather
+/// than stalling the feed.
+pub struct ArchiveSink {
+    queue: SinkQueue<ArchivedPacket>,
+    status: Arc<Mutex<ArchiveStatus>>,
+}
+
+impl ArchiveSink {
+    /// Spawns the batch writer on the current Tokio runtime. The database
+    /// is connected to, and the table created, once the first batch is due.
+    pub fn start(cfg: &ArchiveConfig, status: Arc<Mutex<ArchiveStatus>>) -> Self {
+        let writer_cfg = cfg.clone();
+        let writer_status = status.clone();
+        let queue = SinkQueue::spawn(cfg.queue_size, |rx| run_writer(writer_cfg, rx, writer_status));
+        ArchiveSink { queue, status }
+    }
+}
+
+impl PacketHook for ArchiveSink {
+    fn on_accept(&self, line: &str, packet: &ParsedPacket) {
+        if !self.queue.push(ArchivedPacket::new(line, packet)) {
+            self.status.lock().unwrap().dropped += 1;
+        }
+    }
+}
+
+async fn connect(url: &str) -> Result<Client, tokio_postgres::Error> {
+    let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!(error = %e, "archive database connection error");
+        }
+    });
+    client.batch_execute(CREATE_TABLE).await?;
+    Ok(client)
+}
+
+async fn write_batch(client: &mut Client, batch: &[ArchivedPacket]) -> Result<(), tokio_postgres::Error> {
+    let tx = client.transaction().await?;
+    let insert = tx.prepare(INSERT).await?;
+    for p in batch {
+        tx.execute(&insert, &[&p.received, &p.source, &p.destination, &p.packet_type, &p.raw, &p.lat, &p.lon])
+            .await?;
+    }
+    tx.commit().await
+}
+
+/// Collects packets from the queue and writes them in batches, reconnecting
+/// after errors. A batch that fails to write is discarded.
+async fn run_writer(cfg: ArchiveConfig, mut rx: Receiver<ArchivedPacket>, status: Arc<Mutex<ArchiveStatus>>) {
+    let mut client: Option<Client> = None;
+    let mut batch = Vec::with_capacity(cfg.batch_size);
+    loop {
+        // Wait for the first packet, then give the batch time to fill up
+        match rx.recv().await {
+            Some(p) => batch.push(p),
+            None => return,
+        }
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(cfg.flush_interval_ms);
+        while batch.len() < cfg.batch_size {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(p)) => batch.push(p),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        if client.is_none() {
+            match connect(&cfg.url).await {
+                Ok(c) => {
+                    client = Some(c);
+                    status.lock().unwrap().connected = true;
+                }
+                Err(e) => {
+                    {
+                        let mut s = status.lock().unwrap();
+                        s.errors += 1;
+                        s.dropped += batch.len() as u64;
+                        s.last_error = Some(format!("connect: {}", e));
+                    }
+                    batch.clear();
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            }
+        }
+        let result = write_batch(client.as_mut().unwrap(), &batch).await;
+        let mut s = status.lock().unwrap();
+        match result {
+            Ok(()) => s.written += batch.len() as u64,
+            Err(e) => {
+                s.connected = false;
+                s.errors += 1;
+                s.dropped += batch.len() as u64;
+                s.last_error = Some(format!("write: {}", e));
+                client = None;
+            }
+        }
+        batch.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_archived_packet() {
+        let line = "N0CALL>APRS,TCPIP*:!4903.50N/07201.75W-Test";
+        let p = ArchivedPacket::new(line, &parse(line).unwrap());
+        assert_eq!(p.source, "N0CALL");
+        assert_eq!(p.packet_type, "position");
+        assert_eq!(p.raw, line);
+        assert!((p.lat.unwrap() - 49.058).abs() < 0.001);
+        let p = ArchivedPacket::new("N0CALL>APRS:>hi", &parse("N0CALL>APRS:>hi").unwrap());
+        assert_eq!(p.lat, None);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops() {
+        // A writer that never reads
+        let queue = SinkQueue::spawn(1, |rx| async move {
+            let _rx = rx;
+            std::future::pending::<()>().await
+        });
+        let status = Arc::new(Mutex::new(ArchiveStatus::default()));
+        let sink = ArchiveSink { queue, status: status.clone() };
+        let line = "N0CALL>APRS:>hi";
+        let packet = parse(line).unwrap();
+        sink.on_accept(line, &packet);
+        sink.on_accept(line, &packet);
+        assert_eq!(status.lock().unwrap().dropped, 1);
+    }
+}
@@ -0,0 +1,93 @@
+//! Delegation of client logins to an external HTTP endpoint, so access can
+//! follow a club's member database instead of the passcode algorithm. The
+//! endpoint is POSTed the login as JSON and answers whether to let it in,
+//! verified or not, and optionally which client class it belongs to.
+
+use crate::config::{AuthCalloutConfig, AuthFallback};
+use crate::login::Login;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+#[derive(Serialize)]
+struct AuthRequest<'a> {
+    callsign: &'a str,
+    passcode: &'a str,
+    ip: Option<IpAddr>,
+    software: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Verified,
+    /// Logged in, but receive-only
+    Unverified,
+    Denied,
+}
+
+/// The endpoint's answer, e.g. `{"result": "verified", "class": "members"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AuthReply {
+    pub result: Verdict,
+    /// Name of a configured client class to put the client in
+    #[serde(default)]
+    pub class: Option<String>,
+    /// Sent to a denied client as a comment line
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+pub struct AuthCallout {
+    config: AuthCalloutConfig,
+    /// Built on first use, as the blocking client must not be created
+    /// inside the async runtime
+    client: OnceLock<Result<reqwest::blocking::Client, String>>,
+}
+
+impl AuthCallout {
+    pub fn new(config: AuthCalloutConfig) -> Self {
+        AuthCallout { config, client: OnceLock::new() }
+    }
+
+    pub fn fallback(&self) -> AuthFallback {
+        self.config.on_error
+    }
+
+    /// Asks the endpoint about `login`. Blocks up to the configured
+    /// timeout, so call it from a session thread.
+    pub fn check(&self, login: &Login, ip: Option<IpAddr>) -> Result<AuthReply, String> {
+        let client = self
+            .client
+            .get_or_init(|| {
+                reqwest::blocking::Client::builder()
+                    .timeout(Duration::from_secs(self.config.timeout_secs))
+                    .user_agent(crate::version::SOFTWARE)
+                    .build()
+                    .map_err(|e| e.to_string())
+            })
+            .as_ref()
+            .map_err(Clone::clone)?;
+        let request = AuthRequest { callsign: &login.callsign, passcode: &login.passcode, ip, software: login.software.as_deref() };
+        let response = client.post(&self.config.url).json(&request).send().map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        response.json().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply() {
+        let reply: AuthReply = serde_json::from_str(r#"{"result": "verified", "class": "members"}"#).unwrap();
+        assert_eq!(reply, AuthReply { result: Verdict::Verified, class: Some("members".to_string()), message: None });
+        let reply: AuthReply = serde_json::from_str(r#"{"result": "denied", "message": "not a member"}"#).unwrap();
+        assert_eq!((reply.result, reply.message.as_deref()), (Verdict::Denied, Some("not a member")));
+        assert!(serde_json::from_str::<AuthReply>(r#"{"result": "maybe"}"#).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! AX.25 frames as sent over the air, converted to and from the text
+//! (TNC2) form used on APRS-IS: `SOURCE>DEST,DIGI1*,DIGI2:information`.
+
+use std::fmt;
+
+/// Control field of a UI frame, and the "no layer 3" PID APRS uses
+pub const CONTROL_UI: u8 = 0x03;
+pub const PID_NO_LAYER3: u8 = 0xF0;
+const MAX_DIGIPEATERS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ax25Error {
+    TooShort,
+    InvalidAddress,
+    TooManyDigipeaters,
+}
+
+impl fmt::Display for Ax25Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Ax25Error::TooShort => "frame too short",
+            Ax25Error::InvalidAddress => "invalid address",
+            Ax25Error::TooManyDigipeaters => "more than 8 digipeaters",
+        })
+    }
+}
+
+impl std::error::Error for Ax25Error {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub callsign: String,
+    pub ssid: u8,
+    /// The H bit: this digipeater has repeated the frame
+    pub repeated: bool,
+}
+
+impl Address {
+    /// Parses `CALL` or `CALL-SSID` as written in a TNC2 header.
+    pub fn parse(s: &str) -> Result<Self, Ax25Error> {
+        let (callsign, ssid) = match s.split_once('-') {
+            Some((callsign, ssid)) => (callsign, ssid.parse::<u8>().ok().filter(|&ssid| ssid <= 15).ok_or(Ax25Error::InvalidAddress)?),
+            None => (s, 0),
+        };
+        if callsign.is_empty() || callsign.len() > 6 || !callsign.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) {
+            return Err(Ax25Error::InvalidAddress);
+        }
+        Ok(Address { callsign: callsign.to_string(), ssid, repeated: false })
+    }
+
+    fn encode(&self, last: bool) -> [u8; 7] {
+        let mut field = [b' ' << 1; 7];
+        for (i, b) in self.callsign.bytes().enumerate() {
+            field[i] = b << 1;
+        }
+        field[6] = 0x60 | (self.ssid << 1) | if self.repeated { 0x80 } else { 0 } | u8::from(last);
+        field
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Ax25Error> {
+        let mut callsign = String::new();
+        // Padded with spaces, which may not be followed by more characters
+        let mut padding = false;
+        for &b in &bytes[..6] {
+            match (b >> 1) as char {
+                ' ' => padding = true,
+                c @ ('A'..='Z' | '0'..='9') if !padding => callsign.push(c),
+                _ => return Err(Ax25Error::InvalidAddress),
+            }
+        }
+        if callsign.is_empty() {
+            return Err(Ax25Error::InvalidAddress);
+        }
+        Ok(Address { callsign, ssid: (bytes[6] >> 1) & 0x0F, repeated: bytes[6] & 0x80 != 0 })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ssid {
+            0 => f.write_str(&self.callsign),
+            ssid => write!(f, "{}-{}", self.callsign, ssid),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub destination: Address,
+    pub source: Address,
+    pub digipeaters: Vec<Address>,
+    pub control: u8,
+    pub pid: Option<u8>,
+    pub info: Vec<u8>,
+}
+
+impl Frame {
+    /// A UI frame carrying APRS information.
+    pub fn ui(destination: Address, source: Address, digipeaters: Vec<Address>, info: Vec<u8>) -> Self {
+        Frame { destination, source, digipeaters, control: CONTROL_UI, pid: Some(PID_NO_LAYER3), info }
+    }
+
+    /// Encodes the frame without flags and FCS, as KISS takes it.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16 + 7 * self.digipeaters.len() + self.info.len());
+        out.extend(self.destination.encode(false));
+        out.extend(self.source.encode(self.digipeaters.is_empty()));
+        for (i, digi) in self.digipeaters.iter().enumerate() {
+            out.extend(digi.encode(i == self.digipeaters.len() - 1));
+        }
+        out.push(self.control);
+        out.extend(self.pid);
+        out.extend(&self.info);
+        out
+    }
+
+    /// Decodes a frame without its flags and FCS, as KISS delivers it.
+    pub fn decode(bytes: &[u8]) -> Result<Self, Ax25Error> {
+        let mut addresses = Vec::new();
+        let mut rest = bytes;
+        loop {
+            if rest.len() < 7 {
+                return Err(Ax25Error::TooShort);
+            }
+            let (field, tail) = rest.split_at(7);
+            addresses.push(Address::decode(field)?);
+            rest = tail;
+            // The low bit of the SSID byte marks the last address
+            if field[6] & 0x01 != 0 {
+                break;
+            }
+            if addresses.len() > 2 + MAX_DIGIPEATERS {
+                return Err(Ax25Error::TooManyDigipeaters);
+            }
+        }
+        if addresses.len() < 2 {
+            return Err(Ax25Error::TooShort);
+        }
+        let (&control, rest) = rest.split_first().ok_or(Ax25Error::TooShort)?;
+        // Only I and UI frames carry a PID
+        let (pid, info) = if control & 0x01 == 0 || control & 0xEF == CONTROL_UI {
+            let (&pid, info) = rest.split_first().ok_or(Ax25Error::TooShort)?;
+            (Some(pid), info)
+        } else {
+            (None, rest)
+        };
+        let mut addresses = addresses.into_iter();
+        Ok(Frame {
+            destination: addresses.next().unwrap(),
+            source: addresses.next().unwrap(),
+            digipeaters: addresses.collect(),
+            control,
+            pid,
+            info: info.to_vec(),
+        })
+    }
+
+    /// True for a UI frame without layer 3, the kind APRS uses.
+    pub fn is_aprs(&self) -> bool {
+        self.control & 0xEF == CONTROL_UI && self.pid == Some(PID_NO_LAYER3)
+    }
+
+    /// `SOURCE>DEST,DIGI1,DIGI2*` with the last repeating digipeater marked.
+    pub fn tnc2_header(&self) -> String {
+        let mut header = format!("{}>{}", self.source, self.destination);
+        let last_repeated = self.digipeaters.iter().rposition(|d| d.repeated);
+        for (i, digi) in self.digipeaters.iter().enumerate() {
+            header.push(',');
+            header.push_str(&digi.to_string());
+            if Some(i) == last_repeated {
+                header.push('*');
+            }
+        }
+        header
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Address field for `call`, with the H bit and last-address bit as given.
+    fn address(call: &str, ssid: u8, h: bool, last: bool) -> Vec<u8> {
+        let mut field: Vec<u8> = format!("{:<6}", call).bytes().map(|b| b << 1).collect();
+        field.push(0x60 | (ssid << 1) | if h { 0x80 } else { 0 } | u8::from(last));
+        field
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut bytes = address("APRS", 0, false, false);
+        bytes.extend(address("N0CALL", 9, false, false));
+        bytes.extend(address("WIDE1", 0, true, false));
+        bytes.extend(address("WIDE2", 1, false, true));
+        bytes.extend([CONTROL_UI, PID_NO_LAYER3]);
+        bytes.extend(b"!4903.50N/07201.75W>");
+        let frame = Frame::decode(&bytes).unwrap();
+        assert!(frame.is_aprs());
+        assert_eq!(frame.tnc2_header(), "N0CALL-9>APRS,WIDE1*,WIDE2-1");
+        assert_eq!(frame.info, b"!4903.50N/07201.75W>");
+        assert_eq!(frame.encode(), bytes);
+
+        assert_eq!(Frame::decode(&bytes[..10]), Err(Ax25Error::TooShort));
+        let mut bad = bytes.clone();
+        bad[0] = b'a' << 1;
+        assert_eq!(Frame::decode(&bad), Err(Ax25Error::InvalidAddress));
+        // No address marked last
+        let endless: Vec<u8> = (0..12).flat_map(|_| address("WIDE1", 1, false, false)).collect();
+        assert_eq!(Frame::decode(&endless), Err(Ax25Error::TooManyDigipeaters));
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(Address::parse("N0CALL-10").unwrap().to_string(), "N0CALL-10");
+        assert_eq!(Address::parse("WIDE1-1").map(|a| a.ssid), Ok(1));
+        for bad in ["", "n0call", "N0CALL-16", "TOOLONGCALL", "N0CALL-", "N0-CALL-1"] {
+            assert_eq!(Address::parse(bad), Err(Ax25Error::InvalidAddress), "{}", bad);
+        }
+    }
+}
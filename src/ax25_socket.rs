@@ -0,0 +1,127 @@
+//! Kernel AX.25 ports on Linux. A packet socket bound to the port's network
+//! interface, such as `ax0` set up by kissattach, gets every frame heard on
+//! the port and sends frames on it. Frames carry a leading KISS command
+//! byte, as on a KISS link. Needs `CAP_NET_RAW`.
+
+use std::ffi::CString;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+fn protocol() -> u16 {
+    (libc::ETH_P_AX25 as u16).to_be()
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+pub struct Ax25Port {
+    fd: OwnedFd,
+    ifindex: i32,
+}
+
+impl Ax25Port {
+    /// Binds to the AX.25 network interface `interface`, e.g. `ax0`.
+    pub fn open(interface: &str) -> io::Result<Self> {
+        let name = CString::new(interface).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name"))?;
+        // SAFETY: `name` is a valid C string
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: plain syscall; the descriptor is owned right away
+        let fd = check(unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, protocol().into()) })?;
+        let port = Ax25Port { fd: unsafe { OwnedFd::from_raw_fd(fd) }, ifindex: ifindex as i32 };
+        let addr = port.address();
+        // SAFETY: `addr` is a sockaddr_ll of the given size
+        check(unsafe {
+            libc::bind(fd, &addr as *const libc::sockaddr_ll as *const libc::sockaddr, size_of::<libc::sockaddr_ll>() as libc::socklen_t)
+        })?;
+        Ok(port)
+    }
+
+    fn address(&self) -> libc::sockaddr_ll {
+        // SAFETY: all-zero is a valid sockaddr_ll
+        let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = protocol();
+        addr.sll_ifindex = self.ifindex;
+        addr
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Ax25Port { fd: self.fd.try_clone()?, ifindex: self.ifindex })
+    }
+}
+
+impl Read for Ax25Port {
+    /// Reads the next frame heard on the port. Frames sent from this host
+    /// are skipped.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // SAFETY: all-zero is a valid sockaddr_ll
+            let mut from: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+            let mut len = size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+            // SAFETY: `buf` and `from` are valid for the lengths given
+            let n = unsafe {
+                libc::recvfrom(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                    &mut from as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                    &mut len,
+                )
+            };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Zero would read as end of stream
+            if n > 0 && from.sll_pkttype != libc::PACKET_OUTGOING {
+                return Ok(n as usize);
+            }
+        }
+    }
+}
+
+impl Write for Ax25Port {
+    /// Sends `buf`, a KISS command byte and an AX.25 frame, as one frame.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let addr = self.address();
+        // SAFETY: `buf` and `addr` are valid for the lengths given
+        let n = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                0,
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_missing_interface() {
+        assert!(Ax25Port::open("noax99").is_err());
+        assert!(Ax25Port::open("ax\0").is_err());
+    }
+}
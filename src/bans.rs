@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+
+/// Banned callsigns and IP addresses. A callsign without SSID bans all of
+/// its SSIDs, one with an SSID only that station.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Bans {
+    #[serde(default)]
+    pub callsigns: BTreeSet<String>,
+    #[serde(default)]
+    pub ips: BTreeSet<IpAddr>,
+    /// File the list is saved to after every change
+    #[serde(skip)]
+    pub file: Option<String>,
+}
+
+impl Bans {
+    /// Loads the ban file, starting with an empty list if it does not exist yet.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut bans: Bans = match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content).map_err(|e| format!("{}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Bans::default(),
+            Err(e) => return Err(format!("{}: {}", path, e)),
+        };
+        bans.file = Some(path.to_string());
+        Ok(bans)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        let content = toml::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, content).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn is_callsign_banned(&self, callsign: &str) -> bool {
+        let callsign = callsign.to_uppercase();
+        let base = callsign.split('-').next().unwrap_or(&callsign);
+        self.callsigns.contains(&callsign) || self.callsigns.contains(base)
+    }
+
+    pub fn is_ip_banned(&self, ip: IpAddr) -> bool {
+        self.ips.contains(&ip)
+    }
+
+    /// Adds a callsign ban and saves the list. Returns false if it was already banned.
+    pub fn add_callsign(&mut self, callsign: &str) -> Result<bool, String> {
+        let added = self.callsigns.insert(callsign.to_uppercase());
+        self.save()?;
+        Ok(added)
+    }
+
+    pub fn remove_callsign(&mut self, callsign: &str) -> Result<bool, String> {
+        let removed = self.callsigns.remove(&callsign.to_uppercase());
+        self.save()?;
+        Ok(removed)
+    }
+
+    pub fn add_ip(&mut self, ip: IpAddr) -> Result<bool, String> {
+        let added = self.ips.insert(ip);
+        self.save()?;
+        Ok(added)
+    }
+
+    pub fn remove_ip(&mut self, ip: IpAddr) -> Result<bool, String> {
+        let removed = self.ips.remove(&ip);
+        self.save()?;
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bans_persist() {
+        let path = "test_bans.toml";
+        let _ = std::fs::remove_file(path);
+        let mut bans = Bans::load(path).unwrap();
+        assert!(bans.add_callsign("n0call").unwrap());
+        assert!(bans.add_callsign("N1CALL-5").unwrap());
+        assert!(bans.add_ip("192.0.2.1".parse().unwrap()).unwrap());
+        assert!(!bans.add_callsign("N0CALL").unwrap());
+        let bans = Bans::load(path).unwrap();
+        assert!(bans.is_callsign_banned("N0CALL-9"));
+        assert!(bans.is_callsign_banned("N1CALL-5"));
+        assert!(!bans.is_callsign_banned("N1CALL"));
+        assert!(bans.is_ip_banned("192.0.2.1".parse().unwrap()));
+        let mut bans = bans;
+        assert!(bans.remove_ip("192.0.2.1".parse().unwrap()).unwrap());
+        assert!(!Bans::load(path).unwrap().is_ip_banned("192.0.2.1".parse().unwrap()));
+        let _ = std::fs::remove_file(path);
+    }
+}
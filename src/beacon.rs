@@ -0,0 +1,213 @@
+//! Packets the server originates itself: its own position beacon and the
+//! `[[beacon]]` entries of the config file, sent on a schedule.
+
+use crate::config::{BeaconConfig, BeaconTarget, ObjectBeaconConfig, ServerBeaconConfig};
+use crate::hub::Hub;
+use crate::latency::Outbound;
+use crate::pipeline::{self, Source};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+// Beacons more frequent than this only add load to the network
+const MIN_INTERVAL_SECS: u64 = 60;
+
+/// Formats a latitude as `ddmm.mmN` or a longitude as `dddmm.mmE`.
+fn format_coordinate(value: f64, degree_digits: usize, hemispheres: [char; 2]) -> String {
+    // Rounded in hundredths of a minute so 59.999' does not become 60.00'
+    let hundredths = (value.abs() * 6000.0).round() as u64;
+    let hemisphere = if value < 0.0 { hemispheres[1] } else { hemispheres[0] };
+    format!(
+        "{:0width$}{:05.2}{}",
+        hundredths / 6000,
+        (hundredths % 6000) as f64 / 100.0,
+        hemisphere,
+        width = degree_digits
+    )
+}
+
+/// Uncompressed position with the two character symbol, e.g. `4903.50NI07201.75W&`.
+pub fn format_position(lat: f64, lon: f64, symbol: &str) -> String {
+    let mut symbol = symbol.chars();
+    let table = symbol.next().unwrap_or('/');
+    let code = symbol.next().unwrap_or('&');
+    format!(
+        "{}{}{}{}",
+        format_coordinate(lat, 2, ['N', 'S']),
+        table,
+        format_coordinate(lon, 3, ['E', 'W']),
+        code
+    )
+}
+
+/// Information field of the server's position report, at the beacon's own
+/// coordinates or else at `qth`. None if neither is known.
+pub fn position_report(cfg: &ServerBeaconConfig, qth: Option<(f64, f64)>) -> Option<String> {
+    let (lat, lon) = cfg.lat.zip(cfg.lon).or(qth)?;
+    Some(format!("!{}{}", format_position(lat, lon, &cfg.symbol), cfg.comment))
+}
+
+/// What a beacon carries after the header.
+#[derive(Debug, Clone)]
+pub enum BeaconBody {
+    Payload(String),
+    Object(ObjectBeaconConfig),
+}
+
+/// A packet sent every `interval` to `target`.
+#[derive(Debug, Clone)]
+pub struct Beacon {
+    pub source: String,
+    pub path: String,
+    pub body: BeaconBody,
+    pub interval: Duration,
+    pub target: BeaconTarget,
+}
+
+impl Beacon {
+    /// The server's own position report, sent as `callsign`.
+    pub fn server(cfg: &ServerBeaconConfig, qth: Option<(f64, f64)>, callsign: &str) -> Option<Self> {
+        Some(Beacon {
+            source: callsign.to_string(),
+            path: "TCPIP*".to_string(),
+            body: BeaconBody::Payload(position_report(cfg, qth)?),
+            interval: Duration::from_secs(cfg.interval_secs.max(MIN_INTERVAL_SECS)),
+            target: BeaconTarget::Both,
+        })
+    }
+
+    pub fn from_config(cfg: &BeaconConfig) -> Result<Self, String> {
+        let body = match (&cfg.payload, &cfg.object) {
+            (Some(payload), None) => BeaconBody::Payload(payload.clone()),
+            (None, Some(object)) if (1..=9).contains(&object.name.len()) => BeaconBody::Object(object.clone()),
+            (None, Some(object)) => return Err(format!("object name {:?} must be 1 to 9 characters", object.name)),
+            _ => return Err(format!("beacon from {} needs either payload or object", cfg.source)),
+        };
+        Ok(Beacon {
+            source: cfg.source.clone(),
+            path: cfg.path.clone(),
+            body,
+            interval: Duration::from_secs(cfg.interval_secs.max(MIN_INTERVAL_SECS)),
+            target: cfg.target,
+        })
+    }
+
+    /// The packet line to send at `now`; objects carry the time they were sent.
+    pub fn packet(&self, now: DateTime<Utc>) -> String {
+        let info = match &self.body {
+            BeaconBody::Payload(payload) => payload.clone(),
+            BeaconBody::Object(o) => format!(
+                ";{:<9}*{}{}{}",
+                o.name,
+                now.format("%d%H%Mz"),
+                format_position(o.lat, o.lon, &o.symbol),
+                o.comment
+            ),
+        };
+        if self.path.is_empty() {
+            format!("{}>APRS:{}", self.source, info)
+        } else {
+            format!("{}>APRS,{}:{}", self.source, self.path, info)
+        }
+    }
+}
+
+/// Sends each beacon right away and then at its own interval, into the
+/// local feed and/or to the uplink if there is one.
+pub async fn run(beacons: Vec<Beacon>, hub: Arc<Hub>) {
+    let mut due = vec![Instant::now(); beacons.len()];
+    loop {
+        let Some((i, at)) = due.iter().copied().enumerate().min_by_key(|(_, at)| *at) else {
+            return;
+        };
+        tokio::time::sleep_until(at).await;
+        let beacon = &beacons[i];
+        let packet = beacon.packet(Utc::now());
+        debug!(%packet, "sending beacon");
+        if beacon.target != BeaconTarget::Uplink {
+            let _ = pipeline::ingest(&hub, Source::Local, &packet);
+        }
+        if beacon.target != BeaconTarget::Local
+            && let Ok(parsed) = crate::parser::parse(&packet)
+        {
+            hub.send_to_uplinks("local", &Outbound::now(format!("{}\r\n", packet)), &parsed);
+        }
+        due[i] = at + beacon.interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::PacketData;
+    use chrono::TimeZone;
+
+    fn beacon_config(payload: Option<&str>, object: Option<ObjectBeaconConfig>, target: BeaconTarget) -> BeaconConfig {
+        BeaconConfig {
+            source: "N0CALL".to_string(),
+            path: "TCPIP*".to_string(),
+            payload: payload.map(|p| p.to_string()),
+            object,
+            interval_secs: 600,
+            target,
+        }
+    }
+
+    #[test]
+    fn test_server_beacon() {
+        let cfg = ServerBeaconConfig {
+            callsign: None,
+            lat: None,
+            lon: None,
+            symbol: "I&".to_string(),
+            comment: "APRS-IS server".to_string(),
+            interval_secs: 1800,
+        };
+        assert!(Beacon::server(&cfg, None, "T2TEST").is_none());
+        let packet = Beacon::server(&cfg, Some((49.058333, -72.029167)), "T2TEST").unwrap().packet(Utc::now());
+        assert_eq!(packet, "T2TEST>APRS,TCPIP*:!4903.50NI07201.75W&APRS-IS server");
+        let PacketData::Position(pos) = crate::parser::parse(&packet).unwrap().data else {
+            panic!("not a position");
+        };
+        assert!((pos.lat - 49.058333).abs() < 0.001 && (pos.lon + 72.029167).abs() < 0.001);
+        let own = ServerBeaconConfig { lat: Some(60.0), lon: Some(25.0), ..cfg };
+        assert_eq!(position_report(&own, Some((49.0, -72.0))).unwrap(), "!6000.00NI02500.00E&APRS-IS server");
+        assert_eq!(format_position(-33.99999, 151.0, "/&"), "3400.00S/15100.00E&");
+    }
+    #[test]
+    fn test_object_beacon() {
+        let object = ObjectBeaconConfig {
+            name: "145.500".to_string(),
+            lat: 60.1699,
+            lon: 24.9384,
+            symbol: "/r".to_string(),
+            comment: "Net Tue 19:00".to_string(),
+        };
+        let beacon = Beacon::from_config(&beacon_config(None, Some(object.clone()), BeaconTarget::Both)).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 5, 7, 18, 30, 0).unwrap();
+        assert_eq!(beacon.packet(now), "N0CALL>APRS,TCPIP*:;145.500  *071830z6010.19N/02456.30ErNet Tue 19:00");
+        assert!(matches!(crate::parser::parse(&beacon.packet(now)).unwrap().data, PacketData::Object { .. }));
+
+        assert!(Beacon::from_config(&beacon_config(None, None, BeaconTarget::Both)).is_err());
+        assert!(Beacon::from_config(&beacon_config(Some(">hi"), Some(object.clone()), BeaconTarget::Both)).is_err());
+        let long = ObjectBeaconConfig { name: "TOOLONGNAME".to_string(), ..object };
+        assert!(Beacon::from_config(&beacon_config(None, Some(long), BeaconTarget::Both)).is_err());
+    }
+    #[tokio::test]
+    async fn test_run_targets() {
+        let mut hub = Hub::new();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        hub.uplinks.get_mut().unwrap().push(crate::uplink::test_route(tx));
+        let hub = Arc::new(hub);
+        let beacons = vec![
+            Beacon::from_config(&beacon_config(Some(">local"), None, BeaconTarget::Local)).unwrap(),
+            Beacon::from_config(&beacon_config(Some(">uplink"), None, BeaconTarget::Uplink)).unwrap(),
+        ];
+        tokio::spawn(run(beacons, hub.clone()));
+        assert_eq!(rx.recv().await.unwrap(), "N0CALL>APRS,TCPIP*:>uplink\r\n");
+        assert_eq!(hub.counters.drop_stats().accepted, 1);
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -1,50 +1,346 @@
-use std::net::TcpStream;
-use std::sync::{Arc, Mutex};
-use std::time::{Instant};
-use crate::filter::ClientFilter;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, mpsc};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::warn;
+use crate::client_class::{BandwidthLimiter, ClientClass};
+use crate::filter::FilterSet;
+use crate::geoip::Location;
+use crate::latency::{Histogram, Outbound};
+use crate::stats::{ListenerStats, TrafficRates};
+
+// Packets waiting for a slow client beyond this are dropped, so one client
+// cannot hold up delivery to the others
+pub const CLIENT_QUEUE_SIZE: usize = 1000;
+// A busy client gets the lines queued meanwhile in one write per interval,
+// instead of a write per packet
+const WRITE_INTERVAL: Duration = Duration::from_millis(20);
+const MAX_WRITE_BYTES: usize = 64 * 1024;
+// How often a write to a WebSocket with a full buffer retries
+const WEBSOCKET_RETRY: Duration = Duration::from_millis(5);
+
+/// Sending side of a client connection. WebSocket clients get one text
+/// message per line, without the line terminator, even when several lines
+/// are written at once. Their lines pass through a bounded buffer; a write
+/// waits up to `write_timeout` for room in it, like a TCP write does for
+/// the socket buffer, and then fails.
+#[derive(Debug)]
+pub enum ClientConn {
+    Tcp(TcpStream),
+    WebSocket { tx: Option<Sender<String>>, write_timeout: Duration },
+}
+
+impl ClientConn {
+    /// Closes the connection, which also ends the client's session.
+    pub fn shutdown(&mut self) {
+        match self {
+            ClientConn::Tcp(stream) => {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            ClientConn::WebSocket { tx, .. } => {
+                tx.take();
+            }
+        }
+    }
+}
+
+impl Write for ClientConn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ClientConn::Tcp(stream) => stream.write(buf),
+            ClientConn::WebSocket { tx: Some(tx), write_timeout } => {
+                let deadline = Instant::now() + *write_timeout;
+                for line in String::from_utf8_lossy(buf).lines().filter(|l| !l.is_empty()) {
+                    let mut line = line.to_string();
+                    loop {
+                        match tx.try_send(line) {
+                            Ok(()) => break,
+                            Err(TrySendError::Full(rest)) if Instant::now() < deadline => {
+                                line = rest;
+                                std::thread::sleep(WEBSOCKET_RETRY);
+                            }
+                            Err(TrySendError::Full(_)) => return Err(io::ErrorKind::TimedOut.into()),
+                            Err(TrySendError::Closed(_)) => return Err(io::ErrorKind::BrokenPipe.into()),
+                        }
+                    }
+                }
+                Ok(buf.len())
+            }
+            ClientConn::WebSocket { tx: None, .. } => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ClientConn::Tcp(stream) => stream.flush(),
+            ClientConn::WebSocket { .. } => Ok(()),
+        }
+    }
+}
+
+/// Receiving side of a WebSocket client, read like a socket. Each message is
+/// treated as one or more lines; reads return end of file once the sender
+/// is dropped.
+pub struct ChannelReader {
+    rx: mpsc::Receiver<String>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    pub fn new(rx: mpsc::Receiver<String>) -> Self {
+        ChannelReader { rx, buf: Vec::new(), pos: 0 }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.buf.len() {
+            let Ok(mut text) = self.rx.recv() else {
+                return Ok(0);
+            };
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            self.buf = text.into_bytes();
+            self.pos = 0;
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Outgoing traffic of a client, counted by its writer thread as lines are
+/// actually written.
+#[derive(Debug, Default)]
+pub struct TxStats {
+    pub packets: AtomicU64,
+    pub bytes: AtomicU64,
+    /// Socket writes, each carrying one or more lines
+    pub writes: AtomicU64,
+    /// Lines dropped because the queue was full
+    pub overflows: AtomicU64,
+    /// Lines lost because the write failed
+    pub write_errors: AtomicU64,
+    /// Writes the client did not take within the write timeout, after which
+    /// it was disconnected
+    pub write_timeouts: AtomicU64,
+    /// Most lines ever waiting in the queue
+    pub queue_high_water: AtomicU64,
+    /// Packets not sent to stay within the class bandwidth cap
+    pub shed: AtomicU64,
+    pub shed_bytes: AtomicU64,
+    /// Port the client connected to, which is credited with the traffic too
+    pub listener: OnceLock<Arc<ListenerStats>>,
+    /// Where the time packets took to be written to the client is recorded
+    pub latency: OnceLock<Arc<Histogram>>,
+}
+
+impl TxStats {
+    pub fn add(&self, packets: u64, bytes: usize) {
+        self.packets.fetch_add(packets, Ordering::Relaxed);
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        if let Some(listener) = self.listener.get() {
+            listener.add_tx(packets, bytes);
+        }
+    }
+}
+
+/// Writes queued lines to the connection until the queue is closed. A line
+/// arriving while idle goes out at once; under load, whatever queued up
+/// since the previous write is coalesced into one. Packets carry when they
+/// were received, for the latency histogram. A failed or timed out write
+/// closes the connection, which ends the client's session.
+fn run_writer(mut rx: Receiver<(String, Option<Instant>)>, stream: Arc<Mutex<ClientConn>>, tx: Arc<TxStats>) {
+    let mut buf = String::new();
+    let mut received = Vec::new();
+    while let Some((line, at)) = rx.blocking_recv() {
+        buf.push_str(&line);
+        received.extend(at);
+        let mut lines = 1;
+        while buf.len() < MAX_WRITE_BYTES
+            && let Ok((line, at)) = rx.try_recv()
+        {
+            buf.push_str(&line);
+            received.extend(at);
+            lines += 1;
+        }
+        let result = stream.lock().unwrap().write_all(buf.as_bytes());
+        match result {
+            Ok(()) => {
+                tx.add(lines, buf.len());
+                if let Some(latency) = tx.latency.get() {
+                    for at in &received {
+                        latency.record(at.elapsed());
+                    }
+                }
+            }
+            Err(e) => {
+                tx.write_errors.fetch_add(lines, Ordering::Relaxed);
+                if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) {
+                    tx.write_timeouts.fetch_add(1, Ordering::Relaxed);
+                    warn!(lines, "client write timed out, disconnecting");
+                }
+                stream.lock().unwrap().shutdown();
+                return;
+            }
+        }
+        buf.clear();
+        received.clear();
+        std::thread::sleep(WRITE_INTERVAL);
+    }
+}
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct Client {
     pub _id: usize,
-    pub stream: Arc<Mutex<TcpStream>>,
-    pub filter: Option<Vec<ClientFilter>>,
+    pub stream: Arc<Mutex<ClientConn>>,
+    /// Lines waiting for the writer thread
+    queue: Sender<(String, Option<Instant>)>,
+    pub tx: Arc<TxStats>,
+    pub filter: Option<Arc<FilterSet>>,
+    /// Filter of the listening port, applied on top of `filter`
+    pub listener_filter: Option<Arc<FilterSet>>,
     pub callsign: Option<String>,
     pub connect_time: Instant,
     pub packets_rx: u64,
-    pub packets_tx: u64,
     pub bytes_rx: u64,
-    pub bytes_tx: u64,
+    /// Remote address, when known
+    pub peer: Option<SocketAddr>,
+    /// `peer` for display, prefixed with `ws:` for WebSocket clients
+    pub addr: String,
+    /// Country and region of `peer`, when a GeoIP database is configured
+    pub location: Option<Location>,
+    /// Unix time of a successful login
+    pub login_time: Option<u64>,
+    pub verified: bool,
+    /// Software name and version from the login line
+    pub software: Option<String>,
+    /// Client class the login was assigned to
+    pub class: Option<Arc<ClientClass>>,
+    /// Outbound bandwidth cap of the class
+    pub tx_limit: Option<BandwidthLimiter>,
+    /// Filter as given by the client
+    pub filter_string: Option<String>,
+    pub packets_dropped: u64,
+    /// Packets for the client held back by its filter
+    pub packets_filtered: u64,
+    /// Unix time of the last line received, or of being added to the hub
+    pub last_activity: u64,
+    pub rates: TrafficRates,
 }
 
 impl Client {
     pub fn new(id: usize, stream: TcpStream) -> Self {
+        let peer = stream.peer_addr().ok();
+        Self::with_conn(id, ClientConn::Tcp(stream), peer)
+    }
+    /// Wraps a connection and starts the thread writing queued lines to it.
+    pub fn with_conn(id: usize, conn: ClientConn, peer: Option<SocketAddr>) -> Self {
+        let addr = match (&conn, peer) {
+            (_, None) => "unknown".to_string(),
+            (ClientConn::Tcp(_), Some(peer)) => peer.to_string(),
+            (ClientConn::WebSocket { .. }, Some(peer)) => format!("ws:{}", peer),
+        };
+        let stream = Arc::new(Mutex::new(conn));
+        let tx = Arc::new(TxStats::default());
+        let (queue, rx) = tokio::sync::mpsc::channel(CLIENT_QUEUE_SIZE);
+        std::thread::spawn({
+            let (stream, tx) = (stream.clone(), tx.clone());
+            move || run_writer(rx, stream, tx)
+        });
         Self {
             _id: id,
-            stream: Arc::new(Mutex::new(stream)),
+            stream,
+            queue,
+            tx,
             filter: None,
+            listener_filter: None,
             callsign: None,
             connect_time: Instant::now(),
             packets_rx: 0,
-            packets_tx: 0,
             bytes_rx: 0,
-            bytes_tx: 0,
+            peer,
+            addr,
+            location: None,
+            login_time: None,
+            verified: false,
+            software: None,
+            class: None,
+            tx_limit: None,
+            filter_string: None,
+            packets_dropped: 0,
+            packets_filtered: 0,
+            last_activity: 0,
+            rates: TrafficRates::default(),
         }
     }
-    pub fn inc_rx(&mut self, bytes: usize) {
+    pub fn set_login(&mut self, callsign: &str, verified: bool, software: Option<String>, now: u64) {
+        self.callsign = Some(callsign.to_string());
+        self.login_time = Some(now);
+        self.verified = verified;
+        self.software = software;
+    }
+    pub fn inc_rx(&mut self, bytes: usize, now: u64) {
         self.packets_rx += 1;
         self.bytes_rx += bytes as u64;
+        self.last_activity = now;
+    }
+    pub fn inc_dropped(&mut self) {
+        self.packets_dropped += 1;
+    }
+    /// Queues a line for the writer thread without waiting for the socket.
+    /// Returns false if it was dropped because the client is not keeping up.
+    pub fn send(&self, line: &str) -> bool {
+        self.enqueue(line.to_string(), None)
+    }
+    /// Queues a packet, whose latency is recorded once it is written.
+    pub fn send_packet(&self, out: &Outbound) -> bool {
+        self.enqueue(out.line.clone(), Some(out.received))
+    }
+    fn enqueue(&self, line: String, received: Option<Instant>) -> bool {
+        match self.queue.try_send((line, received)) {
+            Ok(()) => {
+                self.tx.queue_high_water.fetch_max(self.queue_depth() as u64, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.tx.overflows.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.tx.write_errors.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+    /// The writer queue, for sending many lines that should wait for room
+    /// rather than be dropped, without holding the client's lock meanwhile.
+    pub fn queue(&self) -> Sender<(String, Option<Instant>)> {
+        self.queue.clone()
+    }
+    /// Lines waiting for the writer thread.
+    pub fn queue_depth(&self) -> usize {
+        self.queue.max_capacity() - self.queue.capacity()
     }
-    pub fn inc_tx(&mut self, bytes: usize) {
-        self.packets_tx += 1;
-        self.bytes_tx += bytes as u64;
+    pub fn packets_tx(&self) -> u64 {
+        self.tx.packets.load(Ordering::Relaxed)
+    }
+    pub fn bytes_tx(&self) -> u64 {
+        self.tx.bytes.load(Ordering::Relaxed)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::net::{TcpListener};
+    use std::net::TcpListener;
     #[test]
     fn test_client_new() {
         let listener = TcpListener::bind("127.0.0.1:0").unwrap();
@@ -52,5 +348,93 @@ mod tests {
         let client = Client::new(1, stream);
         assert_eq!(client._id, 1);
         assert!(client.filter.is_none());
+        assert_eq!(client.addr, listener.local_addr().unwrap().to_string());
+        assert!(!client.verified);
+    }
+
+    #[test]
+    fn test_websocket_conn() {
+        use std::io::BufRead;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+        let mut conn = ClientConn::WebSocket { tx: Some(tx), write_timeout: Duration::from_millis(50) };
+        conn.write_all(b"N0CALL>APRS:>hi\r\nN1CALL>APRS:>two\r\n").unwrap();
+        // A socket task that falls behind makes writes time out
+        let err = conn.write_all(b"N2CALL>APRS:>three\r\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(rx.try_recv().unwrap(), "N0CALL>APRS:>hi");
+        assert_eq!(rx.try_recv().unwrap(), "N1CALL>APRS:>two");
+        conn.shutdown();
+        assert!(conn.write_all(b"x").is_err());
+
+        let (line_tx, line_rx) = mpsc::channel();
+        line_tx.send("user N0CALL pass -1".to_string()).unwrap();
+        line_tx.send("# filter r/1/2/3\n".to_string()).unwrap();
+        drop(line_tx);
+        let lines: Vec<String> = io::BufReader::new(ChannelReader::new(line_rx)).lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, ["user N0CALL pass -1", "# filter r/1/2/3"]);
+    }
+
+    #[test]
+    fn test_write_coalescing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        let (queue, rx) = tokio::sync::mpsc::channel(CLIENT_QUEUE_SIZE);
+        for _ in 0..3 {
+            queue.try_send(("N0CALL>APRS:>hi\r\n".to_string(), Some(Instant::now()))).unwrap();
+        }
+        drop(queue);
+        let tx = Arc::new(TxStats::default());
+        let latency = Arc::new(Histogram::default());
+        tx.latency.set(latency.clone()).unwrap();
+        run_writer(rx, Arc::new(Mutex::new(ClientConn::Tcp(stream))), tx.clone());
+        assert_eq!(tx.writes.load(Ordering::Relaxed), 1);
+        assert_eq!(tx.packets.load(Ordering::Relaxed), 3);
+        assert_eq!(latency.snapshot().count, 3);
+        let mut out = [0u8; 64];
+        peer.read_exact(&mut out[..51]).unwrap();
+    }
+
+    #[test]
+    fn test_send_queue() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        let client = Client::new(1, stream);
+        assert!(client.send("N0CALL>APRS:>hi\r\n"));
+        let mut buf = [0u8; 64];
+        let n = peer.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"N0CALL>APRS:>hi\r\n");
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!((client.packets_tx(), client.bytes_tx()), (1, 17));
+        // A peer that stops reading fills the socket buffers, then the queue
+        let line = "x".repeat(1000);
+        let sent = (0..100_000).take_while(|_| client.send(&line)).count();
+        assert!(sent >= CLIENT_QUEUE_SIZE);
+        assert_eq!(client.tx.overflows.load(Ordering::Relaxed), 1);
+        assert_eq!(client.queue_depth(), CLIENT_QUEUE_SIZE);
+        assert_eq!(client.tx.queue_high_water.load(Ordering::Relaxed), CLIENT_QUEUE_SIZE as u64);
+    }
+
+    #[test]
+    fn test_write_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        stream.set_write_timeout(Some(Duration::from_millis(100))).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        let client = Client::new(1, stream);
+        // A peer that stops reading wedges the writer until the timeout
+        let line = "x".repeat(1000);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while client.tx.write_timeouts.load(Ordering::Relaxed) == 0 && Instant::now() < deadline {
+            if !client.send(&line) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        assert_eq!(client.tx.write_timeouts.load(Ordering::Relaxed), 1);
+        // The connection was closed
+        let mut out = Vec::new();
+        peer.read_to_end(&mut out).unwrap();
+        assert!(!client.send(&line));
     }
 } 
\ No newline at end of file
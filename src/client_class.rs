@@ -0,0 +1,257 @@
+//! Client classes: logins matched by callsign pattern or listening port get
+//! the policy of their class, such as a packet rate limit, the packet types
+//! they may send and a filter those packets must match.
+
+use crate::config::ClientClassConfig;
+use crate::filter::{ClientFilter, FilterContext, FilterSet, parse_filter_list};
+use crate::parser::{PacketData, ParsedPacket};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+#[derive(Debug)]
+pub struct ClientClass {
+    pub name: String,
+    callsigns: Vec<String>,
+    ports: Vec<u16>,
+    pub max_packets_per_minute: Option<u32>,
+    pub max_tx_bytes_per_sec: Option<u32>,
+    allowed_types: Option<FilterSet>,
+    filter: Option<FilterSet>,
+    pub read_only: bool,
+}
+
+/// `N0CALL` matches the callsign with any SSID, `N0*` every callsign
+/// starting with N0.
+fn callsign_matches(pattern: &str, callsign: &str) -> bool {
+    let (pattern, callsign) = (pattern.to_uppercase(), callsign.to_uppercase());
+    match pattern.strip_suffix('*') {
+        Some(prefix) => callsign.starts_with(prefix),
+        None => callsign == pattern || callsign.split('-').next() == Some(pattern.as_str()),
+    }
+}
+
+impl ClientClass {
+    pub fn from_config(cfg: &ClientClassConfig) -> Result<Self, String> {
+        let allowed_types = match &cfg.allowed_types {
+            Some(letters) => {
+                let filter: ClientFilter = format!("t/{}", letters).parse().map_err(|e| format!("class {}: {}", cfg.name, e))?;
+                Some(FilterSet::new(vec![filter]))
+            }
+            None => None,
+        };
+        let filter = match &cfg.filter {
+            Some(s) => Some(FilterSet::new(parse_filter_list(s).map_err(|e| format!("class {}: {}", cfg.name, e))?)),
+            None => None,
+        };
+        Ok(ClientClass {
+            name: cfg.name.clone(),
+            callsigns: cfg.callsigns.clone(),
+            ports: cfg.ports.clone(),
+            max_packets_per_minute: cfg.max_packets_per_minute,
+            max_tx_bytes_per_sec: cfg.max_tx_bytes_per_sec,
+            allowed_types,
+            filter,
+            read_only: cfg.read_only,
+        })
+    }
+
+    /// A class applies if the callsign matches one of its patterns and the
+    /// login came in on one of its ports; an empty list matches anything.
+    pub fn matches(&self, callsign: &str, port: Option<u16>) -> bool {
+        (self.callsigns.is_empty() || self.callsigns.iter().any(|p| callsign_matches(p, callsign)))
+            && (self.ports.is_empty() || port.is_some_and(|p| self.ports.contains(&p)))
+    }
+
+    /// True if a client of this class may submit `packet`: it must be of an
+    /// allowed type and match the class filter.
+    pub fn allows(&self, packet: &ParsedPacket, ctx: &FilterContext) -> bool {
+        self.allowed_types.as_ref().is_none_or(|t| t.matches(packet, ctx))
+            && self.filter.as_ref().is_none_or(|f| f.matches(packet, ctx))
+    }
+}
+
+/// The `[client_defaults]` filter for `callsign`. An exact pattern wins
+/// over a wildcard, and a longer wildcard over a shorter one.
+pub fn default_filter<'a>(defaults: &'a BTreeMap<String, String>, callsign: &str) -> Option<&'a str> {
+    defaults
+        .iter()
+        .filter(|(pattern, _)| callsign_matches(pattern, callsign))
+        .max_by_key(|(pattern, _)| (!pattern.ends_with('*'), pattern.len()))
+        .map(|(_, filter)| filter.as_str())
+}
+
+/// Packets a client may still send this minute, refilled continuously.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32, now: Instant) -> Self {
+        RateLimiter { per_minute: per_minute as f64, tokens: per_minute as f64, last: now }
+    }
+
+    /// Takes one packet from the allowance, returning false if there is none left.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.per_minute / 60.0).min(self.per_minute);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// How readily a packet type is given up when a client's bandwidth runs short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxPriority {
+    /// Messages, acks and bulletins, sent as long as possible
+    Message,
+    Other,
+    /// Positions, objects and items, the first to go
+    Position,
+}
+
+impl TxPriority {
+    pub fn of(packet: &ParsedPacket) -> Self {
+        if packet.message().is_some() {
+            return TxPriority::Message;
+        }
+        match packet.data {
+            PacketData::Position(_) | PacketData::MicE(_) | PacketData::Object { .. } | PacketData::Item { .. } => {
+                TxPriority::Position
+            }
+            _ => TxPriority::Other,
+        }
+    }
+}
+
+/// Outbound bytes per second allowed to a client, with up to one second of
+/// unused allowance saved up. Positions may only use the upper half of it,
+/// so they are shed first; messages may run up to a second into debt.
+#[derive(Debug)]
+pub struct BandwidthLimiter {
+    per_sec: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl BandwidthLimiter {
+    pub fn new(bytes_per_sec: u32, now: Instant) -> Self {
+        BandwidthLimiter { per_sec: bytes_per_sec as f64, tokens: bytes_per_sec as f64, last: now }
+    }
+
+    /// Takes `bytes` from the allowance if a packet of this priority may be sent.
+    pub fn allow(&mut self, bytes: usize, priority: TxPriority, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.per_sec);
+        let bytes = bytes as f64;
+        let floor = match priority {
+            TxPriority::Message => -self.per_sec,
+            TxPriority::Other => 0.0,
+            TxPriority::Position => self.per_sec / 2.0,
+        };
+        if self.tokens - bytes < floor {
+            return false;
+        }
+        self.tokens -= bytes;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn class_config(name: &str) -> ClientClassConfig {
+        ClientClassConfig {
+            name: name.to_string(),
+            callsigns: Vec::new(),
+            ports: Vec::new(),
+            max_packets_per_minute: None,
+            max_tx_bytes_per_sec: None,
+            allowed_types: None,
+            filter: None,
+            read_only: false,
+        }
+    }
+
+    #[test]
+    fn test_class_matching() {
+        let igate = ClientClass::from_config(&ClientClassConfig {
+            callsigns: vec!["N0CALL".to_string(), "OH*".to_string()],
+            ports: vec![14580],
+            ..class_config("igate")
+        })
+        .unwrap();
+        assert!(igate.matches("n0call-10", Some(14580)));
+        assert!(igate.matches("OH2XYZ", Some(14580)));
+        assert!(!igate.matches("N0CALLS", Some(14580)));
+        assert!(!igate.matches("N0CALL", Some(10152)));
+        assert!(!igate.matches("N0CALL", None));
+        assert!(ClientClass::from_config(&class_config("default")).unwrap().matches("ANY", None));
+        assert!(ClientClass::from_config(&ClientClassConfig { filter: Some("x/1".to_string()), ..class_config("bad") }).is_err());
+    }
+
+    #[test]
+    fn test_class_allows() {
+        let class = ClientClass::from_config(&ClientClassConfig {
+            allowed_types: Some("pm".to_string()),
+            filter: Some("r/60/25/100".to_string()),
+            ..class_config("regional")
+        })
+        .unwrap();
+        let ctx = FilterContext::default();
+        let packet = |line: &str| crate::parser::parse(line).unwrap();
+        assert!(class.allows(&packet("OH2XYZ>APRS:!6010.00N/02456.00E-"), &ctx));
+        assert!(!class.allows(&packet("OH2XYZ>APRS:!4903.50N/07201.75W-"), &ctx));
+        assert!(!class.allows(&packet("OH2XYZ>APRS:>status"), &ctx));
+    }
+
+    #[test]
+    fn test_default_filter() {
+        let defaults: BTreeMap<String, String> = [("OH*", "r/60/25/300"), ("OH7*", "r/62/27/100"), ("OH7RBA", "m/50")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(default_filter(&defaults, "OH2XYZ-9"), Some("r/60/25/300"));
+        assert_eq!(default_filter(&defaults, "oh7abc"), Some("r/62/27/100"));
+        assert_eq!(default_filter(&defaults, "OH7RBA-1"), Some("m/50"));
+        assert_eq!(default_filter(&defaults, "N0CALL"), None);
+    }
+
+    #[test]
+    fn test_bandwidth_limiter() {
+        let packet = |line: &str| crate::parser::parse(line).unwrap();
+        assert_eq!(TxPriority::of(&packet("N0CALL>APRS::N1CALL   :hi{1")), TxPriority::Message);
+        assert_eq!(TxPriority::of(&packet("N0CALL>APRS:!4903.50N/07201.75W-")), TxPriority::Position);
+        assert_eq!(TxPriority::of(&packet("N0CALL>APRS:>status")), TxPriority::Other);
+
+        let now = Instant::now();
+        let mut limiter = BandwidthLimiter::new(100, now);
+        assert!(limiter.allow(40, TxPriority::Position, now));
+        // 60 left, positions must leave 50
+        assert!(!limiter.allow(40, TxPriority::Position, now));
+        assert!(limiter.allow(40, TxPriority::Other, now));
+        assert!(!limiter.allow(40, TxPriority::Other, now));
+        assert!(limiter.allow(100, TxPriority::Message, now));
+        assert!(!limiter.allow(40, TxPriority::Message, now));
+        assert!(limiter.allow(40, TxPriority::Other, now + std::time::Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(2, now);
+        assert!(limiter.allow(now) && limiter.allow(now));
+        assert!(!limiter.allow(now));
+        assert!(limiter.allow(now + Duration::from_secs(30)));
+        assert!(!limiter.allow(now + Duration::from_secs(31)));
+    }
+}
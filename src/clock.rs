@@ -0,0 +1,71 @@
+//! Source of the current time for the hub, the packet pipeline and the
+//! per-session limits. Dupe windows, cache expiry and rate limits read the
+//! hub's clock rather than the system, so tests can step through time with
+//! a `MockClock`. Socket timers run on Tokio time, which has
+//! `tokio::time::pause` for the same purpose.
+
+use crate::hub;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub trait Clock: Send + Sync {
+    /// Monotonic time, for windows and rate limits.
+    fn now(&self) -> Instant;
+    /// Wall clock time in Unix seconds, for timestamps and ages.
+    fn unix_time(&self) -> u64;
+}
+
+/// The system's clocks.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn unix_time(&self) -> u64 {
+        hub::unix_time()
+    }
+}
+
+/// A clock that only moves when advanced. Both times move together.
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    start_unix: u64,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    /// A clock standing at `unix_time`.
+    pub fn new(unix_time: u64) -> Self {
+        MockClock { start: Instant::now(), start_unix: unix_time, elapsed: Mutex::new(Duration::ZERO) }
+    }
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+    fn unix_time(&self) -> u64 {
+        self.start_unix + self.elapsed.lock().unwrap().as_secs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new(1_700_000_000);
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(clock.now() - start, Duration::from_millis(1500));
+        assert_eq!(clock.unix_time(), 1_700_000_001);
+    }
+}
@@ -1,40 +1,827 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
 pub struct UplinkConfig {
+    /// Network name for `[[uplinks]]`, defaults to the host
+    pub name: Option<String>,
     pub host: String,
     pub port: u16,
     pub callsign: String,
     pub passcode: u16,
+    /// Seconds without anything received before reconnecting
+    pub timeout_secs: Option<u64>,
+    /// Only local packets matching this filter are sent to the uplink
+    pub filter: Option<String>,
+    /// Local packets matching `filter` are kept from the other uplinks
+    #[serde(default)]
+    pub exclusive: bool,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl UplinkConfig {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.host)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct S2SPeerConfig {
     pub host: String,
     pub port: u16,
     pub passcode: u16,
     pub peer_name: Option<String>,
+    /// Only packets matching this filter are forwarded to the peer
+    pub filter: Option<String>,
+    /// Also ask the peer to only send us packets matching `filter`
+    #[serde(default)]
+    pub request_filter: bool,
+    #[serde(default)]
+    pub protocol: PeerProtocol,
+}
+
+/// How an S2S peer is connected to.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerProtocol {
+    /// Line based TCP link
+    #[default]
+    Tcp,
+    /// aprsc-compatible UDP core peering
+    Udp,
+}
+
+/// How the `[rf]` TNC is reached.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RfInterface {
+    /// KISS over TCP, as served by Dire Wolf or soundmodem
+    #[default]
+    KissTcp,
+    KissSerial,
+    /// The AGWPE TCP API of AGW Packet Engine or SoundModem
+    Agwpe,
+    /// A Linux kernel AX.25 port
+    Ax25,
+}
+
+/// A TNC whose received packets are gated to APRS-IS.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RfConfig {
+    #[serde(default)]
+    pub interface: RfInterface,
+    /// `host:port` for `kiss-tcp` and `agwpe`
+    pub address: Option<String>,
+    /// Serial device for `kiss-serial`, network interface for `ax25`
+    pub device: Option<String>,
+    #[serde(default = "default_rf_baud")]
+    pub baud: u32,
+    /// Igate callsign in the `qAR` construct, defaults to the server callsign
+    pub callsign: Option<String>,
+    #[serde(default = "default_rf_reconnect_secs")]
+    pub reconnect_secs: u64,
+    /// Gating from APRS-IS to RF; receive only without it
+    pub tx: Option<RfTxConfig>,
+}
+
+/// Rules for transmitting APRS-IS messages to stations heard on RF.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RfTxConfig {
+    /// Kill switch: with `false` nothing is transmitted
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Digipeater path of transmitted frames, e.g. `WIDE1-1`; direct without it
+    pub path: Option<String>,
+    /// Seconds a station counts as heard on RF
+    #[serde(default = "default_rf_tx_heard_secs")]
+    pub heard_secs: u64,
+    /// Addressees heard over more digipeaters are not sent to
+    #[serde(default = "default_rf_tx_max_hops")]
+    pub max_hops: usize,
+    #[serde(default = "default_rf_tx_max_per_minute")]
+    pub max_per_minute: usize,
+    /// Also send the next position of a message's sender
+    #[serde(default = "default_true")]
+    pub courtesy_position: bool,
+}
+
+fn default_rf_tx_heard_secs() -> u64 {
+    1800
+}
+
+fn default_rf_tx_max_hops() -> usize {
+    2
+}
+
+fn default_rf_tx_max_per_minute() -> usize {
+    6
+}
+
+fn default_rf_baud() -> u32 {
+    9600
+}
+
+fn default_rf_reconnect_secs() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    pub client_id: Option<String>,
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub topic_prefix: String,
+    /// Publish the raw packet line
+    #[serde(default = "default_true")]
+    pub raw: bool,
+    /// Publish the parsed packet as JSON
+    #[serde(default = "default_true")]
+    pub json: bool,
+}
+
+/// Sysops allowed to send commands as APRS messages to the server callsign.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RemoteAdminConfig {
+    pub sysops: Vec<String>,
+    /// Shared key for signed commands, accepted from anywhere, e.g. over
+    /// RF; see `remote_admin::command_code`. Never sent on the air.
+    pub secret: Option<String>,
+}
+
+/// A class of client logins with its own policy, see `client_class`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ClientClassConfig {
+    pub name: String,
+    /// Callsigns, `N0CALL` for any SSID or `N0*` for a prefix
+    #[serde(default)]
+    pub callsigns: Vec<String>,
+    /// Listening ports, e.g. 14580
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    pub max_packets_per_minute: Option<u32>,
+    /// Outbound bytes per second; positions are shed first, messages last
+    pub max_tx_bytes_per_sec: Option<u32>,
+    /// `t/` filter letters of the packet types clients may send
+    pub allowed_types: Option<String>,
+    /// Filter the packets clients send must match
+    pub filter: Option<String>,
+    /// Clients only receive; everything they send is dropped
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Whether a `[[route]]` rule lets matching packets through.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteAction {
+    Allow,
+    #[default]
+    Deny,
+}
+
+/// A routing rule between sources and destinations, see `routing`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RouteConfig {
+    /// Source tag such as `s2s:T2PEER` or `client:N0CALL`; `*` at the end matches any rest
+    #[serde(default = "default_any")]
+    pub from: String,
+    /// Destination tag such as `uplink:cwop`, `s2s:T2PEER` or `rf`
+    #[serde(default = "default_any")]
+    pub to: String,
+    /// Only packets matching this filter are covered by the rule
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub action: RouteAction,
+}
+
+fn default_any() -> String {
+    "*".to_string()
+}
+
+/// Drops positions that cannot be right, such as 0N/0E from a GPS without
+/// a fix or a jump no station could make since its last position.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PositionChecksConfig {
+    #[serde(default = "default_true")]
+    pub reject_null_island: bool,
+    /// Highest speed implied by two positions of a station; unchecked if unset
+    pub max_speed_kmh: Option<f64>,
+}
+
+/// Disconnects clients that keep sending lines that are not APRS packets,
+/// such as other protocols probing the port or broken TNC firmware.
+#[derive(Debug, Deserialize, Clone)]
+pub struct InvalidLinesConfig {
+    /// Invalid lines allowed within `window_secs`
+    #[serde(default = "default_max_invalid")]
+    pub max_invalid: usize,
+    #[serde(default = "default_invalid_window_secs")]
+    pub window_secs: u64,
+    /// How many of the last offending lines are logged at disconnect
+    #[serde(default = "default_invalid_log_lines")]
+    pub log_lines: usize,
+}
+
+fn default_max_invalid() -> usize {
+    10
+}
+
+fn default_invalid_window_secs() -> u64 {
+    60
+}
+
+fn default_invalid_log_lines() -> usize {
+    5
+}
+
+/// Which client logins are accepted, and as verified or not.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PasscodePolicy {
+    /// Only the callsign's passcode logs in
+    #[default]
+    Strict,
+    /// Any passcode logs in; only the right one is verified
+    Unverified,
+    /// No passcode needed and every login is unverified
+    Open,
+}
+
+/// What to do with a login when the auth callout cannot be reached.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthFallback {
+    /// Check the passcode as if no callout were configured
+    #[default]
+    Passcode,
+    Deny,
+}
+
+/// External HTTP endpoint deciding on client logins.
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuthCalloutConfig {
+    pub url: String,
+    #[serde(default = "default_auth_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub on_error: AuthFallback,
+}
+
+fn default_auth_timeout_secs() -> u64 {
+    5
+}
+
+/// Blocking of addresses with repeated failed logins.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LoginLimitConfig {
+    /// Failed logins within `window_secs` that block the address
+    #[serde(default = "default_login_max_failures")]
+    pub max_failures: usize,
+    #[serde(default = "default_login_window_secs")]
+    pub window_secs: u64,
+    /// How long a blocked address is refused
+    #[serde(default = "default_login_block_secs")]
+    pub block_secs: u64,
+}
+
+fn default_login_max_failures() -> usize {
+    5
+}
+
+fn default_login_window_secs() -> u64 {
+    60
+}
+
+fn default_login_block_secs() -> u64 {
+    600
+}
+
+/// Limits on the per-station maps, see `station_cache`.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct StationCacheConfig {
+    /// Entries per map: positions, heard stations, weather, telemetry,
+    /// objects and talker statistics
+    pub max_entries: Option<usize>,
+    /// Entries not updated for this long are dropped
+    pub max_age_secs: Option<u64>,
+}
+
+/// Sizing of the duplicate packet cache.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DupeConfig {
+    /// Packets remembered, shared by all ingest shards
+    #[serde(default = "default_dupe_capacity")]
+    pub capacity: usize,
+    /// Packets older than this no longer count as duplicates; by default
+    /// only `capacity` limits how far back the cache reaches
+    pub window_secs: Option<u64>,
+    /// How long a message id is remembered per source and addressee
+    #[serde(default = "default_message_window_secs")]
+    pub message_window_secs: u64,
+}
+
+fn default_dupe_capacity() -> usize {
+    1000
+}
+
+fn default_message_window_secs() -> u64 {
+    30
+}
+
+impl Default for DupeConfig {
+    fn default() -> Self {
+        DupeConfig { capacity: default_dupe_capacity(), window_secs: None, message_window_secs: default_message_window_secs() }
+    }
+}
+
+/// Where a configured beacon is sent.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BeaconTarget {
+    /// Local clients only
+    Local,
+    Uplink,
+    #[default]
+    Both,
+}
+
+/// An object advertised by a `[[beacon]]` entry.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ObjectBeaconConfig {
+    /// Up to 9 characters
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// Symbol table and code
+    pub symbol: String,
+    #[serde(default)]
+    pub comment: String,
+}
+
+/// A packet sent on a schedule; either `payload` or `object` must be set.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BeaconConfig {
+    pub source: String,
+    #[serde(default = "default_beacon_path")]
+    pub path: String,
+    /// Information field, sent as is
+    pub payload: Option<String>,
+    pub object: Option<ObjectBeaconConfig>,
+    #[serde(default = "default_beacon_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub target: BeaconTarget,
+}
+
+fn default_beacon_path() -> String {
+    "TCPIP*".to_string()
+}
+
+/// `[server]`: who runs this server and where.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ServerConfig {
+    /// Server ID used in q constructs and `# logresp`, defaults to `server_name`
+    pub id: Option<String>,
+    pub sysop: Option<String>,
+    pub email: Option<String>,
+    /// Free text, e.g. `Helsinki, Finland`
+    pub location: Option<String>,
+    /// QTH coordinates in degrees
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+}
+
+impl ServerConfig {
+    pub fn qth(&self) -> Option<(f64, f64)> {
+        self.lat.zip(self.lon)
+    }
+}
+
+/// The server's own position beacon.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ServerBeaconConfig {
+    /// Defaults to the server ID
+    pub callsign: Option<String>,
+    /// Both default to the `[server]` QTH
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    /// Symbol table and code
+    #[serde(default = "default_beacon_symbol")]
+    pub symbol: String,
+    #[serde(default)]
+    pub comment: String,
+    #[serde(default = "default_beacon_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_beacon_symbol() -> String {
+    "I&".to_string()
+}
+
+fn default_beacon_interval_secs() -> u64 {
+    1800
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ArchiveConfig {
+    /// PostgreSQL connection string; the database needs the PostGIS extension
+    pub url: String,
+    #[serde(default = "default_archive_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "default_archive_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    /// Packets buffered while the database is slow or unreachable
+    #[serde(default = "default_archive_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_archive_batch_size() -> usize {
+    500
+}
+
+fn default_archive_flush_interval_ms() -> u64 {
+    1000
+}
+
+fn default_archive_queue_size() -> usize {
+    10000
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RedisConfig {
+    /// e.g. `redis://127.0.0.1/`
+    pub url: String,
+    #[serde(default = "default_redis_channel")]
+    pub channel: String,
+    #[serde(default = "default_redis_key_prefix")]
+    pub key_prefix: String,
+}
+
+fn default_redis_channel() -> String {
+    "aprs:packets".to_string()
+}
+
+fn default_redis_key_prefix() -> String {
+    "aprs".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PacketLogConfig {
+    /// Directory holding the current and rotated log files
+    pub dir: String,
+    /// Each stream is only logged when configured
+    pub clients: Option<StreamLogConfig>,
+    pub uplink: Option<StreamLogConfig>,
+    pub s2s: Option<StreamLogConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    #[default]
+    Daily,
+    Size,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct StreamLogConfig {
+    #[serde(default)]
+    pub rotate: LogRotation,
+    /// File size that triggers rotation with `rotate = "size"`
+    #[serde(default = "default_log_max_size_mb")]
+    pub max_size_mb: u64,
+    /// Rotated files older than this are deleted; kept forever if unset
+    pub retention_days: Option<u64>,
+}
+
+fn default_log_max_size_mb() -> u64 {
+    100
+}
+
+/// Threads of the Tokio runtime. Client connections are served on threads
+/// of their own, outside these pools.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RuntimeConfig {
+    /// Async worker threads, one per CPU core if unset
+    pub worker_threads: Option<usize>,
+    /// Upper limit of the pool running blocking tasks such as database writes
+    pub max_blocking_threads: Option<usize>,
+}
+
+/// Running detached with a PID file and without root, for init systems
+/// that expect a forking daemon.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct DaemonConfig {
+    /// Fork into the background and detach from the terminal
+    #[serde(default)]
+    pub detach: bool,
+    pub pid_file: Option<String>,
+    /// User to switch to once the listeners are bound
+    pub user: Option<String>,
+    /// Group to switch to, the user's primary group if unset
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LoggingConfig {
+    /// Level directives, e.g. `info` or `info,aprsserver::s2s=debug`
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    /// Directory for rotating log files; logs go to stdout if unset
+    pub dir: Option<String>,
+    #[serde(default = "default_log_file_prefix")]
+    pub file_prefix: String,
+    #[serde(default)]
+    pub rotation: LogFileRotation,
+    /// Rotated files to keep; all are kept if unset
+    pub max_files: Option<usize>,
+    /// Send logs to syslog instead of stdout or files
+    pub syslog: Option<SyslogConfig>,
+    /// Send logs to systemd-journald; takes precedence over the other outputs
+    #[serde(default)]
+    pub journald: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SyslogConfig {
+    /// `host:port` of a syslog server reached over UDP; the local `/dev/log` socket if unset
+    pub address: Option<String>,
+    #[serde(default = "default_syslog_facility")]
+    pub facility: String,
+    #[serde(default = "default_log_file_prefix")]
+    pub tag: String,
+}
+
+fn default_syslog_facility() -> String {
+    "daemon".to_string()
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            dir: None,
+            file_prefix: default_log_file_prefix(),
+            rotation: LogFileRotation::default(),
+            max_files: None,
+            syslog: None,
+            journald: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFileRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_file_prefix() -> String {
+    "aprsserver".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReplayConfig {
+    /// Packet log to replay, plain or gzipped
+    pub file: String,
+    /// Playback speed relative to the original timing, above zero
+    #[serde(default = "default_replay_speed", deserialize_with = "replay_speed")]
+    pub speed: f64,
+}
+
+fn default_replay_speed() -> f64 {
+    1.0
+}
+
+fn replay_speed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let speed = f64::deserialize(deserializer)?;
+    if speed > 0.0 && speed.is_finite() {
+        Ok(speed)
+    } else {
+        Err(serde::de::Error::custom(format!("replay speed must be a positive number, got {}", speed)))
+    }
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "aprs".to_string()
+}
+
+/// Server ID of a config without `server_name`. It is a valid callsign, so
+/// tests and embedders work without one, but a config file using it is
+/// refused.
+pub const PLACEHOLDER_SERVER_ID: &str = "NOCALL";
+
+fn default_server_name() -> String {
+    PLACEHOLDER_SERVER_ID.to_string()
+}
+
+/// A callsign usable as a server ID on APRS-IS: up to nine letters and
+/// digits with an optional SSID of one or two, e.g. `T2FINLAND` or `OH2XYZ-10`.
+fn is_server_id(id: &str) -> bool {
+    let (call, ssid) = id.split_once('-').unwrap_or((id, "0"));
+    id.len() <= 9
+        && !call.is_empty()
+        && call.chars().all(|c| c.is_ascii_alphanumeric())
+        && (1..=2).contains(&ssid.len())
+        && ssid.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct Config {
+    #[serde(default = "default_server_name")]
     pub server_name: String,
+    pub server: Option<ServerConfig>,
     pub user_port: u16,
     pub server_port: u16,
+    /// Filter always combined with the own filter of clients on `user_port`
+    pub user_filter: Option<String>,
+    /// Same for clients on `server_port`
+    pub server_filter: Option<String>,
+    /// Accept threads on `user_port`; more than one binds the port with
+    /// SO_REUSEPORT so the kernel spreads connections over them
+    pub user_acceptors: Option<usize>,
+    /// Same for `server_port`
+    pub server_acceptors: Option<usize>,
     pub s2s_port: Option<u16>,
+    /// Local UDP port for `protocol = "udp"` peers, defaults to `server_port`
+    pub udp_peer_port: Option<u16>,
+    /// Seconds without anything received before an S2S peer is disconnected
+    pub s2s_timeout_secs: Option<u64>,
+    /// Seconds a write to a TCP client, peer or uplink may take before the
+    /// connection is dropped as wedged
+    pub write_timeout_secs: Option<u64>,
+    #[serde(rename = "allow_callsigns")]
     pub _allow_callsigns: Option<Vec<String>>,
+    #[serde(rename = "deny_callsigns")]
     pub _deny_callsigns: Option<Vec<String>>,
     pub uplink: Option<UplinkConfig>,
+    /// Further upstream networks, such as CWOP, kept apart from `uplink`
+    pub uplinks: Option<Vec<UplinkConfig>>,
     pub s2s_peers: Option<Vec<S2SPeerConfig>>,
+    pub nws_zones_file: Option<String>,
+    /// `tocalls.txt` used to name the software behind packets
+    pub tocalls_file: Option<String>,
+    pub hooks: Option<Vec<String>>,
+    pub scripts: Option<Vec<String>>,
+    pub mqtt: Option<MqttConfig>,
+    pub rf: Option<RfConfig>,
+    pub archive: Option<ArchiveConfig>,
+    /// SQLite file keeping last station positions across restarts
+    pub station_db: Option<String>,
+    pub redis: Option<RedisConfig>,
+    /// Bearer token required by the web admin API
+    pub admin_token: Option<String>,
+    /// TOML file with banned callsigns and IPs, managed through the admin API
+    pub ban_file: Option<String>,
+    /// JSON file keeping counters and the heard list across restarts
+    pub state_file: Option<String>,
+    /// Seconds between saves of the state file (default 300); it is also
+    /// saved on shutdown
+    pub state_save_secs: Option<u64>,
+    pub packet_log: Option<PacketLogConfig>,
+    pub replay: Option<ReplayConfig>,
+    /// Minutes of accepted packets replayed to clients when they first set a filter
+    pub history_minutes: Option<u64>,
+    /// MaxMind/GeoLite2 database used to locate client addresses
+    pub geoip_db: Option<String>,
+    pub server_beacon: Option<ServerBeaconConfig>,
+    #[serde(rename = "beacon")]
+    pub beacons: Option<Vec<BeaconConfig>>,
+    pub remote_admin: Option<RemoteAdminConfig>,
+    /// Port of the admin console, which only listens on localhost
+    pub console_port: Option<u16>,
+    /// Port of the gRPC service; needs the `grpc` build feature
+    pub grpc_port: Option<u16>,
+    pub invalid_lines: Option<InvalidLinesConfig>,
+    pub login_limit: Option<LoginLimitConfig>,
+    #[serde(default)]
+    pub passcode_policy: PasscodePolicy,
+    pub auth_callout: Option<AuthCalloutConfig>,
+    pub position_checks: Option<PositionChecksConfig>,
+    pub dupes: Option<DupeConfig>,
+    pub station_cache: Option<StationCacheConfig>,
+    /// Checked in order; the first class matching a login applies
+    #[serde(rename = "client_class")]
+    pub client_classes: Option<Vec<ClientClassConfig>>,
+    /// Checked in order; the first rule matching a packet and destination applies
+    #[serde(rename = "route")]
+    pub routes: Option<Vec<RouteConfig>>,
+    /// Filters for logins without one of their own, by callsign pattern
+    pub client_defaults: Option<BTreeMap<String, String>>,
+    pub runtime: Option<RuntimeConfig>,
+    pub daemon: Option<DaemonConfig>,
+    /// Worker threads uplink and peer packets are spread over by source
+    /// callsign; they are processed on their connection's task if unset
+    pub ingest_shards: Option<usize>,
+    pub logging: Option<LoggingConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server_name: default_server_name(),
+            server: None,
+            user_port: 14580,
+            server_port: 10152,
+            user_filter: None,
+            server_filter: None,
+            user_acceptors: None,
+            server_acceptors: None,
+            s2s_port: None,
+            udp_peer_port: None,
+            s2s_timeout_secs: None,
+            write_timeout_secs: None,
+            _allow_callsigns: None,
+            _deny_callsigns: None,
+            uplink: None,
+            uplinks: None,
+            s2s_peers: None,
+            nws_zones_file: None,
+            tocalls_file: None,
+            hooks: None,
+            scripts: None,
+            mqtt: None,
+            rf: None,
+            archive: None,
+            station_db: None,
+            redis: None,
+            admin_token: None,
+            ban_file: None,
+            state_file: None,
+            state_save_secs: None,
+            packet_log: None,
+            replay: None,
+            history_minutes: None,
+            geoip_db: None,
+            server_beacon: None,
+            beacons: None,
+            remote_admin: None,
+            console_port: None,
+            grpc_port: None,
+            invalid_lines: None,
+            login_limit: None,
+            passcode_policy: PasscodePolicy::Strict,
+            auth_callout: None,
+            position_checks: None,
+            dupes: None,
+            station_cache: None,
+            client_classes: None,
+            routes: None,
+            client_defaults: None,
+            runtime: None,
+            daemon: None,
+            ingest_shards: None,
+            logging: None,
+        }
+    }
 }
 
 impl Config {
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
         let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
-        toml::from_str(&content).map_err(|e| e.to_string())
+        let config: Config = toml::from_str(&content).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the server ID, which goes into every q construct and login:
+    /// it must be a real callsign, not the placeholder.
+    pub fn validate(&self) -> Result<(), String> {
+        let id = self.server_info().id.unwrap_or_default();
+        let call = id.split('-').next().unwrap_or_default();
+        if call.eq_ignore_ascii_case(PLACEHOLDER_SERVER_ID) || call.eq_ignore_ascii_case("N0CALL") {
+            return Err("set server_name to the server's callsign".to_string());
+        }
+        if !is_server_id(&id) {
+            return Err(format!("server ID {} is not a valid callsign", id));
+        }
+        Ok(())
+    }
+
+    /// The `[server]` section with its ID filled in from `server_name` if unset.
+    pub fn server_info(&self) -> ServerConfig {
+        let mut server = self.server.clone().unwrap_or_default();
+        server.id.get_or_insert_with(|| self.server_name.clone());
+        server
     }
 }
 
@@ -46,7 +833,7 @@ mod tests {
     #[test]
     fn test_load_from_file() {
         let toml = r#"
-            server_name = "test-server"
+            server_name = "T2TEST"
             user_port = 1234
             server_port = 5678
             allow_callsigns = ["N0CALL"]
@@ -60,7 +847,7 @@ mod tests {
         let path = "test_config.toml";
         fs::write(path, toml).unwrap();
         let cfg = Config::load_from_file(path).unwrap();
-        assert_eq!(cfg.server_name, "test-server");
+        assert_eq!(cfg.server_name, "T2TEST");
         assert_eq!(cfg.user_port, 1234);
         assert_eq!(cfg.server_port, 5678);
         assert_eq!(cfg._allow_callsigns.as_ref().unwrap()[0], "N0CALL");
@@ -70,6 +857,50 @@ mod tests {
         assert_eq!(uplink.port, 14580);
         assert_eq!(uplink.callsign, "N0CALL");
         assert_eq!(uplink.passcode, 12345);
+        assert_eq!(cfg.server_info().id.as_deref(), Some("T2TEST"));
         let _ = fs::remove_file(path);
     }
+
+    #[test]
+    fn test_server_id() {
+        let config = |toml: &str| toml::from_str::<Config>(&format!("user_port = 14580\nserver_port = 10152\n{}", toml)).unwrap();
+        assert!(config("").validate().is_err());
+        assert!(config(r#"server_name = "n0call-10""#).validate().is_err());
+        assert!(config(r#"server_name = "aprsserver-rust""#).validate().is_err());
+        assert!(config(r#"server_name = "T2FINLAND""#).validate().is_ok());
+        assert!(config("server_name = \"OH2XYZ-10\"\n[server]\nid = \"OH2XYZ-100\"").validate().is_err());
+        assert!(config("[server]\nid = \"OH2XYZ-1\"").validate().is_ok());
+    }
+
+    #[test]
+    fn test_server_section() {
+        let cfg: Config = toml::from_str(
+            r#"
+            user_port = 14580
+            server_port = 10152
+            [server]
+            id = "T2TEST"
+            sysop = "OH2XYZ"
+            email = "oh2xyz@example.org"
+            lat = 60.1699
+            lon = 24.9384
+        "#,
+        )
+        .unwrap();
+        let server = cfg.server_info();
+        assert_eq!(server.id.as_deref(), Some("T2TEST"));
+        assert_eq!(server.sysop.as_deref(), Some("OH2XYZ"));
+        assert_eq!(server.location, None);
+        assert_eq!(server.qth(), Some((60.1699, 24.9384)));
+    }
+
+    #[test]
+    fn test_replay_speed() {
+        let replay = |toml: &str| toml::from_str::<ReplayConfig>(&format!("file = \"log\"\n{}", toml));
+        assert_eq!(replay("").unwrap().speed, 1.0);
+        assert_eq!(replay("speed = 2.5").unwrap().speed, 2.5);
+        for bad in ["speed = 0.0", "speed = -1.0", "speed = nan", "speed = inf"] {
+            assert!(replay(bad).is_err(), "{}", bad);
+        }
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,220 @@
+//! Line based admin console for operators who prefer a terminal, e.g.
+//! `telnet localhost 14502`. It only listens on localhost and has no login.
+
+use crate::hub::Hub;
+use crate::uplink::UplinkStatus;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+const HELP: &str = "commands: clients, uplink, peers, kick ID, inject PACKET, watch [CALL], unwatch CALL, log [DIRECTIVES], reload, quit";
+
+pub struct Console {
+    pub hub: Arc<Hub>,
+    pub uplink_status: Arc<Mutex<UplinkStatus>>,
+    /// Set to ask the main loop to reload the config, like SIGHUP
+    pub reload: Option<Arc<AtomicBool>>,
+}
+
+impl Console {
+    /// Runs one command line and returns the output, without a final newline.
+    pub fn execute(&self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("").to_lowercase();
+        let rest: Vec<&str> = words.collect();
+        match command.as_str() {
+            "" => String::new(),
+            "help" => HELP.to_string(),
+            "clients" => self.clients(),
+            "uplink" => self.uplink(),
+            "peers" => self.peers(),
+            "kick" => match rest.first().and_then(|id| id.parse().ok()) {
+                Some(id) if self.hub.kick_client(id, "Disconnected by server operator") => {
+                    info!(client = id, "client kicked from console");
+                    format!("kicked {}", id)
+                }
+                Some(id) => format!("no client {}", id),
+                None => "usage: kick ID".to_string(),
+            },
+            "inject" => match line.trim().split_once(char::is_whitespace) {
+                Some((_, packet)) => match crate::pipeline::inject(&self.hub, packet) {
+                    Ok(line) => {
+                        info!(%line, "packet injected from console");
+                        format!("injected {}", line)
+                    }
+                    Err(e) => format!("error: {}", e),
+                },
+                None => "usage: inject PACKET".to_string(),
+            },
+            "watch" => match rest.first() {
+                Some(callsign) => {
+                    self.hub.watch.add(callsign);
+                    info!(%callsign, "watch started from console");
+                    format!("watching {}, decisions are logged", callsign.to_uppercase())
+                }
+                None => format!("watching: {}", self.hub.watch.list().join(" ")),
+            },
+            "unwatch" => match rest.first() {
+                Some(callsign) if self.hub.watch.remove(callsign) => format!("stopped watching {}", callsign.to_uppercase()),
+                Some(callsign) => format!("not watching {}", callsign),
+                None => "usage: unwatch CALL".to_string(),
+            },
+            "log" if rest.is_empty() => crate::logging::level().unwrap_or_else(|| "log level not adjustable".to_string()),
+            "log" => match crate::logging::set_level(&rest.join(" ")) {
+                Ok(()) => format!("log level set to {}", rest.join(" ")),
+                Err(e) => format!("error: {}", e),
+            },
+            "reload" => match &self.reload {
+                Some(flag) => {
+                    flag.store(true, Ordering::Relaxed);
+                    "reload requested".to_string()
+                }
+                None => "reload not supported".to_string(),
+            },
+            _ => format!("unknown command, {}", HELP),
+        }
+    }
+
+    fn clients(&self) -> String {
+        let clients = self.hub.clients.read().unwrap();
+        let mut ids: Vec<&usize> = clients.keys().collect();
+        ids.sort();
+        let mut out = format!("{} clients", ids.len());
+        for id in ids {
+            let c = clients[id].lock().unwrap();
+            out.push_str(&format!(
+                "\n{:>5} {:<10} {:<22} rx {} tx {} filter {}",
+                id,
+                c.callsign.as_deref().unwrap_or("-"),
+                c.addr,
+                c.packets_rx,
+                c.packets_tx(),
+                c.filter_string.as_deref().unwrap_or("-")
+            ));
+        }
+        out
+    }
+
+    fn uplink(&self) -> String {
+        let networks: Vec<String> = self
+            .hub
+            .uplinks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|route| route.network.is_some())
+            .map(|route| {
+                let u = route.status.lock().unwrap();
+                format!("{} {}:{} {} rx {} tx {}", u.name, u.host, u.port, u.state.name(), u.packets_rx, u.packets_tx)
+            })
+            .collect();
+        let u = self.uplink_status.lock().unwrap();
+        if u.host.is_empty() {
+            if networks.is_empty() {
+                return "no uplink configured".to_string();
+            }
+            return networks.join("\n");
+        }
+        let primary = format!(
+            "{}:{} ({}) {} server {}\nrx {} tx {} timeouts {} last error {}",
+            u.host,
+            u.port,
+            u.remote_addr.map(|a| a.to_string()).unwrap_or_else(|| "-".to_string()),
+            u.state.name(),
+            u.server_id.as_deref().unwrap_or("-"),
+            u.packets_rx,
+            u.packets_tx,
+            u.timeouts,
+            u.last_error.as_deref().unwrap_or("-")
+        );
+        std::iter::once(primary).chain(networks).collect::<Vec<_>>().join("\n")
+    }
+
+    fn peers(&self) -> String {
+        let peers = self.hub.s2s_peers.read().unwrap();
+        let mut out = format!("{} peers", peers.len());
+        for peer in peers.iter() {
+            let p = peer.lock().unwrap();
+            out.push_str(&format!(
+                "\n{}:{} {} {} rx {} tx {} last error {}",
+                p.host,
+                p.port,
+                p.peer_name.as_deref().unwrap_or("-"),
+                p.state_summary(),
+                p.packets_rx,
+                p.packets_tx,
+                p.last_error.as_deref().unwrap_or("-")
+            ));
+        }
+        out
+    }
+}
+
+fn handle_session(stream: TcpStream, console: &Console) {
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    let mut writer = stream;
+    let _ = writer.write_all(format!("aprsserver console, {}\r\n> ", HELP).as_bytes());
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let line = line.trim();
+        if line.eq_ignore_ascii_case("quit") || line.eq_ignore_ascii_case("exit") {
+            break;
+        }
+        let mut out = console.execute(line);
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        if writer.write_all(format!("{}> ", out).replace('\n', "\r\n").as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+/// Serves console sessions on `listener`, one thread each.
+pub fn spawn(listener: TcpListener, console: Console) {
+    let console = Arc::new(console);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let console = console.clone();
+                    std::thread::spawn(move || handle_session(stream, &console));
+                }
+                Err(e) => warn!(error = %e, "console connection failed"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UplinkConfig;
+
+    #[test]
+    fn test_execute() {
+        let cfg = UplinkConfig { host: "rotate.aprs2.net".to_string(), port: 10152, callsign: "N0CALL".to_string(), passcode: 0, ..Default::default() };
+        let reload = Arc::new(AtomicBool::new(false));
+        let console = Console {
+            hub: Arc::new(Hub::new()),
+            uplink_status: Arc::new(Mutex::new(UplinkStatus::new(&cfg))),
+            reload: Some(reload.clone()),
+        };
+        assert_eq!(console.execute("clients"), "0 clients");
+        assert_eq!(console.execute("peers"), "0 peers");
+        assert!(console.execute("uplink").starts_with("rotate.aprs2.net:10152 (-) resolving server -"));
+        assert_eq!(console.execute("kick 42"), "no client 42");
+        assert_eq!(console.execute("kick"), "usage: kick ID");
+        assert_eq!(console.execute("inject"), "usage: inject PACKET");
+        assert_eq!(console.execute("inject N0CALL>APRS:>hi  there"), "injected N0CALL>APRS,qAS,NOCALL:>hi  there");
+        assert_eq!(console.execute("reload"), "reload requested");
+        assert!(reload.load(Ordering::Relaxed));
+        assert!(console.execute("bogus").starts_with("unknown command"));
+    }
+}
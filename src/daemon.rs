@@ -0,0 +1,81 @@
+//! Running as a traditional daemon: detaching from the terminal, a PID file
+//! and switching away from root once the listeners are bound.
+
+use nix::unistd::{Gid, Group, Uid, User, setgid, setgroups, setuid};
+
+/// Forks into the background and detaches from the terminal. Must be called
+/// before any threads are started, so before logging and the runtime. The
+/// working directory is kept so relative paths in the config still work.
+pub fn detach() -> Result<(), String> {
+    let dir = std::env::current_dir().map_err(|e| e.to_string())?;
+    daemonize::Daemonize::new().working_directory(dir).start().map_err(|e| e.to_string())
+}
+
+/// Writes our PID to `path`, owned by the `user` and `group` privileges
+/// will be dropped to, so the server can still remove it when it stops.
+pub fn write_pid_file(path: &str, user: Option<&str>, group: Option<&str>) -> Result<(), String> {
+    let (uid, gid) = resolve(user, group)?;
+    std::fs::write(path, format!("{}\n", std::process::id())).map_err(|e| format!("{}: {}", path, e))?;
+    std::os::unix::fs::chown(path, uid.map(Uid::as_raw), gid.map(Gid::as_raw)).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Removes the PID file on shutdown. Needs write access to its directory
+/// as the user the server runs as by then.
+pub fn remove_pid_file(path: &str) -> Result<(), String> {
+    std::fs::remove_file(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// User and group ids for the configured names. Without a group the
+/// user's primary group is used.
+fn resolve(user: Option<&str>, group: Option<&str>) -> Result<(Option<Uid>, Option<Gid>), String> {
+    let user = match user {
+        Some(name) => Some(User::from_name(name).map_err(|e| e.to_string())?.ok_or_else(|| format!("no user {}", name))?),
+        None => None,
+    };
+    let gid = match group {
+        Some(name) => Some(Group::from_name(name).map_err(|e| e.to_string())?.ok_or_else(|| format!("no group {}", name))?.gid),
+        None => user.as_ref().map(|u| u.gid),
+    };
+    Ok((user.map(|u| u.uid), gid))
+}
+
+/// Switches to `user` and `group`, dropping supplementary groups. Nothing
+/// is done if neither is set.
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> Result<(), String> {
+    let (uid, gid) = resolve(user, group)?;
+    if let Some(gid) = gid {
+        setgroups(&[gid]).map_err(|e| format!("setgroups: {}", e))?;
+        setgid(gid).map_err(|e| format!("setgid: {}", e))?;
+    }
+    if let Some(uid) = uid {
+        setuid(uid).map_err(|e| format!("setuid: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve(None, None), Ok((None, None)));
+        let (uid, gid) = resolve(Some("root"), None).unwrap();
+        assert_eq!((uid, gid), (Some(Uid::from_raw(0)), Some(Gid::from_raw(0))));
+        assert!(resolve(Some("no-such-user-here"), None).is_err());
+        assert!(resolve(None, Some("no-such-group-here")).is_err());
+        assert_eq!(drop_privileges(None, None), Ok(()));
+    }
+
+    #[test]
+    fn test_pid_file() {
+        let path = std::env::temp_dir().join(format!("aprsserver-{}.pid", std::process::id()));
+        let path = path.to_str().unwrap();
+        let me = User::from_uid(Uid::current()).unwrap().unwrap().name;
+        write_pid_file(path, Some(&me), None).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), format!("{}\n", std::process::id()));
+        remove_pid_file(path).unwrap();
+        assert!(remove_pid_file(path).is_err());
+        assert!(write_pid_file(path, Some("no-such-user-here"), None).is_err());
+    }
+}
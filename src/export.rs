@@ -0,0 +1,204 @@
+//! Downloads of client, peer, per-callsign and historical statistics as CSV
+//! or newline-delimited JSON, for analysis in a spreadsheet or with `jq`.
+//! Nested objects become dotted CSV columns and lists are kept as JSON.
+
+use crate::web::AppState;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::UNIX_EPOCH;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/export/:file", get(export))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Ndjson,
+}
+
+impl Format {
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+/// Adds the fields of `value` to `row`, nested ones as `parent.child`.
+fn flatten(prefix: &str, value: &Value, row: &mut Vec<(String, String)>) {
+    let key = |k: &str| if prefix.is_empty() { k.to_string() } else { format!("{}.{}", prefix, k) };
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                flatten(&key(k), v, row);
+            }
+        }
+        Value::Null => row.push((prefix.to_string(), String::new())),
+        Value::String(s) => row.push((prefix.to_string(), s.clone())),
+        other => row.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+/// Quotes a field holding a separator, quote or line break.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_string() }
+}
+
+/// Renders `rows` in `format`. CSV columns are the union of the rows'
+/// fields, so a field missing from the first rows still gets a column.
+pub fn render<T: Serialize>(rows: &[T], format: Format) -> Result<String, String> {
+    let mut out = String::new();
+    match format {
+        Format::Ndjson => {
+            for row in rows {
+                out.push_str(&serde_json::to_string(row).map_err(|e| e.to_string())?);
+                out.push('\n');
+            }
+        }
+        Format::Csv => {
+            let mut columns: Vec<String> = Vec::new();
+            let mut flat = Vec::new();
+            for row in rows {
+                let mut fields = Vec::new();
+                flatten("", &serde_json::to_value(row).map_err(|e| e.to_string())?, &mut fields);
+                for (k, _) in &fields {
+                    if !columns.contains(k) {
+                        columns.push(k.clone());
+                    }
+                }
+                flat.push(fields);
+            }
+            out.push_str(&columns.iter().map(|c| csv_field(c)).collect::<Vec<_>>().join(","));
+            out.push_str("\r\n");
+            for fields in flat {
+                let line: Vec<String> = columns
+                    .iter()
+                    .map(|c| fields.iter().find(|(k, _)| k == c).map_or(String::new(), |(_, v)| csv_field(v)))
+                    .collect();
+                out.push_str(&line.join(","));
+                out.push_str("\r\n");
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    /// Unix time of the oldest history sample to include
+    from: Option<u64>,
+    /// Unix time of the newest history sample to include
+    to: Option<u64>,
+    /// Most talkers to include, busiest first
+    limit: Option<usize>,
+}
+
+fn to_value<T: Serialize>(row: &T) -> Value {
+    serde_json::to_value(row).unwrap_or_default()
+}
+
+fn rows(state: &AppState, name: &str, query: &ExportQuery) -> Option<Vec<Value>> {
+    let hub = &state.hub;
+    Some(match name {
+        "clients" => state.snapshot.load().clients.iter().map(to_value).collect(),
+        "peers" => hub
+            .s2s_peers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|peer| {
+                let p = peer.lock().unwrap();
+                let unix = |t: Option<std::time::SystemTime>| t.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs());
+                json!({
+                    "host": p.host,
+                    "port": p.port,
+                    "peer_name": p.peer_name,
+                    "state": p.state.name(),
+                    "connected": p.connected,
+                    "consecutive_failures": p.consecutive_failures,
+                    "packets_rx": p.packets_rx,
+                    "packets_tx": p.packets_tx,
+                    "bytes_rx": p.bytes_rx,
+                    "bytes_tx": p.bytes_tx,
+                    "connect_errors": p.connect_errors,
+                    "read_errors": p.read_errors,
+                    "write_errors": p.write_errors,
+                    "last_error": p.last_error,
+                    "last_connect": unix(p.last_connect),
+                    "last_rx_time": unix(p.last_rx_time),
+                })
+            })
+            .collect(),
+        "talkers" => hub.top_talkers(query.limit.unwrap_or(usize::MAX)).iter().map(to_value).collect(),
+        "history" => hub
+            .stats_history
+            .lock()
+            .unwrap()
+            .samples
+            .iter()
+            .filter(|s| query.from.is_none_or(|from| s.time >= from) && query.to.is_none_or(|to| s.time <= to))
+            .map(to_value)
+            .collect(),
+        _ => return None,
+    })
+}
+
+/// `/export/{clients,peers,talkers,history}.{csv,ndjson}`. `history` takes
+/// `from` and `to` in Unix time, `talkers` a `limit`.
+async fn export(State(state): State<AppState>, Path(file): Path<String>, Query(query): Query<ExportQuery>) -> Response {
+    let Some((name, ext)) = file.rsplit_once('.') else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let format = match ext {
+        "csv" => Format::Csv,
+        "ndjson" | "jsonl" => Format::Ndjson,
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let Some(rows) = rows(&state, name, &query) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match render(&rows, format) {
+        Ok(body) => {
+            let disposition = format!("attachment; filename=\"{}\"", file);
+            ([(CONTENT_TYPE, format.content_type().to_string()), (CONTENT_DISPOSITION, disposition)], body).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Row {
+        callsign: &'static str,
+        packets: u64,
+        rates: Value,
+        note: Option<&'static str>,
+    }
+
+    #[test]
+    fn test_render() {
+        let rows = [
+            Row { callsign: "N0CALL", packets: 3, rates: json!({"rx": 1.5}), note: Some("a, \"b\"") },
+            Row { callsign: "N1CALL", packets: 0, rates: json!({"rx": 0, "tx": 2}), note: None },
+        ];
+        assert_eq!(
+            render(&rows, Format::Csv).unwrap(),
+            "callsign,note,packets,rates.rx,rates.tx\r\nN0CALL,\"a, \"\"b\"\"\",3,1.5,\r\nN1CALL,,0,0,2\r\n"
+        );
+        let ndjson = render(&rows, Format::Ndjson).unwrap();
+        assert_eq!(ndjson.lines().count(), 2);
+        assert!(ndjson.starts_with("{\"callsign\":\"N0CALL\",\"packets\":3,"));
+        assert_eq!(render::<Row>(&[], Format::Csv).unwrap(), "\r\n");
+    }
+}
@@ -1,18 +1,92 @@
 use std::str::FromStr;
 use serde::{Serialize, Deserialize};
+use crate::position_cache::PositionCache;
+use crate::nws::ZoneTable;
+use crate::parser::{self, PacketData, ParsedPacket};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 
+/// One APRS-IS filter. Serialized as its filter string, e.g. `r/60/25/100`.
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
 pub enum ClientFilter {
     Area { lat: f64, lon: f64, radius_km: f64 },
     Box { lat1: f64, lon1: f64, lat2: f64, lon2: f64 },
     Prefix(String),
     Type(String),
     Object(String),
+    Grid(String),
+    MyRange { radius_km: f64 },
+    Friend { callsign: String, radius_km: f64 },
     All, // matches all packets
 }
 
+/// State some filters are evaluated against: the client's own callsign for
+/// `m/`, the last known station positions for `m/` and `f/`, and the NWS
+/// zones that locate bulletins for the area filters.
+#[derive(Default)]
+pub struct FilterContext<'a> {
+    pub callsign: Option<&'a str>,
+    pub positions: Option<&'a PositionCache>,
+    pub zones: Option<&'a ZoneTable>,
+}
+
+impl FilterContext<'_> {
+    fn position_of(&self, callsign: &str) -> Option<(f64, f64)> {
+        self.positions?.coordinates(&callsign.to_uppercase())
+    }
+}
+
+/// Why a single filter could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterError {
+    /// Not a known filter, or the wrong number of fields
+    UnknownType,
+    /// A number that is malformed or out of range, by field name
+    InvalidField(&'static str),
+    InvalidGridSquare(String),
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterError::UnknownType => write!(f, "Unknown filter type"),
+            FilterError::InvalidField(field) => write!(f, "Invalid {}", field),
+            FilterError::InvalidGridSquare(sq) if sq.is_empty() => write!(f, "Invalid grid square"),
+            FilterError::InvalidGridSquare(sq) => write!(f, "Invalid grid square: {}", sq),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+/// The filter in a list that failed to parse, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidFilter {
+    pub filter: String,
+    pub error: FilterError,
+}
+
+impl fmt::Display for InvalidFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.filter, self.error)
+    }
+}
+
+impl std::error::Error for InvalidFilter {}
+
+/// A finite number within `range`; NaN and infinities are rejected.
+fn number(s: &str, field: &'static str, range: std::ops::RangeInclusive<f64>) -> Result<f64, FilterError> {
+    s.parse().ok().filter(|v| range.contains(v)).ok_or(FilterError::InvalidField(field))
+}
+
+const LAT: std::ops::RangeInclusive<f64> = -90.0..=90.0;
+const LON: std::ops::RangeInclusive<f64> = -180.0..=180.0;
+const RADIUS: std::ops::RangeInclusive<f64> = 0.0..=f64::MAX;
+
 impl FromStr for ClientFilter {
-    type Err = String;
+    type Err = FilterError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.trim();
         if s == "a/*" || s == "all" {
@@ -22,9 +96,9 @@ impl FromStr for ClientFilter {
             // r/lat/lon/radius
             let parts: Vec<&str> = s.split('/').collect();
             if parts.len() == 4 {
-                let lat = parts[1].parse().map_err(|_| "Invalid latitude")?;
-                let lon = parts[2].parse().map_err(|_| "Invalid longitude")?;
-                let radius_km = parts[3].parse().map_err(|_| "Invalid radius")?;
+                let lat = number(parts[1], "latitude", LAT)?;
+                let lon = number(parts[2], "longitude", LON)?;
+                let radius_km = number(parts[3], "radius", RADIUS)?;
                 return Ok(ClientFilter::Area { lat, lon, radius_km });
             }
         }
@@ -32,74 +106,385 @@ impl FromStr for ClientFilter {
             // a/lat1/lon1/lat2/lon2
             let parts: Vec<&str> = s.split('/').collect();
             if parts.len() == 5 {
-                let lat1 = parts[1].parse().map_err(|_| "Invalid lat1")?;
-                let lon1 = parts[2].parse().map_err(|_| "Invalid lon1")?;
-                let lat2 = parts[3].parse().map_err(|_| "Invalid lat2")?;
-                let lon2 = parts[4].parse().map_err(|_| "Invalid lon2")?;
+                let lat1 = number(parts[1], "lat1", LAT)?;
+                let lon1 = number(parts[2], "lon1", LON)?;
+                let lat2 = number(parts[3], "lat2", LAT)?;
+                let lon2 = number(parts[4], "lon2", LON)?;
                 return Ok(ClientFilter::Box { lat1, lon1, lat2, lon2 });
             }
         }
-        if s.starts_with("p/") {
+        if let Some(prefix) = s.strip_prefix("p/") {
             // p/callsignprefix
-            let prefix = s[2..].to_string();
+            let prefix = prefix.to_string();
             return Ok(ClientFilter::Prefix(prefix));
         }
-        if s.starts_with("t/") {
+        if let Some(typ) = s.strip_prefix("t/") {
             // t/type
-            let typ = s[2..].to_string();
+            let typ = typ.to_string();
             return Ok(ClientFilter::Type(typ));
         }
-        if s.starts_with("o/") {
+        if let Some(squares) = s.strip_prefix("g/") {
+            // g/JP20*/JN58 maidenhead squares
+            if squares.is_empty() {
+                return Err(FilterError::InvalidGridSquare(String::new()));
+            }
+            if let Some(bad) = squares.split('/').find(|sq| crate::maidenhead::to_lat_lon(&pad_locator(sq)).is_none()) {
+                return Err(FilterError::InvalidGridSquare(bad.to_string()));
+            }
+            return Ok(ClientFilter::Grid(squares.to_string()));
+        }
+        if let Some(dist) = s.strip_prefix("m/") {
+            // m/dist around the client's own last position
+            let radius_km = number(dist, "radius", RADIUS)?;
+            return Ok(ClientFilter::MyRange { radius_km });
+        }
+        if let Some(rest) = s.strip_prefix("f/") {
+            // f/call/dist around another station's last position
+            if let Some((callsign, dist)) = rest.split_once('/') {
+                let radius_km = number(dist, "radius", RADIUS)?;
+                return Ok(ClientFilter::Friend { callsign: callsign.to_string(), radius_km });
+            }
+        }
+        if let Some(obj) = s.strip_prefix("o/") {
             // o/objectname
-            let obj = s[2..].to_string();
+            let obj = obj.to_string();
             return Ok(ClientFilter::Object(obj));
         }
-        Err("Unknown filter type".to_string())
+        Err(FilterError::UnknownType)
+    }
+}
+
+/// The canonical filter string, which parses back to the same filter.
+impl fmt::Display for ClientFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientFilter::Area { lat, lon, radius_km } => write!(f, "r/{}/{}/{}", lat, lon, radius_km),
+            ClientFilter::Box { lat1, lon1, lat2, lon2 } => write!(f, "a/{}/{}/{}/{}", lat1, lon1, lat2, lon2),
+            ClientFilter::Prefix(prefix) => write!(f, "p/{}", prefix),
+            ClientFilter::Type(letters) => write!(f, "t/{}", letters),
+            ClientFilter::Object(names) => write!(f, "o/{}", names),
+            ClientFilter::Grid(squares) => write!(f, "g/{}", squares),
+            ClientFilter::MyRange { radius_km } => write!(f, "m/{}", radius_km),
+            ClientFilter::Friend { callsign, radius_km } => write!(f, "f/{}/{}", callsign, radius_km),
+            ClientFilter::All => write!(f, "a/*"),
+        }
     }
 }
 
+impl From<ClientFilter> for String {
+    fn from(filter: ClientFilter) -> String {
+        filter.to_string()
+    }
+}
+
+impl TryFrom<String> for ClientFilter {
+    type Error = FilterError;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Filters as a client would send them, separated by spaces.
+pub fn filter_string(filters: &[ClientFilter]) -> String {
+    filters.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+/// Parses a whitespace separated filter string such as `r/60/25/100 t/m`.
+pub fn parse_filter_list(s: &str) -> Result<Vec<ClientFilter>, InvalidFilter> {
+    s.split_whitespace()
+        .map(|part| part.parse::<ClientFilter>().map_err(|error| InvalidFilter { filter: part.to_string(), error }))
+        .collect()
+}
+
 impl ClientFilter {
     pub fn matches(&self, packet: &str) -> bool {
+        match parser::parse(packet) {
+            Ok(parsed) => self.matches_parsed(&parsed),
+            Err(_) => false,
+        }
+    }
+    pub fn matches_parsed(&self, packet: &ParsedPacket) -> bool {
+        self.matches_with(packet, &FilterContext::default())
+    }
+    pub fn matches_with(&self, packet: &ParsedPacket, ctx: &FilterContext) -> bool {
         match self {
             ClientFilter::All => true,
+            ClientFilter::MyRange { radius_km } => {
+                let center = ctx.callsign.and_then(|c| ctx.position_of(c));
+                within(packet, ctx, center, *radius_km)
+            }
+            ClientFilter::Friend { callsign, radius_km } => within(packet, ctx, ctx.position_of(callsign), *radius_km),
             ClientFilter::Area { lat, lon, radius_km } => {
-                if let Some((plat, plon)) = super::server::parse_aprs_lat_lon(packet) {
-                    haversine_km(*lat, *lon, plat, plon) <= *radius_km
-                } else {
-                    false
-                }
+                packet_positions(packet, ctx)
+                    .iter()
+                    .any(|(plat, plon)| haversine_km(*lat, *lon, *plat, *plon) <= *radius_km)
             }
             ClientFilter::Box { lat1, lon1, lat2, lon2 } => {
-                if let Some((plat, plon)) = super::server::parse_aprs_lat_lon(packet) {
-                    let (min_lat, max_lat) = (lat1.min(*lat2), lat1.max(*lat2));
-                    let (min_lon, max_lon) = (lon1.min(*lon2), lon1.max(*lon2));
-                    plat >= min_lat && plat <= max_lat && plon >= min_lon && plon <= max_lon
-                } else {
-                    false
-                }
+                let (min_lat, max_lat) = (lat1.min(*lat2), lat1.max(*lat2));
+                let (min_lon, max_lon) = (lon1.min(*lon2), lon1.max(*lon2));
+                packet_positions(packet, ctx).iter().any(|(plat, plon)| {
+                    *plat >= min_lat && *plat <= max_lat && *plon >= min_lon && *plon <= max_lon
+                })
             }
             ClientFilter::Prefix(prefix) => {
-                packet.to_uppercase().starts_with(&prefix.to_uppercase())
-            }
-            ClientFilter::Type(typ) => {
-                // Very basic: check if packet payload starts with the type char
-                if let Some(colon) = packet.find(':') {
-                    let payload = &packet[colon+1..];
-                    payload.starts_with(typ)
-                } else {
-                    false
+                packet.source.to_uppercase().starts_with(&prefix.to_uppercase())
+            }
+            ClientFilter::Type(typ) => typ.chars().any(|t| matches_type(t, packet)),
+            ClientFilter::Grid(squares) => {
+                let positions = packet_positions(packet, ctx);
+                squares.split('/').any(|sq| {
+                    positions.iter().any(|(lat, lon)| crate::maidenhead::in_square(*lat, *lon, sq))
+                })
+            }
+            ClientFilter::Object(names) => {
+                // o/NAME1/NAME2 with an optional trailing '*' wildcard per name
+                match &packet.data {
+                    PacketData::Object { name, .. } | PacketData::Item { name, .. } => {
+                        names.split('/').any(|pattern| match pattern.strip_suffix('*') {
+                            Some(prefix) => name.starts_with(prefix),
+                            None => name == pattern,
+                        })
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+// Letters of the `t/` filter, by bit position in a type mask
+const TYPE_LETTERS: &str = "poimqstunw";
+
+fn type_mask(letters: &str) -> u16 {
+    letters.chars().filter_map(|t| TYPE_LETTERS.find(t)).fold(0, |mask, bit| mask | 1 << bit)
+}
+
+/// Callsign prefixes of the `p/` filters, uppercased, each ending at the
+/// index of its filter.
+#[derive(Debug, Default)]
+struct PrefixTrie {
+    children: HashMap<char, PrefixTrie>,
+    filter: Option<usize>,
+}
+
+impl PrefixTrie {
+    fn insert(&mut self, prefix: &str, index: usize) {
+        let mut node = self;
+        for c in prefix.chars().map(|c| c.to_ascii_uppercase()) {
+            node = node.children.entry(c).or_default();
+        }
+        node.filter.get_or_insert(index);
+    }
+    /// Index of the filter whose prefix the callsign starts with.
+    fn find(&self, callsign: &str) -> Option<usize> {
+        let mut node = self;
+        for c in callsign.chars().map(|c| c.to_ascii_uppercase()) {
+            if node.filter.is_some() {
+                break;
+            }
+            node = node.children.get(&c)?;
+        }
+        node.filter
+    }
+}
+
+/// Latitude and longitude bounds, in degrees.
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    min_lat: f64,
+    max_lat: f64,
+    min_lon: f64,
+    max_lon: f64,
+}
+
+impl Bounds {
+    fn contains(&self, lat: f64, lon: f64) -> bool {
+        lat >= self.min_lat && lat <= self.max_lat && lon >= self.min_lon && lon <= self.max_lon
+    }
+    fn union(self, other: Bounds) -> Bounds {
+        Bounds {
+            min_lat: self.min_lat.min(other.min_lat),
+            max_lat: self.max_lat.max(other.max_lat),
+            min_lon: self.min_lon.min(other.min_lon),
+            max_lon: self.max_lon.max(other.max_lon),
+        }
+    }
+    /// Bounds around a circle; the full longitude range if it reaches a
+    /// pole or crosses the antimeridian. The longitude span is that of the
+    /// widest point of the circle, which lies poleward of its center.
+    fn around(lat: f64, lon: f64, radius_km: f64) -> Bounds {
+        // Widened a little so points on the edge are not lost to rounding
+        let angle = (radius_km / EARTH_RADIUS_KM).to_degrees() + 1e-6;
+        let dlon = if angle >= 90.0 - lat.abs() {
+            180.0
+        } else {
+            (angle.to_radians().sin() / lat.to_radians().cos()).asin().to_degrees()
+        };
+        let (min_lon, max_lon) = if lon - dlon < -180.0 || lon + dlon > 180.0 { (-180.0, 180.0) } else { (lon - dlon, lon + dlon) };
+        Bounds { min_lat: lat - angle, max_lat: lat + angle, min_lon, max_lon }
+    }
+}
+
+/// A client's filter list compiled for matching when it is set: `p/`
+/// prefixes in a trie, `t/` letters as a bitmask, and `r/` and `a/` areas
+/// behind one combined bounding box, so packets are checked without per
+/// filter string work. Matches are counted per filter.
+#[derive(Debug, Default)]
+pub struct FilterSet {
+    filters: Vec<ClientFilter>,
+    all: Option<usize>,
+    prefixes: PrefixTrie,
+    types: Vec<(u16, usize)>,
+    type_union: u16,
+    boxes: Vec<(Bounds, usize)>,
+    areas: Vec<(f64, f64, f64, Bounds, usize)>,
+    geo_bounds: Option<Bounds>,
+    /// Filters without a compiled form, checked last
+    other: Vec<usize>,
+    matches: Vec<AtomicU64>,
+}
+
+impl FilterSet {
+    pub fn new(filters: Vec<ClientFilter>) -> Self {
+        let mut set = FilterSet { matches: filters.iter().map(|_| AtomicU64::new(0)).collect(), ..Default::default() };
+        for (i, filter) in filters.iter().enumerate() {
+            match filter {
+                ClientFilter::All => {
+                    set.all.get_or_insert(i);
+                }
+                ClientFilter::Prefix(prefix) => set.prefixes.insert(prefix, i),
+                ClientFilter::Type(letters) => {
+                    let mask = type_mask(letters);
+                    set.types.push((mask, i));
+                    set.type_union |= mask;
+                }
+                ClientFilter::Box { lat1, lon1, lat2, lon2 } => {
+                    let bounds = Bounds {
+                        min_lat: lat1.min(*lat2),
+                        max_lat: lat1.max(*lat2),
+                        min_lon: lon1.min(*lon2),
+                        max_lon: lon1.max(*lon2),
+                    };
+                    set.boxes.push((bounds, i));
+                    set.geo_bounds = Some(set.geo_bounds.map_or(bounds, |b| b.union(bounds)));
+                }
+                ClientFilter::Area { lat, lon, radius_km } => {
+                    let bounds = Bounds::around(*lat, *lon, *radius_km);
+                    set.areas.push((*lat, *lon, *radius_km, bounds, i));
+                    set.geo_bounds = Some(set.geo_bounds.map_or(bounds, |b| b.union(bounds)));
                 }
+                _ => set.other.push(i),
             }
-            ClientFilter::Object(obj) => {
-                // Check if object name is in the packet (very basic)
-                packet.contains(obj)
+        }
+        set.filters = filters;
+        set
+    }
+    pub fn filters(&self) -> &[ClientFilter] {
+        &self.filters
+    }
+    /// Packets matched by each filter, in filter order. A packet is credited
+    /// to the first filter found to match it.
+    pub fn match_counts(&self) -> Vec<u64> {
+        self.matches.iter().map(|m| m.load(Ordering::Relaxed)).collect()
+    }
+    pub fn matches(&self, packet: &ParsedPacket, ctx: &FilterContext) -> bool {
+        match self.find(packet, ctx) {
+            Some(i) => {
+                self.matches[i].fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+    fn find(&self, packet: &ParsedPacket, ctx: &FilterContext) -> Option<usize> {
+        if self.all.is_some() {
+            return self.all;
+        }
+        if self.type_union != 0 {
+            let bits = TYPE_LETTERS
+                .chars()
+                .enumerate()
+                .filter(|(bit, t)| self.type_union & 1 << bit != 0 && matches_type(*t, packet))
+                .fold(0u16, |mask, (bit, _)| mask | 1 << bit);
+            if let Some((_, i)) = self.types.iter().find(|(mask, _)| mask & bits != 0) {
+                return Some(*i);
             }
         }
+        if let Some(i) = self.prefixes.find(&packet.source) {
+            return Some(i);
+        }
+        if let Some(geo) = self.geo_bounds {
+            for (lat, lon) in packet_positions(packet, ctx).into_iter().filter(|(lat, lon)| geo.contains(*lat, *lon)) {
+                if let Some((_, i)) = self.boxes.iter().find(|(b, _)| b.contains(lat, lon)) {
+                    return Some(*i);
+                }
+                if let Some(area) = self.areas.iter().find(|(alat, alon, radius, b, _)| {
+                    b.contains(lat, lon) && haversine_km(*alat, *alon, lat, lon) <= *radius
+                }) {
+                    return Some(area.4);
+                }
+            }
+        }
+        self.other.iter().copied().find(|i| self.filters[*i].matches_with(packet, ctx))
     }
 }
 
+/// Checks whether the packet is within `radius_km` of `center`; an unknown
+/// center matches nothing.
+fn within(packet: &ParsedPacket, ctx: &FilterContext, center: Option<(f64, f64)>, radius_km: f64) -> bool {
+    let Some((lat, lon)) = center else {
+        return false;
+    };
+    packet_positions(packet, ctx)
+        .iter()
+        .any(|(plat, plon)| haversine_km(lat, lon, *plat, *plon) <= radius_km)
+}
+
+/// Pads a (possibly odd-length or wildcarded) square pattern to a full locator
+/// so it can be validated.
+fn pad_locator(pattern: &str) -> String {
+    let mut locator = pattern.trim_end_matches('*').to_string();
+    if locator.len() % 4 == 1 {
+        locator.push('A');
+    } else if locator.len() % 4 == 3 {
+        locator.push('0');
+    }
+    locator
+}
+
+/// Matches a single `t/` filter letter as defined by APRS-IS.
+fn matches_type(t: char, packet: &ParsedPacket) -> bool {
+    match t {
+        'p' => matches!(packet.data, PacketData::Position(_) | PacketData::MicE(_)),
+        'o' => matches!(packet.data, PacketData::Object { .. }),
+        'i' => matches!(packet.data, PacketData::Item { .. }),
+        'm' => packet.message().is_some(),
+        'q' => matches!(packet.data, PacketData::Query(_)),
+        's' => matches!(packet.data, PacketData::Status(_)),
+        't' => matches!(packet.data, PacketData::Telemetry(_)),
+        'u' => matches!(packet.data, PacketData::UserDefined(_)),
+        'n' => packet.message().and_then(crate::nws::parse_bulletin).is_some(),
+        'w' => packet.weather().is_some(),
+        _ => false,
+    }
+}
+
+/// Positions a packet can be located at: its own coordinates, or the zones an
+/// NWS bulletin applies to when a zone table is loaded.
+fn packet_positions(packet: &ParsedPacket, ctx: &FilterContext) -> Vec<(f64, f64)> {
+    if let Some(pos) = packet.position() {
+        return vec![(pos.lat, pos.lon)];
+    }
+    match (packet.message(), ctx.zones) {
+        (Some(msg), Some(zones)) => zones.bulletin_positions(msg),
+        _ => Vec::new(),
+    }
+}
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
 pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
-    let r = 6371.0; // Earth radius in km
+    let r = EARTH_RADIUS_KM;
     let dlat = (lat2 - lat1).to_radians();
     let dlon = (lon2 - lon1).to_radians();
     let a = (dlat / 2.0).sin().powi(2)
@@ -113,18 +498,147 @@ mod tests {
     use super::*;
     #[test]
     fn test_area_filter_parse() {
-        let f: AreaFilter = "r/60.0/25.0/100.0".parse().unwrap();
-        assert_eq!(f.lat, 60.0);
-        assert_eq!(f.lon, 25.0);
-        assert_eq!(f.radius_km, 100.0);
-        assert!("r/60.0/25.0".parse::<AreaFilter>().is_err());
-        assert!("x/60.0/25.0/100.0".parse::<AreaFilter>().is_err());
+        let f: ClientFilter = "r/60.0/25.0/100.0".parse().unwrap();
+        assert_eq!(f, ClientFilter::Area { lat: 60.0, lon: 25.0, radius_km: 100.0 });
+        assert!("r/60.0/25.0".parse::<ClientFilter>().is_err());
+        assert!("x/60.0/25.0/100.0".parse::<ClientFilter>().is_err());
+    }
+    #[test]
+    fn test_nws_type_filter() {
+        let f: ClientFilter = "t/n".parse().unwrap();
+        assert!(f.matches("NWS>APRS::NWS-WARN :191700z,THUNDER_STORM,KS_Z033"));
+        assert!(!f.matches("N0CALL>APRS::N1CALL   :hello"));
+    }
+    #[test]
+    fn test_object_filter() {
+        let f: ClientFilter = "o/LEADER/NET*".parse().unwrap();
+        assert!(f.matches("N0CALL>APRS:;LEADER   *092345z4903.50N/07201.75W>"));
+        assert!(f.matches("N0CALL>APRS:)NET-1!4903.50N/07201.75WA"));
+        assert!(!f.matches("N0CALL>APRS:;LEADERS  *092345z4903.50N/07201.75W>"));
+        assert!(!f.matches("LEADER>APRS:!4903.50N/07201.75W>LEADER"));
+    }
+    #[test]
+    fn test_grid_filter() {
+        let f: ClientFilter = "g/FN42*/JN58".parse().unwrap();
+        assert!(f.matches("N0CALL>APRS:!4221.00N/07103.00W>"));
+        assert!(f.matches("N0CALL>APRS:!4808.80N/01136.50E>"));
+        assert!(!f.matches("N0CALL>APRS:!6000.00N/02500.00E>"));
+        assert!("g/ZZ99".parse::<ClientFilter>().is_err());
+        assert!("g/".parse::<ClientFilter>().is_err());
+    }
+    #[test]
+    fn test_range_filters() {
+        let positions = PositionCache::default();
+        positions.insert(crate::hub::StationPosition {
+            callsign: "N0CALL".to_string(),
+            lat: 60.0,
+            lon: 25.0,
+            ..Default::default()
+        }, None);
+        let near = parser::parse("N1CALL>APRS:!6030.00N/02500.00E>").unwrap();
+        let far = parser::parse("N1CALL>APRS:!6200.00N/02500.00E>").unwrap();
+        let ctx = FilterContext { callsign: Some("n0call"), positions: Some(&positions), zones: None };
+        let m: ClientFilter = "m/100".parse().unwrap();
+        assert!(m.matches_with(&near, &ctx));
+        assert!(!m.matches_with(&far, &ctx));
+        assert!(!m.matches_parsed(&near));
+        let f: ClientFilter = "f/N0CALL/100".parse().unwrap();
+        assert_eq!(f, ClientFilter::Friend { callsign: "N0CALL".to_string(), radius_km: 100.0 });
+        assert!(f.matches_with(&near, &FilterContext { callsign: None, positions: Some(&positions), zones: None }));
+        assert!(!"f/N9CALL/100".parse::<ClientFilter>().unwrap().matches_with(&near, &ctx));
+        assert!("f/N0CALL".parse::<ClientFilter>().is_err());
+        assert!("m/x".parse::<ClientFilter>().is_err());
+    }
+    #[test]
+    fn test_parse_filter_list() {
+        let filters = parse_filter_list(" r/60.0/25.0/100.0  t/m ").unwrap();
+        assert_eq!(filters.len(), 2);
+        assert_eq!(filters[1], ClientFilter::Type("m".to_string()));
+        assert!(parse_filter_list("").unwrap().is_empty());
+        let err = parse_filter_list("t/m x/1").unwrap_err();
+        assert_eq!(err.error, FilterError::UnknownType);
+        assert_eq!(err.to_string(), "x/1: Unknown filter type");
+    }
+    #[test]
+    fn test_display_round_trip() {
+        let filters = parse_filter_list("r/60.5/-25/100 a/61/24/59/26.25 p/OH t/pm o/NET* g/FN42*/JN58 m/50 f/N0CALL/10 all").unwrap();
+        let s = filter_string(&filters);
+        assert_eq!(s, "r/60.5/-25/100 a/61/24/59/26.25 p/OH t/pm o/NET* g/FN42*/JN58 m/50 f/N0CALL/10 a/*");
+        assert_eq!(parse_filter_list(&s).unwrap(), filters);
+        assert_eq!(serde_json::to_string(&filters[0]).unwrap(), "\"r/60.5/-25/100\"");
+        assert_eq!(serde_json::from_str::<ClientFilter>("\"t/pm\"").unwrap(), filters[3]);
+        assert!(serde_json::from_str::<ClientFilter>("\"x/1\"").is_err());
+    }
+    #[test]
+    fn test_filter_number_ranges() {
+        assert_eq!("r/91/25/100".parse::<ClientFilter>(), Err(FilterError::InvalidField("latitude")));
+        assert_eq!("r/NaN/25/100".parse::<ClientFilter>(), Err(FilterError::InvalidField("latitude")));
+        assert_eq!("a/61/24/59/inf".parse::<ClientFilter>(), Err(FilterError::InvalidField("lon2")));
+        assert_eq!("m/-5".parse::<ClientFilter>(), Err(FilterError::InvalidField("radius")));
+        assert_eq!("g/ZZ99".parse::<ClientFilter>().unwrap_err().to_string(), "Invalid grid square: ZZ99");
+        for s in ["r/é/€/😀", "g/JNé", "f/é/", "a/////", "g/J\u{0}5"] {
+            assert!(s.parse::<ClientFilter>().is_err());
+        }
+    }
+    #[test]
+    fn test_filter_set() {
+        let set = FilterSet::new(parse_filter_list("t/w p/N1/ p/W a/61/24/59/26 r/40/-75/50 o/NET* t/m").unwrap());
+        let ctx = FilterContext::default();
+        let cases = [
+            ("N0CALL>APRS:_10090556c220s004g005t077", Some(0)),
+            ("N1/X>APRS:>status", Some(1)),
+            ("w1aw>APRS:>status", Some(2)),
+            ("N0CALL>APRS:!6000.00N/02500.00E>", Some(3)),
+            ("N0CALL>APRS:!4010.00N/07500.00W>", Some(4)),
+            ("N0CALL>APRS:)NET-1!4903.50N/07201.75WA", Some(5)),
+            ("N0CALL>APRS::N1CALL   :hi", Some(6)),
+            ("N0CALL>APRS:!4100.00N/07500.00W>", None),
+            ("N0CALL>APRS:>status", None),
+        ];
+        for (line, expected) in cases {
+            let packet = parser::parse(line).unwrap();
+            assert_eq!(set.matches(&packet, &ctx), expected.is_some(), "{}", line);
+            // Same decision as checking the filters one by one
+            assert_eq!(set.filters().iter().any(|f| f.matches_parsed(&packet)), expected.is_some(), "{}", line);
+        }
+        assert_eq!(set.match_counts(), [1, 1, 1, 1, 1, 1, 1]);
+        assert!(FilterSet::new(vec![ClientFilter::All]).matches(&parser::parse("N0CALL>APRS:>x").unwrap(), &ctx));
+        assert!(!FilterSet::new(Vec::new()).matches(&parser::parse("N0CALL>APRS:>x").unwrap(), &ctx));
+    }
+    #[test]
+    fn test_area_bounds() {
+        // Points just inside the circle, all the way round, pass the bounding box
+        let aprs = |lat: f64, lon: f64| {
+            let (ns, ew) = (if lat < 0.0 { 'S' } else { 'N' }, if lon < 0.0 { 'W' } else { 'E' });
+            let (lat, lon) = (lat.abs(), lon.abs());
+            let lat = format!("{:02}{:05.2}{}", lat.trunc(), lat.fract() * 60.0, ns);
+            let lon = format!("{:03}{:05.2}{}", lon.trunc(), lon.fract() * 60.0, ew);
+            parser::parse(&format!("N0CALL>APRS:!{}/{}>", lat, lon)).unwrap()
+        };
+        let ctx = FilterContext::default();
+        for (lat, lon, radius_km) in [(70.0, 0.0, 1500.0), (-60.0, 100.0, 800.0), (85.0, 10.0, 700.0), (10.0, -20.0, 3000.0)] {
+            let filter = ClientFilter::Area { lat, lon, radius_km };
+            let set = FilterSet::new(vec![filter.clone()]);
+            let angle = radius_km * 0.999 / EARTH_RADIUS_KM;
+            let (lat1, lon1) = (f64::to_radians(lat), f64::to_radians(lon));
+            for bearing in (0..360).step_by(5).map(|b| f64::from(b).to_radians()) {
+                let lat2 = (lat1.sin() * angle.cos() + lat1.cos() * angle.sin() * bearing.cos()).asin();
+                let lon2 = lon1 + (bearing.sin() * angle.sin() * lat1.cos()).atan2(angle.cos() - lat1.sin() * lat2.sin());
+                let lon2 = (lon2.to_degrees() + 540.0) % 360.0 - 180.0;
+                let packet = aprs(lat2.to_degrees(), lon2);
+                assert!(filter.matches_parsed(&packet), "{:?} {}", filter, bearing);
+                assert!(set.matches(&packet, &ctx), "{:?} {}", filter, bearing);
+            }
+        }
+        // The case that used to fall outside the box
+        let set = FilterSet::new(parse_filter_list("r/70/0/1500").unwrap());
+        assert!(set.matches(&aprs(75.0, 41.0), &ctx));
     }
     #[test]
     fn test_area_filter_match() {
-        let area: AreaFilter = "r/60.0/25.0/100.0".parse().unwrap();
-        assert!(area_filter_match(&area, 60.0, 25.0)); // center
-        assert!(area_filter_match(&area, 60.5, 25.0)); // within 100km
-        assert!(!area_filter_match(&area, 62.0, 25.0)); // outside 100km
+        let area: ClientFilter = "r/60.0/25.0/100.0".parse().unwrap();
+        assert!(area.matches("N0CALL>APRS:!6000.00N/02500.00E>")); // center
+        assert!(area.matches("N0CALL>APRS:!6030.00N/02500.00E>")); // within 100km
+        assert!(!area.matches("N0CALL>APRS:!6200.00N/02500.00E>")); // outside 100km
     }
 } 
\ No newline at end of file
@@ -0,0 +1,46 @@
+//! Path flags that restrict where a packet may go. `TCPXX` marks a packet
+//! from an unverified APRS-IS connection, which must not enter the verified
+//! feed. `NOGATE` and `RFONLY` ask igates not to gate the packet to APRS-IS
+//! at all, so one arriving here has been gated against the sender's wish.
+
+use crate::parser::ParsedPacket;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathFlag {
+    Tcpxx,
+    NoGate,
+    RfOnly,
+}
+
+impl PathFlag {
+    /// The flag a path hop stands for, ignoring a trailing `*`.
+    pub fn from_hop(hop: &str) -> Option<Self> {
+        match hop.trim_end_matches('*') {
+            "TCPXX" => Some(PathFlag::Tcpxx),
+            "NOGATE" => Some(PathFlag::NoGate),
+            "RFONLY" => Some(PathFlag::RfOnly),
+            _ => None,
+        }
+    }
+}
+
+/// The first flag in the path of `packet` that keeps it out of APRS-IS.
+pub fn forbidden_flag(packet: &ParsedPacket) -> Option<PathFlag> {
+    packet.path.iter().find_map(|hop| PathFlag::from_hop(hop))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forbidden_flag() {
+        let flag = |line: &str| forbidden_flag(&crate::parser::parse(line).unwrap());
+        assert_eq!(flag("N0CALL>APRS,TCPXX*,qAX,T2TEST:>hi"), Some(PathFlag::Tcpxx));
+        assert_eq!(flag("N0CALL>APRS,WIDE1-1,NOGATE:>hi"), Some(PathFlag::NoGate));
+        assert_eq!(flag("N0CALL>APRS,RFONLY:>hi"), Some(PathFlag::RfOnly));
+        assert_eq!(flag("N0CALL>APRS,TCPIP*,qAC,T2TEST:>hi"), None);
+        // Only whole hops count
+        assert_eq!(flag("N0CALL>APRS,NOGATE-1:>hi"), None);
+    }
+}
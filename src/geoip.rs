@@ -0,0 +1,68 @@
+//! Country and region lookup of client addresses in a local MaxMind
+//! GeoIP2/GeoLite2 City or Country database.
+
+use maxminddb::{Reader, geoip2};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// Where a client address is located, as far as the database knows.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    /// ISO 3166-1 country code
+    pub country: Option<String>,
+    /// Top-level subdivision name; only City databases have these
+    pub region: Option<String>,
+}
+
+impl Location {
+    /// `country / region`, or whichever of the two is known.
+    pub fn summary(&self) -> String {
+        match (&self.country, &self.region) {
+            (Some(country), Some(region)) => format!("{} / {}", country, region),
+            (Some(s), None) | (None, Some(s)) => s.clone(),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let reader = Reader::open_readfile(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(GeoIp { reader })
+    }
+
+    /// Looks up `ip`, returning None for addresses not in the database,
+    /// such as private and loopback ones.
+    pub fn lookup(&self, ip: IpAddr) -> Option<Location> {
+        // A City record holds everything a Country record does
+        let city: geoip2::City = self.reader.lookup(ip).ok()?;
+        let location = Location {
+            country: city.country.and_then(|c| c.iso_code).map(str::to_string),
+            region: city
+                .subdivisions
+                .and_then(|s| s.into_iter().next())
+                .and_then(|s| s.names)
+                .and_then(|n| n.get("en").map(|n| n.to_string())),
+        };
+        (location != Location::default()).then_some(location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location_summary() {
+        let mut location = Location { country: Some("FI".to_string()), region: Some("Uusimaa".to_string()) };
+        assert_eq!(location.summary(), "FI / Uusimaa");
+        location.region = None;
+        assert_eq!(location.summary(), "FI");
+        assert_eq!(Location::default().summary(), "");
+        assert!(GeoIp::open("/nonexistent/GeoLite2-City.mmdb").is_err());
+    }
+}
@@ -0,0 +1,449 @@
+//! gRPC service for backend consumers, built with the `grpc` feature and
+//! started when `grpc_port` is set. `proto/aprsserver.proto` describes it:
+//! a server-streaming `Subscribe` with an APRS-IS filter, and unary calls
+//! for stats and clients. The service is small enough that it speaks
+//! HTTP/2 through `h2` and encodes its protobuf messages by hand. The tests
+//! check that encoding by decoding it with the `.proto` file.
+
+use crate::hub::{FeedPacket, Hub};
+use bytes::{Buf, Bytes};
+use futures_util::future::poll_fn;
+use h2::server::SendResponse;
+use h2::{RecvStream, SendStream};
+use http::{HeaderMap, HeaderValue, Request, Response};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info, warn};
+
+// Requests are single small messages
+const MAX_REQUEST_BYTES: usize = 64 * 1024;
+
+/// gRPC status codes used here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Code {
+    Ok = 0,
+    InvalidArgument = 3,
+    Unimplemented = 12,
+}
+
+/// Protobuf wire encoding of the few field types the messages use.
+mod wire {
+    pub fn varint(buf: &mut Vec<u8>, mut v: u64) {
+        while v >= 0x80 {
+            buf.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        buf.push(v as u8);
+    }
+    pub fn uint(buf: &mut Vec<u8>, field: u64, v: u64) {
+        if v != 0 {
+            varint(buf, field << 3);
+            varint(buf, v);
+        }
+    }
+    pub fn bytes(buf: &mut Vec<u8>, field: u64, v: &[u8]) {
+        varint(buf, (field << 3) | 2);
+        varint(buf, v.len() as u64);
+        buf.extend_from_slice(v);
+    }
+    pub fn string(buf: &mut Vec<u8>, field: u64, v: &str) {
+        if !v.is_empty() {
+            bytes(buf, field, v.as_bytes());
+        }
+    }
+
+    fn read_varint(buf: &mut &[u8]) -> Result<u64, String> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&b, rest) = buf.split_first().ok_or("truncated varint")?;
+            *buf = rest;
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err("varint too long".to_string())
+    }
+
+    /// The length-delimited fields of a message as `(field, bytes)`, other
+    /// wire types skipped.
+    pub fn fields(mut buf: &[u8]) -> Result<Vec<(u64, &[u8])>, String> {
+        let mut out = Vec::new();
+        while !buf.is_empty() {
+            let key = read_varint(&mut buf)?;
+            let skip = match key & 7 {
+                0 => {
+                    read_varint(&mut buf)?;
+                    0
+                }
+                1 => 8,
+                2 => {
+                    let len = read_varint(&mut buf)? as usize;
+                    let value = buf.get(..len).ok_or("truncated field")?;
+                    out.push((key >> 3, value));
+                    len
+                }
+                5 => 4,
+                t => return Err(format!("unsupported wire type {}", t)),
+            };
+            buf = buf.get(skip..).ok_or("truncated field")?;
+        }
+        Ok(out)
+    }
+}
+
+/// A message with the gRPC length prefix, uncompressed.
+fn frame(message: &[u8]) -> Bytes {
+    let mut out = Vec::with_capacity(message.len() + 5);
+    out.push(0);
+    out.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    out.extend_from_slice(message);
+    out.into()
+}
+
+fn packet_message(p: &FeedPacket, skipped: u64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    wire::uint(&mut buf, 1, p.received);
+    wire::string(&mut buf, 2, &p.line);
+    wire::string(&mut buf, 3, &p.packet.source);
+    wire::string(&mut buf, 4, p.packet.data.type_name());
+    wire::uint(&mut buf, 5, skipped);
+    buf
+}
+
+fn stats_message(hub: &Hub) -> Vec<u8> {
+    let mut buf = Vec::new();
+    wire::uint(&mut buf, 1, hub.uptime());
+    wire::uint(&mut buf, 2, hub.client_count() as u64);
+    let t = hub.counters.totals();
+    for (field, v) in (3..).zip([t.packets_rx, t.bytes_rx, t.packets_tx, t.bytes_tx, t.decisions.accepted, t.decisions.duplicates, t.dropped]) {
+        wire::uint(&mut buf, field, v);
+    }
+    buf
+}
+
+fn clients_message(hub: &Hub) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let clients = hub.clients.read().unwrap();
+    let mut ids: Vec<&usize> = clients.keys().collect();
+    ids.sort();
+    for id in ids {
+        let c = clients[id].lock().unwrap();
+        let mut client = Vec::new();
+        wire::uint(&mut client, 1, *id as u64);
+        wire::string(&mut client, 2, c.callsign.as_deref().unwrap_or(""));
+        wire::string(&mut client, 3, &c.addr);
+        wire::string(&mut client, 4, c.filter_string.as_deref().unwrap_or(""));
+        wire::uint(&mut client, 5, c.packets_rx);
+        wire::uint(&mut client, 6, c.tx.packets.load(Ordering::Relaxed));
+        wire::uint(&mut client, 7, c.connect_time.elapsed().as_secs());
+        wire::bytes(&mut buf, 1, &client);
+    }
+    buf
+}
+
+/// The filter of a `SubscribeRequest`.
+fn subscribe_filter(message: &[u8]) -> Result<String, String> {
+    let fields = wire::fields(message)?;
+    let filter = fields.iter().rev().find(|(field, _)| *field == 1).map_or(&[][..], |(_, v)| v);
+    String::from_utf8(filter.to_vec()).map_err(|_| "filter is not UTF-8".to_string())
+}
+
+fn status_headers(code: Code, message: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("grpc-status", HeaderValue::from(code as u16));
+    // Only ASCII survives in a header without percent-encoding
+    if let Ok(value) = HeaderValue::from_str(&message.replace(|c: char| !c.is_ascii_graphic() && c != ' ', "?"))
+        && !message.is_empty()
+    {
+        headers.insert("grpc-message", value);
+    }
+    headers
+}
+
+fn response(trailers_only: Option<HeaderMap>) -> Response<()> {
+    let mut response = Response::new(());
+    response.headers_mut().insert("content-type", HeaderValue::from_static("application/grpc"));
+    if let Some(status) = trailers_only {
+        response.headers_mut().extend(status);
+    }
+    response
+}
+
+/// Reads the single request message, without its length prefix.
+async fn read_request(mut body: RecvStream) -> Result<Bytes, (Code, String)> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| (Code::InvalidArgument, e.to_string()))?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        buf.extend_from_slice(&chunk);
+        if buf.len() > MAX_REQUEST_BYTES {
+            return Err((Code::InvalidArgument, "request too large".to_string()));
+        }
+    }
+    let mut buf = Bytes::from(buf);
+    if buf.is_empty() {
+        return Ok(buf);
+    }
+    if buf.len() < 5 {
+        return Err((Code::InvalidArgument, "truncated message".to_string()));
+    }
+    if buf.get_u8() != 0 {
+        return Err((Code::Unimplemented, "compressed messages are not supported".to_string()));
+    }
+    let len = buf.get_u32() as usize;
+    if buf.len() < len {
+        return Err((Code::InvalidArgument, "truncated message".to_string()));
+    }
+    Ok(buf.split_to(len))
+}
+
+/// Sends a frame once the peer's flow control window has room for it, so
+/// a slow subscriber holds up its stream instead of growing a buffer.
+async fn send(stream: &mut SendStream<Bytes>, data: Bytes) -> Result<(), h2::Error> {
+    stream.reserve_capacity(data.len());
+    while stream.capacity() < data.len() {
+        match poll_fn(|cx| stream.poll_capacity(cx)).await {
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e),
+            None => return Err(h2::Error::from(h2::Reason::CANCEL)),
+        }
+    }
+    stream.send_data(data, false)
+}
+
+async fn subscribe(hub: Arc<Hub>, request: Bytes, mut respond: SendResponse<Bytes>) -> Result<(), h2::Error> {
+    let filters = match subscribe_filter(&request).and_then(|f| crate::filter::parse_filter_list(&f).map_err(|e| e.to_string())) {
+        Ok(filters) => filters,
+        Err(e) => {
+            respond.send_response(response(Some(status_headers(Code::InvalidArgument, &e))), true)?;
+            return Ok(());
+        }
+    };
+    let mut feed = hub.packet_feed.subscribe();
+    let mut stream = respond.send_response(response(None), false)?;
+    let mut skipped = 0;
+    loop {
+        let p = tokio::select! {
+            received = feed.recv() => match received {
+                Ok(p) => p,
+                Err(RecvError::Lagged(n)) => {
+                    skipped += n;
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // The client cancelled the call
+            _ = poll_fn(|cx| stream.poll_reset(cx)) => return Ok(()),
+        };
+        if crate::web::feed_matches(&hub, &filters, &p) {
+            send(&mut stream, frame(&packet_message(&p, skipped))).await?;
+            skipped = 0;
+        }
+    }
+    stream.send_trailers(status_headers(Code::Ok, ""))
+}
+
+async fn handle(hub: Arc<Hub>, request: Request<RecvStream>, mut respond: SendResponse<Bytes>) -> Result<(), h2::Error> {
+    let method = request.uri().path().to_string();
+    debug!(%method, "gRPC call");
+    let reply = match read_request(request.into_body()).await {
+        Ok(message) => match method.as_str() {
+            "/aprsserver.Aprs/Subscribe" => return subscribe(hub, message, respond).await,
+            "/aprsserver.Aprs/GetStats" => Ok(stats_message(&hub)),
+            "/aprsserver.Aprs/ListClients" => Ok(clients_message(&hub)),
+            _ => Err((Code::Unimplemented, format!("unknown method {}", method))),
+        },
+        Err(e) => Err(e),
+    };
+    match reply {
+        Ok(message) => {
+            let mut stream = respond.send_response(response(None), false)?;
+            stream.send_data(frame(&message), false)?;
+            stream.send_trailers(status_headers(Code::Ok, ""))
+        }
+        Err((code, message)) => respond.send_response(response(Some(status_headers(code, &message))), true).map(|_| ()),
+    }
+}
+
+async fn serve_connection(hub: Arc<Hub>, socket: tokio::net::TcpStream) -> Result<(), h2::Error> {
+    let mut conn = h2::server::handshake(socket).await?;
+    while let Some(request) = conn.accept().await {
+        let (request, respond) = request?;
+        let hub = hub.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(hub, request, respond).await {
+                debug!(error = %e, "gRPC call ended");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Accepts gRPC connections until the listener fails.
+pub async fn serve(listener: TcpListener, hub: Arc<Hub>) {
+    info!(port = listener.local_addr().map(|a| a.port()).unwrap_or(0), "gRPC service started");
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(error = %e, "gRPC accept failed");
+                continue;
+            }
+        };
+        let hub = hub.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(hub, socket).await {
+                debug!(%addr, error = %e, "gRPC connection closed");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::MessageDyn;
+    use protobuf::reflect::{FileDescriptor, MessageDescriptor, ReflectValueBox, ReflectValueRef};
+
+    /// Calls `method` with `message` and returns the response messages and
+    /// the `grpc-status`.
+    async fn call(addr: std::net::SocketAddr, method: &str, message: &[u8], limit: usize) -> (Vec<Bytes>, Option<String>) {
+        let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (client, conn) = h2::client::handshake(socket).await.unwrap();
+        tokio::spawn(conn);
+        let mut client = client.ready().await.unwrap();
+        let request = Request::post(format!("http://localhost{}", method)).header("content-type", "application/grpc").body(()).unwrap();
+        let (response, mut stream) = client.send_request(request, false).unwrap();
+        stream.send_data(frame(message), true).unwrap();
+        let response = response.await.unwrap();
+        let status = |headers: &HeaderMap| headers.get("grpc-status").map(|v| v.to_str().unwrap().to_string());
+        if let Some(code) = status(response.headers()) {
+            return (Vec::new(), Some(code));
+        }
+        let mut body = response.into_body();
+        let mut messages = Vec::new();
+        let mut buf = Vec::new();
+        while messages.len() < limit
+            && let Some(chunk) = body.data().await
+        {
+            buf.extend_from_slice(&chunk.unwrap());
+            while buf.len() >= 5 && buf.len() >= 5 + u32::from_be_bytes(buf[1..5].try_into().unwrap()) as usize {
+                let len = u32::from_be_bytes(buf[1..5].try_into().unwrap()) as usize;
+                messages.push(Bytes::copy_from_slice(&buf[5..5 + len]));
+                buf.drain(..5 + len);
+            }
+        }
+        if messages.len() >= limit {
+            return (messages, None);
+        }
+        let trailers = body.trailers().await.unwrap().unwrap();
+        (messages, status(&trailers))
+    }
+
+    #[test]
+    fn test_wire() {
+        let mut buf = Vec::new();
+        wire::uint(&mut buf, 2, 300);
+        wire::string(&mut buf, 1, "p/N0");
+        wire::uint(&mut buf, 3, 0);
+        assert_eq!(buf, [0x10, 0xac, 0x02, 0x0a, 4, b'p', b'/', b'N', b'0']);
+        assert_eq!(wire::fields(&buf).unwrap(), [(1, &b"p/N0"[..])]);
+        assert!(wire::fields(&buf[..4]).is_err());
+        assert_eq!(subscribe_filter(&buf).unwrap(), "p/N0");
+        assert_eq!(subscribe_filter(&[]).unwrap(), "");
+    }
+
+    /// The schema of `name` in proto/aprsserver.proto, to check the hand
+    /// encoding against.
+    fn schema(name: &str) -> MessageDescriptor {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/proto");
+        let parsed = protobuf_parse::Parser::new()
+            .pure()
+            .include(dir)
+            .input(format!("{}/aprsserver.proto", dir))
+            .parse_and_typecheck()
+            .unwrap();
+        let files = FileDescriptor::new_dynamic_fds(parsed.file_descriptors, &[]).unwrap();
+        files[0].message_by_package_relative_name(name).unwrap()
+    }
+    fn get<'a>(message: &'a dyn MessageDyn, field: &str) -> ReflectValueRef<'a> {
+        message.descriptor_dyn().field_by_name(field).unwrap().get_singular_field_or_default(message)
+    }
+
+    #[test]
+    fn test_schema() {
+        let hub = Hub::new();
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        let conn = crate::client::ClientConn::WebSocket { tx: Some(tx), write_timeout: std::time::Duration::from_secs(1) };
+        let mut client = crate::client::Client::with_conn(0, conn, "127.0.0.1:1".parse().ok());
+        client.callsign = Some("N0CALL".to_string());
+        client.filter_string = Some("r/49/-72/100".to_string());
+        client.packets_rx = 300;
+        let id = hub.add_client(client);
+
+        let line = "N1CALL>APRS,TCPIP*:>hello";
+        let feed = FeedPacket { received: 1_700_000_000, line: line.to_string(), packet: Arc::new(crate::parser::parse(line).unwrap()) };
+        let packet = schema("Packet").parse_from_bytes(&packet_message(&feed, 3)).unwrap();
+        assert_eq!(get(&*packet, "received").to_u64(), Some(1_700_000_000));
+        assert_eq!(get(&*packet, "line").to_str(), Some(line));
+        assert_eq!(get(&*packet, "source").to_str(), Some("N1CALL"));
+        assert_eq!(get(&*packet, "type").to_str(), Some("status"));
+        assert_eq!(get(&*packet, "skipped").to_u64(), Some(3));
+
+        hub.counters.add_rx(100);
+        let stats = schema("Stats").parse_from_bytes(&stats_message(&hub)).unwrap();
+        assert_eq!(get(&*stats, "clients").to_u64(), Some(1));
+        assert_eq!(get(&*stats, "packets_rx").to_u64(), Some(1));
+        assert_eq!(get(&*stats, "bytes_rx").to_u64(), Some(100));
+
+        let list = schema("ClientList").parse_from_bytes(&clients_message(&hub)).unwrap();
+        let clients = list.descriptor_dyn().field_by_name("clients").unwrap().get_repeated(&*list);
+        assert_eq!(clients.len(), 1);
+        let client = clients.get(0).to_message().unwrap();
+        assert_eq!(get(&*client, "id").to_u64(), Some(id as u64));
+        assert_eq!(get(&*client, "callsign").to_str(), Some("N0CALL"));
+        assert_eq!(get(&*client, "addr").to_str(), Some("ws:127.0.0.1:1"));
+        assert_eq!(get(&*client, "filter").to_str(), Some("r/49/-72/100"));
+        assert_eq!(get(&*client, "packets_rx").to_u64(), Some(300));
+
+        // And requests encoded from the schema decode here
+        let descriptor = schema("SubscribeRequest");
+        let mut request = descriptor.new_instance();
+        descriptor.field_by_name("filter").unwrap().set_singular_field(&mut *request, ReflectValueBox::String("p/N0".to_string()));
+        assert_eq!(subscribe_filter(&request.write_to_bytes_dyn().unwrap()).unwrap(), "p/N0");
+    }
+
+    #[tokio::test]
+    async fn test_service() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hub = Arc::new(Hub::new());
+        tokio::spawn(serve(listener, hub.clone()));
+
+        let (messages, code) = call(addr, "/aprsserver.Aprs/GetStats", &[], 10).await;
+        assert_eq!(code.as_deref(), Some("0"));
+        assert_eq!(messages.len(), 1);
+        let (_, code) = call(addr, "/aprsserver.Aprs/Nope", &[], 10).await;
+        assert_eq!(code.as_deref(), Some("12"));
+
+        let mut request = Vec::new();
+        wire::string(&mut request, 1, "x/");
+        let (_, code) = call(addr, "/aprsserver.Aprs/Subscribe", &request, 10).await;
+        assert_eq!(code.as_deref(), Some("3"));
+
+        let mut request = Vec::new();
+        wire::string(&mut request, 1, "p/N1");
+        let subscription = tokio::spawn(async move { call(addr, "/aprsserver.Aprs/Subscribe", &request, 1).await });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        crate::pipeline::ingest(&hub, crate::pipeline::Source::Uplink, "N0CALL>APRS,TCPIP*:>skipped").unwrap();
+        crate::pipeline::ingest(&hub, crate::pipeline::Source::Uplink, "N1CALL>APRS,TCPIP*:>streamed").unwrap();
+        let (messages, _) = tokio::time::timeout(std::time::Duration::from_secs(2), subscription).await.unwrap().unwrap();
+        let fields = wire::fields(&messages[0]).unwrap();
+        let line = format!("N1CALL>APRS,TCPIP*,qAS,{}:>streamed", hub.server_name);
+        assert_eq!(fields[0], (2, line.as_bytes()));
+        assert_eq!(fields[1], (3, &b"N1CALL"[..]));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::parser::ParsedPacket;
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookAction {
+    Continue,
+    Drop,
+    Replace(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropReason {
+    Duplicate,
+    Invalid,
+    Filtered,
+    Hook,
+    /// The packet already passed through this server
+    Loop,
+    /// Not allowed by the client's class
+    Policy,
+    /// Failed the position sanity checks
+    BadPosition,
+    /// `TCPXX` in the path: from an unverified connection
+    Tcpxx,
+    /// `NOGATE` in the path
+    NoGate,
+    /// `RFONLY` in the path
+    RfOnly,
+}
+
+/// Extension point on the packet path. Hooks run on the connection threads,
+/// sometimes with a client locked, so they must not block.
+pub trait PacketHook: Send + Sync {
+    /// Called for every received line before dedupe and parsing. Can drop or rewrite it.
+    fn on_ingress(&self, _line: &str) -> HookAction {
+        HookAction::Continue
+    }
+    /// Called once a packet has been parsed and accepted for distribution.
+    fn on_accept(&self, _line: &str, _packet: &ParsedPacket) {}
+    /// Called when a packet is discarded.
+    fn on_drop(&self, _line: &str, _reason: DropReason) {}
+    /// Called for each client a packet is written to.
+    fn on_egress(&self, _client_id: usize, _line: &str) {}
+}
+
+/// Logs every packet decision.
+pub struct LogHook;
+
+impl PacketHook for LogHook {
+    fn on_accept(&self, _line: &str, packet: &ParsedPacket) {
+        info!(source = %packet.source, destination = %packet.destination, "hook: accept");
+    }
+    fn on_drop(&self, line: &str, reason: DropReason) {
+        info!(?reason, %line, "hook: drop");
+    }
+}
+
+/// Looks up a builtin hook by the name used in the `hooks` config list.
+pub fn builtin(name: &str) -> Option<Arc<dyn PacketHook>> {
+    match name {
+        "log" => Some(Arc::new(LogHook)),
+        _ => None,
+    }
+}
@@ -1,30 +1,342 @@
+use crate::archive::ArchiveStatus;
+use crate::bans::Bans;
 use crate::client::Client;
-use std::collections::{HashMap, HashSet, VecDeque};
+use crate::clock::{Clock, SystemClock};
+use crate::client_class::{ClientClass, TxPriority};
+use crate::auth_callout::AuthCallout;
+use crate::login_limit::LoginLimiter;
+use crate::remote_admin::RemoteAdmin;
+use crate::config::{DupeConfig, InvalidLinesConfig, PasscodePolicy, PositionChecksConfig, ServerConfig, StationCacheConfig};
+use crate::position_check::{self, BadPosition, RejectedPositions};
+use crate::filter::{ClientFilter, FilterContext, FilterSet};
+use crate::geoip::GeoIp;
+use crate::hook::{DropReason, HookAction, PacketHook};
+use serde::{Deserialize, Serialize};
+use crate::message::MessageKind;
+use crate::message_tracker::MessageTracker;
+use crate::mqtt::MqttStatus;
+use crate::nws::ZoneTable;
+use crate::rf::RfStatus;
+use crate::rf_heard::RfHeard;
+use crate::tx_igate::TxIgate;
+use crate::latency::{Latency, Outbound};
+use crate::links::Links;
+use crate::process::ProcessSampler;
+use crate::routing::RouteRule;
+use crate::station_cache::{CacheStats, evict_oldest, expire};
+use crate::uplink::UplinkRoute;
+use crate::watch::Watch;
+use crate::packet_log::{LogStream, PacketLog};
+use crate::parser::{PacketData, ParsedPacket};
+use crate::pipeline::Origin;
+use crate::position_cache::PositionCache;
+use crate::redis_sink::RedisStatus;
+use crate::stats::{Counters, DupeStats, ListenerStats, Protocol, SoftwareStats, SourceStats, StatsHistory, TrafficRates};
+use crate::tocall::Tocalls;
+use crate::telemetry::{self, StationTelemetry};
+use crate::weather::WeatherObservation;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::io::Write;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::UnboundedSender;
 
 pub struct S2SPeerHandle {
     pub peer_name: Option<String>,
-    pub sender: UnboundedSender<String>,
+    pub sender: UnboundedSender<Outbound>,
+    /// Only packets matching one of these are sent to the peer
+    pub filters: Option<Vec<ClientFilter>>,
 }
 
+/// Station state built from accepted packets.
+#[derive(Debug, Default)]
+pub struct Stations {
+    pub weather: HashMap<String, WeatherObservation>,
+    pub telemetry: HashMap<String, StationTelemetry>,
+    pub objects: HashMap<String, ObjectEntry>,
+    pub heard: HashMap<String, HeardStation>,
+    /// Accepted traffic per originating callsign
+    pub source_stats: HashMap<String, SourceStats>,
+    /// Accepted traffic per originating software
+    pub software_stats: HashMap<String, SoftwareStats>,
+    /// Positions dropped by the sanity checks, per sending callsign
+    pub rejected_positions: HashMap<String, RejectedPositions>,
+    /// Entries dropped by the `[station_cache]` limits
+    pub evicted: u64,
+    pub expired: u64,
+}
+
+fn telemetry_updated(t: &StationTelemetry) -> u64 {
+    t.history.back().map_or(0, |s| s.received)
+}
+
+impl Stations {
+    /// Holds each map to `limit` entries, evicting the least recently
+    /// updated.
+    fn bound(&mut self, limit: usize) {
+        let evicted = evict_oldest(&mut self.heard, limit, |h| h.last_heard)
+            + evict_oldest(&mut self.weather, limit, |w| w.received)
+            + evict_oldest(&mut self.telemetry, limit, telemetry_updated)
+            + evict_oldest(&mut self.objects, limit, |o| o.updated)
+            + evict_oldest(&mut self.source_stats, limit, |s| s.last_heard)
+            + evict_oldest(&mut self.rejected_positions, limit, |r| r.last_rejected);
+        self.evicted += evicted as u64;
+    }
+    pub fn cache_stats(&self, positions: usize, max_entries: Option<usize>) -> CacheStats {
+        CacheStats {
+            positions,
+            heard: self.heard.len(),
+            weather: self.weather.len(),
+            telemetry: self.telemetry.len(),
+            objects: self.objects.len(),
+            talkers: self.source_stats.len(),
+            max_entries,
+            evicted: self.evicted,
+            expired: self.expired,
+        }
+    }
+}
+
+type MessageKey = (String, String, String);
+
+/// Recently seen packets and message ids, for duplicate detection.
+#[derive(Debug, Default)]
+struct DupeCache {
+    hashes: HashSet<u64>,
+    order: VecDeque<(u64, Instant)>,
+    /// Source, addressee and id of recent messages, oldest first in
+    /// `message_order`
+    message_ids: HashSet<MessageKey>,
+    message_order: VecDeque<(MessageKey, Instant)>,
+    lookups: u64,
+    hits: u64,
+    /// Duplicates per source callsign
+    sources: HashMap<String, u64>,
+}
+
+impl DupeCache {
+    fn hit(&mut self, source: &str) {
+        self.hits += 1;
+        *self.sources.entry(source.to_uppercase()).or_default() += 1;
+        // Only the worst offenders matter; keep the map from growing forever
+        if self.sources.len() > MAX_DUPE_SOURCES {
+            let mut counts: Vec<u64> = self.sources.values().copied().collect();
+            counts.sort_unstable_by(|a, b| b.cmp(a));
+            let cutoff = counts[MAX_DUPE_SOURCES / 4];
+            self.sources.retain(|_, count| *count > cutoff);
+        }
+    }
+}
+
+/// Shared server state. Each part has its own lock, so packets from
+/// different connections, the stats tasks and the web UI only contend where
+/// they touch the same data. Locks are only ever taken in this order:
+/// `clients`, a client, its stream, `s2s_peer_handles`, `dupes`, `history`,
+/// `stations`, a `positions` shard, `messages`. `broadcast_packet` reads a
+/// `positions` shard for filters while holding a client, but hands lines to
+/// the client's writer queue, so no `positions` shard is ever held together
+/// with a stream. The plain fields are configuration, set before the hub is
+/// shared.
 pub struct Hub {
-    pub clients: HashMap<usize, Arc<Mutex<Client>>>,
+    pub clients: RwLock<HashMap<usize, Arc<Mutex<Client>>>>,
     pub start_time: Instant,
-    pub next_id: usize,
-    pub total_packets_rx: u64,
-    pub total_packets_tx: u64,
-    pub total_bytes_rx: u64,
-    pub total_bytes_tx: u64,
-    pub s2s_peers: Vec<Arc<Mutex<S2SPeerStatus>>>,
-    pub s2s_peer_handles: Vec<S2SPeerHandle>,
-    pub dupe_cache: HashSet<u64>,
-    pub dupe_order: VecDeque<u64>,
+    /// Time for dupe windows, rate limits and cache ages; see `clock`
+    pub clock: Arc<dyn Clock>,
+    /// Unix time the counters started at, earlier than the start if they
+    /// were restored from the state file
+    pub stats_since: u64,
+    next_id: AtomicUsize,
+    pub s2s_peers: RwLock<Vec<Arc<Mutex<S2SPeerStatus>>>>,
+    pub s2s_peer_handles: RwLock<Vec<S2SPeerHandle>>,
+    /// Duplicate caches, one per ingest shard, chosen by source callsign
+    dupes: Vec<Mutex<DupeCache>>,
+    pub stations: RwLock<Stations>,
+    /// Latest station positions, sharded like `dupes`
+    pub positions: PositionCache,
+    /// `nws_zones_file`, for locating NWS bulletins in area filters
+    pub nws_zones: Option<ZoneTable>,
+    pub messages: Mutex<MessageTracker>,
+    /// Stations heard on RF, empty without an `[rf]` interface
+    pub rf_heard: Mutex<RfHeard>,
+    pub hooks: Vec<Arc<dyn PacketHook>>,
+    pub mqtt_status: Option<Arc<Mutex<MqttStatus>>>,
+    pub archive_status: Option<Arc<Mutex<ArchiveStatus>>>,
+    pub rf_status: Option<Arc<Mutex<RfStatus>>>,
+    /// Gating to RF, with `[rf.tx]` configured
+    pub tx_igate: Option<Arc<TxIgate>>,
+    pub redis_status: Option<Arc<Mutex<RedisStatus>>>,
+    /// Accepted packets for in-process subscribers such as the web packet console
+    pub packet_feed: broadcast::Sender<Arc<FeedPacket>>,
+    pub bans: RwLock<Bans>,
+    /// Held while a ban change is saved, so changes do not overwrite each other
+    pub ban_edits: Mutex<()>,
+    pub counters: Counters,
+    pub stats_history: Mutex<StatsHistory>,
+    /// Server-wide rate averages
+    pub rates: Mutex<TrafficRates>,
+    /// Memory, CPU and task figures, sampled with the rates
+    pub process: Mutex<ProcessSampler>,
+    /// Time from ingress until packets are written out, per egress
+    pub latency: Latency,
+    /// Callsigns whose packets have every pipeline decision reported
+    pub watch: Watch,
+    pub packet_log: Option<PacketLog>,
+    /// Recently accepted packets, replayed to clients when they set a filter
+    pub history: Mutex<VecDeque<Arc<FeedPacket>>>,
+    /// How long packets are kept in `history`; 0 disables it
+    pub history_secs: u64,
+    /// Server identifier, used to detect packets looping back
+    pub server_name: String,
+    /// `[server]` section with the ID resolved, shown on the status pages
+    pub server: ServerConfig,
+    /// Server IDs of the configured S2S peers, uppercased
+    pub peer_ids: RwLock<HashSet<String>>,
+    pub s2s_timeout_secs: u64,
+    /// Limit on a single write to a TCP client, peer or uplink
+    pub write_timeout: Duration,
+    pub geoip: Option<GeoIp>,
+    /// Callsign the server sends its own packets as and answers queries to
+    pub callsign: String,
+    /// Information field of the server beacon, the answer to `?APRSP`
+    pub position: Option<String>,
+    /// `[uplink]` and the `[[uplinks]]` networks that are running
+    pub uplinks: RwLock<Vec<UplinkRoute>>,
+    /// The uplink and peer connection tasks, see `links`
+    pub links: Mutex<Links>,
+    pub remote_admin: Option<RemoteAdmin>,
+    pub invalid_lines: Option<InvalidLinesConfig>,
+    /// Blocks addresses with repeated failed logins, if configured
+    pub login_limit: Option<LoginLimiter>,
+    pub passcode_policy: PasscodePolicy,
+    /// Decides on logins instead of the passcode, if configured
+    pub auth_callout: Option<AuthCallout>,
+    pub position_checks: Option<PositionChecksConfig>,
+    pub dupe_config: DupeConfig,
+    pub station_cache: StationCacheConfig,
+    pub client_classes: Vec<Arc<ClientClass>>,
+    /// `[[route]]` rules, checked in order
+    pub routes: Vec<RouteRule>,
+    /// Filters always applied to the feed of clients on a listening port,
+    /// with the filter string, by port
+    pub listener_filters: HashMap<u16, (String, Arc<FilterSet>)>,
+    /// `[client_defaults]` filters by callsign pattern
+    pub client_defaults: BTreeMap<String, String>,
+    /// Traffic per listening port
+    pub listeners: Vec<Arc<ListenerStats>>,
+    pub tocalls: Tocalls,
+    /// Queues of the ingest shard workers, empty when packets are ingested
+    /// on the connection's own task
+    pub ingest_shards: Vec<std::sync::mpsc::SyncSender<(Origin, String)>>,
+    /// Packets dropped because their ingest shard's queue was full
+    pub ingest_dropped: AtomicU64,
+}
+
+/// An accepted packet as delivered on `Hub::packet_feed`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedPacket {
+    pub received: u64,
+    pub line: String,
+    pub packet: Arc<ParsedPacket>,
 }
 
-const DUPE_CACHE_SIZE: usize = 1000;
+/// A station heard recently, for `/heard.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeardStation {
+    pub callsign: String,
+    pub last_heard: u64,
+    pub packets: u64,
+    /// Where its last packet entered the server, e.g. `client:N0CALL`, `uplink` or `s2s:PEER`
+    pub entry: String,
+}
+
+/// Last known position of a station, used by the `m/` and `f/` filters.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StationPosition {
+    pub callsign: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub symbol: String,
+    pub comment: String,
+    pub destination: String,
+    pub updated: u64,
+}
+
+impl StationPosition {
+    /// The station's own position from a position or Mic-E packet, heard at `now`.
+    pub fn from_packet(packet: &ParsedPacket, now: u64) -> Option<Self> {
+        let position = match &packet.data {
+            PacketData::Position(p) | PacketData::MicE(p) => p,
+            _ => return None,
+        };
+        Some(StationPosition {
+            callsign: packet.source.to_uppercase(),
+            lat: position.lat,
+            lon: position.lon,
+            symbol: format!("{}{}", position.symbol_table, position.symbol_code),
+            comment: position.comment.clone(),
+            destination: packet.destination.clone(),
+            updated: now,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjectEntry {
+    pub name: String,
+    pub item: bool,
+    pub owner: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub symbol: String,
+    pub comment: String,
+    pub updated: u64,
+}
+
+/// Shard of a source callsign, so all packets from one station go to the
+/// same ingest worker and duplicate cache.
+pub fn source_shard(callsign: &str, shards: usize) -> usize {
+    if shards <= 1 {
+        return 0;
+    }
+    (seahash::hash(callsign.to_uppercase().as_bytes()) % shards as u64) as usize
+}
+
+// Sources with duplicate counts kept per duplicate cache shard
+const MAX_DUPE_SOURCES: usize = 4096;
+// Feed subscribers that fall further behind than this skip packets
+const PACKET_FEED_SIZE: usize = 1000;
+// Upper bound on the history buffer regardless of its time span
+const HISTORY_MAX_PACKETS: usize = 100_000;
+// Stations not heard for this long are forgotten
+pub const HEARD_MAX_AGE: u64 = 24 * 3600;
+// S2S peers silent for this long are disconnected
+pub const DEFAULT_S2S_TIMEOUT_SECS: u64 = 180;
+// Clients, peers and uplinks not taking a write for this long are disconnected
+pub const DEFAULT_WRITE_TIMEOUT_SECS: u64 = 10;
+
+/// Where an S2S peer link is in its connection cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum PeerState {
+    #[default]
+    Connecting,
+    Authenticating,
+    Established,
+    /// Waiting before the next connection attempt
+    Backoff,
+}
+
+impl PeerState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PeerState::Connecting => "connecting",
+            PeerState::Authenticating => "authenticating",
+            PeerState::Established => "established",
+            PeerState::Backoff => "backoff",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct S2SPeerStatus {
@@ -32,6 +344,12 @@ pub struct S2SPeerStatus {
     pub port: u16,
     pub peer_name: Option<String>,
     pub connected: bool,
+    pub state: PeerState,
+    /// Failed attempts since the link was last established
+    pub consecutive_failures: u32,
+    /// Set once the failures reach the alarm threshold
+    pub alarm: bool,
+    pub next_attempt: Option<std::time::SystemTime>,
     pub last_connect: Option<std::time::SystemTime>,
     pub packets_rx: u64,
     pub packets_tx: u64,
@@ -43,6 +361,7 @@ pub struct S2SPeerStatus {
     pub last_error: Option<String>,
     pub last_rx_time: Option<std::time::SystemTime>,
     pub last_tx_time: Option<std::time::SystemTime>,
+    pub rates: TrafficRates,
 }
 
 impl S2SPeerStatus {
@@ -52,6 +371,10 @@ impl S2SPeerStatus {
             port,
             peer_name,
             connected: false,
+            state: PeerState::Connecting,
+            consecutive_failures: 0,
+            alarm: false,
+            next_attempt: None,
             last_connect: None,
             packets_rx: 0,
             packets_tx: 0,
@@ -63,129 +386,734 @@ impl S2SPeerStatus {
             last_error: None,
             last_rx_time: None,
             last_tx_time: None,
+            rates: TrafficRates::default(),
+        }
+    }
+    /// State for status pages, with the failure count while alarmed.
+    pub fn state_summary(&self) -> String {
+        if self.alarm {
+            format!("{} ({} failures)", self.state.name(), self.consecutive_failures)
+        } else {
+            self.state.name().to_string()
         }
     }
 }
 
+impl Default for Hub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Hub {
     pub fn new() -> Self {
         Self {
-            clients: HashMap::new(),
+            clients: RwLock::new(HashMap::new()),
             start_time: Instant::now(),
-            next_id: 1,
-            total_packets_rx: 0,
-            total_packets_tx: 0,
-            total_bytes_rx: 0,
-            total_bytes_tx: 0,
-            s2s_peers: Vec::new(),
-            s2s_peer_handles: Vec::new(),
-            dupe_cache: HashSet::new(),
-            dupe_order: VecDeque::new(),
-        }
-    }
-    pub fn add_client(&mut self, client: Client) -> usize {
-        let id = self.next_id;
-        self.next_id += 1;
-        self.clients.insert(id, Arc::new(Mutex::new(client)));
+            clock: Arc::new(SystemClock),
+            stats_since: unix_time(),
+            next_id: AtomicUsize::new(1),
+            s2s_peers: RwLock::new(Vec::new()),
+            s2s_peer_handles: RwLock::new(Vec::new()),
+            dupes: vec![Mutex::new(DupeCache::default())],
+            stations: RwLock::new(Stations::default()),
+            positions: PositionCache::default(),
+            nws_zones: None,
+            messages: Mutex::new(MessageTracker::default()),
+            rf_heard: Mutex::new(RfHeard::default()),
+            hooks: Vec::new(),
+            mqtt_status: None,
+            archive_status: None,
+            rf_status: None,
+            tx_igate: None,
+            redis_status: None,
+            packet_feed: broadcast::channel(PACKET_FEED_SIZE).0,
+            bans: RwLock::new(Bans::default()),
+            ban_edits: Mutex::new(()),
+            counters: Counters::default(),
+            stats_history: Mutex::new(StatsHistory::default()),
+            rates: Mutex::new(TrafficRates::default()),
+            process: Mutex::new(ProcessSampler::default()),
+            latency: Latency::default(),
+            watch: Watch::default(),
+            packet_log: None,
+            history: Mutex::new(VecDeque::new()),
+            history_secs: 0,
+            server_name: crate::config::PLACEHOLDER_SERVER_ID.to_string(),
+            server: ServerConfig::default(),
+            peer_ids: RwLock::new(HashSet::new()),
+            s2s_timeout_secs: DEFAULT_S2S_TIMEOUT_SECS,
+            write_timeout: Duration::from_secs(DEFAULT_WRITE_TIMEOUT_SECS),
+            geoip: None,
+            callsign: crate::config::PLACEHOLDER_SERVER_ID.to_string(),
+            position: None,
+            uplinks: RwLock::new(Vec::new()),
+            links: Mutex::new(Links::default()),
+            remote_admin: None,
+            invalid_lines: None,
+            login_limit: None,
+            passcode_policy: PasscodePolicy::Strict,
+            auth_callout: None,
+            position_checks: None,
+            dupe_config: DupeConfig::default(),
+            station_cache: StationCacheConfig::default(),
+            client_classes: Vec::new(),
+            routes: Vec::new(),
+            listener_filters: HashMap::new(),
+            listeners: Vec::new(),
+            client_defaults: BTreeMap::new(),
+            tocalls: Tocalls::default(),
+            ingest_shards: Vec::new(),
+            ingest_dropped: AtomicU64::new(0),
+        }
+    }
+    /// Registers a client under a new id, which is returned.
+    pub fn add_client(&self, mut client: Client) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        client._id = id;
+        client.last_activity = self.clock.unix_time();
+        if let Some(geoip) = &self.geoip
+            && let Some(peer) = client.peer
+        {
+            client.location = geoip.lookup(peer.ip());
+        }
+        let _ = client.tx.latency.set(self.latency.clients.clone());
+        self.clients.write().unwrap().insert(id, Arc::new(Mutex::new(client)));
         id
     }
-    pub fn remove_client(&mut self, id: usize) {
-        self.clients.remove(&id);
+    /// The listener bound to `port`.
+    pub fn listener(&self, protocol: Protocol, port: u16) -> Option<&Arc<ListenerStats>> {
+        self.listeners.iter().find(|l| l.protocol == protocol && l.port == port)
+    }
+    pub fn remove_client(&self, id: usize) {
+        self.clients.write().unwrap().remove(&id);
+    }
+    pub fn client(&self, id: usize) -> Option<Arc<Mutex<Client>>> {
+        self.clients.read().unwrap().get(&id).cloned()
     }
     pub fn update_client(
-        &mut self,
+        &self,
         id: usize,
         callsign: Option<String>,
-        filter: Option<Vec<crate::filter::ClientFilter>>,
+        filter: Option<Arc<FilterSet>>,
     ) {
-        if let Some(client) = self.clients.get(&id) {
+        if let Some(client) = self.client(id) {
             let mut c = client.lock().unwrap();
             c.callsign = callsign;
             c.filter = filter;
         }
     }
+    /// Sends a comment line to a client and closes its connection. The
+    /// client's handler removes it once the read fails.
+    pub fn kick_client(&self, id: usize, reason: &str) -> bool {
+        let Some(client) = self.client(id) else {
+            return false;
+        };
+        let c = client.lock().unwrap();
+        if let Ok(mut stream) = c.stream.lock() {
+            let _ = stream.write_all(format!("# {}\r\n", reason).as_bytes());
+            stream.shutdown();
+        }
+        true
+    }
+    /// Adds a sample to the statistics history; called once per `STATS_INTERVAL_SECS`.
+    pub fn sample_stats(&self) {
+        let clients = self.client_count();
+        self.stats_history.lock().unwrap().record(self.clock.unix_time(), &self.counters, clients);
+    }
+    pub fn record_heard(&self, source: &str, entry: &str) {
+        let callsign = source.to_uppercase();
+        let mut stations = self.stations.write().unwrap();
+        let station = stations.heard.entry(callsign.clone()).or_insert_with(|| HeardStation {
+            callsign,
+            last_heard: 0,
+            packets: 0,
+            entry: String::new(),
+        });
+        station.last_heard = self.clock.unix_time();
+        station.packets += 1;
+        station.entry = entry.to_string();
+        if let Some(limit) = self.station_cache.max_entries {
+            stations.bound(limit);
+        }
+    }
+    /// Forgets stations not heard for a day, or `max_age_secs` if set.
+    pub fn prune_heard(&self) {
+        let now = self.clock.unix_time();
+        let cutoff = now.saturating_sub(self.station_cache.max_age_secs.unwrap_or(HEARD_MAX_AGE));
+        let mut stations = self.stations.write().unwrap();
+        let mut expired = expire(&mut stations.heard, cutoff, |h| h.last_heard)
+            + expire(&mut stations.source_stats, cutoff, |s| s.last_heard)
+            + expire(&mut stations.rejected_positions, cutoff, |r| r.last_rejected);
+        if self.station_cache.max_age_secs.is_some() {
+            expired += self.positions.expire(cutoff)
+                + expire(&mut stations.weather, cutoff, |w| w.received)
+                + expire(&mut stations.telemetry, cutoff, telemetry_updated)
+                + expire(&mut stations.objects, cutoff, |o| o.updated);
+        }
+        stations.expired += expired as u64;
+        drop(stations);
+        let cutoff = now.saturating_sub(HEARD_MAX_AGE);
+        self.rf_heard.lock().unwrap().prune(cutoff);
+    }
+    /// Occupancy of the station maps and the position cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.stations.read().unwrap().cache_stats(self.positions.len(), self.station_cache.max_entries)
+    }
+    /// Traffic by originating software, busiest first.
+    pub fn software_breakdown(&self) -> Vec<SoftwareStats> {
+        let stations = self.stations.read().unwrap();
+        let mut software: Vec<_> = stations.software_stats.values().cloned().collect();
+        software.sort_by(|a, b| b.packets.cmp(&a.packets).then_with(|| a.software.cmp(&b.software)));
+        software
+    }
+    /// Callsigns that originated the most packets, busiest first.
+    pub fn top_talkers(&self, count: usize) -> Vec<SourceStats> {
+        let stations = self.stations.read().unwrap();
+        let mut talkers: Vec<_> = stations.source_stats.values().collect();
+        talkers.sort_by(|a, b| b.packets.cmp(&a.packets).then_with(|| a.callsign.cmp(&b.callsign)));
+        talkers.into_iter().take(count).cloned().collect()
+    }
+    /// Writes a packet to the raw packet log, if the stream is logged.
+    pub fn log_packet(&self, stream: LogStream, line: &str) {
+        if let Some(log) = &self.packet_log {
+            log.log(stream, line);
+        }
+    }
+    /// Updates the rolling rate averages of the server, clients and S2S peers;
+    /// called once per `RATE_INTERVAL_SECS`.
+    pub fn update_rates(&self) {
+        let c = &self.counters;
+        self.rates.lock().unwrap().update(
+            c.packets_rx.load(Ordering::Relaxed),
+            c.bytes_rx.load(Ordering::Relaxed),
+            c.packets_tx.load(Ordering::Relaxed),
+            c.bytes_tx.load(Ordering::Relaxed),
+        );
+        for client in self.clients.read().unwrap().values() {
+            let mut c = client.lock().unwrap();
+            let (prx, brx, ptx, btx) = (c.packets_rx, c.bytes_rx, c.packets_tx(), c.bytes_tx());
+            c.rates.update(prx, brx, ptx, btx);
+        }
+        for peer in self.s2s_peers.read().unwrap().iter() {
+            let mut p = peer.lock().unwrap();
+            let (prx, brx, ptx, btx) = (p.packets_rx, p.bytes_rx, p.packets_tx, p.bytes_tx);
+            p.rates.update(prx, brx, ptx, btx);
+        }
+    }
+    /// Kicks connected clients whose callsign or address is banned.
+    pub fn kick_banned(&self) {
+        let banned: Vec<usize> = {
+            let bans = self.bans.read().unwrap();
+            self.clients
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|(_, client)| {
+                    let c = client.lock().unwrap();
+                    c.callsign.as_deref().is_some_and(|call| bans.is_callsign_banned(call))
+                        || c.peer.is_some_and(|a| bans.is_ip_banned(a.ip()))
+                })
+                .map(|(id, _)| *id)
+                .collect()
+        };
+        for id in banned {
+            self.kick_client(id, "Banned by server operator");
+        }
+    }
     pub fn client_count(&self) -> usize {
-        self.clients.len()
+        self.clients.read().unwrap().len()
     }
     pub fn uptime(&self) -> u64 {
         self.start_time.elapsed().as_secs()
     }
-    pub fn update_totals(&mut self) {
-        self.total_packets_rx = 0;
-        self.total_packets_tx = 0;
-        self.total_bytes_rx = 0;
-        self.total_bytes_tx = 0;
-        for client in self.clients.values() {
+    /// Packet and byte totals over the connected clients, as
+    /// `(packets_rx, packets_tx, bytes_rx, bytes_tx)`.
+    pub fn totals(&self) -> (u64, u64, u64, u64) {
+        let mut totals = (0, 0, 0, 0);
+        for client in self.clients.read().unwrap().values() {
             let c = client.lock().unwrap();
-            self.total_packets_rx += c.packets_rx;
-            self.total_packets_tx += c.packets_tx;
-            self.total_bytes_rx += c.bytes_rx;
-            self.total_bytes_tx += c.bytes_tx;
-        }
-    }
-    pub fn get_totals(&self) -> (u64, u64, u64, u64) {
-        (
-            self.total_packets_rx,
-            self.total_packets_tx,
-            self.total_bytes_rx,
-            self.total_bytes_tx,
-        )
-    }
-    pub fn broadcast_packet(&self, sender_id: usize, packet: &str) {
-        for (id, client) in &self.clients {
-            if *id != sender_id {
-                let c = client.lock().unwrap();
-                if let Ok(mut stream) = c.stream.lock() {
-                    let _ = stream.write_all(packet.as_bytes());
+            totals.0 += c.packets_rx;
+            totals.1 += c.packets_tx();
+            totals.2 += c.bytes_rx;
+            totals.3 += c.bytes_tx();
+        }
+        totals
+    }
+    /// Whether the `[[route]]` rules let a packet from `from` go to the
+    /// destination `to` names; `to` is only built when there are rules.
+    pub fn routed(&self, from: &str, to: impl FnOnce() -> String, packet: &ParsedPacket) -> bool {
+        self.routes.is_empty() || crate::routing::allows(&self.routes, from, &to(), packet)
+    }
+    /// Queues an accepted packet for every client but the sender whose
+    /// filter and listener filter it matches; clients without a filter get
+    /// everything. Messages reach their addressee regardless of filters.
+    /// Slow clients have it dropped, and clients over their bandwidth cap
+    /// have it shed, instead of delaying the others. Returns true if a
+    /// message was queued for its addressee.
+    pub fn broadcast_packet(&self, sender_id: usize, from: &str, out: &Outbound, parsed: &ParsedPacket) -> bool {
+        let packet = &out.line;
+        let message = parsed.message();
+        let matches = |filter: &FilterSet, callsign: Option<&str>| {
+            filter.matches(parsed, &FilterContext { callsign, positions: Some(&self.positions), zones: self.nws_zones.as_ref() })
+        };
+        // Each client's decision is reported for watched stations
+        let watched = self.watch.watching(&parsed.source).then(|| packet.trim_end());
+        let report = |c: &Client, detail: &str| {
+            if let Some(line) = watched {
+                let who = format!("{} {}", c._id, c.callsign.as_deref().unwrap_or("-"));
+                self.watch.report(&parsed.source, line, "client", format!("{}: {}", who, detail));
+            }
+        };
+        let mut delivered = false;
+        for (id, client) in self.clients.read().unwrap().iter() {
+            if *id == sender_id {
+                continue;
+            }
+            let mut c = client.lock().unwrap();
+            let callsign = c.callsign.as_deref();
+            if !self.routed(from, || format!("client:{}", callsign.unwrap_or("unknown")), parsed) {
+                report(&c, "denied by routing rules");
+                continue;
+            }
+            let addressed = message.is_some_and(|m| callsign.is_some_and(|cs| cs.eq_ignore_ascii_case(&m.addressee)));
+            if !addressed {
+                let wanted = c.filter.as_ref().is_none_or(|f| matches(f, callsign))
+                    && c.listener_filter.as_ref().is_none_or(|f| matches(f, callsign));
+                if !wanted {
+                    c.packets_filtered += 1;
+                    self.counters.filtered.fetch_add(1, Ordering::Relaxed);
+                    report(&c, &format!("filter {} did not match", c.filter_string.as_deref().unwrap_or("of the port")));
+                    continue;
                 }
             }
+            if let Some(limit) = &mut c.tx_limit
+                && !limit.allow(packet.len(), TxPriority::of(parsed), self.clock.now())
+            {
+                c.tx.shed.fetch_add(1, Ordering::Relaxed);
+                c.tx.shed_bytes.fetch_add(packet.len() as u64, Ordering::Relaxed);
+                report(&c, "shed over the bandwidth cap");
+                continue;
+            }
+            if c.send_packet(out) {
+                delivered |= addressed;
+                self.notify_egress(*id, packet);
+                let why = match &c.filter_string {
+                    _ if addressed => "as addressee".to_string(),
+                    Some(filter) => format!("filter {} matched", filter),
+                    None => "no filter".to_string(),
+                };
+                report(&c, &format!("queued, {}", why));
+            } else {
+                report(&c, "dropped, queue full");
+            }
         }
+        delivered
     }
-    pub fn check_and_insert_dupe(&mut self, packet: &str) -> bool {
-        let hash = seahash::hash(packet.as_bytes());
-        if self.dupe_cache.contains(&hash) {
+    pub fn add_hook(&mut self, hook: Arc<dyn PacketHook>) {
+        self.hooks.push(hook);
+    }
+    /// Runs all ingress hooks in registration order. Returns the (possibly
+    /// rewritten) line, or None if a hook dropped it.
+    pub fn run_ingress_hooks(&self, line: &str) -> Option<String> {
+        let mut line = line.to_string();
+        for hook in &self.hooks {
+            match hook.on_ingress(&line) {
+                HookAction::Continue => {}
+                HookAction::Drop => {
+                    self.notify_drop(&line, DropReason::Hook);
+                    return None;
+                }
+                HookAction::Replace(new_line) => line = new_line,
+            }
+        }
+        Some(line)
+    }
+    pub fn notify_accept(&self, line: &str, packet: &Arc<ParsedPacket>) {
+        self.counters.accepted.fetch_add(1, Ordering::Relaxed);
+        let source = packet.source.to_uppercase();
+        let software = self.tocalls.software(packet);
+        {
+            let mut stations = self.stations.write().unwrap();
+            stations
+                .software_stats
+                .entry(software.to_string())
+                .or_insert_with(|| SoftwareStats::new(software))
+                .add(line.len(), &source);
+            stations
+                .source_stats
+                .entry(source.clone())
+                .or_insert_with(|| SourceStats { callsign: source, ..Default::default() })
+                .add(line.len(), packet.data.type_name(), self.clock.unix_time());
+            if let Some(limit) = self.station_cache.max_entries {
+                stations.bound(limit);
+            }
+        }
+        for hook in &self.hooks {
+            hook.on_accept(line, packet);
+        }
+        let subscribed = self.packet_feed.receiver_count() > 0;
+        if !subscribed && self.history_secs == 0 {
+            return;
+        }
+        let accepted = Arc::new(FeedPacket {
+            received: self.clock.unix_time(),
+            line: line.to_string(),
+            packet: Arc::clone(packet),
+        });
+        if self.history_secs > 0 {
+            let cutoff = accepted.received.saturating_sub(self.history_secs);
+            let mut history = self.history.lock().unwrap();
+            while history.front().is_some_and(|p| p.received < cutoff) || history.len() >= HISTORY_MAX_PACKETS {
+                history.pop_front();
+            }
+            history.push_back(accepted.clone());
+        }
+        if subscribed {
+            let _ = self.packet_feed.send(accepted);
+        }
+    }
+    /// Buffered packets matching any of the filters, oldest first.
+    pub fn history_matching(&self, filters: &FilterSet, listener_filter: Option<&FilterSet>, callsign: Option<&str>) -> Vec<String> {
+        let cutoff = self.clock.unix_time().saturating_sub(self.history_secs);
+        let history = self.history.lock().unwrap();
+        let ctx = FilterContext { callsign, positions: Some(&self.positions), zones: self.nws_zones.as_ref() };
+        history
+            .iter()
+            .filter(|p| p.received >= cutoff && filters.matches(&p.packet, &ctx))
+            .filter(|p| listener_filter.is_none_or(|f| f.matches(&p.packet, &ctx)))
+            .map(|p| p.line.clone())
+            .collect()
+    }
+    /// True if the packet's path shows it already went through this server,
+    /// or through one of our S2S peers other than the one it arrived from.
+    pub fn is_loop(&self, packet: &ParsedPacket, from_peer: Option<&str>) -> bool {
+        packet.path.iter().any(|hop| {
+            let hop = hop.trim_end_matches('*').to_uppercase();
+            hop == self.server_name.to_uppercase()
+                || (self.peer_ids.read().unwrap().contains(&hop) && from_peer.is_none_or(|p| !p.eq_ignore_ascii_case(&hop)))
+        })
+    }
+    pub fn notify_drop(&self, line: &str, reason: DropReason) {
+        let counter = match reason {
+            DropReason::Duplicate => &self.counters.duplicates,
+            DropReason::Invalid => &self.counters.invalid,
+            DropReason::Filtered => &self.counters.filtered,
+            DropReason::Hook => &self.counters.hook_drops,
+            DropReason::Loop => &self.counters.loops,
+            DropReason::Policy => &self.counters.policy_drops,
+            DropReason::BadPosition => &self.counters.bad_positions,
+            DropReason::Tcpxx => &self.counters.tcpxx_drops,
+            DropReason::NoGate => &self.counters.nogate_drops,
+            DropReason::RfOnly => &self.counters.rfonly_drops,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if reason != DropReason::Duplicate {
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        for hook in &self.hooks {
+            hook.on_drop(line, reason);
+        }
+    }
+    pub fn notify_egress(&self, client_id: usize, line: &str) {
+        self.counters.add_tx(line.len());
+        for hook in &self.hooks {
+            hook.on_egress(client_id, line);
+        }
+    }
+    /// Splits the duplicate and position caches into `shards` parts, one
+    /// per ingest worker. Must be called before the hub is shared.
+    pub fn set_cache_shards(&mut self, shards: usize) {
+        self.dupes = (0..shards.max(1)).map(|_| Mutex::new(DupeCache::default())).collect();
+        self.positions = PositionCache::new(shards);
+    }
+    fn dupe_shard(&self, source: &str) -> &Mutex<DupeCache> {
+        &self.dupes[source_shard(source, self.dupes.len())]
+    }
+    /// Returns true if the packet was seen within the dupe window. Only
+    /// `source>destination:payload` counts, so copies gated by different
+    /// igates or relayed with other q constructs still match.
+    pub fn check_and_insert_dupe(&self, packet: &str) -> bool {
+        let hash = match packet.split_once(':') {
+            Some((header, body)) => {
+                let address = header.split(',').next().unwrap_or(header);
+                seahash::hash(format!("{}:{}", address, body).as_bytes())
+            }
+            None => seahash::hash(packet.as_bytes()),
+        };
+        let source = packet.split('>').next().unwrap_or("");
+        let now = self.clock.now();
+        let mut dupes = self.dupe_shard(source).lock().unwrap();
+        dupes.lookups += 1;
+        if let Some(window) = self.dupe_config.window_secs.map(Duration::from_secs) {
+            while let Some(&(old, seen)) = dupes.order.front()
+                && now.duration_since(seen) >= window
+            {
+                dupes.order.pop_front();
+                dupes.hashes.remove(&old);
+            }
+        }
+        if !dupes.hashes.insert(hash) {
+            dupes.hit(source);
             return true;
         }
-        self.dupe_cache.insert(hash);
-        self.dupe_order.push_back(hash);
-        if self.dupe_order.len() > DUPE_CACHE_SIZE {
-            if let Some(old) = self.dupe_order.pop_front() {
-                self.dupe_cache.remove(&old);
+        dupes.order.push_back((hash, now));
+        if dupes.order.len() > self.dupe_config.capacity.div_ceil(self.dupes.len())
+            && let Some((old, _)) = dupes.order.pop_front()
+        {
+            dupes.hashes.remove(&old);
+        }
+        false
+    }
+    /// Duplicate cache figures over all shards, with the `top` sources
+    /// sending the most duplicates.
+    pub fn dupe_stats(&self, top: usize) -> DupeStats {
+        let mut stats = DupeStats {
+            capacity: self.dupe_config.capacity,
+            shards: self.dupes.len(),
+            window_secs: self.dupe_config.window_secs,
+            ingest_dropped: self.ingest_dropped.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        let mut sources: HashMap<String, u64> = HashMap::new();
+        for shard in &self.dupes {
+            let dupes = shard.lock().unwrap();
+            stats.entries += dupes.order.len();
+            stats.message_ids += dupes.message_ids.len();
+            stats.lookups += dupes.lookups;
+            stats.hits += dupes.hits;
+            for (source, count) in &dupes.sources {
+                *sources.entry(source.clone()).or_default() += count;
+            }
+        }
+        if stats.lookups > 0 {
+            stats.hit_rate = stats.hits as f64 / stats.lookups as f64;
+        }
+        let mut sources: Vec<(String, u64)> = sources.into_iter().collect();
+        sources.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        sources.truncate(top);
+        stats.top_sources = sources;
+        stats
+    }
+    /// Returns true if this is a retransmission of a message id already seen
+    /// from the same source to the same addressee within the dupe window.
+    /// Acks, rejects and messages without an id are never treated as dupes here.
+    pub fn check_and_insert_message_dupe(&self, packet: &ParsedPacket) -> bool {
+        let msg = match packet.message() {
+            Some(msg) if msg.kind == MessageKind::Message => msg,
+            _ => return false,
+        };
+        let Some(msgid) = msg.msgid.clone() else {
+            return false;
+        };
+        let now = self.clock.now();
+        let window = Duration::from_secs(self.dupe_config.message_window_secs);
+        let mut dupes = self.dupe_shard(&msg.source).lock().unwrap();
+        while let Some((_, seen)) = dupes.message_order.front()
+            && now.duration_since(*seen) >= window
+        {
+            if let Some((old, _)) = dupes.message_order.pop_front() {
+                dupes.message_ids.remove(&old);
             }
         }
+        let key = (msg.source.to_uppercase(), msg.addressee.to_uppercase(), msgid);
+        if dupes.message_ids.contains(&key) {
+            dupes.hit(&msg.source);
+            return true;
+        }
+        dupes.message_ids.insert(key.clone());
+        dupes.message_order.push_back((key, now));
         false
     }
-    pub fn broadcast_to_s2s_peers(&self, sender: Option<&str>, packet: &str) {
-        for handle in &self.s2s_peer_handles {
-            if let Some(name) = &handle.peer_name {
-                if let Some(sender_name) = sender {
-                    if name == sender_name { continue; }
+    /// Runs the `[position_checks]` on a packet, counting rejections per
+    /// sending station.
+    pub fn check_position(&self, packet: &ParsedPacket) -> Result<(), BadPosition> {
+        let Some(cfg) = &self.position_checks else {
+            return Ok(());
+        };
+        let callsign = packet.source.to_uppercase();
+        let now = self.clock.unix_time();
+        let result = position_check::check(cfg, packet, self.positions.get(&callsign).as_ref(), now);
+        if let Err(reason) = result {
+            let mut stations = self.stations.write().unwrap();
+            let rejected = stations.rejected_positions.entry(callsign.clone()).or_insert_with(|| RejectedPositions {
+                callsign,
+                count: 0,
+                last_reason: reason,
+                last_rejected: now,
+            });
+            rejected.count += 1;
+            rejected.last_reason = reason;
+            rejected.last_rejected = now;
+        }
+        result
+    }
+    /// Updates per-station state (latest weather, telemetry, objects) from an accepted packet.
+    pub fn record_packet(&self, packet: &ParsedPacket) {
+        let now = self.clock.unix_time();
+        let evicted = StationPosition::from_packet(packet, now).map_or(0, |pos| self.positions.insert(pos, self.station_cache.max_entries));
+        let mut stations = self.stations.write().unwrap();
+        stations.evicted += evicted as u64;
+        match &packet.data {
+            PacketData::Telemetry(frame) => {
+                let station = stations.telemetry.entry(packet.source.to_uppercase()).or_default();
+                station.add_frame(frame.clone(), now);
+            }
+            PacketData::Object { name, live, position } | PacketData::Item { name, live, position } => {
+                if *live {
+                    stations.objects.insert(name.clone(), ObjectEntry {
+                        name: name.clone(),
+                        item: matches!(packet.data, PacketData::Item { .. }),
+                        owner: packet.source.clone(),
+                        lat: position.lat,
+                        lon: position.lon,
+                        symbol: format!("{}{}", position.symbol_table, position.symbol_code),
+                        comment: position.comment.clone(),
+                        updated: now,
+                    });
+                } else if stations.objects.get(name).is_some_and(|o| o.owner.eq_ignore_ascii_case(&packet.source)) {
+                    // Kill packet; only the owner may kill an object
+                    stations.objects.remove(name);
+                }
+            }
+            PacketData::Message(msg) => {
+                // Definitions are sent as messages addressed to the telemetry station
+                if let Some(def) = telemetry::parse_definition(&msg.text) {
+                    let station = stations.telemetry.entry(msg.addressee.to_uppercase()).or_default();
+                    station.apply_definition(def);
                 }
             }
-            let _ = handle.sender.send(packet.to_string());
+            _ => {}
         }
+        if let Some(weather) = packet.weather() {
+            let position = packet.position();
+            stations.weather.insert(packet.source.clone(), WeatherObservation {
+                callsign: packet.source.clone(),
+                received: now,
+                lat: position.map(|p| p.lat),
+                lon: position.map(|p| p.lon),
+                weather,
+            });
+        }
+        if let Some(limit) = self.station_cache.max_entries {
+            stations.bound(limit);
+        }
+    }
+    /// Weather observations received within the last `max_age` seconds.
+    pub fn recent_weather(&self, max_age: u64) -> Vec<WeatherObservation> {
+        let cutoff = self.clock.unix_time().saturating_sub(max_age);
+        self.stations.read().unwrap().weather.values().filter(|w| w.received >= cutoff).cloned().collect()
+    }
+    /// Station positions heard within the last `max_age` seconds.
+    pub fn recent_positions(&self, max_age: u64) -> Vec<StationPosition> {
+        self.positions.updated_since(self.clock.unix_time().saturating_sub(max_age))
     }
+    /// Sends a local packet, CRLF terminated, to the uplinks routed for it.
+    /// Returns whether any took it.
+    pub fn send_to_uplinks(&self, from: &str, out: &Outbound, packet: &ParsedPacket) -> bool {
+        let watched = self.watch.watching(&packet.source);
+        let mut sent = false;
+        for route in crate::uplink::route(&self.uplinks.read().unwrap(), packet) {
+            let to = || route.network.as_ref().map_or("uplink".to_string(), |name| format!("uplink:{}", name));
+            let queued = self.routed(from, to, packet) && route.tx.send(out.clone()).is_ok();
+            if watched {
+                let detail = if queued { "sent" } else { "not sent, denied by routing rules or down" };
+                self.watch.report(&packet.source, out.line.trim_end(), "uplink", format!("{}: {}", to(), detail));
+            }
+            sent |= queued;
+        }
+        if watched && !sent {
+            self.watch.report(&packet.source, out.line.trim_end(), "uplink", "no uplink took it");
+        }
+        sent
+    }
+    /// Sends a line to one network's uplink, `None` for `[uplink]`.
+    pub fn send_to_network(&self, network: Option<&str>, line: &str) -> bool {
+        let out = Outbound::now(line.to_string());
+        self.uplinks.read().unwrap().iter().find(|r| r.network.as_deref() == network).is_some_and(|r| r.tx.send(out).is_ok())
+    }
+    /// Sends a packet to all S2S peers except the one it came from, with our
+    /// server ID added to the path so it is dropped if it comes back. Peer
+    /// filters are applied to the packet as parsed at ingress.
+    pub fn broadcast_to_s2s_peers(&self, sender: Option<&str>, from: &str, packet: &str, received: Instant, parsed: &ParsedPacket) {
+        let packet = add_path_hop(packet, &self.server_name);
+        let handles = self.s2s_peer_handles.read().unwrap();
+        let ctx = FilterContext { callsign: None, positions: Some(&self.positions), zones: self.nws_zones.as_ref() };
+        let watched = self.watch.watching(&parsed.source);
+        let report = |handle: &S2SPeerHandle, detail: &str| {
+            if watched {
+                let peer = handle.peer_name.as_deref().unwrap_or("unknown");
+                self.watch.report(&parsed.source, &packet, "s2s", format!("{}: {}", peer, detail));
+            }
+        };
+        for handle in handles.iter() {
+            if let (Some(name), Some(sender_name)) = (&handle.peer_name, sender)
+                && name == sender_name
+            {
+                continue;
+            }
+            if let Some(filters) = &handle.filters
+                && !filters.iter().any(|f| f.matches_with(parsed, &ctx))
+            {
+                report(handle, "peer filter did not match");
+                continue;
+            }
+            if !self.routed(from, || format!("s2s:{}", handle.peer_name.as_deref().unwrap_or("unknown")), parsed) {
+                report(handle, "denied by routing rules");
+                continue;
+            }
+            let _ = handle.sender.send(Outbound::new(format!("{}\r\n", packet), received));
+            report(handle, "sent");
+        }
+    }
+    /// Removes the handles registered for an S2S peer when its link drops.
+    /// Drops the handle sending to `sender`'s channel. Handles are told apart
+    /// by channel, as unnamed peers share a `peer_name` of `None`.
+    pub fn remove_s2s_peer_handle(&self, sender: &UnboundedSender<Outbound>) {
+        self.s2s_peer_handles.write().unwrap().retain(|h| !h.sender.same_channel(sender));
+    }
+}
+
+/// Appends `hop` to the path of a packet line unless it is already there.
+pub fn add_path_hop(line: &str, hop: &str) -> String {
+    let Some(colon) = line.find(':') else {
+        return line.to_string();
+    };
+    let (header, body) = line.split_at(colon);
+    if header.split(',').skip(1).any(|h| h.trim_end_matches('*').eq_ignore_ascii_case(hop)) {
+        return line.to_string();
+    }
+    format!("{},{}{}", header, hop, body)
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::client::ClientConn;
     use std::io::Read;
     use std::net::{TcpListener, TcpStream};
+
+    fn parse(line: &str) -> Arc<ParsedPacket> {
+        Arc::new(crate::parser::parse(line).unwrap())
+    }
     #[test]
     fn test_hub_add_remove() {
-        let mut hub = Hub::new();
+        let hub = Hub::new();
         let listener = TcpListener::bind("127.0.0.1:0").unwrap();
         let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
-        let client = Client::new(1, stream);
-        let id = hub.add_client(client);
+        let id = hub.add_client(Client::new(0, stream));
+        assert_eq!(hub.client(id).unwrap().lock().unwrap()._id, id);
         assert_eq!(hub.client_count(), 1);
         hub.remove_client(id);
         assert_eq!(hub.client_count(), 0);
     }
     #[test]
     fn test_hub_update_client() {
-        let mut hub = Hub::new();
+        let hub = Hub::new();
         let listener = TcpListener::bind("127.0.0.1:0").unwrap();
         let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
         let client = Client::new(1, stream);
@@ -193,38 +1121,314 @@ mod tests {
         hub.update_client(
             id,
             Some("N0CALL".to_string()),
-            Some(vec![crate::filter::ClientFilter::Prefix("foo".to_string())]),
+            Some(Arc::new(FilterSet::new(vec![ClientFilter::Prefix("foo".to_string())]))),
         );
-        let c = hub.clients.get(&id).unwrap().lock().unwrap();
+        let client = hub.client(id).unwrap();
+        let c = client.lock().unwrap();
         assert_eq!(c.callsign, Some("N0CALL".to_string()));
-        assert_eq!(c.filter, Some(vec![crate::filter::ClientFilter::Prefix("foo".to_string())]));
+        assert_eq!(c.filter.as_ref().unwrap().filters(), [ClientFilter::Prefix("foo".to_string())]);
     }
     #[test]
     fn test_hub_uptime() {
         let hub = Hub::new();
         assert!(hub.uptime() < 2);
     }
+    struct TestHook {
+        accepted: Mutex<Vec<String>>,
+    }
+    impl PacketHook for TestHook {
+        fn on_ingress(&self, line: &str) -> HookAction {
+            if line.starts_with("SPAM") {
+                HookAction::Drop
+            } else {
+                HookAction::Replace(line.replace("APRS", "APZ001"))
+            }
+        }
+        fn on_accept(&self, _line: &str, packet: &ParsedPacket) {
+            self.accepted.lock().unwrap().push(packet.source.clone());
+        }
+    }
     #[test]
-    fn test_broadcast_packet() {
+    fn test_hooks() {
         let mut hub = Hub::new();
+        let hook = Arc::new(TestHook { accepted: Mutex::new(Vec::new()) });
+        hub.add_hook(hook.clone());
+        assert_eq!(hub.run_ingress_hooks("SPAM>APRS:>hi"), None);
+        assert_eq!(hub.run_ingress_hooks("N0CALL>APRS:>hi"), Some("N0CALL>APZ001:>hi".to_string()));
+        hub.notify_accept("N0CALL>APRS:>hi", &parse("N0CALL>APRS:>hi"));
+        assert_eq!(*hook.accepted.lock().unwrap(), vec!["N0CALL"]);
+    }
+    #[test]
+    fn test_packet_feed() {
+        let hub = Hub::new();
+        let mut feed = hub.packet_feed.subscribe();
+        hub.notify_accept("N0CALL>APRS:>hi", &parse("N0CALL>APRS:>hi"));
+        let p = feed.try_recv().unwrap();
+        assert_eq!(p.line, "N0CALL>APRS:>hi");
+        assert_eq!(p.packet.source, "N0CALL");
+    }
+    #[test]
+    fn test_history() {
+        let mut hub = Hub::new();
+        hub.notify_accept("N0CALL>APRS:>off", &parse("N0CALL>APRS:>off"));
+        assert!(hub.history.lock().unwrap().is_empty());
+        hub.history_secs = 600;
+        for line in ["N0CALL>APRS:>one", "N1CALL>APRS:>two", "N0CALL-5>APRS:>three"] {
+            hub.notify_accept(line, &parse(line));
+        }
+        let filters = FilterSet::new(vec!["p/N0".parse().unwrap()]);
+        assert_eq!(hub.history_matching(&filters, None, None), ["N0CALL>APRS:>one", "N0CALL-5>APRS:>three"]);
+        // Packets older than the history span are no longer sent
+        Arc::get_mut(&mut hub.history.get_mut().unwrap()[0]).unwrap().received -= 601;
+        assert_eq!(hub.history_matching(&filters, None, None), ["N0CALL-5>APRS:>three"]);
+    }
+    #[test]
+    fn test_record_heard() {
+        let mut hub = Hub::new();
+        hub.record_heard("n0call-9", "uplink");
+        hub.record_heard("N0CALL-9", "client:N1CALL");
+        let heard = &mut hub.stations.get_mut().unwrap().heard;
+        let h = heard.get_mut("N0CALL-9").unwrap();
+        assert_eq!(h.packets, 2);
+        assert_eq!(h.entry, "client:N1CALL");
+        h.last_heard -= HEARD_MAX_AGE + 1;
+        hub.prune_heard();
+        assert!(hub.stations.read().unwrap().heard.is_empty());
+    }
+    #[test]
+    fn test_station_cache_limit() {
+        let mut hub = Hub::new();
+        hub.station_cache.max_entries = Some(2);
+        for call in ["N0CALL", "N1CALL", "N2CALL"] {
+            hub.record_packet(&parse(&format!("{}>APRS:!4903.50N/07201.75W-", call)));
+        }
+        assert_eq!(hub.positions.len(), 2);
+        assert_eq!(hub.cache_stats().evicted, 1);
+    }
+    #[test]
+    fn test_dupe_stats() {
+        let mut hub = Hub::new();
+        hub.dupe_config.capacity = 2;
+        for line in ["N0CALL>APRS:>one", "N0CALL>APRS:>one", "N1CALL>APRS:>two", "N0CALL>APRS:>one", "N2CALL>APRS:>three"] {
+            hub.check_and_insert_dupe(line);
+        }
+        // The first packet has fallen out of the cache
+        assert!(!hub.check_and_insert_dupe("N0CALL>APRS:>one"));
+        assert!(hub.check_and_insert_dupe("N2CALL>APRS:>three"));
+        let stats = hub.dupe_stats(1);
+        assert_eq!((stats.entries, stats.capacity, stats.lookups, stats.hits), (2, 2, 7, 3));
+        assert_eq!(stats.top_sources, [("N0CALL".to_string(), 2)]);
+        assert!(stats.hit_rate > 0.0);
+
+        hub.dupe_config.window_secs = Some(0);
+        assert!(!hub.check_and_insert_dupe("N0CALL>APRS:>one"));
+        assert!(!hub.check_and_insert_dupe("N0CALL>APRS:>one"));
+
+        hub.dupe_config.window_secs = None;
+        assert!(!hub.check_and_insert_dupe("N3CALL>APRS,qAR,IGATE1:>four"));
+        assert!(hub.check_and_insert_dupe("N3CALL>APRS,WIDE1-1,qAR,IGATE2:>four"));
+        assert!(!hub.check_and_insert_dupe("N3CALL>APDR16,qAR,IGATE1:>four"));
+    }
+    #[test]
+    fn test_mock_clock() {
+        let clock = Arc::new(crate::clock::MockClock::new(1_700_000_000));
+        let mut hub = Hub::new();
+        hub.clock = clock.clone();
+        hub.dupe_config.window_secs = Some(30);
+        hub.dupe_config.message_window_secs = 60;
+        let message = parse("N0CALL>APRS::N1CALL   :Hello{1");
+        assert!(!hub.check_and_insert_dupe("N0CALL>APRS:>one"));
+        assert!(!hub.check_and_insert_message_dupe(&message));
+        hub.record_heard("N0CALL", "uplink");
+        clock.advance(Duration::from_secs(29));
+        assert!(hub.check_and_insert_dupe("N0CALL>APRS:>one"));
+        clock.advance(Duration::from_secs(1));
+        assert!(!hub.check_and_insert_dupe("N0CALL>APRS:>one"));
+        assert!(hub.check_and_insert_message_dupe(&message));
+        clock.advance(Duration::from_secs(30));
+        assert!(!hub.check_and_insert_message_dupe(&parse("N0CALL>APRS::N1CALL   :Hello{2")));
+        assert!(!hub.check_and_insert_message_dupe(&message));
+        // The expired id left the cache before the new ones went in
+        assert_eq!(hub.dupe_stats(0).message_ids, 2);
+        // Heard stations age by the clock too
+        assert_eq!(hub.stations.read().unwrap().heard["N0CALL"].last_heard, 1_700_000_000);
+        clock.advance(Duration::from_secs(HEARD_MAX_AGE));
+        hub.prune_heard();
+        assert!(hub.stations.read().unwrap().heard.is_empty());
+    }
+    #[test]
+    fn test_top_talkers() {
+        let hub = Hub::new();
+        for line in ["N0CALL>APRS:>one", "n0call>APRS:!4903.50N/07201.75W-", "N1CALL>APRS:>two"] {
+            hub.notify_accept(line, &parse(line));
+        }
+        let top = hub.top_talkers(1);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].callsign, "N0CALL");
+        assert_eq!(top[0].packets, 2);
+        assert_eq!(top[0].bytes, 48);
+        assert_eq!(top[0].types["status"], 1);
+        assert_eq!(top[0].types["position"], 1);
+        hub.notify_accept("N2CALL>APDW16:>three", &parse("N2CALL>APDW16:>three"));
+        let software = hub.software_breakdown();
+        assert_eq!((software[0].software.as_str(), software[0].stations, software[0].packets), ("Generic APRS", 2, 3));
+        assert_eq!(software[1].software, "Dire Wolf");
+    }
+    #[test]
+    fn test_add_path_hop() {
+        assert_eq!(add_path_hop("N0CALL>APRS:>a:b", "T2TEST"), "N0CALL>APRS,T2TEST:>a:b");
+        assert_eq!(add_path_hop("N0CALL>APRS,t2test*:>x", "T2TEST"), "N0CALL>APRS,t2test*:>x");
+    }
+    #[test]
+    fn test_message_dupe() {
+        let hub = Hub::new();
+        assert!(!hub.check_and_insert_message_dupe(&parse("N0CALL>APRS::N1CALL   :Hello{1")));
+        // Retransmission via a different path is still the same message
+        assert!(hub.check_and_insert_message_dupe(&parse("N0CALL>APRS,WIDE1-1::N1CALL   :Hello{1")));
+        assert!(!hub.check_and_insert_message_dupe(&parse("N0CALL>APRS::N1CALL   :Hello{2")));
+        // Acks are never suppressed
+        assert!(!hub.check_and_insert_message_dupe(&parse("N1CALL>APRS::N0CALL   :ack1")));
+        assert!(!hub.check_and_insert_message_dupe(&parse("N1CALL>APRS::N0CALL   :ack1")));
+    }
+    #[test]
+    fn test_record_weather() {
+        let hub = Hub::new();
+        let wx = crate::parser::parse("N0CALL>APRS:_10090556c220s004g005t077").unwrap();
+        hub.record_packet(&wx);
+        let pos = crate::parser::parse("N1CALL>APRS:!4903.50N/07201.75W>").unwrap();
+        hub.record_packet(&pos);
+        let recent = hub.recent_weather(3600);
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].callsign, "N0CALL");
+        assert_eq!(recent[0].weather.temperature, Some(77));
+    }
+    #[test]
+    fn test_record_objects() {
+        let hub = Hub::new();
+        let obj = crate::parser::parse("N0CALL>APRS:;LEADER   *092345z4903.50N/07201.75W>comment").unwrap();
+        hub.record_packet(&obj);
+        let item = crate::parser::parse("N0CALL>APRS:)AID #2!4903.50N/07201.75WA").unwrap();
+        hub.record_packet(&item);
+        {
+            let objects = &hub.stations.read().unwrap().objects;
+            assert_eq!(objects.len(), 2);
+            assert_eq!(objects["LEADER"].owner, "N0CALL");
+            assert!(objects["AID #2"].item);
+        }
+        // Other stations cannot kill it
+        let kill = crate::parser::parse("N1CALL>APRS:;LEADER   _092350z4903.50N/07201.75W>").unwrap();
+        hub.record_packet(&kill);
+        assert!(hub.stations.read().unwrap().objects.contains_key("LEADER"));
+        let kill = crate::parser::parse("n0call>APRS:;LEADER   _092350z4903.50N/07201.75W>").unwrap();
+        hub.record_packet(&kill);
+        let objects = &hub.stations.read().unwrap().objects;
+        assert_eq!(objects.len(), 1);
+        assert!(!objects.contains_key("LEADER"));
+    }
+    #[test]
+    fn test_record_positions() {
+        let hub = Hub::new();
+        hub.record_packet(&parse("n0call-9>APRS:!4903.50N/07201.75W>moving"));
+        hub.record_packet(&parse("N0CALL>APRS:>status only"));
+        assert_eq!(hub.positions.len(), 1);
+        let pos = hub.positions.get("N0CALL-9").unwrap();
+        assert_eq!(pos.symbol, "/>");
+        assert_eq!(pos.comment, "moving");
+        assert!((pos.lat - 49.058).abs() < 0.001);
+    }
+    #[test]
+    fn test_recent_positions() {
+        let hub = Hub::new();
+        hub.record_packet(&parse("N0CALL>APRS:!4903.50N/07201.75W>"));
+        hub.positions.insert(StationPosition { callsign: "OLD".to_string(), ..Default::default() }, None);
+        let recent = hub.recent_positions(3600);
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].callsign, "N0CALL");
+    }
+    #[test]
+    fn test_record_telemetry() {
+        let hub = Hub::new();
+        let def = crate::parser::parse("N0CALL>APRS::N0CALL-1 :PARM.Battery").unwrap();
+        hub.record_packet(&def);
+        let frame = crate::parser::parse("N0CALL-1>APRS:T#001,138").unwrap();
+        hub.record_packet(&frame);
+        let stations = hub.stations.read().unwrap();
+        let station = stations.telemetry.get("N0CALL-1").unwrap();
+        assert_eq!(station.names, vec!["Battery"]);
+        assert_eq!(station.history.len(), 1);
+    }
+    #[test]
+    fn test_broadcast_packet() {
+        let hub = Hub::new();
         let listener = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = listener.local_addr().unwrap();
         let stream1 = TcpStream::connect(addr).unwrap();
+        let (server1, _) = listener.accept().unwrap();
         let stream2 = TcpStream::connect(addr).unwrap();
-        let client1 = Client::new(1, stream1.try_clone().unwrap());
-        let client2 = Client::new(2, stream2.try_clone().unwrap());
+        let (server2, _) = listener.accept().unwrap();
+        let client1 = Client::new(1, server1);
+        let client2 = Client::new(2, server2);
         let id1 = hub.add_client(client1);
         let id2 = hub.add_client(client2);
-        hub.broadcast_packet(id1, "test123\n");
+        let packet = crate::parser::parse("N0CALL>APRS:>test123").unwrap();
+        hub.broadcast_packet(id1, "uplink", &Outbound::now("N0CALL>APRS:>test123\r\n".to_string()), &packet);
         let mut buf = [0u8; 128];
         let mut s2 = stream2.try_clone().unwrap();
         let n = s2.read(&mut buf).unwrap_or(0);
         assert!(std::str::from_utf8(&buf[..n]).unwrap().contains("test123"));
         // Sender should not receive its own packet
         let mut s1 = stream1.try_clone().unwrap();
+        s1.set_read_timeout(Some(std::time::Duration::from_millis(100))).unwrap();
         let n = s1.read(&mut buf).unwrap_or(0);
         assert_eq!(n, 0);
         hub.remove_client(id1);
         hub.remove_client(id2);
     }
+    #[test]
+    fn test_egress_filter() {
+        let hub = Hub::new();
+        let connect = |callsign: &str, filter: Option<&str>| {
+            let (tx, rx) = tokio::sync::mpsc::channel(16);
+            let mut client = Client::with_conn(0, ClientConn::WebSocket { tx: Some(tx), write_timeout: std::time::Duration::from_secs(1) }, "127.0.0.1:1".parse().ok());
+            client.callsign = Some(callsign.to_string());
+            client.filter = filter.map(|f| Arc::new(FilterSet::new(crate::filter::parse_filter_list(f).unwrap())));
+            (hub.add_client(client), rx)
+        };
+        let (n1, mut n1_rx) = connect("N1CALL", Some("p/N1"));
+        let (_, mut all_rx) = connect("N2CALL", None);
+        for line in ["N0CALL>APRS:>outside", "N1CALL>APRS:>inside", "N0CALL>APRS::N1CALL   :hello"] {
+            hub.broadcast_packet(0, "uplink", &Outbound::now(format!("{}\r\n", line)), &parse(line));
+        }
+        let received = |rx: &mut tokio::sync::mpsc::Receiver<String>, count: usize| {
+            (0..count).map(|_| rx.blocking_recv().unwrap().trim_end().to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(received(&mut n1_rx, 2), ["N1CALL>APRS:>inside", "N0CALL>APRS::N1CALL   :hello"]);
+        assert_eq!(received(&mut all_rx, 3).len(), 3);
+        assert_eq!(hub.client(n1).unwrap().lock().unwrap().packets_filtered, 1);
+    }
+    #[test]
+    fn test_kick_client() {
+        let hub = Hub::new();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let id = hub.add_client(Client::new(1, server));
+        assert!(hub.kick_client(id, "bye"));
+        assert!(!hub.kick_client(id + 1, "bye"));
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "# bye\r\n");
+    }
+    #[test]
+    fn test_kick_banned_websocket() {
+        let hub = Hub::new();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let peer: std::net::SocketAddr = "192.0.2.1:1234".parse().unwrap();
+        let id = hub.add_client(Client::with_conn(0, ClientConn::WebSocket { tx: Some(tx), write_timeout: std::time::Duration::from_secs(1) }, Some(peer)));
+        assert_eq!(hub.client(id).unwrap().lock().unwrap().addr, "ws:192.0.2.1:1234");
+        hub.bans.write().unwrap().add_ip(peer.ip()).unwrap();
+        hub.kick_banned();
+        assert_eq!(rx.blocking_recv().unwrap(), "# Banned by server operator");
+        assert_eq!(rx.blocking_recv(), None);
+    }
 }
@@ -0,0 +1,91 @@
+//! KISS framing, the byte protocol between a host and a TNC such as Dire
+//! Wolf. Frames are delimited by FEND; FEND and FESC inside a frame are
+//! escaped. The first byte of a frame holds the TNC port in its high nibble
+//! and the command in the low one, 0 for data.
+
+const FEND: u8 = 0xC0;
+const FESC: u8 = 0xDB;
+const TFEND: u8 = 0xDC;
+const TFESC: u8 = 0xDD;
+const CMD_DATA: u8 = 0x00;
+/// Longer frames are garbage, e.g. from a serial line at the wrong speed
+const MAX_FRAME: usize = 1024;
+
+/// Splits a KISS byte stream into data frames.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+    escaped: bool,
+    overflow: bool,
+}
+
+impl Decoder {
+    /// Feeds `bytes` and returns the data frames completed by them, with
+    /// their TNC port.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut frames = Vec::new();
+        for &b in bytes {
+            match b {
+                FEND => {
+                    if let Some((&command, frame)) = self.buf.split_first()
+                        && !self.overflow
+                        && command & 0x0F == CMD_DATA
+                    {
+                        frames.push((command >> 4, frame.to_vec()));
+                    }
+                    self.buf.clear();
+                    self.escaped = false;
+                    self.overflow = false;
+                }
+                FESC => self.escaped = true,
+                _ if self.buf.len() >= MAX_FRAME => self.overflow = true,
+                _ => {
+                    let b = match (self.escaped, b) {
+                        (true, TFEND) => FEND,
+                        (true, TFESC) => FESC,
+                        _ => b,
+                    };
+                    self.escaped = false;
+                    self.buf.push(b);
+                }
+            }
+        }
+        frames
+    }
+}
+
+/// Wraps an AX.25 frame as a KISS data frame for TNC port `port`.
+pub fn encode(port: u8, frame: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(frame.len() + 4);
+    out.push(FEND);
+    out.push((port << 4) | CMD_DATA);
+    for &b in frame {
+        match b {
+            FEND => out.extend([FESC, TFEND]),
+            FESC => out.extend([FESC, TFESC]),
+            _ => out.push(b),
+        }
+    }
+    out.push(FEND);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let frame = [0x82, FEND, 0x40, FESC, 0x03, 0xF0];
+        let encoded = encode(1, &frame);
+        assert_eq!(encoded, [FEND, 0x10, 0x82, FESC, TFEND, 0x40, FESC, TFESC, 0x03, 0xF0, FEND]);
+        let mut decoder = Decoder::default();
+        // Split anywhere, including inside an escape
+        assert!(decoder.push(&encoded[..4]).is_empty());
+        assert_eq!(decoder.push(&encoded[4..]), [(1, frame.to_vec())]);
+        // Empty frames and non-data commands are skipped
+        assert!(decoder.push(&[FEND, FEND, 0x01, 0x32, FEND]).is_empty());
+        assert!(decoder.push(&vec![0x00; MAX_FRAME + 10]).is_empty());
+        assert!(decoder.push(&[FEND]).is_empty());
+    }
+}
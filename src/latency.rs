@@ -0,0 +1,130 @@
+//! End-to-end packet latency: the time from a packet entering the pipeline
+//! until it has been written to a client, an uplink or a peer. Each kind of
+//! egress has its own histogram with fixed buckets, cheap enough to update
+//! for every line written.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Bucket upper bounds in microseconds; a last bucket takes the rest
+const BUCKETS_US: [u64; 14] =
+    [100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 2_500_000];
+
+/// A packet line on its way out, CRLF terminated, with when its packet
+/// entered the server.
+#[derive(Debug, Clone)]
+pub struct Outbound {
+    pub line: String,
+    pub received: Instant,
+}
+
+impl Outbound {
+    pub fn new(line: String, received: Instant) -> Self {
+        Outbound { line, received }
+    }
+    /// A line the server originates now, such as a beacon or a reply.
+    pub fn now(line: String) -> Self {
+        Outbound::new(line, Instant::now())
+    }
+}
+
+impl PartialEq<&str> for Outbound {
+    fn eq(&self, other: &&str) -> bool {
+        self.line == *other
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Histogram {
+    counts: [AtomicU64; BUCKETS_US.len() + 1],
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    pub fn record(&self, latency: Duration) {
+        let us = latency.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = BUCKETS_US.iter().position(|&bound| us <= bound).unwrap_or(BUCKETS_US.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let counts: Vec<u64> = self.counts.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+        let count: u64 = counts.iter().sum();
+        // Upper bound of the bucket holding the `q` quantile
+        let quantile = |q: f64| {
+            let target = (count as f64 * q).ceil().max(1.0) as u64;
+            let mut seen = 0;
+            for (i, c) in counts.iter().enumerate() {
+                seen += c;
+                if seen >= target {
+                    return BUCKETS_US.get(i).copied().unwrap_or(BUCKETS_US[BUCKETS_US.len() - 1]) as f64 / 1000.0;
+                }
+            }
+            0.0
+        };
+        HistogramSnapshot {
+            count,
+            mean_ms: if count == 0 { 0.0 } else { self.sum_us.load(Ordering::Relaxed) as f64 / count as f64 / 1000.0 },
+            p50_ms: if count == 0 { 0.0 } else { quantile(0.5) },
+            p99_ms: if count == 0 { 0.0 } else { quantile(0.99) },
+            buckets: BUCKETS_US.iter().map(|&us| Some(us as f64 / 1000.0)).chain([None]).zip(counts).collect(),
+        }
+    }
+}
+
+/// Latency figures of one histogram. Quantiles are bucket upper bounds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p99_ms: f64,
+    /// `(upper bound in ms, lines)`, the last bucket without a bound
+    pub buckets: Vec<(Option<f64>, u64)>,
+}
+
+/// One histogram per kind of egress.
+#[derive(Debug, Default)]
+pub struct Latency {
+    pub clients: Arc<Histogram>,
+    pub uplinks: Arc<Histogram>,
+    pub peers: Arc<Histogram>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub clients: HistogramSnapshot,
+    pub uplinks: HistogramSnapshot,
+    pub peers: HistogramSnapshot,
+}
+
+impl Latency {
+    pub fn stats(&self) -> LatencyStats {
+        LatencyStats { clients: self.clients.snapshot(), uplinks: self.uplinks.snapshot(), peers: self.peers.snapshot() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::default();
+        assert_eq!(histogram.snapshot().p99_ms, 0.0);
+        for _ in 0..98 {
+            histogram.record(Duration::from_micros(80));
+        }
+        histogram.record(Duration::from_millis(3));
+        histogram.record(Duration::from_secs(10));
+        let s = histogram.snapshot();
+        assert_eq!(s.count, 100);
+        assert_eq!((s.p50_ms, s.p99_ms), (0.1, 5.0));
+        assert_eq!(s.buckets[0], (Some(0.1), 98));
+        assert_eq!(s.buckets.last(), Some(&(None, 1)));
+        assert!(s.mean_ms > 100.0);
+    }
+}
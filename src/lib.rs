@@ -0,0 +1,777 @@
+//! APRS-IS server library. The `aprsserver` binary is a thin wrapper around
+//! [`Server`]; other projects can embed the hub, filters and packet parser
+//! directly.
+
+use std::io;
+use socket2::{Domain, Protocol as SockProtocol, Socket, Type};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, error, info, warn};
+
+pub mod admin;
+pub mod agwpe;
+pub mod archive;
+pub mod auth_callout;
+pub mod ax25;
+#[cfg(target_os = "linux")]
+pub mod ax25_socket;
+pub mod bans;
+pub mod beacon;
+pub mod client;
+pub mod client_class;
+pub mod clock;
+pub mod config;
+pub mod console;
+pub mod daemon;
+pub mod export;
+pub mod filter;
+pub mod gating;
+pub mod geoip;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hook;
+pub mod hub;
+pub mod kiss;
+pub mod latency;
+pub mod links;
+pub mod logging;
+pub mod login;
+pub mod login_limit;
+pub mod maidenhead;
+pub mod message;
+pub mod message_tracker;
+pub mod mqtt;
+pub mod nws;
+pub mod openapi;
+pub mod packet_log;
+pub mod parser;
+pub mod pipeline;
+pub mod process;
+pub mod qconstruct;
+pub mod position_cache;
+pub mod position_check;
+pub mod query;
+pub mod redis_sink;
+pub mod remote_admin;
+pub mod replay;
+pub mod rf;
+pub mod rf_heard;
+pub mod routing;
+pub mod runtime;
+pub mod s2s;
+pub mod script;
+pub mod server;
+pub mod sink;
+pub mod state_file;
+pub mod station_cache;
+pub mod stats;
+pub mod store;
+pub mod telemetry;
+pub mod testing;
+pub mod tocall;
+pub mod tx_igate;
+pub mod udp_peer;
+pub mod uplink;
+pub mod version;
+pub mod watch;
+pub mod weather;
+pub mod web;
+
+pub use config::Config;
+use config::PeerProtocol;
+pub use filter::ClientFilter;
+pub use hub::Hub;
+pub use parser::{parse, PacketData, ParsedPacket};
+use stats::{ListenerStats, Protocol};
+
+const DEFAULT_S2S_PORT: u16 = 14579;
+const DEFAULT_WEB_ADDR: &str = "0.0.0.0:14501";
+const DEFAULT_STATE_SAVE_SECS: u64 = 300;
+
+pub struct Server;
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            config: Config::default(),
+            bind_host: "0.0.0.0".to_string(),
+            web_addr: Some(DEFAULT_WEB_ADDR.to_string()),
+            hooks: Vec::new(),
+            reload_flag: None,
+            clock: None,
+        }
+    }
+}
+
+pub struct ServerBuilder {
+    config: Config,
+    bind_host: String,
+    web_addr: Option<String>,
+    hooks: Vec<Arc<dyn hook::PacketHook>>,
+    reload_flag: Option<Arc<AtomicBool>>,
+    clock: Option<Arc<dyn clock::Clock>>,
+}
+
+/// Handles to a running server. Listener addresses are the actually bound
+/// ones, so port 0 can be used to get ephemeral ports.
+pub struct ServerHandle {
+    pub hub: Arc<Hub>,
+    pub uplink_status: Arc<Mutex<uplink::UplinkStatus>>,
+    pub user_addr: SocketAddr,
+    pub server_addr: SocketAddr,
+    pub s2s_addr: SocketAddr,
+    /// Bound only when UDP core peers are configured
+    pub udp_peer_addr: Option<SocketAddr>,
+    pub web_addr: Option<SocketAddr>,
+    pub console_addr: Option<SocketAddr>,
+    /// Bound only with the `grpc` feature and `grpc_port` set
+    pub grpc_addr: Option<SocketAddr>,
+    state_file: Option<String>,
+}
+
+impl ServerHandle {
+    /// Writes the state file, if one is configured; call before exiting.
+    pub fn save_state(&self) -> Result<(), String> {
+        match &self.state_file {
+            Some(path) => state_file::save(path, &state_file::SavedState::capture(&self.hub, &self.uplink_status)),
+            None => Ok(()),
+        }
+    }
+    /// Starts and stops uplinks and TCP peers to match a reloaded config.
+    pub fn reload(&self, config: &Config) -> links::LinkChanges {
+        links::apply(&self.hub, config, None)
+    }
+}
+
+impl ServerBuilder {
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+    /// Address the APRS-IS and S2S listeners bind to (default `0.0.0.0`).
+    pub fn bind_host(mut self, host: &str) -> Self {
+        self.bind_host = host.to_string();
+        self
+    }
+    /// Flag the admin console's `reload` command sets, for the embedding
+    /// program to act on like SIGHUP.
+    pub fn reload_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.reload_flag = Some(flag);
+        self
+    }
+    /// Address of the web UI (default `0.0.0.0:14501`); `None` disables it.
+    pub fn web_addr(mut self, addr: Option<&str>) -> Self {
+        self.web_addr = addr.map(|a| a.to_string());
+        self
+    }
+
+    /// Registers a packet hook, called after any hooks named in the config.
+    pub fn hook(mut self, hook: Arc<dyn hook::PacketHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Clock for the hub's time-based logic, e.g. a `clock::MockClock` in
+    /// tests (default: the system clock).
+    pub fn clock(mut self, clock: Arc<dyn clock::Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Binds all listeners and starts the accept loops, uplink and S2S peers
+    /// in the background. Must be called from within a Tokio runtime.
+    pub fn spawn(self) -> io::Result<ServerHandle> {
+        let config = self.config;
+        let mut hub = Hub::new();
+        if let Some(path) = &config.nws_zones_file {
+            match nws::ZoneTable::load(path) {
+                Ok(zones) => {
+                    info!(count = zones.len(), %path, "loaded NWS zones");
+                    hub.nws_zones = Some(zones);
+                }
+                Err(e) => error!(%path, error = %e, "failed to load NWS zones"),
+            }
+        }
+        if let Some(clock) = self.clock {
+            hub.clock = clock;
+        }
+        let saved_state = match config.state_file.as_deref().map(state_file::load) {
+            Some(Ok(state)) => state,
+            Some(Err(e)) => {
+                error!(error = %e, "failed to load state file");
+                None
+            }
+            None => None,
+        };
+        if let Some(state) = &saved_state {
+            info!(since = state.stats_since, heard = state.heard.len(), "restored saved state");
+            state.restore_hub(&mut hub);
+        }
+        for name in config.hooks.iter().flatten() {
+            match hook::builtin(name) {
+                Some(h) => hub.add_hook(h),
+                None => error!(%name, "unknown packet hook"),
+            }
+        }
+        for path in config.scripts.iter().flatten() {
+            match script::ScriptHook::load(path) {
+                Ok(h) => hub.add_hook(Arc::new(h)),
+                Err(e) => error!(error = %e, "failed to load script"),
+            }
+        }
+        if let Some(cfg) = &config.mqtt {
+            let status = Arc::new(Mutex::new(mqtt::MqttStatus {
+                host: cfg.host.clone(),
+                port: cfg.port,
+                ..Default::default()
+            }));
+            let sink = mqtt::MqttSink::start(cfg, status.clone());
+            hub.mqtt_status = Some(status);
+            hub.add_hook(Arc::new(sink));
+        }
+        if let Some(path) = &config.ban_file {
+            match bans::Bans::load(path) {
+                Ok(bans) => *hub.bans.get_mut().unwrap() = bans,
+                Err(e) => {
+                    // Bans made from now on are still saved to the file
+                    error!(error = %e, "failed to load ban file, starting with no bans");
+                    hub.bans.get_mut().unwrap().file = Some(path.clone());
+                }
+            }
+        }
+        hub.server = config.server_info();
+        hub.server_name = hub.server.id.clone().unwrap_or_default();
+        hub.callsign = config
+            .server_beacon
+            .as_ref()
+            .and_then(|b| b.callsign.clone())
+            .unwrap_or_else(|| hub.server_name.clone());
+        if let Some(cfg) = &config.server_beacon {
+            hub.position = beacon::position_report(cfg, hub.server.qth());
+            if hub.position.is_none() {
+                error!("server beacon needs lat and lon, or a [server] QTH");
+            }
+        }
+        hub.remote_admin = config.remote_admin.clone().map(remote_admin::RemoteAdmin::new);
+        hub.invalid_lines = config.invalid_lines.clone();
+        hub.login_limit = config.login_limit.clone().map(login_limit::LoginLimiter::new);
+        hub.passcode_policy = config.passcode_policy;
+        hub.auth_callout = config.auth_callout.clone().map(auth_callout::AuthCallout::new);
+        hub.position_checks = config.position_checks.clone();
+        if let Some(cfg) = &config.dupes {
+            hub.dupe_config = cfg.clone();
+        }
+        if let Some(cfg) = &config.station_cache {
+            hub.station_cache = cfg.clone();
+        }
+        hub.client_defaults = config.client_defaults.clone().unwrap_or_default();
+        if let Some(path) = &config.tocalls_file {
+            match tocall::Tocalls::load(path) {
+                Ok(tocalls) => {
+                    info!(count = tocalls.len(), %path, "loaded TOCALL list");
+                    hub.tocalls = tocalls;
+                }
+                Err(e) => error!(error = %e, "failed to load TOCALL list"),
+            }
+        }
+        for cfg in config.client_classes.iter().flatten() {
+            match client_class::ClientClass::from_config(cfg) {
+                Ok(class) => hub.client_classes.push(Arc::new(class)),
+                Err(e) => error!(error = %e, "invalid client class"),
+            }
+        }
+        for cfg in config.routes.iter().flatten() {
+            match routing::RouteRule::from_config(cfg) {
+                Ok(rule) => hub.routes.push(rule),
+                Err(e) => error!(error = %e, "invalid route"),
+            }
+        }
+        let shard_queues: Vec<_> = match config.ingest_shards {
+            Some(shards) if shards > 0 => {
+                hub.set_cache_shards(shards);
+                (0..shards)
+                    .map(|_| {
+                        let (tx, rx) = std::sync::mpsc::sync_channel(pipeline::INGEST_QUEUE_SIZE);
+                        hub.ingest_shards.push(tx);
+                        rx
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        // Shared with the handle, so it outlives an `[uplink]` removed on reload
+        let uplink_status = Arc::new(Mutex::new(uplink::UplinkStatus::new(&config::UplinkConfig::default())));
+        hub.links = Mutex::new(links::Links::new(uplink_status.clone()));
+        if let Some(secs) = config.s2s_timeout_secs {
+            hub.s2s_timeout_secs = secs;
+        }
+        if let Some(secs) = config.write_timeout_secs {
+            hub.write_timeout = std::time::Duration::from_secs(secs.max(1));
+        }
+        if let Some(minutes) = config.history_minutes {
+            hub.history_secs = minutes * 60;
+        }
+        if let Some(path) = &config.geoip_db {
+            match geoip::GeoIp::open(path) {
+                Ok(db) => hub.geoip = Some(db),
+                Err(e) => error!(error = %e, "failed to open GeoIP database"),
+            }
+        }
+        if let Some(cfg) = &config.packet_log {
+            match packet_log::PacketLog::start(cfg) {
+                Ok(log) => hub.packet_log = Some(log),
+                Err(e) => error!(error = %e, "failed to start packet log"),
+            }
+        }
+        if let Some(path) = &config.station_db {
+            match store::StationStore::open(path).and_then(|s| s.load_positions().map(|p| (s, p))) {
+                Ok((store, positions)) => {
+                    info!(count = positions.len(), %path, "loaded station positions");
+                    for position in positions {
+                        hub.positions.insert(position, None);
+                    }
+                    hub.add_hook(Arc::new(store.into_sink(hub.clock.clone())));
+                }
+                Err(e) => error!(error = %e, "failed to open station database"),
+            }
+        }
+        if let Some(cfg) = &config.archive {
+            let status = Arc::new(Mutex::new(archive::ArchiveStatus::default()));
+            let sink = archive::ArchiveSink::start(cfg, status.clone());
+            hub.archive_status = Some(status);
+            hub.add_hook(Arc::new(sink));
+        }
+        if let Some(cfg) = &config.redis {
+            let status = Arc::new(Mutex::new(redis_sink::RedisStatus::default()));
+            let sink = redis_sink::RedisSink::start(cfg, status.clone(), hub.clock.clone());
+            hub.redis_status = Some(status);
+            hub.add_hook(Arc::new(sink));
+        }
+        for h in self.hooks {
+            hub.add_hook(h);
+        }
+        // Bind everything up front so errors are reported to the caller
+        let s2s_listener = TcpListener::bind((self.bind_host.as_str(), config.s2s_port.unwrap_or(DEFAULT_S2S_PORT)))?;
+        let user_listeners = bind_listeners(&self.bind_host, config.user_port, config.user_acceptors.unwrap_or(1))?;
+        let server_listeners = bind_listeners(&self.bind_host, config.server_port, config.server_acceptors.unwrap_or(1))?;
+        let (user_listener, server_listener) = (&user_listeners[0], &server_listeners[0]);
+        let has_udp_peers = config.s2s_peers.iter().flatten().any(|p| p.protocol == PeerProtocol::Udp);
+        let udp_peer_socket = if has_udp_peers {
+            let socket = UdpSocket::bind((self.bind_host.as_str(), config.udp_peer_port.unwrap_or(config.server_port)))?;
+            socket.set_nonblocking(true)?;
+            Some(socket)
+        } else {
+            None
+        };
+        let web_listener = match &self.web_addr {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Some(listener)
+            }
+            None => None,
+        };
+        let console_listener = config.console_port.map(|port| TcpListener::bind(("127.0.0.1", port))).transpose()?;
+        let grpc_listener = match config.grpc_port {
+            Some(port) if cfg!(feature = "grpc") => {
+                let listener = TcpListener::bind((self.bind_host.as_str(), port))?;
+                listener.set_nonblocking(true)?;
+                Some(listener)
+            }
+            Some(_) => {
+                warn!("grpc_port is set but this build lacks the grpc feature");
+                None
+            }
+            None => None,
+        };
+        for (listener, filter) in [(user_listener, &config.user_filter), (server_listener, &config.server_filter)] {
+            let Some(filter) = filter else {
+                continue;
+            };
+            match filter::parse_filter_list(filter) {
+                Ok(filters) => {
+                    let filters = Arc::new(filter::FilterSet::new(filters));
+                    hub.listener_filters.insert(listener.local_addr()?.port(), (filter.clone(), filters));
+                }
+                Err(e) => error!(error = %e, "invalid listener filter"),
+            }
+        }
+        let s2s_stats = Arc::new(ListenerStats::new("S2S", Protocol::Tcp, s2s_listener.local_addr()?.port()));
+        let user_stats = Arc::new(ListenerStats::new("User", Protocol::Tcp, user_listener.local_addr()?.port()));
+        let server_stats = Arc::new(ListenerStats::new("Server", Protocol::Tcp, server_listener.local_addr()?.port()));
+        hub.listeners = vec![user_stats.clone(), server_stats.clone(), s2s_stats.clone()];
+        if let Some(socket) = &udp_peer_socket {
+            hub.listeners.push(Arc::new(ListenerStats::new("UDP", Protocol::Udp, socket.local_addr()?.port())));
+        }
+        let rf_status = config.rf.as_ref().map(|cfg| Arc::new(Mutex::new(rf::RfStatus { interface: rf::describe(cfg), ..Default::default() })));
+        hub.rf_status = rf_status.clone();
+        if let (Some(cfg), Some(status)) = (&config.rf, &rf_status)
+            && let Some(tx) = &cfg.tx
+        {
+            let igate = cfg.callsign.as_deref().unwrap_or(&hub.callsign);
+            match tx_igate::TxIgate::new(tx.clone(), cfg.interface, igate, status.clone()) {
+                Ok(tx) => hub.tx_igate = Some(Arc::new(tx)),
+                Err(e) => error!(error = %e, "RF transmit disabled"),
+            }
+        }
+        let hub = Arc::new(hub);
+        // Started first so the hub can send to the uplinks right away
+        links::apply(&hub, &config, saved_state.as_ref());
+        if let (Some(cfg), Some(status)) = (&config.rf, rf_status) {
+            let igate = cfg.callsign.clone().unwrap_or_else(|| hub.callsign.clone());
+            rf::spawn(cfg.clone(), igate, hub.clone(), status);
+        }
+        for (i, queue) in shard_queues.into_iter().enumerate() {
+            let hub = hub.clone();
+            std::thread::Builder::new().name(format!("ingest-{}", i)).spawn(move || pipeline::run_shard(queue, hub))?;
+        }
+        if let Some(state) = &saved_state {
+            state.restore_uplink(&mut uplink_status.lock().unwrap());
+        }
+
+        let handle = ServerHandle {
+            hub: hub.clone(),
+            uplink_status: uplink_status.clone(),
+            user_addr: user_listener.local_addr()?,
+            server_addr: server_listener.local_addr()?,
+            s2s_addr: s2s_listener.local_addr()?,
+            udp_peer_addr: udp_peer_socket.as_ref().map(|s| s.local_addr()).transpose()?,
+            web_addr: web_listener.as_ref().map(|l| l.local_addr()).transpose()?,
+            console_addr: console_listener.as_ref().map(|l| l.local_addr()).transpose()?,
+            grpc_addr: grpc_listener.as_ref().map(|l| l.local_addr()).transpose()?,
+            state_file: config.state_file.clone(),
+        };
+        if let Some(listener) = console_listener {
+            info!(port = listener.local_addr()?.port(), "admin console started");
+            let console = console::Console {
+                hub: hub.clone(),
+                uplink_status: uplink_status.clone(),
+                reload: self.reload_flag.clone(),
+            };
+            console::spawn(listener, console);
+        }
+
+        // Start web UI in background
+        if let Some(listener) = web_listener {
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            let state = web::AppState::new(hub.clone(), uplink_status.clone(), config.admin_token.clone());
+            tokio::spawn(web::serve_web_listener(listener, state));
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Some(listener) = grpc_listener {
+            tokio::spawn(grpc::serve(tokio::net::TcpListener::from_std(listener)?, hub.clone()));
+        }
+
+        // Sample statistics history
+        let stats_hub = hub.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(stats::STATS_INTERVAL_SECS));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                stats_hub.sample_stats();
+                stats_hub.prune_heard();
+            }
+        });
+
+        // Update rolling rate averages
+        let rates_hub = hub.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(stats::RATE_INTERVAL_SECS));
+            loop {
+                interval.tick().await;
+                rates_hub.update_rates();
+                rates_hub.process.lock().unwrap().sample();
+                for route in rates_hub.uplinks.read().unwrap().iter() {
+                    route.status.lock().unwrap().update_rates();
+                }
+            }
+        });
+
+        if let Some(path) = config.state_file.clone() {
+            let state_hub = hub.clone();
+            let state_uplink = uplink_status.clone();
+            let secs = config.state_save_secs.unwrap_or(DEFAULT_STATE_SAVE_SECS).max(1);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(std::time::Duration::from_secs(secs));
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = state_file::save(&path, &state_file::SavedState::capture(&state_hub, &state_uplink)) {
+                        warn!(error = %e, "failed to save state file");
+                    }
+                }
+            });
+        }
+
+        if let Some(cfg) = config.replay.clone() {
+            replay::spawn(cfg, hub.clone());
+        }
+
+        let mut beacons: Vec<beacon::Beacon> =
+            config.server_beacon.iter().filter_map(|cfg| beacon::Beacon::server(cfg, hub.server.qth(), &hub.callsign)).collect();
+        for cfg in config.beacons.iter().flatten() {
+            match beacon::Beacon::from_config(cfg) {
+                Ok(b) => beacons.push(b),
+                Err(e) => error!(error = %e, "invalid beacon"),
+            }
+        }
+        if !beacons.is_empty() {
+            tokio::spawn(beacon::run(beacons, hub.clone()));
+        }
+
+        // TCP peers are started with the uplinks; UDP ones share a socket
+        if let Some(s2s_peers) = config.s2s_peers.clone() {
+            let mut udp_peers = Vec::new();
+            for peer_cfg in s2s_peers.into_iter().filter(|p| p.protocol == PeerProtocol::Udp) {
+                let mut status = hub::S2SPeerStatus::new(peer_cfg.host.clone(), peer_cfg.port, peer_cfg.peer_name.clone());
+                if let Some(state) = &saved_state {
+                    state.restore_peer(&mut status);
+                }
+                let status = Arc::new(Mutex::new(status));
+                hub.s2s_peers.write().unwrap().push(status.clone());
+                udp_peers.push((peer_cfg, status));
+            }
+            if let Some(socket) = udp_peer_socket {
+                info!(port = socket.local_addr()?.port(), "UDP core peering started");
+                tokio::spawn(udp_peer::run(socket, udp_peers, hub.clone()));
+            }
+        }
+
+        info!(port = handle.s2s_addr.port(), "S2S listener started");
+        accept_loop(s2s_listener, hub.clone(), s2s_stats, s2s::s2s_server_handler);
+        info!(
+            server = %hub.server_name,
+            version = %version::long_version(),
+            user_port = handle.user_addr.port(),
+            server_port = handle.server_addr.port(),
+            "listening"
+        );
+        for listener in server_listeners {
+            accept_loop(listener, hub.clone(), server_stats.clone(), server::handle_client);
+        }
+        for listener in user_listeners {
+            accept_loop(listener, hub.clone(), user_stats.clone(), server::handle_client);
+        }
+        Ok(handle)
+    }
+}
+
+/// Binds `count` listeners to one port. With more than one, each is bound
+/// with SO_REUSEPORT and gets its own accept thread, and the kernel spreads
+/// new connections over them. Port 0 picks a port for the first.
+fn bind_listeners(host: &str, port: u16, count: usize) -> io::Result<Vec<TcpListener>> {
+    if count <= 1 {
+        return Ok(vec![TcpListener::bind((host, port))?]);
+    }
+    let addr = (host, port).to_socket_addrs()?.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to bind"))?;
+    let mut listeners: Vec<TcpListener> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let addr = listeners.first().map_or(Ok(addr), |l| l.local_addr())?;
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(SockProtocol::TCP))?;
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        listeners.push(socket.into());
+    }
+    Ok(listeners)
+}
+
+fn accept_loop(listener: TcpListener, hub: Arc<Hub>, stats: Arc<ListenerStats>, handler: fn(TcpStream, Arc<Hub>)) {
+    let name = stats.name;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Ok(addr) = stream.peer_addr()
+                        && hub.bans.read().unwrap().is_ip_banned(addr.ip())
+                    {
+                        info!(port = name, %addr, "rejected banned address");
+                        continue;
+                    }
+                    if let (Ok(addr), Some(limit)) = (stream.peer_addr(), &hub.login_limit)
+                        && limit.is_blocked(addr.ip(), hub.clock.now())
+                    {
+                        debug!(port = name, %addr, "rejected address blocked after failed logins");
+                        continue;
+                    }
+                    let hub = hub.clone();
+                    let stats = stats.clone();
+                    stats.connected();
+                    std::thread::spawn(move || {
+                        handler(stream, hub);
+                        stats.disconnected();
+                    });
+                }
+                Err(e) => {
+                    warn!(port = name, error = %e, "connection failed");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+
+    #[tokio::test]
+    async fn test_spawn_on_ephemeral_ports() {
+        let config = Config { user_port: 0, server_port: 0, s2s_port: Some(0), ..Config::default() };
+        let handle = Server::builder().config(config).bind_host("127.0.0.1").web_addr(None).spawn().unwrap();
+        assert_ne!(handle.user_addr.port(), 0);
+        let mut stream = TcpStream::connect(handle.user_addr).unwrap();
+        let login = format!("user N0CALL pass {} vers test 1.0\n", server::aprs_passcode("N0CALL"));
+        stream.write_all(login.as_bytes()).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, format!("# {}\r\n", version::SOFTWARE));
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "# logresp N0CALL verified, server NOCALL\r\n");
+        assert_eq!(handle.hub.client_count(), 1);
+        let clients = handle.hub.clients.read().unwrap();
+        let client = clients.values().next().unwrap().lock().unwrap();
+        assert_eq!(client.callsign.as_deref(), Some("N0CALL"));
+        assert_eq!(client.software.as_deref(), Some("test 1.0"));
+        assert!(client.verified);
+    }
+
+    #[tokio::test]
+    async fn test_listener_filter() {
+        let config = Config { user_port: 0, server_port: 0, s2s_port: Some(0), user_filter: Some("p/OH".to_string()), ..Config::default() };
+        let handle = Server::builder().config(config).bind_host("127.0.0.1").web_addr(None).spawn().unwrap();
+        let mut stream = TcpStream::connect(handle.user_addr).unwrap();
+        let login = format!("user N0CALL pass {}\n", server::aprs_passcode("N0CALL"));
+        stream.write_all(login.as_bytes()).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        // Greeting and logresp
+        for _ in 0..3 {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+        assert_eq!(line, "# server filter p/OH applies to this port\n");
+        for packet in ["N0CALL>APRS:>elsewhere", "OH2XYZ>APRS:>in range"] {
+            pipeline::ingest(&handle.hub, pipeline::Source::Local, packet).unwrap();
+        }
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "OH2XYZ>APRS:>in range\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_open_passcode_policy() {
+        let config = Config { user_port: 0, server_port: 0, s2s_port: Some(0), passcode_policy: config::PasscodePolicy::Open, ..Config::default() };
+        let handle = Server::builder().config(config).bind_host("127.0.0.1").web_addr(None).spawn().unwrap();
+        let mut stream = TcpStream::connect(handle.user_addr).unwrap();
+        stream.write_all(b"user N0CALL vers test 1.0\nN0CALL>APRS:>from the lab\n").unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        for _ in 0..2 {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+        assert_eq!(line, "# logresp N0CALL unverified, server NOCALL\r\n");
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(2);
+        while handle.hub.counters.totals().decisions.accepted == 0 && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(handle.hub.counters.totals().decisions.accepted, 1);
+        let clients = handle.hub.clients.read().unwrap();
+        assert!(!clients.values().next().unwrap().lock().unwrap().verified);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_auth_callout() {
+        async fn auth(axum::Json(login): axum::Json<serde_json::Value>) -> axum::response::Response {
+            use axum::response::IntoResponse;
+            assert_eq!(login["ip"], "127.0.0.1");
+            match login["callsign"].as_str().unwrap() {
+                "N0CALL" => axum::Json(serde_json::json!({"result": "verified", "class": "members"})).into_response(),
+                "N1CALL" => axum::Json(serde_json::json!({"result": "denied", "message": "not a member\nN1CALL>APRS:>x"})).into_response(),
+                _ => axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+        }
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/auth", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, axum::Router::new().route("/auth", axum::routing::post(auth))).await });
+        let callout = config::AuthCalloutConfig { url, timeout_secs: 5, on_error: config::AuthFallback::Deny };
+        let members: config::ClientClassConfig = toml::from_str("name = \"members\"\nports = [1]").unwrap();
+        let config = Config {
+            user_port: 0,
+            server_port: 0,
+            s2s_port: Some(0),
+            auth_callout: Some(callout),
+            client_classes: Some(vec![members]),
+            ..Config::default()
+        };
+        let handle = Server::builder().config(config).bind_host("127.0.0.1").web_addr(None).spawn().unwrap();
+        // The reply after the greeting, up to `lines` lines, and the connection
+        let attempt = |login: &'static str, lines: usize| {
+            let addr = handle.user_addr;
+            tokio::task::spawn_blocking(move || {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                stream.write_all(login.as_bytes()).unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut reply = String::new();
+                reader.read_line(&mut reply).unwrap();
+                reply.clear();
+                for _ in 0..lines {
+                    reader.read_line(&mut reply).unwrap();
+                }
+                (reply, reader)
+            })
+        };
+        // The passcode is left to the endpoint
+        let (reply, _conn) = attempt("user N0CALL pass 1\n", 1).await.unwrap();
+        assert_eq!(reply, "# logresp N0CALL verified, server NOCALL\r\n");
+        let clients = handle.hub.clients.read().unwrap().values().cloned().collect::<Vec<_>>();
+        assert_eq!(clients[0].lock().unwrap().class.as_ref().unwrap().name, "members");
+        assert_eq!(attempt("user N1CALL pass 1\n", 2).await.unwrap().0, "# logresp N1CALL unverified, server NOCALL\r\n# not a member\n");
+        assert_eq!(attempt("user N2CALL pass 1\n", 1).await.unwrap().0, "# authentication unavailable, try again later\n");
+    }
+
+    #[tokio::test]
+    async fn test_login_limit() {
+        let login_limit = config::LoginLimitConfig { max_failures: 2, window_secs: 60, block_secs: 600 };
+        let config = Config { user_port: 0, server_port: 0, s2s_port: Some(0), login_limit: Some(login_limit), ..Config::default() };
+        let handle = Server::builder().config(config).bind_host("127.0.0.1").web_addr(None).spawn().unwrap();
+        let attempt = |login: &str| {
+            let mut stream = TcpStream::connect(handle.user_addr).unwrap();
+            let _ = stream.write_all(login.as_bytes());
+            let mut out = String::new();
+            let _ = std::io::Read::read_to_string(&mut stream, &mut out);
+            out
+        };
+        assert!(attempt("user N0CALL pass 1\n").ends_with("# invalid passcode\n"));
+        assert!(attempt("GET / HTTP/1.0\n").ends_with("# invalid login\n"));
+        // Refused at accept, without a greeting
+        assert_eq!(attempt(&format!("user N0CALL pass {}\n", server::aprs_passcode("N0CALL"))), "");
+        let stats = handle.hub.login_limit.as_ref().unwrap().stats(std::time::Instant::now());
+        assert_eq!((stats.passcode_failures, stats.invalid_logins, stats.blocks, stats.rejected), (1, 1, 1, 1));
+        assert_eq!(stats.blocked[0].ip.to_string(), "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_reuseport_acceptors() {
+        let listeners = bind_listeners("127.0.0.1", 0, 3).unwrap();
+        let port = listeners[0].local_addr().unwrap().port();
+        assert!(listeners.iter().all(|l| l.local_addr().unwrap().port() == port));
+        drop(listeners);
+
+        let config = Config { user_port: 0, server_port: 0, s2s_port: Some(0), user_acceptors: Some(4), ..Config::default() };
+        let handle = Server::builder().config(config).bind_host("127.0.0.1").web_addr(None).spawn().unwrap();
+        for _ in 0..8 {
+            let mut reader = BufReader::new(TcpStream::connect(handle.user_addr).unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, format!("# {}\r\n", version::SOFTWARE));
+        }
+    }
+}
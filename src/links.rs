@@ -0,0 +1,264 @@
+//! The uplink and TCP S2S peer connections. They are started from the
+//! config and can be added and removed while the server runs, on a config
+//! reload or through the admin API. UDP core peers share one socket and
+//! are fixed at startup.
+
+use crate::config::{Config, PeerProtocol, S2SPeerConfig, UplinkConfig};
+use crate::hub::{Hub, S2SPeerStatus};
+use crate::state_file::SavedState;
+use crate::uplink::{UplinkRoute, UplinkStatus};
+use std::sync::{Arc, Mutex};
+use tokio::task::AbortHandle;
+use tracing::{error, info};
+
+struct RunningUplink {
+    network: Option<String>,
+    cfg: UplinkConfig,
+    task: AbortHandle,
+}
+
+struct RunningPeer {
+    cfg: S2SPeerConfig,
+    status: Arc<Mutex<S2SPeerStatus>>,
+    task: AbortHandle,
+}
+
+pub struct Links {
+    /// Status of `[uplink]`, kept while none is configured
+    primary_status: Arc<Mutex<UplinkStatus>>,
+    uplinks: Vec<RunningUplink>,
+    peers: Vec<RunningPeer>,
+}
+
+/// How many connections a reload started and stopped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LinkChanges {
+    pub started: usize,
+    pub stopped: usize,
+}
+
+/// `peer_name`, or `host:port` for a peer without one.
+pub fn peer_key(cfg: &S2SPeerConfig) -> String {
+    cfg.peer_name.clone().unwrap_or_else(|| format!("{}:{}", cfg.host, cfg.port))
+}
+
+impl Links {
+    pub fn new(primary_status: Arc<Mutex<UplinkStatus>>) -> Self {
+        Links { primary_status, uplinks: Vec::new(), peers: Vec::new() }
+    }
+}
+
+impl Default for Links {
+    fn default() -> Self {
+        Links::new(Arc::new(Mutex::new(UplinkStatus::new(&UplinkConfig::default()))))
+    }
+}
+
+fn start_uplink(hub: &Arc<Hub>, links: &mut Links, cfg: UplinkConfig, network: Option<String>) -> Result<(), String> {
+    let status = match network {
+        None => {
+            *links.primary_status.lock().unwrap() = UplinkStatus::new(&cfg);
+            links.primary_status.clone()
+        }
+        Some(_) => Arc::new(Mutex::new(UplinkStatus::new(&cfg))),
+    };
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    hub.uplinks.write().unwrap().push(UplinkRoute::new(&cfg, network.clone(), tx, status.clone())?);
+    let task = tokio::spawn(crate::uplink::connect_and_run(cfg.clone(), network.clone(), hub.clone(), status, rx)).abort_handle();
+    links.uplinks.push(RunningUplink { network, cfg, task });
+    Ok(())
+}
+
+fn stop_uplink(hub: &Hub, links: &Links, uplink: RunningUplink) {
+    uplink.task.abort();
+    hub.uplinks.write().unwrap().retain(|route| route.network != uplink.network);
+    if uplink.network.is_none() {
+        *links.primary_status.lock().unwrap() = UplinkStatus::new(&UplinkConfig::default());
+    }
+    info!(uplink = uplink.cfg.name(), "uplink stopped");
+}
+
+fn start_peer(hub: &Arc<Hub>, links: &mut Links, cfg: S2SPeerConfig, saved: Option<&SavedState>) {
+    let mut status = S2SPeerStatus::new(cfg.host.clone(), cfg.port, cfg.peer_name.clone());
+    if let Some(state) = saved {
+        state.restore_peer(&mut status);
+    }
+    let status = Arc::new(Mutex::new(status));
+    hub.s2s_peers.write().unwrap().push(status.clone());
+    let task = tokio::spawn(crate::s2s::connect_s2s_peer(cfg.clone(), status.clone(), hub.clone())).abort_handle();
+    links.peers.push(RunningPeer { cfg, status, task });
+}
+
+fn stop_peer(hub: &Hub, peer: RunningPeer) {
+    peer.task.abort();
+    // The aborted session drops its own handle and writer task
+    hub.s2s_peers.write().unwrap().retain(|status| !Arc::ptr_eq(status, &peer.status));
+    info!(peer = %peer_key(&peer.cfg), "S2S peer stopped");
+}
+
+/// Brings the running uplinks and TCP peers in line with `config`. Ones no
+/// longer configured, or configured differently, are torn down; new ones
+/// are started, with their counters from `saved` if given.
+pub fn apply(hub: &Arc<Hub>, config: &Config, saved: Option<&SavedState>) -> LinkChanges {
+    let mut links = hub.links.lock().unwrap();
+    let mut changes = LinkChanges::default();
+
+    let wanted: Vec<(UplinkConfig, Option<String>)> = config
+        .uplink
+        .iter()
+        .map(|cfg| (cfg.clone(), None))
+        .chain(config.uplinks.iter().flatten().map(|cfg| (cfg.clone(), Some(cfg.name().to_string()))))
+        .collect();
+    let (keep, stop): (Vec<_>, Vec<_>) =
+        links.uplinks.drain(..).partition(|u| wanted.iter().any(|(cfg, network)| *network == u.network && *cfg == u.cfg));
+    links.uplinks = keep;
+    for uplink in stop {
+        stop_uplink(hub, &links, uplink);
+        changes.stopped += 1;
+    }
+    for (cfg, network) in wanted {
+        if links.uplinks.iter().any(|u| u.network == network) {
+            continue;
+        }
+        match start_uplink(hub, &mut links, cfg, network) {
+            Ok(()) => changes.started += 1,
+            Err(e) => error!(error = %e, "invalid uplink filter, not connecting"),
+        }
+    }
+
+    let wanted: Vec<&S2SPeerConfig> = config.s2s_peers.iter().flatten().filter(|p| p.protocol == PeerProtocol::Tcp).collect();
+    let (keep, stop): (Vec<_>, Vec<_>) = links.peers.drain(..).partition(|p| wanted.iter().any(|cfg| **cfg == p.cfg));
+    links.peers = keep;
+    for peer in stop {
+        stop_peer(hub, peer);
+        changes.stopped += 1;
+    }
+    for cfg in wanted {
+        if !links.peers.iter().any(|p| peer_key(&p.cfg) == peer_key(cfg)) {
+            start_peer(hub, &mut links, cfg.clone(), saved);
+            changes.started += 1;
+        }
+    }
+    // Every configured peer, UDP ones included, may appear in paths
+    *hub.peer_ids.write().unwrap() =
+        config.s2s_peers.iter().flatten().filter_map(|p| p.peer_name.as_ref()).map(|n| n.to_uppercase()).collect();
+    changes
+}
+
+/// Starts a TCP peer that is not in the config, until the next reload.
+pub fn add_peer(hub: &Arc<Hub>, cfg: S2SPeerConfig) -> Result<(), String> {
+    if cfg.protocol != PeerProtocol::Tcp {
+        return Err("UDP peers can only be configured at startup".to_string());
+    }
+    let mut links = hub.links.lock().unwrap();
+    let key = peer_key(&cfg);
+    if links.peers.iter().any(|p| peer_key(&p.cfg) == key) {
+        return Err(format!("peer {} is already running", key));
+    }
+    if let Some(name) = &cfg.peer_name {
+        hub.peer_ids.write().unwrap().insert(name.to_uppercase());
+    }
+    start_peer(hub, &mut links, cfg, None);
+    Ok(())
+}
+
+/// Tears down the TCP peer named `key`, see [`peer_key`]. Returns whether
+/// there was one.
+pub fn remove_peer(hub: &Hub, key: &str) -> bool {
+    let mut links = hub.links.lock().unwrap();
+    let Some(i) = links.peers.iter().position(|p| peer_key(&p.cfg) == key) else {
+        return false;
+    };
+    let peer = links.peers.remove(i);
+    if let Some(name) = &peer.cfg.peer_name {
+        hub.peer_ids.write().unwrap().remove(&name.to_uppercase());
+    }
+    stop_peer(hub, peer);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(name: &str, port: u16) -> S2SPeerConfig {
+        S2SPeerConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            passcode: 0,
+            peer_name: Some(name.to_string()),
+            filter: None,
+            request_filter: false,
+            protocol: PeerProtocol::Tcp,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let hub = Arc::new(Hub::new());
+        let uplink = |name: &str| UplinkConfig { name: Some(name.to_string()), host: "127.0.0.1".to_string(), port: 1, ..Default::default() };
+        let mut config = Config { uplinks: Some(vec![uplink("a"), uplink("b")]), s2s_peers: Some(vec![peer("T2A", 1)]), ..Config::default() };
+        assert_eq!(apply(&hub, &config, None), LinkChanges { started: 3, stopped: 0 });
+        assert_eq!(apply(&hub, &config, None), LinkChanges::default());
+        assert!(hub.peer_ids.read().unwrap().contains("T2A"));
+
+        // Dropped, changed and new entries
+        config.uplinks = Some(vec![UplinkConfig { port: 2, ..uplink("b") }]);
+        config.s2s_peers = Some(vec![peer("T2B", 1)]);
+        assert_eq!(apply(&hub, &config, None), LinkChanges { started: 2, stopped: 3 });
+        let networks: Vec<_> = hub.uplinks.read().unwrap().iter().map(|r| r.network.clone()).collect();
+        assert_eq!(networks, [Some("b".to_string())]);
+        assert_eq!(hub.s2s_peers.read().unwrap().len(), 1);
+        assert!(!hub.peer_ids.read().unwrap().contains("T2A"));
+
+        assert!(add_peer(&hub, peer("T2B", 2)).is_err());
+        assert!(add_peer(&hub, peer("T2C", 1)).is_ok());
+        assert_eq!(hub.s2s_peers.read().unwrap().len(), 2);
+        assert!(remove_peer(&hub, "T2C"));
+        assert!(!remove_peer(&hub, "T2C"));
+        assert_eq!(hub.s2s_peers.read().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_remove_unnamed_peer() {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+        let hub = Arc::new(Hub::new());
+        let mut peers = Vec::new();
+        for _ in 0..2 {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            add_peer(&hub, S2SPeerConfig { peer_name: None, ..peer("", port) }).unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            reader.get_mut().write_all(b"# aprsc 2.1.5 s2s T2PEER 12345 14579\n").await.unwrap();
+            peers.push((port, reader));
+        }
+        let wait_for = |count: usize| {
+            let hub = hub.clone();
+            async move {
+                while hub.s2s_peer_handles.read().unwrap().len() != count {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(5), wait_for(2)).await.unwrap();
+
+        let (port, mut stopped) = peers.remove(0);
+        assert!(remove_peer(&hub, &format!("127.0.0.1:{}", port)));
+        tokio::time::timeout(std::time::Duration::from_secs(5), wait_for(1)).await.unwrap();
+        // The stopped session's connection is closed, the other still relays
+        let mut rest = Vec::new();
+        let closed = tokio::time::timeout(std::time::Duration::from_secs(5), stopped.read_to_end(&mut rest)).await;
+        assert!(matches!(closed, Ok(Ok(_))));
+        let client = crate::pipeline::Source::Client { id: 1, callsign: Some("N0CALL"), verified: true, class: None };
+        crate::pipeline::ingest(&hub, client, "N0CALL>APRS,TCPIP*:>still here").unwrap();
+        let (_, running) = &mut peers[0];
+        let mut line = String::new();
+        while !line.contains("still here") {
+            line.clear();
+            tokio::time::timeout(std::time::Duration::from_secs(5), running.read_line(&mut line)).await.unwrap().unwrap();
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use crate::config::{LogFileRotation, LoggingConfig, SyslogConfig};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, OnceLock};
+use tracing::{Level, Metadata};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+// Set by `init`, lets the level directives be changed at runtime
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Installs the global tracing subscriber. `level` takes `RUST_LOG` style
+/// directives such as `info,aprsserver::s2s=debug`, and a `RUST_LOG`
+/// environment variable overrides it. With file logging the returned guard
+/// must be kept alive, or buffered lines are lost on exit.
+pub fn init(cfg: &LoggingConfig) -> Result<Option<WorkerGuard>, String> {
+    let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| cfg.level.clone());
+    let filter = EnvFilter::try_new(&directives).map_err(|e| format!("{}: {}", directives, e))?;
+    let (filter, handle) = reload::Layer::new(filter);
+    let registry = tracing_subscriber::registry().with(filter);
+    if cfg.journald {
+        let journald = tracing_journald::layer().map_err(|e| format!("journald: {}", e))?;
+        registry.with(journald).try_init().map_err(|e| e.to_string())?;
+        let _ = FILTER.set(handle);
+        return Ok(None);
+    }
+    if let Some(syslog) = &cfg.syslog {
+        // Syslog adds its own timestamp and carries the level in the priority
+        let writer = SyslogMakeWriter::connect(syslog)?;
+        let layer = fmt::layer().with_writer(writer).with_ansi(false).without_time().with_level(false);
+        registry.with(layer).try_init().map_err(|e| e.to_string())?;
+        let _ = FILTER.set(handle);
+        return Ok(None);
+    }
+    let Some(dir) = &cfg.dir else {
+        registry.with(fmt::layer()).try_init().map_err(|e| e.to_string())?;
+        let _ = FILTER.set(handle);
+        return Ok(None);
+    };
+    let rotation = match cfg.rotation {
+        LogFileRotation::Hourly => Rotation::HOURLY,
+        LogFileRotation::Daily => Rotation::DAILY,
+        LogFileRotation::Never => Rotation::NEVER,
+    };
+    let mut appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(&cfg.file_prefix)
+        .filename_suffix("log");
+    if let Some(max_files) = cfg.max_files {
+        appender = appender.max_log_files(max_files);
+    }
+    let appender = appender.build(dir).map_err(|e| format!("{}: {}", dir, e))?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    registry.with(fmt::layer().with_writer(writer).with_ansi(false)).try_init().map_err(|e| e.to_string())?;
+    let _ = FILTER.set(handle);
+    Ok(Some(guard))
+}
+
+/// The level directives in effect, if logging was set up by `init`.
+pub fn level() -> Option<String> {
+    FILTER.get()?.with_current(|filter| filter.to_string()).ok()
+}
+
+/// Replaces the level directives, e.g. `info,aprsserver::s2s=debug`.
+pub fn set_level(directives: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(directives).map_err(|e| format!("{}: {}", directives, e))?;
+    let handle = FILTER.get().ok_or("logging is not managed by this server")?;
+    handle.reload(filter).map_err(|e| e.to_string())
+}
+
+fn facility_code(name: &str) -> Result<u8, String> {
+    let code = match name {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "lpr" => 6,
+        "news" => 7,
+        "uucp" => 8,
+        "cron" => 9,
+        "authpriv" => 10,
+        "ftp" => 11,
+        _ => match name.strip_prefix("local").and_then(|n| n.parse::<u8>().ok()) {
+            Some(n) if n <= 7 => 16 + n,
+            _ => return Err(format!("unknown syslog facility {}", name)),
+        },
+    };
+    Ok(code)
+}
+
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+enum SyslogSocket {
+    Udp(UdpSocket),
+    Unix(UnixDatagram),
+}
+
+/// Sends each log event as one syslog datagram, `<PRI>tag[pid]: message`.
+struct SyslogMakeWriter {
+    socket: Arc<SyslogSocket>,
+    facility: u8,
+    tag: String,
+}
+
+impl SyslogMakeWriter {
+    fn connect(cfg: &SyslogConfig) -> Result<Self, String> {
+        let socket = match &cfg.address {
+            Some(address) => {
+                let target = address
+                    .to_socket_addrs()
+                    .map_err(|e| format!("{}: {}", address, e))?
+                    .next()
+                    .ok_or_else(|| format!("{}: no address", address))?;
+                // The local end must be of the target's family
+                let local: SocketAddr = match target {
+                    SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                    SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+                };
+                let socket = UdpSocket::bind(local).map_err(|e| e.to_string())?;
+                socket.connect(target).map_err(|e| format!("{}: {}", address, e))?;
+                SyslogSocket::Udp(socket)
+            }
+            None => {
+                let socket = UnixDatagram::unbound().map_err(|e| e.to_string())?;
+                socket.connect("/dev/log").map_err(|e| format!("/dev/log: {}", e))?;
+                SyslogSocket::Unix(socket)
+            }
+        };
+        Ok(SyslogMakeWriter { socket: Arc::new(socket), facility: facility_code(&cfg.facility)?, tag: cfg.tag.clone() })
+    }
+
+    fn writer(&self, severity: u8) -> SyslogWriter {
+        let prefix = format!("<{}>{}[{}]: ", self.facility * 8 + severity, self.tag, std::process::id());
+        SyslogWriter { socket: self.socket.clone(), message: prefix.into_bytes() }
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogMakeWriter {
+    type Writer = SyslogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer(6)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.writer(severity(meta.level()))
+    }
+}
+
+/// Collects one formatted event and sends it when dropped.
+struct SyslogWriter {
+    socket: Arc<SyslogSocket>,
+    message: Vec<u8>,
+}
+
+impl io::Write for SyslogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.message.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogWriter {
+    fn drop(&mut self) {
+        while self.message.last() == Some(&b'\n') {
+            self.message.pop();
+        }
+        let _ = match &*self.socket {
+            SyslogSocket::Udp(socket) => socket.send(&self.message),
+            SyslogSocket::Unix(socket) => socket.send(&self.message),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_set_level_validates() {
+        assert!(set_level("aprsserver=loud").unwrap_err().starts_with("aprsserver=loud"));
+    }
+
+    #[test]
+    fn test_facility_code() {
+        assert_eq!(facility_code("daemon"), Ok(3));
+        assert_eq!(facility_code("local7"), Ok(23));
+        assert!(facility_code("local8").is_err());
+    }
+
+    #[test]
+    fn test_syslog_datagram() {
+        for server in ["127.0.0.1:0", "[::1]:0"] {
+            let server = UdpSocket::bind(server).unwrap();
+            let cfg = SyslogConfig {
+                address: Some(server.local_addr().unwrap().to_string()),
+                facility: "local0".to_string(),
+                tag: "aprsserver".to_string(),
+            };
+            let make_writer = SyslogMakeWriter::connect(&cfg).unwrap();
+            let mut writer = make_writer.writer(severity(&Level::WARN));
+            writer.write_all(b"uplink disconnected\n").unwrap();
+            drop(writer);
+            let mut buf = [0; 256];
+            let n = server.recv(&mut buf).unwrap();
+            let expected = format!("<132>aprsserver[{}]: uplink disconnected", std::process::id());
+            assert_eq!(std::str::from_utf8(&buf[..n]).unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! The login line an APRS-IS client sends first:
+//! `user CALLSIGN pass PASSCODE vers SOFTWARE VERSION filter FILTER...`.
+//! Keywords may come in any order and in any case.
+
+use crate::config::PasscodePolicy;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Login {
+    pub callsign: String,
+    /// As sent; receive-only clients use `-1`
+    pub passcode: String,
+    /// Software name and version
+    pub software: Option<String>,
+    /// Everything after `filter`
+    pub filter: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginError {
+    MissingCallsign,
+    MissingPasscode,
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LoginError::MissingCallsign => "Missing user",
+            LoginError::MissingPasscode => "Missing pass",
+        })
+    }
+}
+
+impl std::error::Error for LoginError {}
+
+impl Login {
+    /// True if the passcode is the one for the callsign.
+    pub fn verified(&self) -> bool {
+        self.passcode.parse::<u16>().is_ok_and(|p| crate::server::aprs_passcode(&self.callsign) == p)
+    }
+    /// Whether `policy` lets the login in, and if so as verified or not.
+    pub fn admit(&self, policy: PasscodePolicy) -> Option<bool> {
+        match policy {
+            PasscodePolicy::Strict => self.verified().then_some(true),
+            PasscodePolicy::Unverified => Some(self.verified()),
+            PasscodePolicy::Open => Some(false),
+        }
+    }
+}
+
+pub fn parse_login(line: &str) -> Result<Login, LoginError> {
+    parse_login_for(line, PasscodePolicy::Strict)
+}
+
+/// Like [`parse_login`], but under the `open` policy a login without
+/// passcode is taken as receive-only, `-1`.
+pub fn parse_login_for(line: &str, policy: PasscodePolicy) -> Result<Login, LoginError> {
+    let (mut callsign, mut passcode, mut software, mut filter) = (None, None, None, None);
+    let mut parts = line.split_whitespace();
+    while let Some(part) = parts.next() {
+        if part.eq_ignore_ascii_case("user") {
+            callsign = parts.next();
+        } else if part.eq_ignore_ascii_case("pass") {
+            passcode = parts.next();
+        } else if part.eq_ignore_ascii_case("vers") {
+            software = parts.next().map(|name| match parts.next() {
+                Some(version) => format!("{} {}", name, version),
+                None => name.to_string(),
+            });
+        } else if part.eq_ignore_ascii_case("filter") {
+            filter = Some(parts.by_ref().collect::<Vec<_>>().join(" "));
+        }
+    }
+    Ok(Login {
+        callsign: callsign.ok_or(LoginError::MissingCallsign)?.to_string(),
+        passcode: match passcode {
+            Some(passcode) => passcode.to_string(),
+            None if policy == PasscodePolicy::Open => "-1".to_string(),
+            None => return Err(LoginError::MissingPasscode),
+        },
+        software,
+        filter,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_login() {
+        let line = format!("user N0CALL-10 pass {} vers aprsd 1.0 filter r/60/25/100 t/m\r\n", crate::server::aprs_passcode("N0CALL"));
+        let login = parse_login(&line).unwrap();
+        assert_eq!(login.callsign, "N0CALL-10");
+        assert_eq!(login.software.as_deref(), Some("aprsd 1.0"));
+        assert_eq!(login.filter.as_deref(), Some("r/60/25/100 t/m"));
+        assert!(login.verified());
+        let login = parse_login("USER N0CALL PASS -1").unwrap();
+        assert!(!login.verified() && login.software.is_none());
+        assert_eq!(parse_login("user N0CALL"), Err(LoginError::MissingPasscode));
+        assert_eq!(parse_login("pass 1 user"), Err(LoginError::MissingCallsign));
+        assert_eq!(parse_login_for("user N0CALL", PasscodePolicy::Open).unwrap().passcode, "-1");
+        for line in ["", "user é pass 😀 vers", "filter", "\u{0}user\u{85}x pass\u{a0}1"] {
+            let _ = parse_login(line);
+        }
+    }
+
+    #[test]
+    fn test_admit() {
+        let good = parse_login(&format!("user N0CALL pass {}", crate::server::aprs_passcode("N0CALL"))).unwrap();
+        let bad = parse_login("user N0CALL pass 1").unwrap();
+        let admit = |policy| (good.admit(policy), bad.admit(policy));
+        assert_eq!(admit(PasscodePolicy::Strict), (Some(true), None));
+        assert_eq!(admit(PasscodePolicy::Unverified), (Some(true), Some(false)));
+        assert_eq!(admit(PasscodePolicy::Open), (Some(false), Some(false)));
+    }
+}
@@ -0,0 +1,213 @@
+//! Temporary blocking of source addresses that keep failing to log in,
+//! with wrong passcodes or lines that are no login at all. Brute-force
+//! attempts and scanners on the public port are turned away at accept
+//! until the block runs out.
+
+use crate::config::LoginLimitConfig;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// Addresses without a block or recent failure are forgotten beyond this,
+// and if all are live, those that would be forgotten soonest
+const MAX_SOURCES: usize = 10_000;
+
+/// Why a login failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Passcode,
+    /// Not a parsable login line
+    Invalid,
+}
+
+#[derive(Debug, Default)]
+struct Source {
+    /// Failures within the window, oldest first
+    failures: VecDeque<Instant>,
+    blocked_until: Option<Instant>,
+}
+
+impl Source {
+    /// When the source would be forgotten anyway.
+    fn expiry(&self, window: Duration) -> Option<Instant> {
+        self.blocked_until.max(self.failures.back().map(|&t| t + window))
+    }
+}
+
+#[derive(Debug)]
+pub struct LoginLimiter {
+    config: LoginLimitConfig,
+    sources: Mutex<HashMap<IpAddr, Source>>,
+    passcode_failures: AtomicU64,
+    invalid_logins: AtomicU64,
+    blocks: AtomicU64,
+    /// Connections refused because their address was blocked
+    rejected: AtomicU64,
+}
+
+/// A currently blocked address, for the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlockedAddress {
+    pub ip: IpAddr,
+    pub remaining_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoginLimitStats {
+    pub passcode_failures: u64,
+    pub invalid_logins: u64,
+    pub blocks: u64,
+    pub rejected: u64,
+    pub blocked: Vec<BlockedAddress>,
+}
+
+impl LoginLimiter {
+    pub fn new(config: LoginLimitConfig) -> Self {
+        LoginLimiter {
+            config,
+            sources: Mutex::new(HashMap::new()),
+            passcode_failures: AtomicU64::new(0),
+            invalid_logins: AtomicU64::new(0),
+            blocks: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether connections from `ip` are refused, counting the refusal.
+    pub fn is_blocked(&self, ip: IpAddr, now: Instant) -> bool {
+        let blocked = self.sources.lock().unwrap().get(&ip).and_then(|s| s.blocked_until).is_some_and(|until| until > now);
+        if blocked {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+        blocked
+    }
+
+    /// Counts a failed login from `ip`. Returns true if this blocked it.
+    pub fn record_failure(&self, ip: IpAddr, failure: Failure, now: Instant) -> bool {
+        match failure {
+            Failure::Passcode => self.passcode_failures.fetch_add(1, Ordering::Relaxed),
+            Failure::Invalid => self.invalid_logins.fetch_add(1, Ordering::Relaxed),
+        };
+        let window = Duration::from_secs(self.config.window_secs);
+        let mut sources = self.sources.lock().unwrap();
+        if sources.len() >= MAX_SOURCES && !sources.contains_key(&ip) {
+            sources.retain(|_, s| s.expiry(window).is_some_and(|t| t > now));
+            if sources.len() >= MAX_SOURCES {
+                // Make room for a tenth more, so the scan is not repeated
+                // for every new address
+                let mut by_expiry: Vec<(Option<Instant>, IpAddr)> = sources.iter().map(|(ip, s)| (s.expiry(window), *ip)).collect();
+                let evict = sources.len() - (MAX_SOURCES - MAX_SOURCES / 10);
+                by_expiry.select_nth_unstable(evict - 1);
+                for (_, ip) in &by_expiry[..evict] {
+                    sources.remove(ip);
+                }
+            }
+        }
+        let source = sources.entry(ip).or_default();
+        while source.failures.front().is_some_and(|&t| now.saturating_duration_since(t) >= window) {
+            source.failures.pop_front();
+        }
+        source.failures.push_back(now);
+        if source.failures.len() < self.config.max_failures {
+            return false;
+        }
+        source.failures.clear();
+        source.blocked_until = Some(now + Duration::from_secs(self.config.block_secs));
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+        warn!(%ip, block_secs = self.config.block_secs, ?failure, "blocking address after repeated failed logins");
+        true
+    }
+
+    /// Forgets the failures of an address that logged in.
+    pub fn record_success(&self, ip: IpAddr) {
+        if let Some(source) = self.sources.lock().unwrap().get_mut(&ip) {
+            source.failures.clear();
+        }
+    }
+
+    /// Lifts the block on `ip`. Returns false if it was not blocked.
+    pub fn unblock(&self, ip: IpAddr, now: Instant) -> bool {
+        let removed = self.sources.lock().unwrap().remove(&ip);
+        removed.and_then(|s| s.blocked_until).is_some_and(|until| until > now)
+    }
+
+    pub fn stats(&self, now: Instant) -> LoginLimitStats {
+        let mut blocked: Vec<BlockedAddress> = self
+            .sources
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(ip, s)| {
+                let remaining = s.blocked_until?.checked_duration_since(now).filter(|d| !d.is_zero())?;
+                Some(BlockedAddress { ip: *ip, remaining_secs: remaining.as_secs_f64().ceil() as u64 })
+            })
+            .collect();
+        blocked.sort_by_key(|b| b.ip);
+        LoginLimitStats {
+            passcode_failures: self.passcode_failures.load(Ordering::Relaxed),
+            invalid_logins: self.invalid_logins.load(Ordering::Relaxed),
+            blocks: self.blocks.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            blocked,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_limiter() {
+        let limiter = LoginLimiter::new(LoginLimitConfig { max_failures: 3, window_secs: 60, block_secs: 600 });
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        // Failures spread wider than the window do not add up
+        assert!(!limiter.record_failure(ip, Failure::Passcode, at(0)));
+        assert!(!limiter.record_failure(ip, Failure::Invalid, at(10)));
+        assert!(!limiter.record_failure(ip, Failure::Passcode, at(65)));
+        assert!(!limiter.is_blocked(ip, at(65)));
+        assert!(limiter.record_failure(ip, Failure::Passcode, at(68)));
+        assert!(limiter.is_blocked(ip, at(100)));
+        assert!(!limiter.is_blocked("192.0.2.2".parse().unwrap(), at(100)));
+        let stats = limiter.stats(at(100));
+        assert_eq!((stats.passcode_failures, stats.invalid_logins, stats.blocks, stats.rejected), (3, 1, 1, 1));
+        assert_eq!(stats.blocked, [BlockedAddress { ip, remaining_secs: 568 }]);
+        // Blocks run out, or are lifted
+        assert!(!limiter.is_blocked(ip, at(675)));
+        assert!(limiter.stats(at(675)).blocked.is_empty());
+        for secs in 700..703 {
+            limiter.record_failure(ip, Failure::Invalid, at(secs));
+        }
+        assert!(limiter.unblock(ip, at(710)));
+        assert!(!limiter.unblock(ip, at(710)));
+        assert!(!limiter.is_blocked(ip, at(710)));
+        // A good login clears earlier failures
+        limiter.record_failure(ip, Failure::Passcode, at(800));
+        limiter.record_failure(ip, Failure::Passcode, at(801));
+        limiter.record_success(ip);
+        assert!(!limiter.record_failure(ip, Failure::Passcode, at(802)));
+    }
+
+    #[test]
+    fn test_source_cap() {
+        let limiter = LoginLimiter::new(LoginLimitConfig { max_failures: 1, window_secs: 60, block_secs: 600 });
+        let start = Instant::now();
+        let addr = |i: u32| IpAddr::from(std::net::Ipv4Addr::from(0x0a00_0000 + i));
+        let first = addr(0);
+        limiter.record_failure(first, Failure::Invalid, start);
+        // Every address stays blocked, yet the map does not grow past the cap
+        for i in 1..(MAX_SOURCES as u32 + 100) {
+            limiter.record_failure(addr(i), Failure::Invalid, start + Duration::from_millis(i as u64));
+        }
+        assert!(limiter.sources.lock().unwrap().len() <= MAX_SOURCES);
+        // The blocks ending first are the ones forgotten
+        assert!(!limiter.is_blocked(first, start));
+        assert!(limiter.is_blocked(addr(MAX_SOURCES as u32 + 99), start));
+    }
+}
@@ -0,0 +1,101 @@
+/// Converts a position to a Maidenhead locator with the given number of pairs
+/// (2 = `JP20`, 3 = `JP20qe`, up to 5). Subsquares are lower case as usual.
+pub fn to_locator(lat: f64, lon: f64, pairs: usize) -> String {
+    let pairs = pairs.clamp(1, 5);
+    let mut lon = (lon + 180.0).clamp(0.0, 359.999_999);
+    let mut lat = (lat + 90.0).clamp(0.0, 179.999_999);
+    let mut out = String::new();
+    // Field (18x18), square (10x10), subsquare (24x24), extended square (10x10), ...
+    let mut lon_size = 20.0;
+    let mut lat_size = 10.0;
+    for pair in 0..pairs {
+        let divisions = if pair == 0 { 18.0 } else if pair % 2 == 1 { 10.0 } else { 24.0 };
+        if pair > 0 {
+            lon_size /= divisions;
+            lat_size /= divisions;
+        }
+        let lon_idx = (lon / lon_size).floor() as u8;
+        let lat_idx = (lat / lat_size).floor() as u8;
+        lon -= lon_idx as f64 * lon_size;
+        lat -= lat_idx as f64 * lat_size;
+        let base = match pair {
+            0 => b'A',
+            p if p % 2 == 1 => b'0',
+            _ => b'a',
+        };
+        out.push((base + lon_idx) as char);
+        out.push((base + lat_idx) as char);
+    }
+    out
+}
+
+/// Returns the center of the square described by a locator of 2 to 10 characters.
+pub fn to_lat_lon(locator: &str) -> Option<(f64, f64)> {
+    let b = locator.as_bytes();
+    if b.len() < 2 || b.len() > 10 || !b.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut lon = -180.0;
+    let mut lat = -90.0;
+    let mut lon_size = 20.0;
+    let mut lat_size = 10.0;
+    for (pair, chunk) in b.chunks(2).enumerate() {
+        let (lon_c, lat_c) = (chunk[0].to_ascii_uppercase(), chunk[1].to_ascii_uppercase());
+        let (lon_idx, lat_idx, divisions) = match pair {
+            0 if (b'A'..=b'R').contains(&lon_c) && (b'A'..=b'R').contains(&lat_c) => (lon_c - b'A', lat_c - b'A', 18.0),
+            p if p % 2 == 1 && lon_c.is_ascii_digit() && lat_c.is_ascii_digit() => (lon_c - b'0', lat_c - b'0', 10.0),
+            p if p > 0 && p % 2 == 0 && (b'A'..=b'X').contains(&lon_c) && (b'A'..=b'X').contains(&lat_c) => {
+                (lon_c - b'A', lat_c - b'A', 24.0)
+            }
+            _ => return None,
+        };
+        if pair > 0 {
+            lon_size /= divisions;
+            lat_size /= divisions;
+        }
+        lon += lon_idx as f64 * lon_size;
+        lat += lat_idx as f64 * lat_size;
+    }
+    Some((lat + lat_size / 2.0, lon + lon_size / 2.0))
+}
+
+/// Checks whether a position lies in the square given by `pattern` (`JP20`,
+/// `JP20qe`, with an optional trailing `*`).
+pub fn in_square(lat: f64, lon: f64, pattern: &str) -> bool {
+    let pattern = pattern.trim_end_matches('*');
+    if pattern.is_empty() || pattern.len() > 10 {
+        return false;
+    }
+    let locator = to_locator(lat, lon, pattern.len().div_ceil(2));
+    locator.to_ascii_uppercase().starts_with(&pattern.to_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_locator() {
+        // Munich
+        assert_eq!(to_locator(48.1467, 11.6083, 3), "JN58td");
+        // Sydney
+        assert_eq!(to_locator(-33.8688, 151.2093, 2), "QF56");
+    }
+
+    #[test]
+    fn test_to_lat_lon() {
+        let (lat, lon) = to_lat_lon("JN58td").unwrap();
+        assert!((lat - 48.1458).abs() < 0.05);
+        assert!((lon - 11.625).abs() < 0.05);
+        assert_eq!(to_locator(lat, lon, 3), "JN58td");
+        assert!(to_lat_lon("ZZ00").is_none());
+        assert!(to_lat_lon("JN5").is_none());
+    }
+
+    #[test]
+    fn test_in_square() {
+        assert!(in_square(48.1467, 11.6083, "JN58*"));
+        assert!(in_square(48.1467, 11.6083, "jn58TD"));
+        assert!(!in_square(48.1467, 11.6083, "JN59"));
+    }
+}
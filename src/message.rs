@@ -0,0 +1,138 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+    Message,
+    Ack,
+    Rej,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AprsMessage {
+    pub source: String,
+    pub addressee: String,
+    pub text: String,
+    pub msgid: Option<String>,
+    pub kind: MessageKind,
+}
+
+impl AprsMessage {
+    pub fn is_ack_or_rej(&self) -> bool {
+        matches!(self.kind, MessageKind::Ack | MessageKind::Rej)
+    }
+}
+
+pub fn parse_message(packet: &str) -> Option<AprsMessage> {
+    // APRS message format: SRC>DEST,PATH::ADDRESSEE:text{msgid
+    let gt = packet.find('>')?;
+    let colon = packet.find(':')?;
+    if gt == 0 || colon < gt {
+        return None;
+    }
+    parse_message_body(&packet[..gt], &packet[colon + 1..])
+}
+
+/// Parses a message payload (`:ADDRESSEE:text{msgid`). The addressee field is
+/// always 9 characters, space padded.
+pub fn parse_message_body(source: &str, payload: &str) -> Option<AprsMessage> {
+    if !payload.starts_with(':') || payload.get(10..11) != Some(":") {
+        return None;
+    }
+    let addressee = payload.get(1..10)?.trim();
+    if addressee.is_empty() || !addressee.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return None;
+    }
+    let text = payload[11..].trim_end();
+    let (kind, text, msgid) = if let Some(id) = ack_rej_id(text, "ack") {
+        (MessageKind::Ack, text, Some(id))
+    } else if let Some(id) = ack_rej_id(text, "rej") {
+        (MessageKind::Rej, text, Some(id))
+    } else if let Some(brace) = text.rfind('{') {
+        // Reply-ack capable stations send "{MM}AA"; only MM identifies this message
+        let id = text[brace + 1..].split('}').next().unwrap_or("");
+        let msgid = if id.is_empty() { None } else { Some(id.to_string()) };
+        (MessageKind::Message, &text[..brace], msgid)
+    } else {
+        (MessageKind::Message, text, None)
+    };
+    Some(AprsMessage {
+        source: source.to_string(),
+        addressee: addressee.to_string(),
+        text: text.to_string(),
+        msgid,
+        kind,
+    })
+}
+
+/// Longest message text allowed by the APRS spec.
+pub const MAX_MESSAGE_TEXT: usize = 67;
+
+/// A message packet from `source` to `addressee`, as sent by the server.
+pub fn message_packet(source: &str, addressee: &str, text: &str) -> String {
+    format!("{}>APRS,TCPIP*::{:<9}:{}", source, addressee, text)
+}
+
+/// Acknowledges message `msgid` from `addressee`.
+pub fn ack_packet(source: &str, addressee: &str, msgid: &str) -> String {
+    message_packet(source, addressee, &format!("ack{}", msgid))
+}
+
+fn ack_rej_id(text: &str, prefix: &str) -> Option<String> {
+    let id = text.strip_prefix(prefix)?;
+    let id = id.split('}').next().unwrap_or("");
+    if id.is_empty() || id.len() > 5 || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_message_addressee() {
+        let addressee = |p: &str| parse_message(p).map(|m| m.addressee);
+        assert_eq!(addressee("N0CALL>APRS,TCPIP*::DEST     :Hello"), Some("DEST".to_string()));
+        assert_eq!(addressee("N0CALL>APRS,TCPIP*::FOO      :Test msg"), Some("FOO".to_string()));
+        assert_eq!(addressee("N0CALL>APRS,TCPIP*::N1CALL-9 :Hi"), Some("N1CALL-9".to_string()));
+        assert_eq!(addressee("N0CALL>APRS,TCPIP*:payload"), None);
+        assert_eq!(addressee("N0CALL>APRS,TCPIP*::   :No dest"), None);
+    }
+
+    #[test]
+    fn test_parse_message_msgid() {
+        let msg = parse_message("N0CALL>APRS::N1CALL   :Hello there{42").unwrap();
+        assert_eq!(msg.source, "N0CALL");
+        assert_eq!(msg.kind, MessageKind::Message);
+        assert_eq!(msg.text, "Hello there");
+        assert_eq!(msg.msgid, Some("42".to_string()));
+        // Reply-ack format
+        let msg = parse_message("N0CALL>APRS::N1CALL   :Hi{AB}CD").unwrap();
+        assert_eq!(msg.msgid, Some("AB".to_string()));
+        let msg = parse_message("N0CALL>APRS::N1CALL   :No id").unwrap();
+        assert_eq!(msg.msgid, None);
+    }
+
+    #[test]
+    fn test_parse_ack_rej() {
+        let ack = parse_message("N1CALL>APRS::N0CALL   :ack42").unwrap();
+        assert_eq!(ack.kind, MessageKind::Ack);
+        assert_eq!(ack.msgid, Some("42".to_string()));
+        assert!(ack.is_ack_or_rej());
+        let rej = parse_message("N1CALL>APRS::N0CALL   :rej7").unwrap();
+        assert_eq!(rej.kind, MessageKind::Rej);
+        // Plain text starting with "ack" is still a message
+        let msg = parse_message("N1CALL>APRS::N0CALL   :acknowledged, thanks").unwrap();
+        assert_eq!(msg.kind, MessageKind::Message);
+    }
+
+    #[test]
+    fn test_message_packet() {
+        let packet = message_packet("T2TEST", "N0CALL-9", "Hello");
+        assert_eq!(packet, "T2TEST>APRS,TCPIP*::N0CALL-9 :Hello");
+        assert_eq!(parse_message(&packet).unwrap().addressee, "N0CALL-9");
+        assert_eq!(parse_message(&ack_packet("T2TEST", "N0CALL", "42")).unwrap().kind, MessageKind::Ack);
+    }
+}
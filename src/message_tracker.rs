@@ -0,0 +1,197 @@
+//! Delivery tracking for APRS messages: counters for messages routed,
+//! delivered to a local addressee, forwarded to the uplink, acked, rejected
+//! and expired, and the recent messages to each addressee with their state,
+//! so users can see why a message did not arrive.
+
+use crate::message::{AprsMessage, MessageKind};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Seconds a message waits for its ack before it counts as expired
+const ACK_TIMEOUT_SECS: u64 = 600;
+/// Seconds messages are kept for `/messages.json`
+const KEEP_SECS: u64 = 3600;
+const MAX_PER_ADDRESSEE: usize = 20;
+const MAX_ADDRESSEES: usize = 1000;
+
+/// Message counters since startup. Acks and rejects count as routed,
+/// delivered and forwarded like the messages they answer.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageCounters {
+    pub routed: u64,
+    pub delivered: u64,
+    pub forwarded: u64,
+    pub acked: u64,
+    pub rejected: u64,
+    pub expired: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageState {
+    Pending,
+    Acked,
+    Rejected,
+    /// No ack within ten minutes
+    Expired,
+}
+
+/// A message with an id, which expects an ack.
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageRecord {
+    pub source: String,
+    pub addressee: String,
+    pub msgid: String,
+    pub text: String,
+    /// Where it entered the server, as in the heard list
+    pub entry: String,
+    /// Unix time it was accepted
+    pub received: u64,
+    /// Delivered to the addressee logged in here
+    pub delivered: bool,
+    pub forwarded: bool,
+    pub state: MessageState,
+    /// Unix time of the ack or reject
+    pub answered: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct MessageTracker {
+    pub counters: MessageCounters,
+    /// Recent messages by uppercased addressee, oldest first
+    addressees: HashMap<String, VecDeque<MessageRecord>>,
+}
+
+impl MessageTracker {
+    /// Records an accepted message, ack or reject.
+    pub fn record(&mut self, msg: &AprsMessage, entry: &str, delivered: bool, now: u64) {
+        self.expire(now);
+        self.counters.routed += 1;
+        if delivered {
+            self.counters.delivered += 1;
+        }
+        let Some(msgid) = &msg.msgid else {
+            return;
+        };
+        if msg.is_ack_or_rej() {
+            // Answers the message from its addressee to its source
+            let original = self.addressees.get_mut(&msg.source.to_uppercase()).and_then(|records| {
+                records.iter_mut().find(|r| {
+                    r.state == MessageState::Pending && &r.msgid == msgid && r.source.eq_ignore_ascii_case(&msg.addressee)
+                })
+            });
+            if let Some(record) = original {
+                record.answered = Some(now);
+                if msg.kind == MessageKind::Rej {
+                    record.state = MessageState::Rejected;
+                    self.counters.rejected += 1;
+                } else {
+                    record.state = MessageState::Acked;
+                    self.counters.acked += 1;
+                }
+            }
+            return;
+        }
+        let addressee = msg.addressee.to_uppercase();
+        if !self.addressees.contains_key(&addressee) && self.addressees.len() >= MAX_ADDRESSEES {
+            self.evict_oldest();
+        }
+        let records = self.addressees.entry(addressee).or_default();
+        if records.len() >= MAX_PER_ADDRESSEE {
+            records.pop_front();
+        }
+        records.push_back(MessageRecord {
+            source: msg.source.clone(),
+            addressee: msg.addressee.clone(),
+            msgid: msgid.clone(),
+            text: msg.text.clone(),
+            entry: entry.to_string(),
+            received: now,
+            delivered,
+            forwarded: false,
+            state: MessageState::Pending,
+            answered: None,
+        });
+    }
+
+    /// Counts `msg` as sent to the uplink.
+    pub fn forwarded(&mut self, msg: &AprsMessage) {
+        self.counters.forwarded += 1;
+        if let Some(record) = self.addressees.get_mut(&msg.addressee.to_uppercase()).and_then(|records| {
+            records.iter_mut().rev().find(|r| Some(&r.msgid) == msg.msgid.as_ref() && r.source.eq_ignore_ascii_case(&msg.source))
+        }) {
+            record.forwarded = true;
+        }
+    }
+
+    /// Marks messages unanswered for too long as expired and forgets old ones.
+    pub fn expire(&mut self, now: u64) {
+        for records in self.addressees.values_mut() {
+            for record in records.iter_mut() {
+                if record.state == MessageState::Pending && now.saturating_sub(record.received) >= ACK_TIMEOUT_SECS {
+                    record.state = MessageState::Expired;
+                    self.counters.expired += 1;
+                }
+            }
+            records.retain(|r| now.saturating_sub(r.received) < KEEP_SECS);
+        }
+        self.addressees.retain(|_, records| !records.is_empty());
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .addressees
+            .iter()
+            .min_by_key(|(_, records)| records.back().map(|r| r.received))
+            .map(|(addressee, _)| addressee.clone());
+        if let Some(addressee) = oldest {
+            self.addressees.remove(&addressee);
+        }
+    }
+
+    /// Recent messages to `addressee`, oldest first.
+    pub fn to(&self, addressee: &str) -> Vec<MessageRecord> {
+        self.addressees.get(&addressee.to_uppercase()).map(|r| r.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Recent messages by addressee, in callsign order.
+    pub fn all(&self) -> std::collections::BTreeMap<String, Vec<MessageRecord>> {
+        self.addressees.iter().map(|(addressee, records)| (addressee.clone(), records.iter().cloned().collect())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::parse_message;
+
+    #[test]
+    fn test_message_tracking() {
+        let msg = |line: &str| parse_message(line).unwrap();
+        let mut tracker = MessageTracker::default();
+        tracker.record(&msg("N0CALL>APRS::N1CALL   :Hello{1"), "client:N0CALL", false, 1000);
+        tracker.forwarded(&msg("N0CALL>APRS::N1CALL   :Hello{1"));
+        tracker.record(&msg("N0CALL>APRS::N2CALL   :Hi{2"), "client:N0CALL", true, 1000);
+        tracker.record(&msg("N0CALL>APRS::BLN1     :No id"), "client:N0CALL", false, 1000);
+        assert_eq!(tracker.to("n1call")[0].state, MessageState::Pending);
+        assert!(tracker.to("N1CALL")[0].forwarded);
+        assert!(tracker.to("BLN1").is_empty());
+
+        // An ack for someone else's message does not count
+        tracker.record(&msg("N1CALL>APRS::N9CALL   :ack1"), "uplink", false, 1010);
+        tracker.record(&msg("N1CALL>APRS::N0CALL   :ack1"), "uplink", true, 1010);
+        let record = &tracker.to("N1CALL")[0];
+        assert_eq!((record.state, record.answered), (MessageState::Acked, Some(1010)));
+        tracker.record(&msg("N2CALL>APRS::N0CALL   :rej2"), "client:N2CALL", true, 1020);
+        assert_eq!(tracker.to("N2CALL")[0].state, MessageState::Rejected);
+
+        tracker.record(&msg("N0CALL>APRS::N3CALL   :Anyone?{3"), "client:N0CALL", false, 1030);
+        tracker.expire(1030 + ACK_TIMEOUT_SECS);
+        assert_eq!(tracker.to("N3CALL")[0].state, MessageState::Expired);
+        let counters = &tracker.counters;
+        assert_eq!((counters.routed, counters.delivered, counters.forwarded), (7, 3, 1));
+        assert_eq!((counters.acked, counters.rejected, counters.expired), (1, 1, 1));
+        tracker.expire(1030 + KEEP_SECS);
+        assert!(tracker.all().is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::config::MqttConfig;
+use crate::hook::PacketHook;
+use crate::parser::ParsedPacket;
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Publishes queued in the client before new ones are dropped
+const MQTT_QUEUE_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MqttStatus {
+    pub host: String,
+    pub port: u16,
+    pub connected: bool,
+    pub published: u64,
+    pub dropped: u64,
+    pub connect_errors: u64,
+    pub last_error: Option<String>,
+}
+
+/// Packet hook publishing accepted packets to an MQTT broker. Publishing never
+/// blocks; when the broker falls behind packets are counted as dropped.
+pub struct MqttSink {
+    cfg: MqttConfig,
+    client: AsyncClient,
+    status: Arc<Mutex<MqttStatus>>,
+}
+
+impl MqttSink {
+    /// Spawns the client's event loop on the current Tokio runtime. It
+    /// connects to the broker in the background and reconnects after
+    /// errors, so publishing can start right away.
+    pub fn start(cfg: &MqttConfig, status: Arc<Mutex<MqttStatus>>) -> Self {
+        let client_id = cfg.client_id.clone().unwrap_or_else(|| crate::version::NAME.to_string());
+        let mut options = MqttOptions::new(client_id, &cfg.host, cfg.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        let (client, mut eventloop) = AsyncClient::new(options, MQTT_QUEUE_SIZE);
+        let task_status = status.clone();
+        tokio::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        let mut s = task_status.lock().unwrap();
+                        s.connected = true;
+                        s.last_error = None;
+                    }
+                    Ok(Event::Outgoing(Outgoing::Publish(_))) => {
+                        task_status.lock().unwrap().published += 1;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        {
+                            let mut s = task_status.lock().unwrap();
+                            s.connected = false;
+                            s.connect_errors += 1;
+                            s.last_error = Some(e.to_string());
+                        }
+                        // The next poll reconnects
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+        MqttSink { cfg: cfg.clone(), client, status }
+    }
+}
+
+impl PacketHook for MqttSink {
+    fn on_accept(&self, line: &str, packet: &ParsedPacket) {
+        for (topic, payload) in messages(&self.cfg, line, packet) {
+            if self.client.try_publish(topic, QoS::AtMostOnce, false, payload).is_err() {
+                self.status.lock().unwrap().dropped += 1;
+            }
+        }
+    }
+}
+
+/// Topic level for a callsign; MQTT wildcards and separators are replaced.
+fn topic_level(s: &str) -> String {
+    s.chars().map(|c| if matches!(c, '/' | '+' | '#') { '_' } else { c }).collect()
+}
+
+/// Builds the publishes for one packet: the raw line on
+/// `{prefix}/{source}/{type}` and the parsed packet as JSON on `.../json`.
+fn messages(cfg: &MqttConfig, line: &str, packet: &ParsedPacket) -> Vec<(String, Vec<u8>)> {
+    let topic = format!("{}/{}/{}", cfg.topic_prefix, topic_level(&packet.source), packet.data.type_name());
+    let mut out = Vec::new();
+    if cfg.raw {
+        out.push((topic.clone(), line.as_bytes().to_vec()));
+    }
+    if cfg.json
+        && let Ok(json) = serde_json::to_vec(packet)
+    {
+        out.push((format!("{}/json", topic), json));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_messages() {
+        let cfg: MqttConfig = toml::from_str(r#"host = "localhost""#).unwrap();
+        let line = "N0CALL-9>APRS:!4903.50N/07201.75W-Test";
+        let out = messages(&cfg, line, &parse(line).unwrap());
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].0, "aprs/N0CALL-9/position");
+        assert_eq!(out[0].1, line.as_bytes());
+        assert_eq!(out[1].0, "aprs/N0CALL-9/position/json");
+        let json: serde_json::Value = serde_json::from_slice(&out[1].1).unwrap();
+        assert_eq!(json["source"], "N0CALL-9");
+        assert!((json["data"]["position"]["lat"].as_f64().unwrap() - 49.058).abs() < 0.001);
+
+        let cfg: MqttConfig = toml::from_str("host = \"localhost\"\ntopic_prefix = \"x\"\njson = false").unwrap();
+        let out = messages(&cfg, "A/B>APRS:>hi", &parse("A/B>APRS:>hi").unwrap());
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].0, "x/A_B/status");
+    }
+}
@@ -0,0 +1,151 @@
+use crate::message::AprsMessage;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Addressee prefixes used for NWS-format bulletins (same set aprsc treats as t/n)
+const NWS_PREFIXES: [&str; 4] = ["NWS-", "SKY", "CWA", "BOM"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NwsBulletin {
+    pub addressee: String,
+    pub text: String,
+    pub zones: Vec<String>,
+}
+
+pub fn parse_bulletin(msg: &AprsMessage) -> Option<NwsBulletin> {
+    let addressee = msg.addressee.to_uppercase();
+    if !NWS_PREFIXES.iter().any(|p| addressee.starts_with(p)) {
+        return None;
+    }
+    let zones = msg.text.split(',').flat_map(expand_zones).collect();
+    Some(NwsBulletin { addressee, text: msg.text.clone(), zones })
+}
+
+/// Expands a zone field such as `NYZ001>003-005` or `CA_Z001` into individual
+/// zone codes (`NYZ001`, `NYZ002`, `NYZ003`, `NYZ005`). Fields that don't look
+/// like zone codes yield nothing.
+fn expand_zones(field: &str) -> Vec<String> {
+    let field = field.trim().to_uppercase().replace('_', "");
+    let mut zones = Vec::new();
+    let mut prefix: Option<String> = None;
+    for part in field.split('-') {
+        let (code, range_end) = match part.split_once('>') {
+            Some((code, end)) => (code, Some(end)),
+            None => (part, None),
+        };
+        let (zone_prefix, start) = if is_zone_code(code) {
+            (code[..3].to_string(), &code[3..])
+        } else if let Some(prefix) = &prefix {
+            (prefix.clone(), code)
+        } else {
+            return Vec::new();
+        };
+        let Ok(start_num) = start.parse::<u32>() else {
+            return zones;
+        };
+        let end_num = match range_end.map(|e| e.parse::<u32>()) {
+            Some(Ok(end)) if end >= start_num && end - start_num < 1000 => end,
+            Some(_) => return zones,
+            None => start_num,
+        };
+        for n in start_num..=end_num {
+            zones.push(format!("{}{:03}", zone_prefix, n));
+        }
+        prefix = Some(zone_prefix);
+    }
+    zones
+}
+
+fn is_zone_code(code: &str) -> bool {
+    let b = code.as_bytes();
+    b.len() == 6
+        && b[0].is_ascii_alphabetic()
+        && b[1].is_ascii_alphabetic()
+        && (b[2] == b'Z' || b[2] == b'C')
+        && b[3..].iter().all(|c| c.is_ascii_digit())
+}
+
+/// Zone centroids that let area filters match bulletins. Kept on the hub.
+#[derive(Debug, Default)]
+pub struct ZoneTable {
+    zones: HashMap<String, (f64, f64)>,
+}
+
+impl ZoneTable {
+    /// Loads a table with one `ZONE,lat,lon` entry per line.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        parse_zones(&fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.zones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+
+    /// Positions of all zones affected by an NWS bulletin.
+    pub fn bulletin_positions(&self, msg: &AprsMessage) -> Vec<(f64, f64)> {
+        match parse_bulletin(msg) {
+            Some(bulletin) => bulletin.zones.iter().filter_map(|z| self.zones.get(z).copied()).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+fn parse_zones(content: &str) -> Result<ZoneTable, String> {
+    let mut zones = HashMap::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if fields.len() < 3 {
+            return Err(format!("Invalid zone line: {}", line));
+        }
+        let lat: f64 = fields[1].parse().map_err(|_| format!("Invalid latitude: {}", line))?;
+        let lon: f64 = fields[2].parse().map_err(|_| format!("Invalid longitude: {}", line))?;
+        zones.insert(fields[0].to_uppercase().replace('_', ""), (lat, lon));
+    }
+    Ok(ZoneTable { zones })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::parse_message;
+
+    fn parse_nws(packet: &str) -> Option<NwsBulletin> {
+        parse_bulletin(&parse_message(packet)?)
+    }
+
+    #[test]
+    fn test_parse_nws() {
+        let b = parse_nws("NWS>APRS::NWS-WARN :191700z,THUNDER_STORM,KS_Z033,KS_Z034{AB").unwrap();
+        assert_eq!(b.addressee, "NWS-WARN");
+        assert_eq!(b.zones, vec!["KSZ033", "KSZ034"]);
+        assert!(parse_nws("N0CALL>APRS::SKYDSC   :test").is_some());
+        assert!(parse_nws("N0CALL>APRS::N1CALL   :NWS-WARN").is_none());
+        assert!(parse_nws("N0CALL>APRS:!4903.50N/07201.75W>").is_none());
+    }
+
+    #[test]
+    fn test_expand_zones() {
+        assert_eq!(expand_zones("NYZ001>003-005"), vec!["NYZ001", "NYZ002", "NYZ003", "NYZ005"]);
+        assert_eq!(expand_zones("ca_c071"), vec!["CAC071"]);
+        assert!(expand_zones("THUNDER_STORM").is_empty());
+        assert!(expand_zones("191700z").is_empty());
+    }
+
+    #[test]
+    fn test_zone_table() {
+        let table = parse_zones("# zone,lat,lon\nKS_Z033, 38.5, -98.0\nKSZ034,38.6,-97.5\n").unwrap();
+        assert_eq!(table.len(), 2);
+        let msg = parse_message("NWS>APRS::NWS-WARN :191700z,THUNDER_STORM,KS_Z033>035").unwrap();
+        assert_eq!(table.bulletin_positions(&msg), vec![(38.5, -98.0), (38.6, -97.5)]);
+        assert!(parse_zones("KSZ033,north,-98.0").is_err());
+    }
+}
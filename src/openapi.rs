@@ -0,0 +1,195 @@
+//! OpenAPI description of the HTTP API, served at `/api/openapi.json`. The
+//! operations are listed by hand next to each other so the document is
+//! easy to keep in step with the routes in `web`, `admin` and `export`.
+
+use serde_json::{Map, Value, json};
+
+struct Param {
+    name: &'static str,
+    /// `path` or `query`
+    location: &'static str,
+    kind: &'static str,
+    description: &'static str,
+}
+
+const fn path(name: &'static str, description: &'static str) -> Param {
+    Param { name, location: "path", kind: "string", description }
+}
+
+const fn query(name: &'static str, kind: &'static str, description: &'static str) -> Param {
+    Param { name, location: "query", kind, description }
+}
+
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    params: &'static [Param],
+    /// Content type of a successful response, `None` for 204
+    response: Option<&'static str>,
+    /// Content type of the request body, if any
+    body: Option<&'static str>,
+    /// Requires the admin bearer token
+    admin: bool,
+}
+
+const fn get(path: &'static str, summary: &'static str, response: &'static str, params: &'static [Param]) -> Operation {
+    Operation { method: "get", path, summary, params, response: Some(response), body: None, admin: false }
+}
+
+const fn admin(
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    response: Option<&'static str>,
+    body: Option<&'static str>,
+    params: &'static [Param],
+) -> Operation {
+    Operation { method, path, summary, params, response, body, admin: true }
+}
+
+const JSON: &str = "application/json";
+const TEXT: &str = "text/plain";
+const CALLSIGN: Param = path("callsign", "Callsign, without SSID for every SSID");
+
+const OPERATIONS: &[Operation] = &[
+    get("/status.json", "Server status, counters and link states", JSON, &[]),
+    get("/clients.json", "Connected clients", JSON, &[]),
+    get("/clients/{file}", "One client, `{id}.json` as JSON or `{id}` as a page", JSON, &[path("file", "Client ID, optionally with `.json`")]),
+    get("/weather.json", "Weather observations of the last two hours", JSON, &[]),
+    get("/objects.json", "Objects and items", JSON, &[]),
+    get("/positions.json", "Station positions of the last two hours", JSON, &[]),
+    get("/heard.json", "Stations heard in the last day, most recent first", JSON, &[
+        query("prefix", "string", "Only callsigns starting with this"),
+        query("since", "integer", "Only stations heard at or after this Unix time"),
+    ]),
+    get("/messages.json", "Message counters and recent messages by addressee", JSON, &[query("callsign", "string", "Only messages to this station")]),
+    get("/rejected-positions.json", "Stations whose positions failed the sanity checks", JSON, &[]),
+    get("/rf-heard.json", "Stations heard on RF", JSON, &[]),
+    get("/dupes.json", "Duplicate cache occupancy, hit rate and top sources", JSON, &[query("limit", "integer", "Sources to list")]),
+    get("/talkers.json", "Callsigns originating the most packets", JSON, &[query("limit", "integer", "Callsigns to list, 20 by default")]),
+    get("/software.json", "Accepted traffic by originating software", JSON, &[]),
+    get("/graphs.json", "Per-minute traffic samples of the last day", JSON, &[]),
+    get("/telemetry/{file}", "Telemetry of a station, `{callsign}.json`", JSON, &[path("file", "Callsign with `.json`")]),
+    get("/stream", "Accepted packets as Server-Sent Events", "text/event-stream", &[
+        query("filter", "string", "APRS-IS filter; empty passes everything"),
+        query("format", "string", "`raw` (default) or `json`"),
+    ]),
+    get("/export/{file}", "Statistics as CSV or NDJSON, e.g. `history.csv`", "text/csv", &[
+        path("file", "`clients`, `peers`, `talkers` or `history` with `.csv` or `.ndjson`"),
+        query("from", "integer", "Oldest history sample, Unix time"),
+        query("to", "integer", "Newest history sample, Unix time"),
+        query("limit", "integer", "Most talkers to include"),
+    ]),
+    get("/api/openapi.json", "This document", JSON, &[]),
+    admin("post", "/admin/clients/{id}/kick", "Disconnect a client", None, None, &[path("id", "Client ID")]),
+    admin("get", "/admin/bans", "Banned callsigns and IP addresses", Some(JSON), None, &[]),
+    admin("post", "/admin/bans/callsigns/{callsign}", "Ban a callsign", None, None, &[CALLSIGN]),
+    admin("delete", "/admin/bans/callsigns/{callsign}", "Lift a callsign ban", None, None, &[CALLSIGN]),
+    admin("post", "/admin/bans/ips/{ip}", "Ban an IP address", None, None, &[path("ip", "IPv4 or IPv6 address")]),
+    admin("delete", "/admin/bans/ips/{ip}", "Lift an IP ban", None, None, &[path("ip", "IPv4 or IPv6 address")]),
+    admin("get", "/admin/logins", "Failed login counters and blocked addresses", Some(JSON), None, &[]),
+    admin("delete", "/admin/logins/{ip}", "Lift a block after failed logins", None, None, &[path("ip", "IPv4 or IPv6 address")]),
+    admin("get", "/admin/log-level", "Current log level directives", Some(TEXT), None, &[]),
+    admin("put", "/admin/log-level", "Replace the log level directives", None, Some(TEXT), &[]),
+    admin("post", "/admin/inject", "Inject a packet, answering with the tagged line", Some(TEXT), Some(TEXT), &[]),
+    admin("post", "/admin/peers", "Start a TCP S2S peer, in `[[s2s_peers]]` form", None, Some(JSON), &[]),
+    admin("delete", "/admin/peers/{name}", "Stop a TCP S2S peer", None, None, &[path("name", "Peer name, or host:port")]),
+    admin("get", "/admin/watch", "Watched callsigns", Some(JSON), None, &[]),
+    admin("post", "/admin/watch/{callsign}", "Watch a callsign", None, None, &[CALLSIGN]),
+    admin("delete", "/admin/watch/{callsign}", "Stop watching a callsign", None, None, &[CALLSIGN]),
+];
+
+/// Bodies are described by content type only; the JSON shapes are those
+/// of the structs in `web` and `stats`.
+fn content(kind: &str) -> Value {
+    let schema = match kind {
+        JSON => json!({}),
+        _ => json!({"type": "string"}),
+    };
+    json!({ kind: { "schema": schema } })
+}
+
+fn operation(op: &Operation) -> Value {
+    let mut responses = Map::new();
+    match op.response {
+        Some(kind) => responses.insert("200".to_string(), json!({"description": "OK", "content": content(kind)})),
+        None => responses.insert("204".to_string(), json!({"description": "Done"})),
+    };
+    if !op.params.is_empty() || op.body.is_some() {
+        responses.insert("400".to_string(), json!({"description": "Invalid request"}));
+    }
+    if op.admin {
+        responses.insert("401".to_string(), json!({"description": "Wrong or missing token"}));
+        responses.insert("403".to_string(), json!({"description": "Admin API disabled, no admin_token set"}));
+    }
+    let params: Vec<Value> = op
+        .params
+        .iter()
+        .map(|p| {
+            json!({
+                "name": p.name,
+                "in": p.location,
+                "required": p.location == "path",
+                "description": p.description,
+                "schema": {"type": p.kind},
+            })
+        })
+        .collect();
+    let mut out = json!({"summary": op.summary, "parameters": params, "responses": responses});
+    if let Some(kind) = op.body {
+        out["requestBody"] = json!({"required": true, "content": content(kind)});
+    }
+    if op.admin {
+        out["security"] = json!([{"bearer": []}]);
+        out["tags"] = json!(["admin"]);
+    }
+    out
+}
+
+/// The OpenAPI 3 document for this build.
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    for op in OPERATIONS {
+        let item = paths.entry(op.path).or_insert_with(|| json!({}));
+        item[op.method] = operation(op);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": crate::version::NAME,
+            "version": crate::version::VERSION,
+            "description": "Status, statistics and administration of an APRS-IS server. \
+                WebSockets at /ws/packets, /ws/aprs-is and /admin/watch/ws are not described.",
+        },
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "bearer": {"type": "http", "scheme": "bearer", "description": "The configured admin_token"},
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document() {
+        let doc = document();
+        assert_eq!(doc["info"]["version"], crate::version::VERSION);
+        let heard = &doc["paths"]["/heard.json"]["get"];
+        assert_eq!(heard["parameters"][1]["name"], "since");
+        assert!(heard.get("security").is_none());
+        let callsign = &doc["paths"]["/admin/bans/callsigns/{callsign}"];
+        assert!(callsign["post"]["responses"].get("204").is_some());
+        assert_eq!(callsign["delete"]["security"][0]["bearer"], json!([]));
+        // Every path parameter is declared
+        for op in OPERATIONS {
+            for name in op.path.split('{').skip(1).filter_map(|s| s.split_once('}')).map(|(name, _)| name) {
+                assert!(op.params.iter().any(|p| p.name == name && p.location == "path"), "{} {}", op.path, name);
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::config::{LogRotation, PacketLogConfig, StreamLogConfig};
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::time::{Duration, SystemTime};
+use tracing::error;
+
+// Lines buffered for the writer thread before new ones are dropped
+const LOG_QUEUE_SIZE: usize = 10000;
+
+/// Where a logged packet came from; each stream has its own files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+    Clients,
+    Uplink,
+    S2s,
+}
+
+impl LogStream {
+    pub fn name(self) -> &'static str {
+        match self {
+            LogStream::Clients => "clients",
+            LogStream::Uplink => "uplink",
+            LogStream::S2s => "s2s",
+        }
+    }
+}
+
+struct LogEntry {
+    stream: LogStream,
+    time: DateTime<Utc>,
+    line: String,
+}
+
+/// Raw packet log. Every packet is written as `timestamp<TAB>packet` to
+/// `{dir}/{stream}.log`, which is gzipped to `{stream}-{opened}.log.gz` when
+/// it is rotated. Writing happens on a separate thread.
+pub struct PacketLog {
+    queue: SyncSender<LogEntry>,
+    streams: Vec<LogStream>,
+}
+
+impl PacketLog {
+    pub fn start(cfg: &PacketLogConfig) -> Result<Self, String> {
+        let dir = Path::new(&cfg.dir);
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", cfg.dir, e))?;
+        let mut files = Vec::new();
+        for (stream, stream_cfg) in [
+            (LogStream::Clients, &cfg.clients),
+            (LogStream::Uplink, &cfg.uplink),
+            (LogStream::S2s, &cfg.s2s),
+        ] {
+            if let Some(stream_cfg) = stream_cfg {
+                let file = LogFile::open(dir, stream, stream_cfg.clone()).map_err(|e| format!("{}: {}", cfg.dir, e))?;
+                files.push(file);
+            }
+        }
+        let streams = files.iter().map(|f| f.stream).collect();
+        let (queue, rx) = mpsc::sync_channel(LOG_QUEUE_SIZE);
+        std::thread::spawn(move || run_writer(files, rx));
+        Ok(PacketLog { queue, streams })
+    }
+
+    pub fn log(&self, stream: LogStream, line: &str) {
+        if self.streams.contains(&stream) {
+            let _ = self.queue.try_send(LogEntry { stream, time: Utc::now(), line: line.to_string() });
+        }
+    }
+}
+
+struct LogFile {
+    dir: PathBuf,
+    stream: LogStream,
+    cfg: StreamLogConfig,
+    writer: BufWriter<File>,
+    /// When the current file was started; names the file once rotated
+    opened: DateTime<Utc>,
+    size: u64,
+}
+
+impl LogFile {
+    /// Opens the stream's current file, continuing one left by a previous run.
+    fn open(dir: &Path, stream: LogStream, cfg: StreamLogConfig) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(current_path(dir, stream))?;
+        let meta = file.metadata()?;
+        let opened = match meta.modified() {
+            Ok(modified) if meta.len() > 0 => modified.into(),
+            _ => Utc::now(),
+        };
+        let log = LogFile { dir: dir.to_path_buf(), stream, cfg, writer: BufWriter::new(file), opened, size: meta.len() };
+        log.prune(SystemTime::now());
+        Ok(log)
+    }
+
+    fn write(&mut self, time: DateTime<Utc>, line: &str) -> io::Result<()> {
+        if self.needs_rotation(time) {
+            self.rotate(time)?;
+        }
+        let entry = format!("{}\t{}\n", time.to_rfc3339_opts(SecondsFormat::Millis, true), line);
+        self.writer.write_all(entry.as_bytes())?;
+        self.size += entry.len() as u64;
+        Ok(())
+    }
+
+    fn needs_rotation(&self, time: DateTime<Utc>) -> bool {
+        self.size > 0
+            && match self.cfg.rotate {
+                LogRotation::Daily => time.date_naive() != self.opened.date_naive(),
+                LogRotation::Size => self.size >= self.cfg.max_size_mb * 1024 * 1024,
+            }
+    }
+
+    /// Compresses the current file and starts a new one.
+    fn rotate(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        self.writer.flush()?;
+        let current = current_path(&self.dir, self.stream);
+        let rotated = self.dir.join(format!("{}-{}.log.gz", self.stream.name(), self.opened.format("%Y%m%d-%H%M%S")));
+        let mut input = File::open(&current)?;
+        let mut gz = GzEncoder::new(File::create(&rotated)?, Compression::default());
+        io::copy(&mut input, &mut gz)?;
+        gz.finish()?;
+        self.writer = BufWriter::new(File::create(&current)?);
+        self.opened = now;
+        self.size = 0;
+        self.prune(now.into());
+        Ok(())
+    }
+
+    /// Deletes rotated files older than the retention period.
+    fn prune(&self, now: SystemTime) {
+        let Some(days) = self.cfg.retention_days else {
+            return;
+        };
+        let Some(cutoff) = now.checked_sub(Duration::from_secs(days * 86400)) else {
+            return;
+        };
+        let prefix = format!("{}-", self.stream.name());
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with(&prefix)
+                && name.ends_with(".log.gz")
+                && entry.metadata().and_then(|m| m.modified()).is_ok_and(|m| m < cutoff)
+            {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+fn current_path(dir: &Path, stream: LogStream) -> PathBuf {
+    dir.join(format!("{}.log", stream.name()))
+}
+
+fn run_writer(mut files: Vec<LogFile>, rx: Receiver<LogEntry>) {
+    while let Ok(entry) = rx.recv() {
+        // Write everything queued, then flush once
+        let mut next = Some(entry);
+        while let Some(entry) = next {
+            if let Some(file) = files.iter_mut().find(|f| f.stream == entry.stream)
+                && let Err(e) = file.write(entry.time, &entry.line)
+            {
+                error!(stream = entry.stream.name(), error = %e, "packet log write failed");
+            }
+            next = rx.try_recv().ok();
+        }
+        for file in &mut files {
+            let _ = file.writer.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_daily_rotation() {
+        let dir = Path::new("test_packet_log");
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let mut log = LogFile::open(dir, LogStream::Uplink, StreamLogConfig::default()).unwrap();
+        let day1: DateTime<Utc> = "2024-05-01T23:59:00Z".parse().unwrap();
+        let day2: DateTime<Utc> = "2024-05-02T00:01:00Z".parse().unwrap();
+        log.opened = day1;
+        log.write(day1, "N0CALL>APRS:>one").unwrap();
+        log.write(day1, "N0CALL>APRS:>two").unwrap();
+        log.write(day2, "N0CALL>APRS:>three").unwrap();
+        log.writer.flush().unwrap();
+
+        let mut rotated = String::new();
+        GzDecoder::new(File::open(dir.join("uplink-20240501-235900.log.gz")).unwrap())
+            .read_to_string(&mut rotated)
+            .unwrap();
+        assert_eq!(rotated, "2024-05-01T23:59:00.000Z\tN0CALL>APRS:>one\n2024-05-01T23:59:00.000Z\tN0CALL>APRS:>two\n");
+        let current = fs::read_to_string(dir.join("uplink.log")).unwrap();
+        assert_eq!(current, "2024-05-02T00:01:00.000Z\tN0CALL>APRS:>three\n");
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,527 @@
+//! APRS packet parsing. Any input yields either a packet or a
+//! [`ParseError`], never a panic; payloads that cannot be decoded are kept
+//! as [`PacketData::Unknown`].
+
+use crate::message::{parse_message_body, AprsMessage};
+use crate::telemetry::{self, TelemetryFrame};
+use crate::weather::{self, Weather};
+use serde::Serialize;
+use std::fmt;
+
+// Third-party packets can nest; don't follow them forever
+const MAX_THIRD_PARTY_DEPTH: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Position {
+    pub lat: f64,
+    pub lon: f64,
+    pub symbol_table: char,
+    pub symbol_code: char,
+    pub timestamp: Option<String>,
+    pub messaging: bool,
+    pub comment: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketData {
+    Position(Position),
+    MicE(Position),
+    Object { name: String, live: bool, position: Position },
+    Item { name: String, live: bool, position: Position },
+    Message(AprsMessage),
+    Status(String),
+    Telemetry(TelemetryFrame),
+    Weather { timestamp: String, weather: Weather },
+    Query(String),
+    ThirdParty(Box<ParsedPacket>),
+    UserDefined(String),
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParsedPacket {
+    pub source: String,
+    pub destination: String,
+    pub path: Vec<String>,
+    pub qconstruct: Option<String>,
+    pub data: PacketData,
+}
+
+impl PacketData {
+    /// Short lowercase name of the packet type, as used in MQTT topics and scripts.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            PacketData::Position(_) => "position",
+            PacketData::MicE(_) => "mice",
+            PacketData::Object { .. } => "object",
+            PacketData::Item { .. } => "item",
+            PacketData::Message(_) => "message",
+            PacketData::Status(_) => "status",
+            PacketData::Telemetry(_) => "telemetry",
+            PacketData::Weather { .. } => "weather",
+            PacketData::Query(_) => "query",
+            PacketData::ThirdParty(_) => "thirdparty",
+            PacketData::UserDefined(_) => "userdefined",
+            PacketData::Unknown(_) => "unknown",
+        }
+    }
+}
+
+impl ParsedPacket {
+    pub fn position(&self) -> Option<&Position> {
+        match &self.data {
+            PacketData::Position(p) | PacketData::MicE(p) => Some(p),
+            PacketData::Object { position, .. } | PacketData::Item { position, .. } => Some(position),
+            PacketData::ThirdParty(inner) => inner.position(),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> Option<&AprsMessage> {
+        match &self.data {
+            PacketData::Message(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    /// Weather from positionless reports, or from positions carrying the
+    /// weather station symbol.
+    pub fn weather(&self) -> Option<Weather> {
+        match &self.data {
+            PacketData::Weather { weather, .. } => Some(weather.clone()),
+            PacketData::Position(p) if p.symbol_code == '_' => weather::parse_complete(&p.comment),
+            _ => None,
+        }
+    }
+}
+
+/// Why a line is not an APRS packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    InvalidUtf8,
+    MissingPayloadSeparator,
+    MissingSourceSeparator,
+    EmptySource,
+    EmptyDestination,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ParseError::InvalidUtf8 => "Invalid UTF-8",
+            ParseError::MissingPayloadSeparator => "Missing payload separator",
+            ParseError::MissingSourceSeparator => "Missing source separator",
+            ParseError::EmptySource => "Empty source callsign",
+            ParseError::EmptyDestination => "Empty destination",
+        })
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a full APRS-IS line (`SRC>DEST,PATH:payload`).
+pub fn parse(line: &str) -> Result<ParsedPacket, ParseError> {
+    parse_with_depth(line.trim_end_matches(['\r', '\n']), 0)
+}
+
+/// Parses a line as received from the network, which need not be UTF-8.
+pub fn parse_bytes(line: &[u8]) -> Result<ParsedPacket, ParseError> {
+    std::str::from_utf8(line).map_err(|_| ParseError::InvalidUtf8).and_then(parse)
+}
+
+fn parse_with_depth(line: &str, depth: usize) -> Result<ParsedPacket, ParseError> {
+    let (header, body) = line.split_once(':').ok_or(ParseError::MissingPayloadSeparator)?;
+    let (source, dest_path) = header.split_once('>').ok_or(ParseError::MissingSourceSeparator)?;
+    if source.is_empty() {
+        return Err(ParseError::EmptySource);
+    }
+    let mut fields = dest_path.split(',');
+    let destination = fields.next().unwrap_or("");
+    if destination.is_empty() {
+        return Err(ParseError::EmptyDestination);
+    }
+    let path: Vec<String> = fields.map(|s| s.to_string()).collect();
+    let qconstruct = path.iter().find(|p| crate::qconstruct::is_qconstruct(p)).cloned();
+    let data = parse_data(source, destination, body, depth);
+    Ok(ParsedPacket {
+        source: source.to_string(),
+        destination: destination.to_string(),
+        path,
+        qconstruct,
+        data,
+    })
+}
+
+fn parse_data(source: &str, destination: &str, body: &str, depth: usize) -> PacketData {
+    let Some(dti) = body.chars().next() else {
+        return PacketData::Unknown(String::new());
+    };
+    let rest = &body[dti.len_utf8()..];
+    let parsed = match dti {
+        '!' | '=' => parse_position(rest, None, dti == '='),
+        '/' | '@' => rest
+            .get(..7)
+            .and_then(|ts| parse_position(&rest[7..], Some(ts.to_string()), dti == '@')),
+        ';' => parse_object(rest),
+        ')' => parse_item(rest),
+        ':' => parse_message_body(source, body).map(PacketData::Message),
+        '>' => Some(PacketData::Status(rest.to_string())),
+        'T' if rest.starts_with('#') => telemetry::parse_frame(&rest[1..]).map(PacketData::Telemetry),
+        '_' => weather::parse_positionless(rest).map(|weather| PacketData::Weather {
+            timestamp: rest[..8].to_string(),
+            weather,
+        }),
+        '?' => Some(PacketData::Query(rest.to_string())),
+        '{' => Some(PacketData::UserDefined(rest.to_string())),
+        '}' if depth < MAX_THIRD_PARTY_DEPTH => parse_with_depth(rest, depth + 1)
+            .ok()
+            .map(|inner| PacketData::ThirdParty(Box::new(inner))),
+        '`' | '\'' | '\x1c' | '\x1d' => parse_mic_e(destination, rest).map(PacketData::MicE),
+        _ => {
+            // Some TNCs prepend text; the spec allows '!' up to the 40th character
+            body.char_indices()
+                .take(40)
+                .find(|(_, c)| *c == '!')
+                .and_then(|(i, _)| parse_position(&body[i + 1..], None, false))
+        }
+    };
+    parsed.unwrap_or_else(|| PacketData::Unknown(body.to_string()))
+}
+
+fn parse_position(data: &str, timestamp: Option<String>, messaging: bool) -> Option<PacketData> {
+    let (lat, lon, symbol_table, symbol_code, comment) = parse_coordinates(data)?;
+    Some(PacketData::Position(Position {
+        lat,
+        lon,
+        symbol_table,
+        symbol_code,
+        timestamp,
+        messaging,
+        comment: comment.to_string(),
+    }))
+}
+
+/// Parses an uncompressed (`DDMM.hhN/DDDMM.hhW>`) or compressed (`/YYYYXXXX>csT`)
+/// position, returning the remaining comment text.
+fn parse_coordinates(data: &str) -> Option<(f64, f64, char, char, &str)> {
+    let first = data.chars().next()?;
+    if first.is_ascii_digit() {
+        parse_uncompressed(data)
+    } else {
+        parse_compressed(data)
+    }
+}
+
+fn parse_uncompressed(data: &str) -> Option<(f64, f64, char, char, &str)> {
+    let b = data.as_bytes();
+    if b.len() < 19 || !data.is_char_boundary(19) {
+        return None;
+    }
+    // Position ambiguity replaces trailing digits with spaces
+    let lat_str = data.get(0..7)?.replace(' ', "0");
+    let lon_str = data.get(9..17)?.replace(' ', "0");
+    if !(lat_str.bytes().chain(lon_str.bytes())).all(|c| c.is_ascii_digit() || c == b'.') {
+        return None;
+    }
+    let lat_deg: f64 = lat_str.get(0..2)?.parse().ok()?;
+    let lat_min: f64 = lat_str.get(2..7)?.parse().ok()?;
+    let lon_deg: f64 = lon_str.get(0..3)?.parse().ok()?;
+    let lon_min: f64 = lon_str.get(3..8)?.parse().ok()?;
+    let mut lat = lat_deg + lat_min / 60.0;
+    let mut lon = lon_deg + lon_min / 60.0;
+    match b[7] {
+        b'N' | b'n' => {}
+        b'S' | b's' => lat = -lat,
+        _ => return None,
+    }
+    match b[17] {
+        b'E' | b'e' => {}
+        b'W' | b'w' => lon = -lon,
+        _ => return None,
+    }
+    if lat.abs() > 90.0 || lon.abs() > 180.0 {
+        return None;
+    }
+    let (table, code) = symbol(b[8], b[18])?;
+    Some((lat, lon, table, code, &data[19..]))
+}
+
+fn parse_compressed(data: &str) -> Option<(f64, f64, char, char, &str)> {
+    let b = data.as_bytes();
+    if b.len() < 13 || !data.is_char_boundary(13) {
+        return None;
+    }
+    let table = b[0];
+    if !(table == b'/' || table == b'\\' || table.is_ascii_uppercase() || (b'a'..=b'j').contains(&table)) {
+        return None;
+    }
+    let y = base91(&b[1..5])?;
+    let x = base91(&b[5..9])?;
+    let lat = 90.0 - y as f64 / 380926.0;
+    let lon = -180.0 + x as f64 / 190463.0;
+    let (table, code) = symbol(table, b[9])?;
+    Some((lat, lon, table, code, &data[13..]))
+}
+
+/// Symbol table and code, which are printable ASCII.
+fn symbol(table: u8, code: u8) -> Option<(char, char)> {
+    (table.is_ascii_graphic() && code.is_ascii_graphic()).then_some((table as char, code as char))
+}
+
+fn base91(bytes: &[u8]) -> Option<u32> {
+    bytes.iter().try_fold(0u32, |acc, &c| {
+        if (33..=124).contains(&c) {
+            Some(acc * 91 + (c - 33) as u32)
+        } else {
+            None
+        }
+    })
+}
+
+fn parse_object(data: &str) -> Option<PacketData> {
+    // ;NAME_____*DDHHMMzDDMM.hhN/DDDMM.hhW>comment
+    let name = data.get(0..9)?.trim_end().to_string();
+    let live = match data.as_bytes().get(9)? {
+        b'*' => true,
+        b'_' => false,
+        _ => return None,
+    };
+    let timestamp = data.get(10..17)?.to_string();
+    let (lat, lon, symbol_table, symbol_code, comment) = parse_coordinates(&data[17..])?;
+    Some(PacketData::Object {
+        name,
+        live,
+        position: Position {
+            lat,
+            lon,
+            symbol_table,
+            symbol_code,
+            timestamp: Some(timestamp),
+            messaging: false,
+            comment: comment.to_string(),
+        },
+    })
+}
+
+fn parse_item(data: &str) -> Option<PacketData> {
+    // )NAME!DDMM.hhN/DDDMM.hhW>comment, name is 3-9 characters
+    let end = data.char_indices().take(10).find(|(_, c)| *c == '!' || *c == '_')?.0;
+    if end < 3 {
+        return None;
+    }
+    let live = data.as_bytes()[end] == b'!';
+    let (lat, lon, symbol_table, symbol_code, comment) = parse_coordinates(&data[end + 1..])?;
+    Some(PacketData::Item {
+        name: data[..end].to_string(),
+        live,
+        position: Position {
+            lat,
+            lon,
+            symbol_table,
+            symbol_code,
+            timestamp: None,
+            messaging: false,
+            comment: comment.to_string(),
+        },
+    })
+}
+
+fn parse_mic_e(destination: &str, data: &str) -> Option<Position> {
+    // Latitude and flags are encoded in the destination callsign
+    let dest = destination.split('-').next().unwrap_or("").as_bytes();
+    let info = data.as_bytes();
+    if dest.len() < 6 || info.len() < 8 {
+        return None;
+    }
+    let mut digits = [0u8; 6];
+    for (i, &c) in dest[..6].iter().enumerate() {
+        digits[i] = match c {
+            b'0'..=b'9' => c - b'0',
+            b'A'..=b'J' => c - b'A',
+            b'P'..=b'Y' => c - b'P',
+            b'K' | b'L' | b'Z' => 0,
+            _ => return None,
+        };
+    }
+    let flag = |i: usize| (b'P'..=b'Z').contains(&dest[i]);
+    let lat_deg = (digits[0] * 10 + digits[1]) as f64;
+    let lat_min = (digits[2] * 10 + digits[3]) as f64 + (digits[4] * 10 + digits[5]) as f64 / 100.0;
+    let mut lat = lat_deg + lat_min / 60.0;
+    if !flag(3) {
+        lat = -lat;
+    }
+    let mut lon_deg = info[0].checked_sub(28)? as i32;
+    if flag(4) {
+        lon_deg += 100;
+    }
+    if (180..=189).contains(&lon_deg) {
+        lon_deg -= 80;
+    } else if (190..=199).contains(&lon_deg) {
+        lon_deg -= 190;
+    }
+    let mut lon_min = info[1].checked_sub(28)? as i32;
+    if lon_min >= 60 {
+        lon_min -= 60;
+    }
+    let lon_hundredths = info[2].checked_sub(28)? as i32;
+    let mut lon = lon_deg as f64 + (lon_min as f64 + lon_hundredths as f64 / 100.0) / 60.0;
+    if flag(5) {
+        lon = -lon;
+    }
+    if lat.abs() > 90.0 || lon.abs() > 180.0 {
+        return None;
+    }
+    let (symbol_table, symbol_code) = symbol(info[7], info[6])?;
+    Some(Position {
+        lat,
+        lon,
+        symbol_table,
+        symbol_code,
+        timestamp: None,
+        messaging: false,
+        comment: data[8..].to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        let p = parse("N0CALL-9>APRS,WIDE1-1,qAR,IGATE:>status text").unwrap();
+        assert_eq!(p.source, "N0CALL-9");
+        assert_eq!(p.destination, "APRS");
+        assert_eq!(p.path, vec!["WIDE1-1", "qAR", "IGATE"]);
+        assert_eq!(p.qconstruct, Some("qAR".to_string()));
+        assert_eq!(p.data, PacketData::Status("status text".to_string()));
+        assert_eq!(parse("N0CALL payload"), Err(ParseError::MissingPayloadSeparator));
+        assert_eq!(parse(">APRS:payload"), Err(ParseError::EmptySource));
+        assert_eq!(parse_bytes(b"N0CALL>APRS:>\xff"), Err(ParseError::InvalidUtf8));
+    }
+
+    #[test]
+    fn test_parse_aprs_lat_lon() {
+        let pkt = "N0CALL>APRS,TCPIP*:!4903.50N/07201.75W>Test";
+        let p = parse(pkt).unwrap();
+        let pos = p.position().unwrap();
+        assert!((pos.lat - 49.0583).abs() < 0.01);
+        assert!((pos.lon + 72.0291).abs() < 0.01);
+        assert_eq!(pos.symbol_code, '>');
+        assert_eq!(pos.comment, "Test");
+    }
+
+    #[test]
+    fn test_parse_timestamped_and_compressed() {
+        let p = parse("N0CALL>APRS:@092345z4903.50N/07201.75W>").unwrap();
+        let pos = p.position().unwrap();
+        assert_eq!(pos.timestamp.as_deref(), Some("092345z"));
+        assert!(pos.messaging);
+        let p = parse("N0CALL>APRS:!/5L!!<*e7>7P[").unwrap();
+        let pos = p.position().unwrap();
+        assert!((pos.lat - 49.5).abs() < 0.01);
+        assert!((pos.lon + 72.75).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_parse_mic_e() {
+        let p = parse("N0CALL>S32UVT:`(#bl\"O>/").unwrap();
+        let pos = p.position().unwrap();
+        assert!((pos.lat - 33.4273).abs() < 0.01);
+        assert!((pos.lon + 112.1283).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_parse_object_item_message() {
+        let p = parse("N0CALL>APRS:;LEADER   *092345z4903.50N/07201.75W>comment").unwrap();
+        assert!(matches!(p.data, PacketData::Object { ref name, live: true, .. } if name == "LEADER"));
+        let p = parse("N0CALL>APRS:)AID #2!4903.50N/07201.75WA").unwrap();
+        assert!(matches!(p.data, PacketData::Item { ref name, live: true, .. } if name == "AID #2"));
+        let p = parse("N0CALL>APRS::N1CALL   :hello{1").unwrap();
+        assert_eq!(p.message().unwrap().addressee, "N1CALL");
+    }
+
+    #[test]
+    fn test_parse_weather() {
+        let p = parse("N0CALL>APRS:_10090556c220s004g005t077r000p000P000h50b09900wRSW").unwrap();
+        assert_eq!(p.weather().unwrap().temperature, Some(77));
+        let p = parse("N0CALL>APRS:!4903.50N/07201.75W_220/004g005t077r000p000P000h50b09900").unwrap();
+        let wx = p.weather().unwrap();
+        assert_eq!(wx.wind_direction, Some(220));
+        assert!(p.position().is_some());
+        let p = parse("N0CALL>APRS:!4903.50N/07201.75W>220/004").unwrap();
+        assert!(p.weather().is_none());
+    }
+
+    #[test]
+    fn test_parse_telemetry() {
+        let p = parse("N0CALL>APRS:T#005,199,000,255,073,123,01101001").unwrap();
+        match p.data {
+            PacketData::Telemetry(frame) => assert_eq!(frame.sequence, "005"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_third_party() {
+        let p = parse("N0CALL>APRS:}N1CALL>APRS,TCPIP,N0CALL*:!4903.50N/07201.75W>").unwrap();
+        match &p.data {
+            PacketData::ThirdParty(inner) => assert_eq!(inner.source, "N1CALL"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(p.position().is_some());
+    }
+
+    #[test]
+    fn test_parse_short_and_multibyte_input() {
+        assert!(matches!(parse("N0CALL>APRS:!49").unwrap().data, PacketData::Unknown(_)));
+        assert!(matches!(parse("N0CALL>APRS:!4903.50N/0720ä.75W>").unwrap().data, PacketData::Unknown(_)));
+        assert!(matches!(parse("N0CALL>APRS:").unwrap().data, PacketData::Unknown(_)));
+        // Symbols must be ASCII, even where the coordinates parse
+        assert!(matches!(parse("N0CALL>APRS:!4903.50N/07201.75Wé").unwrap().data, PacketData::Unknown(_)));
+        assert!(matches!(parse("N0CALL>APRS:!/5L!!<*e7é7P").unwrap().data, PacketData::Unknown(_)));
+        assert!(matches!(parse("N0CALL>APRS:!49+3.50N/07201.75W>").unwrap().data, PacketData::Unknown(_)));
+    }
+
+    /// Every prefix of real packets with a multibyte character spliced in
+    /// at every position, and pseudo-random bytes, must parse without panic.
+    #[test]
+    fn test_arbitrary_input() {
+        let samples = [
+            "N0CALL>APRS:@092345z4903.50N/07201.75W>",
+            "N0CALL>APRS:!/5L!!<*e7>7P[",
+            "N0CALL>S32UVT:`(#bl\"O>/",
+            "N0CALL>APRS:;LEADER   *092345z4903.50N/07201.75W>",
+            "N0CALL>APRS:)AID #2!4903.50N/07201.75WA",
+            "N0CALL>APRS::N1CALL   :hello{1",
+            "N0CALL>APRS:_10090556c220s004g005t077r000p000P000h50b09900",
+            "N0CALL>APRS:!4903.50N/07201.75W_220/004g005t077",
+            "N0CALL>APRS:T#005,199,000,255,073,123,01101001",
+            "N0CALL>APRS:}N1CALL>S32UVT,TCPIP,N0CALL*:`(#bl\"O>/",
+        ];
+        for sample in samples {
+            for (i, _) in sample.char_indices() {
+                let _ = parse(&sample[..i]);
+                for insert in ["é", "€", "😀"] {
+                    let _ = parse(&format!("{}{}{}", &sample[..i], insert, &sample[i..]));
+                }
+            }
+        }
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for len in 0..2000 {
+            let line: Vec<u8> = b"N0CALL>S32UVT:"
+                .iter()
+                .copied()
+                .chain((0..len % 40).map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                }))
+                .collect();
+            let _ = parse_bytes(&line);
+            let _ = parse(&String::from_utf8_lossy(&line));
+        }
+    }
+}
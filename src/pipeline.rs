@@ -0,0 +1,649 @@
+//! The ingress pipeline. Packets from clients, the uplink, S2S peers and
+//! replays all go through [`ingest`], so every source gets the same hooks,
+//! validation, loop and duplicate checks, statistics and distribution.
+
+use crate::client_class::ClientClass;
+use crate::filter::FilterContext;
+use crate::gating::PathFlag;
+use crate::hook::DropReason;
+use crate::hub::Hub;
+use crate::latency::Outbound;
+use crate::message::{AprsMessage, ack_packet, parse_message};
+use crate::packet_log::LogStream;
+use crate::parser::ParsedPacket;
+use crate::qconstruct::{self, Link, Looped};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, mpsc};
+use std::time::Instant;
+
+// Packets waiting for an ingest shard worker beyond this are dropped, so a
+// stalled worker cannot take the server's memory
+pub const INGEST_QUEUE_SIZE: usize = 10_000;
+
+/// Where a packet entered the server.
+#[derive(Debug, Clone, Copy)]
+pub enum Source<'a> {
+    Client {
+        id: usize,
+        callsign: Option<&'a str>,
+        /// Logged in with the callsign's passcode; other clients are only
+        /// let in by a lenient `passcode_policy`
+        verified: bool,
+        class: Option<&'a ClientClass>,
+    },
+    Uplink,
+    /// An `[[uplinks]]` network other than `[uplink]`
+    Network { name: &'a str },
+    S2s { peer: Option<&'a str> },
+    Replay,
+    /// Packets heard on the air by the `[rf]` TNC
+    Rf,
+    /// Packets the server originates itself, such as beacons
+    Local,
+    /// Packets injected by an operator, tagged with `qAS` and our name
+    Injected,
+}
+
+impl Source<'_> {
+    /// Entry point recorded in the heard list.
+    fn entry(&self) -> String {
+        match self {
+            Source::Client { callsign, .. } => format!("client:{}", callsign.unwrap_or("unknown")),
+            Source::Uplink => "uplink".to_string(),
+            Source::Network { name } => format!("uplink:{}", name),
+            Source::S2s { peer } => format!("s2s:{}", peer.unwrap_or("unknown")),
+            Source::Replay => "replay".to_string(),
+            Source::Rf => "rf".to_string(),
+            Source::Local => "local".to_string(),
+            Source::Injected => "injected".to_string(),
+        }
+    }
+    fn log_stream(&self) -> Option<LogStream> {
+        match self {
+            Source::Client { .. } => Some(LogStream::Clients),
+            Source::Uplink | Source::Network { .. } => Some(LogStream::Uplink),
+            Source::S2s { .. } => Some(LogStream::S2s),
+            Source::Replay | Source::Rf | Source::Local | Source::Injected => None,
+        }
+    }
+    /// Client that must not get its own packet back; 0 is never a client.
+    fn client_id(&self) -> usize {
+        match self {
+            Source::Client { id, .. } => *id,
+            _ => 0,
+        }
+    }
+    /// The connection the q construct algorithm sees; packets the server
+    /// originates, replays and RF are tagged where they are made.
+    fn link<'a>(&'a self, server: &'a str) -> Option<Link<'a>> {
+        match self {
+            Source::Client { callsign, verified, .. } => Some(Link::Client { login: *callsign, verified: *verified }),
+            Source::S2s { peer } => Some(Link::Server { via: peer.unwrap_or(server) }),
+            Source::Uplink | Source::Network { .. } => Some(Link::Server { via: server }),
+            Source::Replay | Source::Rf | Source::Local | Source::Injected => None,
+        }
+    }
+    fn peer(&self) -> Option<&str> {
+        match self {
+            Source::S2s { peer } => *peer,
+            _ => None,
+        }
+    }
+    /// The uplink carries the full feed, which peers get from their own
+    /// uplinks, and replays and our own packets stay local. So do packets
+    /// of unverified clients, which the wider network does not trust.
+    fn forwards_to_peers(&self) -> bool {
+        matches!(self, Source::Client { verified: true, .. } | Source::S2s { .. } | Source::Rf | Source::Injected)
+    }
+}
+
+/// A source whose packets can be queued for an ingest shard worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Uplink,
+    Network(String),
+    S2s(Option<String>),
+}
+
+impl Origin {
+    fn source(&self) -> Source<'_> {
+        match self {
+            Origin::Uplink => Source::Uplink,
+            Origin::Network(name) => Source::Network { name },
+            Origin::S2s(peer) => Source::S2s { peer: peer.as_deref() },
+        }
+    }
+}
+
+/// Ingests a packet from the uplink or a peer. With ingest shards it is
+/// queued for the worker owning its source callsign, so one station's
+/// packets stay in order and each duplicate cache shard is only touched
+/// by its own worker; otherwise it is processed right away. Packets for a
+/// full queue are dropped and counted.
+pub fn submit(hub: &Hub, origin: Origin, line: &str) {
+    if hub.ingest_shards.is_empty() {
+        let _ = ingest(hub, origin.source(), line);
+        return;
+    }
+    let source = line.split('>').next().unwrap_or("");
+    let shard = crate::hub::source_shard(source, hub.ingest_shards.len());
+    if let Err(mpsc::TrySendError::Full(_)) = hub.ingest_shards[shard].try_send((origin, line.to_string())) {
+        hub.ingest_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Runs an ingest shard worker until the hub's queue is dropped.
+pub fn run_shard(queue: mpsc::Receiver<(Origin, String)>, hub: Arc<Hub>) {
+    for (origin, line) in queue {
+        let _ = ingest(&hub, origin.source(), &line);
+    }
+}
+
+/// Processes one packet line and distributes it if accepted. Drops are
+/// counted in the hub before the reason is returned.
+pub fn ingest(hub: &Hub, source: Source, line: &str) -> Result<(), DropReason> {
+    let received = Instant::now();
+    hub.counters.add_rx(line.len());
+    let callsign = line.split('>').next().unwrap_or("");
+    let watched = hub.watch.watching(callsign);
+    if watched {
+        hub.watch.report(callsign, line, "received", format!("from {}", source.entry()));
+    }
+    // Hooks may rewrite or reject the packet before any other processing
+    let Some(mut packet) = hub.run_ingress_hooks(line) else {
+        if watched {
+            hub.watch.report(callsign, line, "drop", "rejected by a hook");
+        }
+        return Err(DropReason::Hook);
+    };
+    let drop = |packet: &str, reason: DropReason| {
+        hub.notify_drop(packet, reason);
+        if watched {
+            hub.watch.report(callsign, packet, "drop", format!("{:?}", reason));
+        }
+        Err(reason)
+    };
+    // Parsed once here; everything downstream shares this copy
+    let Ok(mut parsed) = crate::parser::parse(&packet) else {
+        return drop(&packet, DropReason::Invalid);
+    };
+    // Injected packets carry our name by design
+    if !matches!(source, Source::Injected) && hub.is_loop(&parsed, source.peer()) {
+        return drop(&packet, DropReason::Loop);
+    }
+    if let Some(link) = source.link(&hub.server_name) {
+        match qconstruct::process(&parsed, link, &hub.server_name) {
+            Err(Looped) => return drop(&packet, DropReason::Loop),
+            Ok(Some((q, via))) => {
+                if let Some((header, body)) = packet.split_once(':') {
+                    packet = format!("{},{},{}:{}", header, q, via, body);
+                }
+                parsed.path.extend([q.to_string(), via]);
+                parsed.qconstruct = Some(q.to_string());
+            }
+            Ok(None) => {}
+        }
+    }
+    let parsed = Arc::new(parsed);
+    // A class's policy goes before anything that records the packet, so a
+    // packet it rejects neither sets state nor shadows a later legitimate copy
+    if let Source::Client { callsign, class: Some(class), .. } = source {
+        let allowed = class.allows(&parsed, &FilterContext { callsign, positions: Some(&hub.positions), zones: hub.nws_zones.as_ref() });
+        if !allowed {
+            return drop(&packet, DropReason::Policy);
+        }
+    }
+    // Our own packets never carry these; checked before the duplicate cache
+    // so a clean copy arriving later still gets through
+    if !matches!(source, Source::Local)
+        && let Some(flag) = crate::gating::forbidden_flag(&parsed)
+    {
+        return drop(&packet, match flag {
+            PathFlag::Tcpxx => DropReason::Tcpxx,
+            PathFlag::NoGate => DropReason::NoGate,
+            PathFlag::RfOnly => DropReason::RfOnly,
+        });
+    }
+    // Both checks ignore the path, which differs between copies heard by
+    // different igates or relayed by different servers
+    if hub.check_and_insert_dupe(&packet) || hub.check_and_insert_message_dupe(&parsed) {
+        return drop(&packet, DropReason::Duplicate);
+    }
+    if hub.check_position(&parsed).is_err() {
+        return drop(&packet, DropReason::BadPosition);
+    }
+    hub.record_packet(&parsed);
+    // The source tag routing rules match on
+    let entry = source.entry();
+    hub.record_heard(&parsed.source, &entry);
+    let out = Outbound::new(format!("{}\r\n", packet), received);
+
+    // Messages to the server, such as queries and sysop commands, end here
+    // rather than going out to clients, peers, logs and sinks
+    if let Some(msg) = parsed.message()
+        && msg.addressee.eq_ignore_ascii_case(&hub.callsign)
+        && !msg.source.eq_ignore_ascii_case(&hub.callsign)
+    {
+        hub.counters.accepted.fetch_add(1, Ordering::Relaxed);
+        if watched {
+            hub.watch.report(callsign, &packet, "server", "addressed to the server");
+        }
+        if !msg.is_ack_or_rej() {
+            respond(hub, source, msg);
+        }
+        return Ok(());
+    }
+
+    hub.notify_accept(&packet, &parsed);
+    if watched {
+        hub.watch.report(callsign, &packet, "accepted", crate::watch::q_construct(&parsed));
+    }
+    if let Some(stream) = source.log_stream() {
+        hub.log_packet(stream, &packet);
+    }
+    let delivered = hub.broadcast_packet(source.client_id(), &entry, &out, &parsed);
+    if source.forwards_to_peers() {
+        hub.broadcast_to_s2s_peers(source.peer(), &entry, &packet, received, &parsed);
+    }
+    // Packets heard on the air are gated to APRS-IS
+    let gated = matches!(source, Source::Rf) && hub.send_to_uplinks(&entry, &out, &parsed);
+    if let Some(msg) = parsed.message() {
+        route_message(hub, source, &parsed, msg, &out, delivered, gated);
+    }
+    if let Some(igate) = &hub.tx_igate
+        && !matches!(source, Source::Rf | Source::Replay | Source::Client { verified: false, .. })
+        && hub.routed(&entry, || "rf".to_string(), &parsed)
+    {
+        let decision = igate.consider(&hub.rf_heard, &parsed, &packet, hub.clock.unix_time());
+        if watched && let Some(decision) = decision {
+            hub.watch.report(callsign, &packet, "rf", format!("{:?}", decision));
+        }
+    }
+    Ok(())
+}
+
+/// Validates a packet from an operator, tags it with `qAS` and the server
+/// name and ingests it. Returns the tagged line, or why it was refused.
+pub fn inject(hub: &Hub, line: &str) -> Result<String, String> {
+    let line = line.trim();
+    crate::parser::parse(line).map_err(|e| e.to_string())?;
+    let (header, body) = line.split_once(':').ok_or("missing information field")?;
+    if header.split(',').any(|hop| hop.starts_with("qA")) {
+        return Err("packet already has a q construct".to_string());
+    }
+    let tagged = format!("{},qAS,{}:{}", header, hub.server_name, body);
+    ingest(hub, Source::Injected, &tagged).map_err(|reason| format!("packet dropped ({:?})", reason))?;
+    Ok(tagged)
+}
+
+/// Records a message for delivery tracking. Messages from local clients
+/// whose addressee is not logged in here go out to the uplinks; `gated`
+/// ones from RF have already been sent there.
+fn route_message(hub: &Hub, source: Source, packet: &ParsedPacket, msg: &AprsMessage, out: &Outbound, delivered: bool, gated: bool) {
+    let mut messages = hub.messages.lock().unwrap();
+    messages.record(msg, &source.entry(), delivered, hub.clock.unix_time());
+    let forwarded = gated
+        || matches!(source, Source::Client { .. })
+            && !delivered
+            && !msg.addressee.eq_ignore_ascii_case(&hub.callsign)
+            && hub.send_to_uplinks(&source.entry(), out, packet);
+    if forwarded {
+        messages.forwarded(msg);
+    }
+}
+
+/// Answers a message to the server's callsign. Replies go to local clients,
+/// and back to the uplink if the message came from there.
+fn respond(hub: &Hub, source: Source, msg: &AprsMessage) {
+    let mut replies = Vec::new();
+    if let Some(msgid) = &msg.msgid {
+        replies.push(ack_packet(&hub.callsign, &msg.source, msgid));
+    }
+    let answers = crate::query::replies(hub, msg);
+    if answers.is_empty() {
+        replies.extend(crate::remote_admin::handle(hub, source, msg).unwrap_or_default());
+    } else {
+        replies.extend(answers);
+    }
+    for reply in replies {
+        let _ = ingest(hub, Source::Local, &reply);
+        // Answered on the network the query came from
+        let network = match source {
+            Source::Uplink => Some(None),
+            Source::Network { name } => Some(Some(name)),
+            _ => None,
+        };
+        if let Some(network) = network
+            && hub.send_to_network(network, &format!("{}\r\n", reply))
+            && let Some(msg) = parse_message(&reply)
+        {
+            hub.messages.lock().unwrap().forwarded(&msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hub::S2SPeerHandle;
+
+    fn s2s(peer: &str) -> Source<'_> {
+        Source::S2s { peer: Some(peer) }
+    }
+
+    #[test]
+    fn test_sources_share_dupe_cache() {
+        let hub = Hub::new();
+        let line = "N0CALL>APRS,TCPIP*:>hello";
+        assert_eq!(ingest(&hub, Source::Uplink, line), Ok(()));
+        assert_eq!(ingest(&hub, s2s("T2PEER"), line), Err(DropReason::Duplicate));
+        let client = Source::Client { id: 1, callsign: Some("N0CALL"), verified: true, class: None };
+        assert_eq!(ingest(&hub, client, line), Err(DropReason::Duplicate));
+        assert_eq!(ingest(&hub, Source::Replay, "garbage"), Err(DropReason::Invalid));
+        // The same packet gated by two igates, then relayed by a peer
+        assert_eq!(ingest(&hub, Source::Uplink, "N1CALL>APRS,WIDE1-1,qAR,IGATE1:>hi"), Ok(()));
+        assert_eq!(ingest(&hub, Source::Uplink, "N1CALL>APRS,qAR,IGATE2:>hi"), Err(DropReason::Duplicate));
+        assert_eq!(ingest(&hub, s2s("T2PEER"), "N1CALL>APRS,qAR,IGATE1,T2PEER*:>hi"), Err(DropReason::Duplicate));
+        let drops = hub.counters.drop_stats();
+        assert_eq!((drops.accepted, drops.duplicates, drops.invalid), (2, 4, 1));
+        assert_eq!(hub.stations.read().unwrap().heard["N0CALL"].entry, "uplink");
+    }
+    #[test]
+    fn test_class_policy_before_dupes() {
+        let hub = Hub::new();
+        let config: crate::config::ClientClassConfig = toml::from_str("name = \"messages\"\nallowed_types = \"m\"").unwrap();
+        let class = ClientClass::from_config(&config).unwrap();
+        let client = Source::Client { id: 1, callsign: Some("N0CALL"), verified: true, class: Some(&class) };
+        let line = "N0CALL>APRS,TCPIP*:!4903.50N/07201.75W-";
+        assert_eq!(ingest(&hub, client, line), Err(DropReason::Policy));
+        assert!(hub.stations.read().unwrap().heard.is_empty());
+        assert!(hub.positions.is_empty());
+        // The rejected copy did not reach the duplicate cache
+        assert_eq!(ingest(&hub, Source::Uplink, line), Ok(()));
+        assert!(hub.positions.get("N0CALL").is_some());
+    }
+    #[test]
+    fn test_ingest_shards() {
+        let mut hub = Hub::new();
+        hub.set_cache_shards(2);
+        let queues: Vec<_> = (0..2)
+            .map(|_| {
+                let (tx, rx) = mpsc::sync_channel(INGEST_QUEUE_SIZE);
+                hub.ingest_shards.push(tx);
+                rx
+            })
+            .collect();
+        let hub = Arc::new(hub);
+        for queue in queues {
+            let hub = hub.clone();
+            std::thread::spawn(move || run_shard(queue, hub));
+        }
+        for i in 0..20 {
+            let line = format!("N{}CALL>APRS:>hello", i);
+            submit(&hub, Origin::Uplink, &line);
+            submit(&hub, Origin::S2s(Some("T2PEER".to_string())), &line);
+        }
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while hub.counters.drop_stats().duplicates < 20 && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let drops = hub.counters.drop_stats();
+        assert_eq!((drops.accepted, drops.duplicates), (20, 20));
+        assert_eq!(crate::hub::source_shard("n0call", 8), crate::hub::source_shard("N0CALL", 8));
+    }
+    #[test]
+    fn test_ingest_queue_full() {
+        let mut hub = Hub::new();
+        let (tx, rx) = mpsc::sync_channel(2);
+        hub.ingest_shards.push(tx);
+        // No worker drains the queue
+        for i in 0..5 {
+            submit(&hub, Origin::Uplink, &format!("N{}CALL>APRS:>hello", i));
+        }
+        assert_eq!(hub.dupe_stats(0).ingest_dropped, 3);
+        assert_eq!(rx.try_iter().count(), 2);
+    }
+    #[test]
+    fn test_loop_drop() {
+        let mut hub = Hub::new();
+        hub.server_name = "T2TEST".to_string();
+        let unknown = Source::S2s { peer: None };
+        assert_eq!(ingest(&hub, unknown, "N0CALL>APRS,TCPIP*,qAC,t2test:>looped"), Err(DropReason::Loop));
+        assert_eq!(ingest(&hub, unknown, "N0CALL>APRS,TCPIP*,qAC,T2OTHER:>fine"), Ok(()));
+        assert_eq!(ingest(&hub, unknown, "garbage"), Err(DropReason::Invalid));
+        let drops = hub.counters.drop_stats();
+        assert_eq!((drops.accepted, drops.loops, drops.invalid), (1, 1, 1));
+        assert_eq!(hub.counters.dropped.load(Ordering::Relaxed), 2);
+    }
+    #[test]
+    fn test_q_constructs() {
+        let mut hub = Hub::new();
+        hub.server_name = "T2TEST".to_string();
+        let mut rx = hub.packet_feed.subscribe();
+        let client = Source::Client { id: 1, callsign: Some("N0CALL"), verified: true, class: None };
+        assert_eq!(ingest(&hub, client, "N0CALL>APRS,TCPIP*:>own"), Ok(()));
+        assert_eq!(rx.try_recv().unwrap().line, "N0CALL>APRS,TCPIP*,qAC,T2TEST:>own");
+        assert_eq!(ingest(&hub, client, "N1CALL>APRS,WIDE1-1:>gated"), Ok(()));
+        assert_eq!(rx.try_recv().unwrap().line, "N1CALL>APRS,WIDE1-1,qAo,N0CALL:>gated");
+        assert_eq!(ingest(&hub, s2s("T2PEER"), "N2CALL>APRS,TCPIP*:>peer"), Ok(()));
+        assert_eq!(rx.try_recv().unwrap().line, "N2CALL>APRS,TCPIP*,qAS,T2PEER:>peer");
+        // Looped packets are dropped
+        assert_eq!(ingest(&hub, client, "N3CALL>APRS,qAZ,N0CALL:>zapped"), Err(DropReason::Loop));
+        assert_eq!(ingest(&hub, client, "N3CALL>APRS,qAR,N0CALL,T2PEER:>looped"), Err(DropReason::Loop));
+        assert_eq!(ingest(&hub, s2s("T2PEER"), "N3CALL>APRS,qAR,N4CALL,T2PEER,N4CALL:>looped"), Err(DropReason::Loop));
+        assert_eq!(hub.counters.drop_stats().loops, 3);
+    }
+    #[test]
+    fn test_peer_loop() {
+        let mut hub = Hub::new();
+        hub.server_name = "T2TEST".to_string();
+        hub.peer_ids.get_mut().unwrap().insert("T2PEERA".to_string());
+        hub.peer_ids.get_mut().unwrap().insert("T2PEERB".to_string());
+        // A peer's own ID is expected on packets it sends us
+        assert_eq!(ingest(&hub, s2s("T2PEERA"), "N0CALL>APRS,TCPIP*,qAC,T2PEERA:>direct"), Ok(()));
+        // Came from A but already went through B, which feeds us directly
+        let via_b = "N0CALL>APRS,TCPIP*,qAC,T2PEERB,T2PEERA:>via b";
+        assert_eq!(ingest(&hub, s2s("T2PEERA"), via_b), Err(DropReason::Loop));
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        hub.s2s_peer_handles.write().unwrap().push(S2SPeerHandle { peer_name: Some("T2PEERB".to_string()), sender: tx, filters: None });
+        assert_eq!(ingest(&hub, s2s("T2PEERA"), "N1CALL>APRS,TCPIP*,qAC,T2PEERA:>relayed"), Ok(()));
+        assert_eq!(rx.try_recv().unwrap(), "N1CALL>APRS,TCPIP*,qAC,T2PEERA,T2TEST:>relayed\r\n");
+        // Uplink traffic stays local
+        assert_eq!(ingest(&hub, Source::Uplink, "N2CALL>APRS,TCPIP*,qAS,T2OTHER:>feed"), Ok(()));
+        assert!(rx.try_recv().is_err());
+    }
+    #[test]
+    fn test_s2s_peer_filter() {
+        let hub = Hub::new();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let filters = crate::filter::parse_filter_list("p/N0").unwrap();
+        hub.s2s_peer_handles.write().unwrap().push(S2SPeerHandle { peer_name: Some("T2PEERB".to_string()), sender: tx, filters: Some(filters) });
+        assert_eq!(ingest(&hub, s2s("T2PEERA"), "N1CALL>APRS,TCPIP*:>skipped"), Ok(()));
+        assert_eq!(ingest(&hub, s2s("T2PEERA"), "N0CALL>APRS,TCPIP*:>sent"), Ok(()));
+        assert!(rx.try_recv().unwrap().line.ends_with(":>sent\r\n"));
+        assert!(rx.try_recv().is_err());
+    }
+    #[test]
+    fn test_routes() {
+        use crate::config::{RouteAction, RouteConfig};
+        let mut hub = Hub::new();
+        let deny = RouteConfig { from: "s2s:T2PEERA".to_string(), to: "s2s:T2PEERB".to_string(), filter: None, action: RouteAction::Deny };
+        hub.routes.push(crate::routing::RouteRule::from_config(&deny).unwrap());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        hub.s2s_peer_handles.write().unwrap().push(S2SPeerHandle { peer_name: Some("T2PEERB".to_string()), sender: tx, filters: None });
+        assert_eq!(ingest(&hub, s2s("T2PEERA"), "N0CALL>APRS,TCPIP*:>from a"), Ok(()));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(ingest(&hub, s2s("T2PEERC"), "N0CALL>APRS,TCPIP*:>from c"), Ok(()));
+        assert!(rx.try_recv().unwrap().line.ends_with(":>from c\r\n"));
+    }
+    #[test]
+    fn test_concurrent_ingest() {
+        let hub = Arc::new(Hub::new());
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let hub = hub.clone();
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        // Every thread also sends a packet the others send
+                        let _ = ingest(&hub, Source::Uplink, &format!("N{}CALL>APRS:>{}", t, i));
+                        let _ = ingest(&hub, Source::Uplink, &format!("N9CALL>APRS:>{}", i));
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let drops = hub.counters.drop_stats();
+        assert_eq!((drops.accepted, drops.duplicates), (500, 300));
+    }
+    #[test]
+    fn test_query_reply() {
+        let mut hub = Hub::new();
+        hub.callsign = "T2TEST".to_string();
+        hub.position = Some("!4903.50NI07201.75W&".to_string());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        hub.uplinks.get_mut().unwrap().push(crate::uplink::test_route(tx));
+        assert_eq!(ingest(&hub, Source::Uplink, "N0CALL>APRS,TCPIP*,qAR,N0GATE::T2TEST   :?APRSP{7"), Ok(()));
+        assert_eq!(rx.try_recv().unwrap(), "T2TEST>APRS,TCPIP*::N0CALL   :ack7\r\n");
+        assert_eq!(rx.try_recv().unwrap(), "T2TEST>APRS,TCPIP*:!4903.50NI07201.75W&\r\n");
+        assert!(hub.positions.get("T2TEST").is_some());
+        // Queries from local clients are only answered locally
+        let client = Source::Client { id: 1, callsign: Some("N1CALL"), verified: true, class: None };
+        assert_eq!(ingest(&hub, client, "N1CALL>APRS::T2TEST   :?APRSD"), Ok(()));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(hub.counters.drop_stats().accepted, 5);
+    }
+    #[test]
+    fn test_admin_message_not_distributed() {
+        use crate::client::{Client, ClientConn};
+        use crate::config::RemoteAdminConfig;
+        let mut hub = Hub::new();
+        hub.callsign = "T2TEST".to_string();
+        let config = RemoteAdminConfig { sysops: vec!["N0CALL".to_string()], secret: Some("s3cret".to_string()) };
+        hub.remote_admin = Some(crate::remote_admin::RemoteAdmin::new(config));
+        let (client_tx, mut client_rx) = tokio::sync::mpsc::channel(16);
+        // Would see every message that is passed on
+        let mut client = Client::with_conn(0, ClientConn::WebSocket { tx: Some(client_tx), write_timeout: std::time::Duration::from_secs(1) }, "127.0.0.1:1".parse().ok());
+        client.filter = Some(Arc::new(crate::filter::FilterSet::new(crate::filter::parse_filter_list("t/m").unwrap())));
+        hub.add_client(client);
+        let (tx, mut peer_rx) = tokio::sync::mpsc::unbounded_channel();
+        hub.s2s_peer_handles.write().unwrap().push(S2SPeerHandle { peer_name: Some("T2PEER".to_string()), sender: tx, filters: None });
+        let mut feed = hub.packet_feed.subscribe();
+
+        let code = crate::remote_admin::command_code("s3cret", "N0CALL", "3", hub.clock.unix_time(), "status");
+        let command = format!("N0CALL>APRS,qAR,N0GATE::T2TEST   :{} status{{3", code);
+        assert_eq!(ingest(&hub, s2s("T2OTHER"), &command), Ok(()));
+        // Only the server's ack and reply go out, never the command. The
+        // client's writer thread passes them on shortly
+        let mut sent = Vec::new();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while sent.len() < 2 && std::time::Instant::now() < deadline {
+            match client_rx.try_recv() {
+                Ok(line) => sent.push(line),
+                Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        sent.extend(std::iter::from_fn(|| client_rx.try_recv().ok()));
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|line| line.starts_with("T2TEST>")), "{:?}", sent);
+        assert!(std::iter::from_fn(|| peer_rx.try_recv().ok()).all(|p| !p.line.contains(&code)));
+        assert!(std::iter::from_fn(|| feed.try_recv().ok()).all(|p| !p.line.contains(&code)));
+        assert_eq!(hub.counters.drop_stats().accepted, 3);
+    }
+    #[test]
+    fn test_message_routing() {
+        use crate::client::{Client, ClientConn};
+        use crate::message_tracker::MessageState;
+        let mut hub = Hub::new();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        hub.uplinks.get_mut().unwrap().push(crate::uplink::test_route(tx));
+        let (client_tx, _client_rx) = tokio::sync::mpsc::channel(16);
+        let mut client = Client::with_conn(0, ClientConn::WebSocket { tx: Some(client_tx), write_timeout: std::time::Duration::from_secs(1) }, "127.0.0.1:1".parse().ok());
+        client.callsign = Some("N1CALL".to_string());
+        let id = hub.add_client(client);
+        let n0call = Source::Client { id: 99, callsign: Some("N0CALL"), verified: true, class: None };
+
+        // Local addressees get it here, others through the uplink
+        assert_eq!(ingest(&hub, n0call, "N0CALL>APRS::N1CALL   :Hi{1"), Ok(()));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(ingest(&hub, n0call, "N0CALL>APRS::N2CALL   :Hi{2"), Ok(()));
+        assert_eq!(rx.try_recv().unwrap().line, format!("N0CALL>APRS,qAC,{}::N2CALL   :Hi{{2\r\n", hub.server_name));
+        let n1call = Source::Client { id, callsign: Some("N1CALL"), verified: true, class: None };
+        assert_eq!(ingest(&hub, n1call, "N1CALL>APRS::N0CALL   :ack1"), Ok(()));
+        assert_eq!(rx.try_recv().unwrap().line, format!("N1CALL>APRS,qAC,{}::N0CALL   :ack1\r\n", hub.server_name));
+
+        let messages = hub.messages.lock().unwrap();
+        assert_eq!(messages.to("N1CALL")[0].state, MessageState::Acked);
+        assert!(messages.to("N1CALL")[0].delivered);
+        assert!(messages.to("N2CALL")[0].forwarded);
+        let counters = &messages.counters;
+        assert_eq!((counters.routed, counters.delivered, counters.forwarded, counters.acked), (3, 1, 2, 1));
+    }
+    #[test]
+    fn test_position_checks() {
+        let mut hub = Hub::new();
+        hub.position_checks = Some(crate::config::PositionChecksConfig { reject_null_island: true, max_speed_kmh: Some(500.0) });
+        assert_eq!(ingest(&hub, Source::Uplink, "N0CALL>APRS:!6000.00N/02500.00E>"), Ok(()));
+        assert_eq!(ingest(&hub, Source::Uplink, "N0CALL>APRS:!0000.00N/00000.00E>"), Err(DropReason::BadPosition));
+        assert_eq!(ingest(&hub, Source::Uplink, "N0CALL>APRS:!4903.50N/07201.75W>"), Err(DropReason::BadPosition));
+        assert_eq!(hub.stations.read().unwrap().rejected_positions["N0CALL"].count, 2);
+        assert_eq!(hub.positions.get("N0CALL").unwrap().lat, 60.0);
+        assert_eq!(hub.counters.drop_stats().bad_position, 2);
+    }
+    #[test]
+    fn test_path_flags() {
+        let hub = Hub::new();
+        assert_eq!(ingest(&hub, Source::Uplink, "N0CALL>APRS,TCPXX*,qAX,T2TEST:>hi"), Err(DropReason::Tcpxx));
+        assert_eq!(ingest(&hub, s2s("T2PEER"), "N0CALL>APRS,NOGATE,qAR,N0GATE:>hi"), Err(DropReason::NoGate));
+        // A clean copy is not a duplicate of the dropped one
+        assert_eq!(ingest(&hub, Source::Uplink, "N0CALL>APRS,TCPIP*,qAC,T2TEST:>hi"), Ok(()));
+        let drops = hub.counters.drop_stats();
+        assert_eq!((drops.tcpxx, drops.nogate, drops.rfonly, drops.accepted), (1, 1, 0, 1));
+    }
+    #[test]
+    fn test_inject() {
+        let mut hub = Hub::new();
+        hub.server_name = "T2TEST".to_string();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        hub.s2s_peer_handles.write().unwrap().push(S2SPeerHandle { peer_name: Some("T2PEER".to_string()), sender: tx, filters: None });
+        let object = "T2TEST>APRS,TCPIP*:;NET      *092345z4903.50N/07201.75W-Net tonight";
+        assert_eq!(inject(&hub, object).unwrap(), "T2TEST>APRS,TCPIP*,qAS,T2TEST:;NET      *092345z4903.50N/07201.75W-Net tonight");
+        assert!(rx.try_recv().unwrap().line.contains(",qAS,T2TEST:;NET"));
+        assert_eq!(hub.stations.read().unwrap().objects.len(), 1);
+        assert_eq!(inject(&hub, object), Err("packet dropped (Duplicate)".to_string()));
+        assert_eq!(inject(&hub, "N0CALL>APRS,qAC,T2OTHER:>status"), Err("packet already has a q construct".to_string()));
+        assert!(inject(&hub, "garbage").is_err());
+    }
+    #[test]
+    fn test_watch() {
+        use crate::client::{Client, ClientConn};
+        let hub = Hub::new();
+        let (client_tx, _client_rx) = tokio::sync::mpsc::channel(16);
+        let mut client = Client::with_conn(0, ClientConn::WebSocket { tx: Some(client_tx), write_timeout: std::time::Duration::from_secs(1) }, "127.0.0.1:1".parse().ok());
+        client.callsign = Some("N1CALL".to_string());
+        client.filter = Some(Arc::new(crate::filter::FilterSet::new(crate::filter::parse_filter_list("p/N9").unwrap())));
+        client.filter_string = Some("p/N9".to_string());
+        hub.add_client(client);
+        hub.watch.add("N0CALL");
+        let mut events = hub.watch.subscribe();
+
+        let line = "N0CALL-9>APRS,TCPIP*,qAC,T2TEST:>hi";
+        assert_eq!(ingest(&hub, Source::Uplink, line), Ok(()));
+        assert_eq!(ingest(&hub, Source::Uplink, line), Err(DropReason::Duplicate));
+        assert_eq!(ingest(&hub, Source::Uplink, "N1CALL>APRS:>not watched"), Ok(()));
+        let events: Vec<(&str, String)> = std::iter::from_fn(|| events.try_recv().ok()).map(|e| (e.step, e.detail)).collect();
+        assert_eq!(
+            events,
+            [
+                ("received", "from uplink".to_string()),
+                ("accepted", "qAC via T2TEST".to_string()),
+                ("client", "1 N1CALL: filter p/N9 did not match".to_string()),
+                ("received", "from uplink".to_string()),
+                ("drop", "Duplicate".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Latest own position of each station. With ingest shards the cache is
+//! split by source callsign like the duplicate cache, so workers recording
+//! the positions of different stations do not wait on one lock. Filters
+//! may look up any station, which only takes that station's shard.
+
+use crate::hub::{StationPosition, source_shard};
+use crate::station_cache::{evict_oldest, expire};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Debug)]
+pub struct PositionCache {
+    shards: Vec<RwLock<HashMap<String, StationPosition>>>,
+}
+
+impl Default for PositionCache {
+    fn default() -> Self {
+        PositionCache::new(1)
+    }
+}
+
+impl PositionCache {
+    pub fn new(shards: usize) -> Self {
+        PositionCache { shards: (0..shards.max(1)).map(|_| RwLock::default()).collect() }
+    }
+
+    fn shard(&self, callsign: &str) -> &RwLock<HashMap<String, StationPosition>> {
+        &self.shards[source_shard(callsign, self.shards.len())]
+    }
+
+    /// The position of `callsign`, given in upper case.
+    pub fn get(&self, callsign: &str) -> Option<StationPosition> {
+        self.shard(callsign).read().unwrap().get(callsign).cloned()
+    }
+
+    /// Latitude and longitude of `callsign`, given in upper case.
+    pub fn coordinates(&self, callsign: &str) -> Option<(f64, f64)> {
+        self.shard(callsign).read().unwrap().get(callsign).map(|p| (p.lat, p.lon))
+    }
+
+    /// Stores a position, holding its shard to its share of `limit`.
+    /// Returns how many entries were evicted for it.
+    pub fn insert(&self, position: StationPosition, limit: Option<usize>) -> usize {
+        let mut shard = self.shard(&position.callsign).write().unwrap();
+        shard.insert(position.callsign.clone(), position);
+        limit.map_or(0, |limit| evict_oldest(&mut shard, limit.div_ceil(self.shards.len()), |p| p.updated))
+    }
+
+    /// Drops positions last updated before `cutoff`. Returns how many went.
+    pub fn expire(&self, cutoff: u64) -> usize {
+        self.shards.iter().map(|s| expire(&mut s.write().unwrap(), cutoff, |p| p.updated)).sum()
+    }
+
+    /// Positions updated at or after `since`.
+    pub fn updated_since(&self, since: u64) -> Vec<StationPosition> {
+        self.shards
+            .iter()
+            .flat_map(|s| s.read().unwrap().values().filter(|p| p.updated >= since).cloned().collect::<Vec<_>>())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.read().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sharded_positions() {
+        let cache = PositionCache::new(4);
+        let position = |i: u64| StationPosition { callsign: format!("N{}CALL", i), lat: i as f64, updated: i, ..Default::default() };
+        for i in 0..40 {
+            cache.insert(position(i), None);
+        }
+        assert_eq!(cache.len(), 40);
+        assert_eq!(cache.coordinates("N7CALL"), Some((7.0, 0.0)));
+        assert_eq!(cache.get("N9CALL").unwrap().updated, 9);
+        assert_eq!(cache.updated_since(30).len(), 10);
+        assert_eq!(cache.expire(20), 20);
+        assert!(cache.get("N19CALL").is_none());
+        // Each shard keeps its share of the limit
+        let evicted: usize = (40..80).map(|i| cache.insert(position(i), Some(40))).sum();
+        assert_eq!(cache.len(), 60 - evicted);
+        assert!(cache.len() <= 40);
+        assert!(cache.get("N79CALL").is_some());
+    }
+}
@@ -0,0 +1,95 @@
+//! Sanity checks on reported positions. A GPS without a fix often reports
+//! 0N/0E, and a bad fix can put a station thousands of kilometres from
+//! where it was a minute ago; such positions are dropped before they reach
+//! clients and the station state.
+
+use crate::config::PositionChecksConfig;
+use crate::filter::haversine_km;
+use crate::hub::StationPosition;
+use crate::parser::{PacketData, ParsedPacket};
+use serde::Serialize;
+
+/// Degrees from 0N/0E still taken as a receiver without a fix
+const NULL_ISLAND_DEGREES: f64 = 0.001;
+/// Movement always allowed, for GPS jitter between closely spaced reports
+const JITTER_KM: f64 = 1.0;
+/// Only positions this recent are compared against, so a station whose
+/// cached position was wrong is not locked out for good
+const SPEED_WINDOW_SECS: u64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BadPosition {
+    NullIsland,
+    OutOfRange,
+    /// Farther from the station's last position than it could have moved
+    SpeedJump,
+}
+
+/// Checks the position in `packet`, if any. `previous` is the sender's
+/// last accepted position, used for the speed check of its own positions.
+pub fn check(cfg: &PositionChecksConfig, packet: &ParsedPacket, previous: Option<&StationPosition>, now: u64) -> Result<(), BadPosition> {
+    let Some(position) = packet.position() else {
+        return Ok(());
+    };
+    let (lat, lon) = (position.lat, position.lon);
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(BadPosition::OutOfRange);
+    }
+    if cfg.reject_null_island && lat.abs() < NULL_ISLAND_DEGREES && lon.abs() < NULL_ISLAND_DEGREES {
+        return Err(BadPosition::NullIsland);
+    }
+    // Objects and items are placed by hand and may move anywhere
+    if let (Some(max_speed), Some(prev), PacketData::Position(_) | PacketData::MicE(_)) = (cfg.max_speed_kmh, previous, &packet.data) {
+        let elapsed = now.saturating_sub(prev.updated);
+        if elapsed < SPEED_WINDOW_SECS
+            && haversine_km(prev.lat, prev.lon, lat, lon) > JITTER_KM + max_speed * elapsed as f64 / 3600.0
+        {
+            return Err(BadPosition::SpeedJump);
+        }
+    }
+    Ok(())
+}
+
+/// Positions dropped from one station.
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedPositions {
+    pub callsign: String,
+    pub count: u64,
+    pub last_reason: BadPosition,
+    pub last_rejected: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let cfg = PositionChecksConfig { reject_null_island: true, max_speed_kmh: Some(1000.0) };
+        let packet = |line: &str| crate::parser::parse(line).unwrap();
+        let null = packet("N0CALL>APRS:!0000.00N/00000.00E>");
+        assert_eq!(check(&cfg, &null, None, 0), Err(BadPosition::NullIsland));
+        assert_eq!(check(&PositionChecksConfig { reject_null_island: false, ..cfg.clone() }, &null, None, 0), Ok(()));
+        assert_eq!(check(&cfg, &packet("N0CALL>APRS:>no position"), None, 0), Ok(()));
+
+        let prev = StationPosition {
+            callsign: "N0CALL".to_string(),
+            lat: 60.0,
+            lon: 25.0,
+            symbol: "/>".to_string(),
+            comment: String::new(),
+            destination: "APRS".to_string(),
+            updated: 1000,
+        };
+        // About 111 km north: fine after an hour at 1000 km/h, not after a minute
+        let moved = packet("N0CALL>APRS:!6100.00N/02500.00E>");
+        assert_eq!(check(&cfg, &moved, Some(&prev), 1060), Err(BadPosition::SpeedJump));
+        assert_eq!(check(&cfg, &moved, Some(&prev), 1000 + 600), Ok(()));
+        assert_eq!(check(&cfg, &packet("N0CALL>APRS:!6000.10N/02500.00E>"), Some(&prev), 1001), Ok(()));
+        // Stale positions and objects are not compared
+        assert_eq!(check(&cfg, &moved, Some(&prev), 1000 + SPEED_WINDOW_SECS), Ok(()));
+        let object = packet("N0CALL>APRS:;NET      *092345z6100.00N/02500.00E-");
+        assert_eq!(check(&cfg, &object, Some(&prev), 1060), Ok(()));
+    }
+}
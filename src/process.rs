@@ -0,0 +1,87 @@
+//! Resource use of the server process: memory, file descriptors, CPU time
+//! and tokio tasks, for spotting pressure on small igate boards. Memory
+//! and descriptors come from `/proc` and are missing where there is none.
+
+use nix::sys::resource::{UsageWho, getrusage};
+use nix::sys::time::TimeValLike;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessStats {
+    /// Resident memory
+    pub rss_bytes: Option<u64>,
+    pub open_fds: Option<usize>,
+    /// Share of one core used since the previous sample
+    pub cpu_percent: Option<f64>,
+    /// User plus system CPU time since start
+    pub cpu_secs: f64,
+    pub tokio_tasks: Option<usize>,
+    pub tokio_workers: Option<usize>,
+}
+
+/// `VmRSS` from `/proc/self/status`, which is given in kB.
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+fn cpu_secs() -> f64 {
+    match getrusage(UsageWho::RUSAGE_SELF) {
+        Ok(usage) => (usage.user_time().num_microseconds() + usage.system_time().num_microseconds()) as f64 / 1e6,
+        Err(_) => 0.0,
+    }
+}
+
+/// Takes samples, keeping the last CPU reading to work out usage since.
+#[derive(Debug, Default)]
+pub struct ProcessSampler {
+    last_cpu: Option<(Instant, f64)>,
+    pub stats: ProcessStats,
+}
+
+impl ProcessSampler {
+    /// Reads the current figures into `stats`. Task counts are only known
+    /// when called on a tokio runtime.
+    pub fn sample(&mut self) {
+        let now = Instant::now();
+        let cpu = cpu_secs();
+        let cpu_percent = self.last_cpu.and_then(|(at, last)| {
+            let elapsed = now.duration_since(at).as_secs_f64();
+            (elapsed > 0.0).then(|| (cpu - last) / elapsed * 100.0)
+        });
+        self.last_cpu = Some((now, cpu));
+        let metrics = tokio::runtime::Handle::try_current().ok().map(|h| h.metrics());
+        self.stats = ProcessStats {
+            rss_bytes: std::fs::read_to_string("/proc/self/status").ok().as_deref().and_then(parse_vm_rss),
+            open_fds: std::fs::read_dir("/proc/self/fd").ok().map(|dir| dir.count()),
+            cpu_percent,
+            cpu_secs: cpu,
+            tokio_tasks: metrics.as_ref().map(|m| m.num_alive_tasks()),
+            tokio_workers: metrics.as_ref().map(|m| m.num_workers()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_rss() {
+        let status = "Name:\taprsserver\nVmPeak:\t  20000 kB\nVmRSS:\t    5120 kB\nThreads:\t4\n";
+        assert_eq!(parse_vm_rss(status), Some(5120 * 1024));
+        assert_eq!(parse_vm_rss("Name:\taprsserver\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_sample() {
+        let mut sampler = ProcessSampler::default();
+        sampler.sample();
+        assert_eq!(sampler.stats.cpu_percent, None);
+        assert!(sampler.stats.tokio_tasks.is_some());
+        sampler.sample();
+        assert!(sampler.stats.cpu_percent.is_some_and(|p| p >= 0.0));
+    }
+}
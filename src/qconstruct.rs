@@ -0,0 +1,95 @@
+//! The APRS-IS q construct algorithm. Every packet entering from a client or
+//! server connection is tagged with how it reached the network (`qAC`,
+//! `qAX`, `qAo`, `qAS`) unless it already carries a construct, and the
+//! path after an existing construct is checked for loops.
+//! See <https://www.aprs-is.net/q.aspx>.
+
+use crate::parser::ParsedPacket;
+
+/// The connection a packet arrived on.
+#[derive(Debug, Clone, Copy)]
+pub enum Link<'a> {
+    /// A client logged in as `login`, `verified` by its passcode
+    Client { login: Option<&'a str>, verified: bool },
+    /// The uplink or a peer, named by `via`
+    Server { via: &'a str },
+}
+
+/// A packet the algorithm rejects as having looped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Looped;
+
+/// Checks `packet` from `link` for loops and returns the construct and
+/// via callsign to append when it has none. `server` is our login.
+pub fn process(packet: &ParsedPacket, link: Link, server: &str) -> Result<Option<(&'static str, String)>, Looped> {
+    let Some(q) = packet.path.iter().position(|hop| is_qconstruct(hop)) else {
+        return Ok(Some(match link {
+            Link::Client { login: Some(login), verified: true } if login.eq_ignore_ascii_case(&packet.source) => ("qAC", server.to_string()),
+            // Gated by the client from RF without saying so
+            Link::Client { login: Some(login), verified: true } => ("qAo", login.to_string()),
+            Link::Client { .. } => ("qAX", server.to_string()),
+            Link::Server { via } => ("qAS", via.to_string()),
+        }));
+    };
+    if packet.path[q] == "qAZ" {
+        return Err(Looped);
+    }
+    let after: Vec<&str> = packet.path[q + 1..].iter().map(|hop| hop.trim_end_matches('*')).collect();
+    for (i, hop) in after.iter().enumerate() {
+        if hop.eq_ignore_ascii_case(server) || after[i + 1..].iter().any(|other| other.eq_ignore_ascii_case(hop)) {
+            return Err(Looped);
+        }
+    }
+    // A client's own login may only end the path, as the igate's via
+    if let Link::Client { login: Some(login), .. } = link
+        && let Some((_, before_last)) = after.split_last()
+        && before_last.iter().any(|hop| hop.eq_ignore_ascii_case(login))
+    {
+        return Err(Looped);
+    }
+    Ok(None)
+}
+
+pub fn is_qconstruct(hop: &str) -> bool {
+    let b = hop.as_bytes();
+    b.len() == 3 && b[0] == b'q' && b[1] == b'A' && b[2].is_ascii_alphabetic()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    const SERVER: &str = "T2TEST";
+
+    fn run(line: &str, link: Link) -> Result<Option<(&'static str, String)>, Looped> {
+        process(&parse(line).unwrap(), link, SERVER)
+    }
+    fn client(login: &str) -> Link<'_> {
+        Link::Client { login: Some(login), verified: true }
+    }
+
+    #[test]
+    fn test_constructs() {
+        let tagged = |q: &'static str, via: &str| Ok(Some((q, via.to_string())));
+        assert_eq!(run("N0CALL>APRS,TCPIP*:>hi", client("N0CALL")), tagged("qAC", SERVER));
+        assert_eq!(run("N0CALL>APRS,TCPIP*:>hi", client("n0call")), tagged("qAC", SERVER));
+        assert_eq!(run("N1CALL>APRS,WIDE1-1:>hi", client("N0CALL")), tagged("qAo", "N0CALL"));
+        assert_eq!(run("N0CALL>APRS,TCPIP*:>hi", Link::Client { login: Some("N0CALL"), verified: false }), tagged("qAX", SERVER));
+        assert_eq!(run("N0CALL>APRS:>hi", Link::Client { login: None, verified: false }), tagged("qAX", SERVER));
+        assert_eq!(run("N0CALL>APRS,TCPIP*:>hi", Link::Server { via: "T2PEER" }), tagged("qAS", "T2PEER"));
+        // Existing constructs are kept
+        assert_eq!(run("N1CALL>APRS,WIDE1-1,qAR,N0CALL:>hi", client("N0CALL")), Ok(None));
+        assert_eq!(run("N1CALL>APRS,qAo,N0CALL:>hi", client("N0CALL")), Ok(None));
+        assert_eq!(run("N0CALL>APRS,TCPIP*,qAC,T2PEER:>hi", Link::Server { via: "T2PEER" }), Ok(None));
+    }
+    #[test]
+    fn test_loops() {
+        assert_eq!(run("N0CALL>APRS,qAZ,N0CALL:>hi", client("N0CALL")), Err(Looped));
+        assert_eq!(run("N0CALL>APRS,TCPIP*,qAC,T2TEST:>hi", Link::Server { via: "T2PEER" }), Err(Looped));
+        assert_eq!(run("N0CALL>APRS,qAC,T2PEER,T2OTHER,t2peer:>hi", Link::Server { via: "T2OTHER" }), Err(Looped));
+        assert_eq!(run("N1CALL>APRS,qAR,N0CALL,T2PEER:>hi", client("N0CALL")), Err(Looped));
+        // Hops before the construct are RF digipeaters and may repeat
+        assert_eq!(run("N1CALL>APRS,WIDE1*,WIDE1*,qAR,N0CALL:>hi", client("N0CALL")), Ok(None));
+    }
+}
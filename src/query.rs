@@ -0,0 +1,92 @@
+//! Answers to the standard APRS queries sent as messages to the server's
+//! callsign: `?APRSP` (position), `?APRSD` (stations heard direct: on RF
+//! with an `[rf]` interface, otherwise the logged in clients) and `?IGATE?`
+//! (capabilities).
+
+use crate::hub::Hub;
+use crate::message::{AprsMessage, MAX_MESSAGE_TEXT, message_packet};
+use std::collections::BTreeSet;
+
+/// `Directs=` followed by as many callsigns as fit in one message.
+fn directs_text(callsigns: &BTreeSet<String>) -> String {
+    let mut text = "Directs=".to_string();
+    for callsign in callsigns {
+        if text.len() + 1 + callsign.len() > MAX_MESSAGE_TEXT {
+            break;
+        }
+        text.push(' ');
+        text.push_str(callsign);
+    }
+    text
+}
+
+/// Reply packets for a query in `msg`, sent as the hub's callsign. Empty if
+/// the text is not a query we answer.
+pub fn replies(hub: &Hub, msg: &AprsMessage) -> Vec<String> {
+    let callsign = &hub.callsign;
+    match msg.text.trim().to_uppercase().as_str() {
+        "?APRSP" => hub.position.iter().map(|p| format!("{}>APRS,TCPIP*:{}", callsign, p)).collect(),
+        "?APRSD" => {
+            let directs: BTreeSet<String> = if hub.rf_status.is_some() {
+                hub.rf_heard.lock().unwrap().directs(hub.clock.unix_time()).into_iter().collect()
+            } else {
+                hub.clients.read().unwrap().values().filter_map(|c| c.lock().unwrap().callsign.clone()).collect()
+            };
+            vec![message_packet(callsign, &msg.source, &directs_text(&directs))]
+        }
+        "?IGATE?" => {
+            let local = if hub.rf_status.is_some() {
+                hub.rf_heard.lock().unwrap().len()
+            } else {
+                hub.clients.read().unwrap().values().filter(|c| c.lock().unwrap().callsign.is_some()).count()
+            };
+            let messages: u64 = hub
+                .stations
+                .read()
+                .unwrap()
+                .source_stats
+                .values()
+                .filter_map(|s| s.types.get("message"))
+                .sum();
+            vec![format!("{}>APRS,TCPIP*:<IGATE,MSG_CNT={},LOC_CNT={}", callsign, messages, local)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::parse_message;
+
+    #[test]
+    fn test_replies() {
+        let clock = std::sync::Arc::new(crate::clock::MockClock::new(1_700_000_000));
+        let mut hub = Hub::new();
+        hub.clock = clock.clone();
+        hub.callsign = "T2TEST".to_string();
+        let query = |text: &str| parse_message(&format!("N0CALL>APRS::T2TEST   :{}", text)).unwrap();
+        assert!(replies(&hub, &query("?APRSP")).is_empty());
+        hub.position = Some("!4903.50NI07201.75W&".to_string());
+        assert_eq!(replies(&hub, &query("?aprsp")), ["T2TEST>APRS,TCPIP*:!4903.50NI07201.75W&"]);
+        assert_eq!(replies(&hub, &query("?APRSD")), ["T2TEST>APRS,TCPIP*::N0CALL   :Directs="]);
+        assert_eq!(replies(&hub, &query("?IGATE?")), ["T2TEST>APRS,TCPIP*:<IGATE,MSG_CNT=0,LOC_CNT=0"]);
+        assert!(replies(&hub, &query("hello")).is_empty());
+
+        // With RF, stations heard direct on the air
+        hub.rf_status = Some(Default::default());
+        hub.rf_heard.lock().unwrap().record("N1CALL", 0, hub.clock.unix_time());
+        hub.rf_heard.lock().unwrap().record("N2CALL", 1, hub.clock.unix_time());
+        assert_eq!(replies(&hub, &query("?APRSD")), ["T2TEST>APRS,TCPIP*::N0CALL   :Directs= N1CALL"]);
+        assert_eq!(replies(&hub, &query("?IGATE?")), ["T2TEST>APRS,TCPIP*:<IGATE,MSG_CNT=0,LOC_CNT=2"]);
+        // Directs age out on the hub's clock
+        clock.advance(std::time::Duration::from_secs(crate::rf_heard::DIRECT_SECS - 1));
+        assert_eq!(replies(&hub, &query("?APRSD")), ["T2TEST>APRS,TCPIP*::N0CALL   :Directs= N1CALL"]);
+        clock.advance(std::time::Duration::from_secs(1));
+        assert_eq!(replies(&hub, &query("?APRSD")), ["T2TEST>APRS,TCPIP*::N0CALL   :Directs="]);
+
+        let many: BTreeSet<String> = (0..20).map(|i| format!("N{}CALL-{}", i, i)).collect();
+        let text = directs_text(&many);
+        assert!(text.starts_with("Directs= N0CALL-0 N10CALL-10") && text.len() <= MAX_MESSAGE_TEXT);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::clock::Clock;
+use crate::config::RedisConfig;
+use crate::hook::PacketHook;
+use crate::hub::StationPosition;
+use crate::parser::ParsedPacket;
+use crate::sink::SinkQueue;
+use redis::aio::MultiplexedConnection;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+
+// Packets buffered while Redis is slow or unreachable
+const REDIS_QUEUE_SIZE: usize = 10000;
+// Tries at writing one update before it is dropped
+const WRITE_ATTEMPTS: u32 = 3;
+// Delays between tries double while the connection keeps failing
+const BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RedisStatus {
+    pub connected: bool,
+    pub published: u64,
+    pub dropped: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+}
+
+struct RedisUpdate {
+    line: String,
+    position: Option<StationPosition>,
+}
+
+/// Packet hook publishing accepted packets to a Redis channel and mirroring
+/// station positions into `{prefix}:station:{callsign}` hashes.
+pub struct RedisSink {
+    queue: SinkQueue<RedisUpdate>,
+    status: Arc<Mutex<RedisStatus>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RedisSink {
+    /// Spawns the writer on the current Tokio runtime. It connects to Redis
+    /// when the first update arrives; `clock` stamps the station hashes.
+    pub fn start(cfg: &RedisConfig, status: Arc<Mutex<RedisStatus>>, clock: Arc<dyn Clock>) -> Self {
+        let writer_cfg = cfg.clone();
+        let writer_status = status.clone();
+        let queue = SinkQueue::spawn(REDIS_QUEUE_SIZE, |rx| run_writer(writer_cfg, rx, writer_status));
+        RedisSink { queue, status, clock }
+    }
+}
+
+impl PacketHook for RedisSink {
+    fn on_accept(&self, line: &str, packet: &ParsedPacket) {
+        let update = RedisUpdate {
+            line: line.to_string(),
+            position: StationPosition::from_packet(packet, self.clock.unix_time()),
+        };
+        if !self.queue.push(update) {
+            self.status.lock().unwrap().dropped += 1;
+        }
+    }
+}
+
+fn station_key(prefix: &str, callsign: &str) -> String {
+    format!("{}:station:{}", prefix, callsign)
+}
+
+fn station_fields(pos: &StationPosition) -> Vec<(&'static str, String)> {
+    vec![
+        ("lat", pos.lat.to_string()),
+        ("lon", pos.lon.to_string()),
+        ("symbol", pos.symbol.clone()),
+        ("comment", pos.comment.clone()),
+        ("destination", pos.destination.clone()),
+        ("updated", pos.updated.to_string()),
+    ]
+}
+
+async fn connect(url: &str) -> redis::RedisResult<MultiplexedConnection> {
+    redis::Client::open(url)?.get_multiplexed_async_connection().await
+}
+
+/// Writes an update, connecting first if there is no connection.
+async fn write_update(conn: &mut Option<MultiplexedConnection>, cfg: &RedisConfig, update: &RedisUpdate) -> Result<(), String> {
+    let conn = match conn {
+        Some(conn) => conn,
+        None => conn.insert(connect(&cfg.url).await.map_err(|e| format!("connect: {}", e))?),
+    };
+    let mut pipe = redis::pipe();
+    pipe.publish(&cfg.channel, &update.line).ignore();
+    if let Some(pos) = &update.position {
+        pipe.hset_multiple(station_key(&cfg.key_prefix, &pos.callsign), &station_fields(pos)).ignore();
+    }
+    pipe.query_async(conn).await.map_err(|e| format!("write: {}", e))
+}
+
+/// Delay before the next try after `failures` consecutive failed ones.
+fn backoff(failures: u32) -> Duration {
+    BACKOFF_INITIAL.saturating_mul(1 << failures.saturating_sub(1).min(16)).min(BACKOFF_MAX)
+}
+
+/// Writes queued updates in order. A failed write drops the connection and
+/// is retried after a backoff that grows while Redis stays unreachable, so
+/// updates wait in the queue instead of being lost one by one; an update
+/// still failing after `WRITE_ATTEMPTS` tries is counted as dropped.
+async fn run_writer(cfg: RedisConfig, mut rx: Receiver<RedisUpdate>, status: Arc<Mutex<RedisStatus>>) {
+    let mut conn: Option<MultiplexedConnection> = None;
+    let mut failures = 0;
+    while let Some(update) = rx.recv().await {
+        for attempt in 1..=WRITE_ATTEMPTS {
+            if failures > 0 {
+                tokio::time::sleep(backoff(failures)).await;
+            }
+            let result = write_update(&mut conn, &cfg, &update).await;
+            let mut s = status.lock().unwrap();
+            match result {
+                Ok(()) => {
+                    s.connected = true;
+                    s.published += 1;
+                    failures = 0;
+                    break;
+                }
+                Err(e) => {
+                    s.connected = false;
+                    s.errors += 1;
+                    s.last_error = Some(e);
+                    conn = None;
+                    failures += 1;
+                    if attempt == WRITE_ATTEMPTS {
+                        s.dropped += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_station_hash() {
+        let packet = crate::parser::parse("N0CALL-9>APRS:!4903.50N/07201.75W>moving").unwrap();
+        let pos = StationPosition::from_packet(&packet, 0).unwrap();
+        assert_eq!(station_key("aprs", &pos.callsign), "aprs:station:N0CALL-9");
+        let fields = station_fields(&pos);
+        assert!(fields.contains(&("symbol", "/>".to_string())));
+        assert!(fields.contains(&("comment", "moving".to_string())));
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), BACKOFF_INITIAL);
+        assert_eq!(backoff(3), BACKOFF_INITIAL * 4);
+        assert_eq!(backoff(20), BACKOFF_MAX);
+        assert_eq!(backoff(u32::MAX), BACKOFF_MAX);
+    }
+}
@@ -0,0 +1,231 @@
+//! Remote administration with APRS messages. Sysops send commands as
+//! messages to the server's callsign and get the result back as a message:
+//! `status`, `kick CALL` and `filter CALL FILTER...`. Messages to the
+//! server are never passed on, but may still be heard on the air, so
+//! commands from outside carry a code signed with a shared secret that is
+//! only good for one message id, command and a few minutes.
+
+use crate::config::RemoteAdminConfig;
+use crate::filter::{FilterSet, parse_filter_list};
+use crate::hub::Hub;
+use crate::message::{AprsMessage, MAX_MESSAGE_TEXT, message_packet};
+use crate::pipeline::Source;
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of a code's time step; codes of the neighbouring steps are also
+/// accepted, for clock skew and slow delivery
+const STEP_SECS: u64 = 300;
+/// Hex digits of the code before a signed command
+const CODE_LEN: usize = 8;
+
+fn mac(secret: &str, sysop: &str, msgid: &str, step: u64, command: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(format!("{}:{}:{}:{}", sysop.to_uppercase(), msgid, step, command).as_bytes());
+    mac
+}
+
+/// The code a sysop puts before `command` in the message with id `msgid`
+/// sent at `unix_time`: the first eight hex digits of HMAC-SHA256, keyed
+/// with the secret, over `SYSOP:MSGID:STEP:COMMAND`, where STEP is the Unix
+/// time divided by 300.
+pub fn command_code(secret: &str, sysop: &str, msgid: &str, unix_time: u64, command: &str) -> String {
+    let tag = mac(secret, sysop, msgid, unix_time / STEP_SECS, command).finalize().into_bytes();
+    tag[..CODE_LEN / 2].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_code(code: &str) -> Option<Vec<u8>> {
+    if code.len() != CODE_LEN || !code.is_ascii() {
+        return None;
+    }
+    (0..CODE_LEN).step_by(2).map(|i| u8::from_str_radix(&code[i..i + 2], 16).ok()).collect()
+}
+
+pub struct RemoteAdmin {
+    config: RemoteAdminConfig,
+    /// Signed commands already run, as sysop, code and time step, so a
+    /// copy heard again is refused
+    used: Mutex<VecDeque<(String, String, u64)>>,
+}
+
+impl RemoteAdmin {
+    pub fn new(config: RemoteAdminConfig) -> Self {
+        RemoteAdmin { config, used: Mutex::new(VecDeque::new()) }
+    }
+
+    fn is_sysop(&self, callsign: &str) -> bool {
+        self.config.sysops.iter().any(|s| s.eq_ignore_ascii_case(callsign))
+    }
+
+    /// The command in `msg`, if it comes from a sysop who is either logged
+    /// in here under that callsign or signed it.
+    fn authenticate<'a>(&self, source: Source, msg: &'a AprsMessage, now: u64) -> Option<&'a str> {
+        if !self.is_sysop(&msg.source) {
+            return None;
+        }
+        let text = msg.text.trim();
+        if let Some(command) = self.verify(msg, text, now) {
+            return Some(command);
+        }
+        // The callsign of a verified login is trustworthy
+        match source {
+            Source::Client { callsign: Some(callsign), verified: true, .. } if callsign.eq_ignore_ascii_case(&msg.source) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The command of a `CODE COMMAND` text whose code is valid now and
+    /// has not been used before.
+    fn verify<'a>(&self, msg: &AprsMessage, text: &'a str, now: u64) -> Option<&'a str> {
+        let secret = self.config.secret.as_deref()?;
+        let msgid = msg.msgid.as_deref()?;
+        let (code, command) = text.split_once(' ')?;
+        let tag = parse_code(code)?;
+        let command = command.trim();
+        let step = now / STEP_SECS;
+        let signed = (step.saturating_sub(1)..=step + 1).find(|&s| mac(secret, &msg.source, msgid, s, command).verify_truncated_left(&tag).is_ok())?;
+        let mut used = self.used.lock().unwrap();
+        while used.front().is_some_and(|(_, _, s)| s + 2 < step) {
+            used.pop_front();
+        }
+        let key = (msg.source.to_uppercase(), code.to_lowercase(), signed);
+        if used.contains(&key) {
+            warn!(sysop = %msg.source, "replayed remote admin command refused");
+            return None;
+        }
+        used.push_back(key);
+        Some(command)
+    }
+}
+
+/// Ids of the clients logged in as `callsign`.
+fn clients_by_callsign(hub: &Hub, callsign: &str) -> Vec<usize> {
+    hub.clients
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(_, c)| c.lock().unwrap().callsign.as_deref().is_some_and(|cs| cs.eq_ignore_ascii_case(callsign)))
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+fn run(hub: &Hub, command: &str) -> String {
+    let mut words = command.split_whitespace();
+    let verb = words.next().unwrap_or("").to_lowercase();
+    let target = words.next();
+    match (verb.as_str(), target) {
+        ("status", _) => {
+            let drops = hub.counters.drop_stats();
+            format!(
+                "Up {}h{:02}m clients {} accepted {} dupes {}",
+                hub.uptime() / 3600,
+                hub.uptime() / 60 % 60,
+                hub.client_count(),
+                drops.accepted,
+                drops.duplicates
+            )
+        }
+        ("kick", Some(callsign)) => {
+            let ids = clients_by_callsign(hub, callsign);
+            for id in &ids {
+                hub.kick_client(*id, "Disconnected by sysop");
+            }
+            if ids.is_empty() { format!("{} not connected", callsign) } else { format!("Kicked {}", callsign) }
+        }
+        ("filter", Some(callsign)) => {
+            let filter_str = words.collect::<Vec<_>>().join(" ");
+            let filters = match parse_filter_list(&filter_str) {
+                Ok(filters) if !filters.is_empty() => Arc::new(FilterSet::new(filters)),
+                Ok(_) => return "Usage: filter CALL FILTER".to_string(),
+                Err(e) => return format!("Invalid filter: {}", e),
+            };
+            let ids = clients_by_callsign(hub, callsign);
+            for client in ids.iter().filter_map(|id| hub.client(*id)) {
+                let mut c = client.lock().unwrap();
+                c.filter = Some(filters.clone());
+                c.filter_string = Some(filter_str.clone());
+            }
+            if ids.is_empty() { format!("{} not connected", callsign) } else { format!("Filter set for {}", callsign) }
+        }
+        _ => "Commands: status, kick CALL, filter CALL FILTER".to_string(),
+    }
+}
+
+/// Runs a sysop command in `msg` and returns the reply packets, or None if
+/// the message is not an authenticated command.
+pub fn handle(hub: &Hub, source: Source, msg: &AprsMessage) -> Option<Vec<String>> {
+    let admin = hub.remote_admin.as_ref()?;
+    let Some(command) = admin.authenticate(source, msg, hub.clock.unix_time()) else {
+        if admin.is_sysop(&msg.source) {
+            warn!(source = %msg.source, "unauthenticated sysop message ignored");
+        }
+        return None;
+    };
+    info!(sysop = %msg.source, command, "remote admin command");
+    let mut reply = run(hub, command);
+    // Replies can echo the sysop's text, so cut on a character boundary
+    while reply.len() > MAX_MESSAGE_TEXT {
+        reply.pop();
+    }
+    Some(vec![message_packet(&hub.callsign, &msg.source, &reply)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Client, ClientConn};
+    use crate::message::parse_message;
+
+    #[test]
+    fn test_remote_admin() {
+        let mut hub = Hub::new();
+        hub.callsign = "T2TEST".to_string();
+        hub.remote_admin = Some(RemoteAdmin::new(RemoteAdminConfig { sysops: vec!["N0CALL".to_string()], secret: Some("s3cret".to_string()) }));
+        let clock = Arc::new(crate::clock::MockClock::new(1_700_000_000));
+        hub.clock = clock.clone();
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        let mut client = Client::with_conn(0, ClientConn::WebSocket { tx: Some(tx), write_timeout: std::time::Duration::from_secs(1) }, "127.0.0.1:1".parse().ok());
+        client.callsign = Some("N1CALL".to_string());
+        let id = hub.add_client(client);
+
+        let msg = |text: &str| parse_message(&format!("N0CALL>APRS::T2TEST   :{}", text)).unwrap();
+        let sysop = Source::Client { id: 99, callsign: Some("N0CALL"), verified: true, class: None };
+        let reply = handle(&hub, sysop, &msg("status")).unwrap();
+        assert!(reply[0].starts_with("T2TEST>APRS,TCPIP*::N0CALL   :Up 0h00m clients 1 "));
+        // Anyone may log in unverified under a lenient passcode policy
+        let unverified = Source::Client { id: 99, callsign: Some("N0CALL"), verified: false, class: None };
+        assert_eq!(handle(&hub, unverified, &msg("status")), None);
+
+        // Over the uplink the command must be signed
+        let command = "filter N1CALL r/60/25/50";
+        assert_eq!(handle(&hub, Source::Uplink, &msg(&format!("{}{{5", command))), None);
+        let code = command_code("s3cret", "N0CALL", "5", 1_700_000_000, command);
+        let signed = msg(&format!("{} {}{{5", code, command));
+        // Still good in the next time step, but only once
+        clock.advance(std::time::Duration::from_secs(STEP_SECS));
+        let reply = handle(&hub, Source::Uplink, &signed).unwrap();
+        assert!(reply[0].ends_with(":Filter set for N1CALL"));
+        assert_eq!(hub.client(id).unwrap().lock().unwrap().filter_string.as_deref(), Some("r/60/25/50"));
+        assert_eq!(handle(&hub, Source::Uplink, &signed), None);
+        // Codes are bound to the message id, the command and the time
+        let reused = msg(&format!("{} {}{{6", code, command));
+        assert_eq!(handle(&hub, Source::Uplink, &reused), None);
+        let altered = msg(&format!("{} filter N1CALL r/0/0/1{{5", code));
+        assert_eq!(handle(&hub, Source::Uplink, &altered), None);
+        let late = command_code("s3cret", "N0CALL", "7", 1_700_000_000, "status");
+        clock.advance(std::time::Duration::from_secs(STEP_SECS));
+        assert_eq!(handle(&hub, Source::Uplink, &msg(&format!("{} status{{7", late))), None);
+
+        let code = command_code("s3cret", "N2CALL", "1", hub.clock.unix_time(), "status");
+        let other = parse_message(&format!("N2CALL>APRS::T2TEST   :{} status{{1", code)).unwrap();
+        assert_eq!(handle(&hub, Source::Uplink, &other), None);
+        assert!(handle(&hub, sysop, &msg("kick N9CALL")).unwrap()[0].ends_with(":N9CALL not connected"));
+        let reply = handle(&hub, sysop, &msg(&format!("filter N1CALL {}", "é".repeat(40)))).unwrap();
+        assert!(reply[0].contains(":Invalid filter: éé"));
+    }
+}
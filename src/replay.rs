@@ -0,0 +1,101 @@
+use crate::config::ReplayConfig;
+use crate::hub::Hub;
+use crate::pipeline::{self, Source};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+/// Splits a packet log line into its timestamp and packet. Lines without a
+/// timestamp are taken as bare packets.
+fn parse_line(line: &str) -> (Option<DateTime<Utc>>, &str) {
+    if let Some((time, packet)) = line.split_once('\t')
+        && let Ok(time) = time.parse()
+    {
+        return (Some(time), packet);
+    }
+    (None, line)
+}
+
+fn open(path: &str) -> Result<BufReader<Box<dyn Read + Send>>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let reader: Box<dyn Read + Send> = if path.ends_with(".gz") { Box::new(GzDecoder::new(file)) } else { Box::new(file) };
+    Ok(BufReader::new(reader))
+}
+
+/// Replays a packet log into the hub, keeping the original spacing between
+/// packets divided by `speed`. Blocks until the file is done; returns the
+/// number of packets read and accepted.
+pub fn run(cfg: &ReplayConfig, hub: &Hub) -> Result<(u64, u64), String> {
+    let reader = open(&cfg.file)?;
+    let started = Instant::now();
+    let mut first: Option<DateTime<Utc>> = None;
+    let (mut read, mut accepted) = (0, 0);
+    for line in reader.lines() {
+        let line = line.map_err(|e| format!("{}: {}", cfg.file, e))?;
+        let (time, packet) = parse_line(line.trim_end());
+        if packet.is_empty() || packet.starts_with('#') {
+            continue;
+        }
+        if let Some(time) = time {
+            let first = *first.get_or_insert(time);
+            let offset = (time - first).to_std().unwrap_or_default().div_f64(cfg.speed);
+            if let Some(wait) = offset.checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+        read += 1;
+        if pipeline::ingest(hub, Source::Replay, packet).is_ok() {
+            accepted += 1;
+        }
+    }
+    Ok((read, accepted))
+}
+
+/// Starts a replay on its own thread.
+pub fn spawn(cfg: ReplayConfig, hub: Arc<Hub>) {
+    std::thread::spawn(move || {
+        // Give clients a moment to connect before traffic starts
+        std::thread::sleep(Duration::from_secs(1));
+        match run(&cfg, &hub) {
+            Ok((read, accepted)) => info!(file = %cfg.file, read, accepted, "replay finished"),
+            Err(e) => error!(error = %e, "replay failed"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let (time, packet) = parse_line("2024-05-01T12:00:00.000Z\tN0CALL>APRS:>hi");
+        assert_eq!(time, Some("2024-05-01T12:00:00Z".parse().unwrap()));
+        assert_eq!(packet, "N0CALL>APRS:>hi");
+        assert_eq!(parse_line("N0CALL>APRS:>a\tb"), (None, "N0CALL>APRS:>a\tb"));
+    }
+
+    #[test]
+    fn test_replay_into_hub() {
+        let path = "test_replay.log";
+        std::fs::write(
+            path,
+            "2024-05-01T12:00:00.000Z\tN0CALL>APRS:!4903.50N/07201.75W-one\n\
+             2024-05-01T12:00:00.050Z\tN0CALL>APRS:!4903.50N/07201.75W-one\n\
+             # comment\n\
+             N1CALL>APRS:>status\n",
+        )
+        .unwrap();
+        let hub = Hub::new();
+        let cfg = ReplayConfig { file: path.to_string(), speed: 1.0 };
+        let started = Instant::now();
+        assert_eq!(run(&cfg, &hub).unwrap(), (3, 2));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(hub.positions.get("N0CALL").is_some());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,443 @@
+//! The receive side of an igate: a TNC reached over KISS, by TCP (Dire Wolf,
+//! soundmodem) or a serial port, an AGWPE engine or a Linux kernel AX.25
+//! port. Frames heard on the air are converted to
+//! APRS-IS lines carrying a `qAR` construct with the igate callsign, then
+//! ingested like any other packet and gated to the uplink.
+
+use crate::agwpe;
+use crate::ax25::Frame;
+use crate::config::{RfConfig, RfInterface};
+use crate::gating::PathFlag;
+use crate::hub::Hub;
+use crate::kiss;
+use crate::pipeline::{self, Source};
+use crate::tx_igate::TxCounters;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RfStatus {
+    /// The TNC, e.g. `kiss-tcp 127.0.0.1:8001`
+    pub interface: String,
+    pub connected: bool,
+    pub frames: u64,
+    /// Packets passed to the pipeline
+    pub gated: u64,
+    /// Frames that are not APRS or could not be decoded
+    pub invalid: u64,
+    /// Packets that must not be gated, such as ones from APRS-IS
+    pub not_gated: u64,
+    pub connect_errors: u64,
+    pub last_error: Option<String>,
+    /// Transmit igate counters, with `[rf.tx]` configured
+    #[serde(default)]
+    pub tx: Option<TxCounters>,
+}
+
+/// Why a frame heard on the air is not gated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotGated {
+    /// Not a UI frame with APRS information
+    Invalid,
+    /// Came from APRS-IS, via `TCPIP` or `TCPXX` in the path
+    FromInternet,
+    /// `NOGATE` or `RFONLY` in the path
+    Flagged,
+    /// Queries are answered locally
+    Query,
+}
+
+/// Checks path hops for the ones that keep a packet off APRS-IS.
+fn check_hops<'a>(mut hops: impl Iterator<Item = &'a str>) -> Result<(), NotGated> {
+    hops.try_for_each(|hop| match hop.trim_end_matches('*') {
+        "TCPIP" | "TCPXX" => Err(NotGated::FromInternet),
+        _ if PathFlag::from_hop(hop).is_some() => Err(NotGated::Flagged),
+        _ => Ok(()),
+    })
+}
+
+/// The APRS-IS line for a frame heard on the air, gated by `igate`. Third
+/// party packets are unwrapped and gated with their inner header.
+pub fn igate_line(frame: &Frame, igate: &str) -> Result<String, NotGated> {
+    if !frame.is_aprs() {
+        return Err(NotGated::Invalid);
+    }
+    check_hops(frame.digipeaters.iter().map(|d| d.callsign.as_str()))?;
+    // Only the first line of the information field is gated
+    let end = frame.info.iter().position(|&b| b == b'\r' || b == b'\n').unwrap_or(frame.info.len());
+    let info = std::str::from_utf8(&frame.info[..end]).map_err(|_| NotGated::Invalid)?;
+    let (header, body) = match info.strip_prefix('}') {
+        Some(inner) => {
+            let (header, body) = inner.split_once(':').ok_or(NotGated::Invalid)?;
+            let path = header.split_once('>').ok_or(NotGated::Invalid)?.1;
+            check_hops(path.split(',').skip(1))?;
+            (header.to_string(), body)
+        }
+        None => (frame.tnc2_header(), info),
+    };
+    if body.is_empty() {
+        return Err(NotGated::Invalid);
+    }
+    if body.starts_with('?') {
+        return Err(NotGated::Query);
+    }
+    Ok(format!("{},qAR,{}:{}", header, igate, body))
+}
+
+impl RfInterface {
+    fn name(&self) -> &'static str {
+        match self {
+            RfInterface::KissTcp => "kiss-tcp",
+            RfInterface::KissSerial => "kiss-serial",
+            RfInterface::Agwpe => "agwpe",
+            RfInterface::Ax25 => "ax25",
+        }
+    }
+}
+
+/// Status line naming the TNC in `cfg`.
+pub fn describe(cfg: &RfConfig) -> String {
+    let target = match cfg.interface {
+        RfInterface::KissTcp | RfInterface::Agwpe => cfg.address.as_deref(),
+        RfInterface::KissSerial | RfInterface::Ax25 => cfg.device.as_deref(),
+    };
+    format!("{} {}", cfg.interface.name(), target.unwrap_or("(not set)"))
+}
+
+/// Opens the serial device in raw mode at `baud`.
+fn open_serial(device: &str, baud: u32) -> Result<std::fs::File, String> {
+    use nix::sys::termios::{self, BaudRate, SetArg};
+    let rate = match baud {
+        1200 => BaudRate::B1200,
+        2400 => BaudRate::B2400,
+        4800 => BaudRate::B4800,
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115200 => BaudRate::B115200,
+        _ => return Err(format!("unsupported baud rate {}", baud)),
+    };
+    let file = std::fs::OpenOptions::new().read(true).write(true).open(device).map_err(|e| format!("{}: {}", device, e))?;
+    let mut tio = termios::tcgetattr(&file).map_err(|e| format!("{}: {}", device, e))?;
+    termios::cfmakeraw(&mut tio);
+    termios::cfsetspeed(&mut tio, rate).map_err(|e| format!("{}: {}", device, e))?;
+    termios::tcsetattr(&file, SetArg::TCSANOW, &tio).map_err(|e| format!("{}: {}", device, e))?;
+    Ok(file)
+}
+
+/// Splits the byte stream from the TNC into AX.25 frames.
+enum Framing {
+    Kiss(kiss::Decoder),
+    Agwpe(agwpe::Decoder),
+    /// One frame per read, after its KISS command byte
+    Datagram,
+}
+
+impl Framing {
+    fn push(&mut self, bytes: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        match self {
+            Framing::Kiss(decoder) => Ok(decoder.push(bytes).into_iter().map(|(_port, frame)| frame).collect()),
+            Framing::Agwpe(decoder) => {
+                Ok(decoder.push(bytes)?.iter().filter_map(|f| f.raw_ax25()).map(|frame| frame.to_vec()).collect())
+            }
+            Framing::Datagram => Ok(match bytes.split_first() {
+                Some((&command, frame)) if command & 0x0F == 0 => vec![frame.to_vec()],
+                _ => Vec::new(),
+            }),
+        }
+    }
+}
+
+fn connect(address: Option<&str>, interface: RfInterface) -> Result<(TcpStream, TcpStream), String> {
+    let address = address.ok_or_else(|| format!("{} needs an address", interface.name()))?;
+    let stream = TcpStream::connect(address).map_err(|e| format!("{}: {}", address, e))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT)).map_err(|e| e.to_string())?;
+    let writer = stream.try_clone().map_err(|e| e.to_string())?;
+    Ok((stream, writer))
+}
+
+/// Reading and writing halves of a TNC connection.
+type Link = (Box<dyn Read + Send>, Box<dyn Write + Send>, Framing);
+
+fn open(cfg: &RfConfig) -> Result<Link, String> {
+    match cfg.interface {
+        RfInterface::KissTcp => {
+            let (stream, writer) = connect(cfg.address.as_deref(), cfg.interface)?;
+            Ok((Box::new(stream), Box::new(writer), Framing::Kiss(kiss::Decoder::default())))
+        }
+        RfInterface::KissSerial => {
+            let device = cfg.device.as_deref().ok_or("kiss-serial needs a device")?;
+            let file = open_serial(device, cfg.baud)?;
+            let writer = file.try_clone().map_err(|e| e.to_string())?;
+            Ok((Box::new(file), Box::new(writer), Framing::Kiss(kiss::Decoder::default())))
+        }
+        RfInterface::Agwpe => {
+            let (stream, mut writer) = connect(cfg.address.as_deref(), cfg.interface)?;
+            // Raw monitoring delivers every frame heard on every port
+            writer.write_all(&agwpe::encode(0, agwpe::KIND_RAW_MONITOR, &[])).map_err(|e| e.to_string())?;
+            Ok((Box::new(stream), Box::new(writer), Framing::Agwpe(agwpe::Decoder::default())))
+        }
+        #[cfg(target_os = "linux")]
+        RfInterface::Ax25 => {
+            let device = cfg.device.as_deref().ok_or("ax25 needs a device")?;
+            let port = crate::ax25_socket::Ax25Port::open(device).map_err(|e| format!("{}: {}", device, e))?;
+            let writer = port.try_clone().map_err(|e| e.to_string())?;
+            Ok((Box::new(port), Box::new(writer), Framing::Datagram))
+        }
+        #[cfg(not(target_os = "linux"))]
+        RfInterface::Ax25 => Err("ax25 is only available on Linux".to_string()),
+    }
+}
+
+/// Reads frames until the TNC connection fails, returning why.
+fn receive(mut reader: Box<dyn Read + Send>, mut framing: Framing, igate: &str, hub: &Hub, status: &Mutex<RfStatus>) -> String {
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return "connection closed by TNC".to_string(),
+            Ok(n) => n,
+            Err(e) => return e.to_string(),
+        };
+        let frames = match framing.push(&buf[..n]) {
+            Ok(frames) => frames,
+            Err(e) => return e,
+        };
+        for bytes in frames {
+            let frame = Frame::decode(&bytes);
+            // Our own transmissions come back through digipeaters
+            if let Ok(frame) = &frame
+                && frame.is_aprs()
+                && !frame.source.to_string().eq_ignore_ascii_case(igate)
+            {
+                let hops = frame.digipeaters.iter().filter(|d| d.repeated).count();
+                hub.rf_heard.lock().unwrap().record(&frame.source.to_string(), hops, hub.clock.unix_time());
+            }
+            let line = frame.map_err(|_| NotGated::Invalid).and_then(|frame| igate_line(&frame, igate));
+            {
+                let mut s = status.lock().unwrap();
+                s.frames += 1;
+                match line {
+                    Ok(_) => s.gated += 1,
+                    Err(NotGated::Invalid) => s.invalid += 1,
+                    Err(_) => s.not_gated += 1,
+                }
+            }
+            if let Ok(line) = line {
+                let _ = pipeline::ingest(hub, Source::Rf, &line);
+            }
+        }
+    }
+}
+
+/// Starts a thread that keeps a connection to the TNC, reconnecting after
+/// failures, and ingests what it hears.
+pub fn spawn(cfg: RfConfig, igate: String, hub: Arc<Hub>, status: Arc<Mutex<RfStatus>>) {
+    std::thread::spawn(move || {
+        loop {
+            match open(&cfg) {
+                Ok((reader, writer, framing)) => {
+                    info!(tnc = %describe(&cfg), "connected to TNC");
+                    if let Some(tx) = &hub.tx_igate {
+                        tx.attach(writer);
+                    }
+                    {
+                        let mut s = status.lock().unwrap();
+                        s.connected = true;
+                        s.last_error = None;
+                    }
+                    let error = receive(reader, framing, &igate, &hub, &status);
+                    warn!(%error, "TNC connection lost");
+                    if let Some(tx) = &hub.tx_igate {
+                        tx.detach();
+                    }
+                    let mut s = status.lock().unwrap();
+                    s.connected = false;
+                    s.last_error = Some(error);
+                }
+                Err(error) => {
+                    warn!(%error, "failed to connect to TNC");
+                    let mut s = status.lock().unwrap();
+                    s.connect_errors += 1;
+                    s.last_error = Some(error);
+                }
+            }
+            std::thread::sleep(Duration::from_secs(cfg.reconnect_secs));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ax25::{CONTROL_UI, PID_NO_LAYER3};
+
+    /// A UI frame from `source` to APRS via `digis`, as (callsign, repeated).
+    fn frame(source: &str, digis: &[(&str, bool)], info: &[u8]) -> Vec<u8> {
+        let address = |call: &str, h: bool, last: bool| {
+            let (call, ssid) = call.split_once('-').map_or((call, 0), |(c, s)| (c, s.parse::<u8>().unwrap()));
+            let mut field: Vec<u8> = format!("{:<6}", call).bytes().map(|b| b << 1).collect();
+            field.push(0x60 | (ssid << 1) | if h { 0x80 } else { 0 } | u8::from(last));
+            field
+        };
+        let mut bytes = address("APRS", false, false);
+        bytes.extend(address(source, false, digis.is_empty()));
+        for (i, (digi, h)) in digis.iter().enumerate() {
+            bytes.extend(address(digi, *h, i == digis.len() - 1));
+        }
+        bytes.extend([CONTROL_UI, PID_NO_LAYER3]);
+        bytes.extend(info);
+        bytes
+    }
+
+    #[test]
+    fn test_igate_line() {
+        let line = |bytes: Vec<u8>| igate_line(&Frame::decode(&bytes).unwrap(), "N0GATE-10");
+        assert_eq!(
+            line(frame("N0CALL-9", &[("WIDE1", true), ("WIDE2-1", false)], b"!4903.50N/07201.75W>\r")),
+            Ok("N0CALL-9>APRS,WIDE1*,WIDE2-1,qAR,N0GATE-10:!4903.50N/07201.75W>".to_string())
+        );
+        assert_eq!(line(frame("N0CALL", &[("TCPIP", true)], b">hi")), Err(NotGated::FromInternet));
+        assert_eq!(line(frame("N0CALL", &[("NOGATE", false)], b">hi")), Err(NotGated::Flagged));
+        assert_eq!(line(frame("N0CALL", &[], b"?APRS?")), Err(NotGated::Query));
+        assert_eq!(line(frame("N0CALL", &[], b"")), Err(NotGated::Invalid));
+
+        // Third party packets are gated with their inner header, unless they
+        // came from APRS-IS in the first place
+        assert_eq!(
+            line(frame("N0GATE", &[], b"}N1CALL>APRS,WIDE1-1:>hello")),
+            Ok("N1CALL>APRS,WIDE1-1,qAR,N0GATE-10:>hello".to_string())
+        );
+        assert_eq!(line(frame("N0GATE", &[], b"}N1CALL>APRS,TCPIP,N0GATE*:>hi")), Err(NotGated::FromInternet));
+        assert_eq!(line(frame("N0GATE", &[], b"}N1CALL>APRS,RFONLY:>hi")), Err(NotGated::Flagged));
+    }
+
+    #[test]
+    fn test_kiss_tcp_igate() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = RfConfig {
+            interface: RfInterface::KissTcp,
+            address: Some(listener.local_addr().unwrap().to_string()),
+            device: None,
+            baud: 9600,
+            callsign: None,
+            reconnect_secs: 1,
+            tx: None,
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut hub = Hub::new();
+        hub.uplinks.get_mut().unwrap().push(crate::uplink::test_route(tx));
+        let hub = Arc::new(hub);
+        let status = Arc::new(Mutex::new(RfStatus::default()));
+        spawn(cfg, "N0GATE-10".to_string(), hub.clone(), status.clone());
+
+        let (mut tnc, _) = listener.accept().unwrap();
+        tnc.write_all(&kiss::encode(0, &frame("N0CALL", &[], b">on the air"))).unwrap();
+        tnc.write_all(&kiss::encode(0, &frame("N1CALL", &[("TCPXX", true)], b">from IS"))).unwrap();
+        tnc.write_all(&kiss::encode(0, b"junk")).unwrap();
+        for _ in 0..100 {
+            if status.lock().unwrap().frames == 3 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let s = status.lock().unwrap().clone();
+        assert!(s.connected);
+        assert_eq!((s.frames, s.gated, s.not_gated, s.invalid), (3, 1, 1, 1));
+        assert_eq!(rx.try_recv().unwrap(), "N0CALL>APRS,qAR,N0GATE-10:>on the air\r\n");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_tx_igate() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let tx = crate::config::RfTxConfig {
+            enabled: true,
+            path: None,
+            heard_secs: 1800,
+            max_hops: 2,
+            max_per_minute: 6,
+            courtesy_position: true,
+        };
+        let cfg = RfConfig {
+            interface: RfInterface::KissTcp,
+            address: Some(listener.local_addr().unwrap().to_string()),
+            device: None,
+            baud: 9600,
+            callsign: None,
+            reconnect_secs: 1,
+            tx: Some(tx.clone()),
+        };
+        let mut hub = Hub::new();
+        let status = Arc::new(Mutex::new(RfStatus::default()));
+        hub.tx_igate = Some(Arc::new(crate::tx_igate::TxIgate::new(tx, cfg.interface, "N0GATE-10", status.clone()).unwrap()));
+        let hub = Arc::new(hub);
+        spawn(cfg, "N0GATE-10".to_string(), hub.clone(), status.clone());
+
+        let (mut tnc, _) = listener.accept().unwrap();
+        tnc.write_all(&kiss::encode(0, &frame("N1CALL", &[], b">on the air"))).unwrap();
+        for _ in 0..100 {
+            if status.lock().unwrap().frames == 1 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        pipeline::ingest(&hub, Source::Uplink, "N0CALL>APRS,TCPIP*,qAC,T2TEST::N1CALL   :hi{1").unwrap();
+        pipeline::ingest(&hub, Source::Uplink, "N0CALL>APRS,TCPIP*,qAC,T2TEST::N9CALL   :hi{2").unwrap();
+
+        tnc.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut decoder = kiss::Decoder::default();
+        let mut buf = [0u8; 256];
+        let sent = loop {
+            let n = tnc.read(&mut buf).unwrap();
+            if let Some((_, bytes)) = decoder.push(&buf[..n]).pop() {
+                break Frame::decode(&bytes).unwrap();
+            }
+        };
+        assert_eq!(sent.tnc2_header(), "N0GATE-10>APRS");
+        assert_eq!(sent.info, b"}N0CALL>APRS,TCPIP,N0GATE-10*::N1CALL   :hi{1");
+        let counters = status.lock().unwrap().tx.clone().unwrap();
+        assert_eq!((counters.messages, counters.not_heard), (1, 1));
+    }
+
+    #[test]
+    fn test_agwpe_igate() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = RfConfig {
+            interface: RfInterface::Agwpe,
+            address: Some(listener.local_addr().unwrap().to_string()),
+            device: None,
+            baud: 9600,
+            callsign: None,
+            reconnect_secs: 1,
+            tx: None,
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut hub = Hub::new();
+        hub.uplinks.get_mut().unwrap().push(crate::uplink::test_route(tx));
+        let status = Arc::new(Mutex::new(RfStatus::default()));
+        spawn(cfg, "N0GATE-10".to_string(), Arc::new(hub), status.clone());
+
+        let (mut engine, _) = listener.accept().unwrap();
+        let mut request = [0u8; 36];
+        engine.read_exact(&mut request).unwrap();
+        assert_eq!(request[4], agwpe::KIND_RAW_MONITOR);
+        let mut raw = vec![0x00];
+        raw.extend(frame("N0CALL", &[("WIDE2", true)], b">via agw"));
+        engine.write_all(&agwpe::encode(0, agwpe::KIND_RAW, &raw)).unwrap();
+        // Monitoring frames in text form are ignored
+        engine.write_all(&agwpe::encode(0, b'U', b"N0CALL>APRS")).unwrap();
+        let line = (0..100).find_map(|_| {
+            std::thread::sleep(Duration::from_millis(10));
+            rx.try_recv().ok()
+        });
+        assert_eq!(line.as_ref().map(|out| out.line.as_str()), Some("N0CALL>APRS,WIDE2*,qAR,N0GATE-10:>via agw\r\n"));
+        assert_eq!(status.lock().unwrap().frames, 1);
+    }
+}
@@ -0,0 +1,110 @@
+//! Stations heard on RF, telling those heard direct from those only heard
+//! through digipeaters. Directs answer `?APRSD` and, with digipeated
+//! stations within the hop limit, decide what the transmit igate sends.
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Seconds a station heard direct stays in the directs list
+pub const DIRECT_SECS: u64 = 1800;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RfStation {
+    pub callsign: String,
+    pub last_heard: u64,
+    pub last_direct: Option<u64>,
+    pub last_digipeated: Option<u64>,
+    /// Digipeaters its last digipeated packet went through
+    pub hops: usize,
+    pub packets: u64,
+}
+
+impl RfStation {
+    /// Heard direct within [`DIRECT_SECS`].
+    pub fn is_direct(&self, now: u64) -> bool {
+        self.last_direct.is_some_and(|t| now.saturating_sub(t) < DIRECT_SECS)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RfHeard {
+    stations: HashMap<String, RfStation>,
+}
+
+impl RfHeard {
+    /// Records a packet from `callsign` that went through `hops` digipeaters.
+    pub fn record(&mut self, callsign: &str, hops: usize, now: u64) {
+        let station = self.stations.entry(callsign.to_uppercase()).or_insert_with(|| RfStation {
+            callsign: callsign.to_uppercase(),
+            last_heard: now,
+            last_direct: None,
+            last_digipeated: None,
+            hops: 0,
+            packets: 0,
+        });
+        station.last_heard = now;
+        station.packets += 1;
+        if hops == 0 {
+            station.last_direct = Some(now);
+        } else {
+            station.last_digipeated = Some(now);
+            station.hops = hops;
+        }
+    }
+
+    pub fn get(&self, callsign: &str) -> Option<&RfStation> {
+        self.stations.get(&callsign.to_uppercase())
+    }
+
+    /// Callsigns heard direct within [`DIRECT_SECS`], sorted.
+    pub fn directs(&self, now: u64) -> Vec<String> {
+        let mut directs: Vec<String> = self
+            .stations
+            .values()
+            .filter(|s| s.is_direct(now))
+            .map(|s| s.callsign.clone())
+            .collect();
+        directs.sort();
+        directs
+    }
+
+    /// All stations, most recently heard first.
+    pub fn all(&self) -> Vec<RfStation> {
+        let mut stations: Vec<RfStation> = self.stations.values().cloned().collect();
+        stations.sort_by(|a, b| b.last_heard.cmp(&a.last_heard).then_with(|| a.callsign.cmp(&b.callsign)));
+        stations
+    }
+
+    pub fn len(&self) -> usize {
+        self.stations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty()
+    }
+
+    /// Forgets stations not heard since `cutoff`.
+    pub fn prune(&mut self, cutoff: u64) {
+        self.stations.retain(|_, s| s.last_heard >= cutoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directs() {
+        let mut heard = RfHeard::default();
+        heard.record("n0call-9", 0, 5000);
+        heard.record("N0CALL-9", 2, 5100);
+        heard.record("N1CALL", 1, 5100);
+        heard.record("N2CALL", 0, 5100 - DIRECT_SECS);
+        let station = heard.get("N0CALL-9").unwrap();
+        assert_eq!((station.last_direct, station.last_digipeated, station.hops, station.packets), (Some(5000), Some(5100), 2, 2));
+        assert_eq!(heard.directs(5100), ["N0CALL-9"]);
+        assert_eq!(heard.all()[2].callsign, "N2CALL");
+        heard.prune(5100);
+        assert_eq!(heard.len(), 2);
+    }
+}
@@ -0,0 +1,79 @@
+//! Routing rules between sources and destinations. Every packet carries
+//! the tag of where it entered the server, the same one recorded in the
+//! heard list: `client:CALL`, `uplink`, `uplink:NAME`, `s2s:PEER`, `rf`,
+//! `replay`, `local` or `injected`. Destinations are tagged the same way:
+//! `client:CALL`, `uplink`, `uplink:NAME`, `s2s:PEER` and `rf`. The first
+//! rule matching a packet's source, destination and filter decides whether
+//! it goes there; without a matching rule it goes everywhere it normally
+//! would.
+
+use crate::config::{RouteAction, RouteConfig};
+use crate::filter::{FilterContext, FilterSet, parse_filter_list};
+use crate::parser::ParsedPacket;
+
+#[derive(Debug)]
+pub struct RouteRule {
+    from: String,
+    to: String,
+    filter: Option<FilterSet>,
+    action: RouteAction,
+}
+
+/// `uplink:cwop` matches only itself, `s2s:*` every peer and `*` anything.
+fn tag_matches(pattern: &str, tag: &str) -> bool {
+    let (pattern, tag) = (pattern.to_lowercase(), tag.to_lowercase());
+    match pattern.strip_suffix('*') {
+        Some(prefix) => tag.starts_with(prefix),
+        None => tag == pattern,
+    }
+}
+
+impl RouteRule {
+    pub fn from_config(cfg: &RouteConfig) -> Result<Self, String> {
+        let filter = match &cfg.filter {
+            Some(s) => Some(FilterSet::new(parse_filter_list(s).map_err(|e| format!("route {} to {}: {}", cfg.from, cfg.to, e))?)),
+            None => None,
+        };
+        Ok(RouteRule { from: cfg.from.clone(), to: cfg.to.clone(), filter, action: cfg.action })
+    }
+
+    fn matches(&self, from: &str, to: &str, packet: &ParsedPacket) -> bool {
+        tag_matches(&self.from, from)
+            && tag_matches(&self.to, to)
+            && self.filter.as_ref().is_none_or(|f| f.matches(packet, &FilterContext::default()))
+    }
+}
+
+/// Whether a packet from `from` may go to `to` under `rules`.
+pub fn allows(rules: &[RouteRule], from: &str, to: &str, packet: &ParsedPacket) -> bool {
+    rules.iter().find(|rule| rule.matches(from, to, packet)).is_none_or(|rule| rule.action == RouteAction::Allow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(from: &str, to: &str, filter: Option<&str>, action: RouteAction) -> RouteRule {
+        let cfg = RouteConfig { from: from.to_string(), to: to.to_string(), filter: filter.map(str::to_string), action };
+        RouteRule::from_config(&cfg).unwrap()
+    }
+
+    #[test]
+    fn test_allows() {
+        let rules = vec![
+            rule("s2s:T2PEERA", "s2s:T2PEERB", None, RouteAction::Deny),
+            rule("*", "uplink:cwop", Some("t/w"), RouteAction::Allow),
+            rule("*", "uplink:cwop", None, RouteAction::Deny),
+        ];
+        let position = crate::parser::parse("N0CALL>APRS:!4903.50N/07201.75W>").unwrap();
+        let weather = crate::parser::parse("N0CALL>APRS:_10090556c220s004g005t077r000p000P000h50b09900wRSW").unwrap();
+        assert!(!allows(&rules, "s2s:t2peera", "s2s:T2PEERB", &position));
+        assert!(allows(&rules, "s2s:T2PEERA", "s2s:T2PEERC", &position));
+        assert!(allows(&rules, "client:N0CALL", "uplink:cwop", &weather));
+        assert!(!allows(&rules, "client:N0CALL", "uplink:cwop", &position));
+        assert!(allows(&rules, "client:N0CALL", "uplink", &position));
+
+        let cfg = RouteConfig { from: "*".to_string(), to: "*".to_string(), filter: Some("x/".to_string()), action: RouteAction::Deny };
+        assert!(RouteRule::from_config(&cfg).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+//! Builds the Tokio runtime the server runs on, sized from the `[runtime]`
+//! config section.
+
+use crate::config::RuntimeConfig;
+use std::io;
+use tokio::runtime::{Builder, Runtime};
+
+/// A multi-threaded runtime with the configured number of worker and
+/// blocking threads; Tokio's defaults (one worker per core, up to 512
+/// blocking threads) apply to whatever is unset.
+pub fn build(cfg: &RuntimeConfig) -> io::Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all().thread_name("aprsserver-worker");
+    if let Some(threads) = cfg.worker_threads {
+        if threads == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "worker_threads must be at least 1"));
+        }
+        builder.worker_threads(threads);
+    }
+    if let Some(threads) = cfg.max_blocking_threads {
+        if threads == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "max_blocking_threads must be at least 1"));
+        }
+        builder.max_blocking_threads(threads);
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let runtime = build(&RuntimeConfig { worker_threads: Some(2), max_blocking_threads: Some(4) }).unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 2);
+        assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+        assert!(build(&RuntimeConfig { worker_threads: Some(0), max_blocking_threads: None }).is_err());
+    }
+}
@@ -0,0 +1,488 @@
+use crate::config;
+use crate::filter::{parse_filter_list, ClientFilter, InvalidFilter};
+use crate::hub::{self, PeerState, S2SPeerHandle};
+use crate::latency::Outbound;
+use crate::pipeline::{self, Origin};
+use crate::stats::{count_lines, Protocol};
+use crate::version;
+use chrono::Utc;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex as TokioMutex;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tracing::{error, info, warn};
+
+/// Filter requested by the peer in its login line, after `filter`.
+fn login_filter(line: &str) -> Option<Result<Vec<ClientFilter>, InvalidFilter>> {
+    let (_, filter) = line.split_once(" filter ")?;
+    Some(parse_filter_list(filter))
+}
+
+// Reconnect delays double from the initial one up to the cap, +/-20% jitter
+const BACKOFF_INITIAL_SECS: f64 = 5.0;
+const BACKOFF_MAX_SECS: f64 = 300.0;
+// Consecutive failures after which the peer is flagged in the status
+const ALARM_FAILURES: u32 = 10;
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often to send keepalives and check for a silent peer, often enough
+/// that short timeouts are still noticed in time.
+pub(crate) fn keepalive_interval(timeout: Duration) -> Duration {
+    KEEPALIVE_INTERVAL.min(timeout / 3).max(Duration::from_millis(100))
+}
+
+/// Writes all of `buf`, failing with `TimedOut` if the other end does not
+/// take it within `limit`.
+pub(crate) async fn write_within<W: AsyncWrite + Unpin>(writer: &mut W, buf: &[u8], limit: Duration) -> io::Result<()> {
+    match tokio::time::timeout(limit, writer.write_all(buf)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "write timed out")),
+    }
+}
+
+/// Delay before the next attempt after `failures` consecutive failures.
+/// `jitter` is a random value in `[0, 1)`.
+fn backoff_delay(failures: u32, jitter: f64) -> Duration {
+    let base = BACKOFF_INITIAL_SECS * 2f64.powi(failures.saturating_sub(1).min(16) as i32);
+    Duration::from_secs_f64(base.min(BACKOFF_MAX_SECS) * (0.8 + 0.4 * jitter))
+}
+
+fn random_jitter() -> f64 {
+    let hash = RandomState::new().build_hasher().finish();
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn set_state(status: &Mutex<hub::S2SPeerStatus>, state: PeerState) {
+    status.lock().unwrap().state = state;
+}
+
+#[tracing::instrument(name = "s2s_peer", skip_all, fields(host = %cfg.host, port = cfg.port))]
+pub async fn connect_s2s_peer(cfg: config::S2SPeerConfig, status: Arc<Mutex<hub::S2SPeerStatus>>, hub: Arc<hub::Hub>) {
+    let filters = match cfg.filter.as_deref().map(parse_filter_list).transpose() {
+        Ok(filters) => filters,
+        Err(e) => {
+            error!(error = %e, "invalid S2S peer filter, not connecting");
+            status.lock().unwrap().last_error = Some(format!("filter: {}", e));
+            return;
+        }
+    };
+    loop {
+        set_state(&status, PeerState::Connecting);
+        let result = run_session(&cfg, filters.clone(), &status, &hub).await;
+        let delay = {
+            let mut s = status.lock().unwrap();
+            s.connected = false;
+            s.state = PeerState::Backoff;
+            s.consecutive_failures += 1;
+            if let Err(e) = result {
+                s.last_error = Some(e);
+            }
+            if s.consecutive_failures >= ALARM_FAILURES && !s.alarm {
+                s.alarm = true;
+                error!(failures = s.consecutive_failures, last_error = ?s.last_error, "S2S peer keeps failing");
+            }
+            let delay = backoff_delay(s.consecutive_failures, random_jitter());
+            s.next_attempt = Some(SystemTime::now() + delay);
+            delay
+        };
+        info!(delay_secs = delay.as_secs_f64(), "S2S peer reconnecting after backoff");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// A session's handle and writer task, removed and stopped when the session
+/// ends, also when its task is aborted because the peer was unconfigured.
+struct Registration<'a> {
+    hub: &'a hub::Hub,
+    sender: UnboundedSender<Outbound>,
+    forward: tokio::task::AbortHandle,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.forward.abort();
+        self.hub.remove_s2s_peer_handle(&self.sender);
+    }
+}
+
+/// Connects, logs in and relays until the link drops. Returns the error
+/// that ended it; `Ok` means the peer closed the connection.
+async fn run_session(
+    cfg: &config::S2SPeerConfig,
+    filters: Option<Vec<ClientFilter>>,
+    status: &Mutex<hub::S2SPeerStatus>,
+    hub: &hub::Hub,
+) -> Result<(), String> {
+    let addr = format!("{}:{}", cfg.host, cfg.port);
+    let stream = match TcpStream::connect(&addr).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            status.lock().unwrap().connect_errors += 1;
+            return Err(format!("connect: {}", e));
+        }
+    };
+    {
+        let mut s = status.lock().unwrap();
+        s.state = PeerState::Authenticating;
+        s.connected = true;
+        s.last_connect = Some(SystemTime::now());
+        s.next_attempt = None;
+    }
+    info!(%addr, "connected to S2S peer");
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    // Outgoing channel for this peer
+    let (tx, mut rx) = unbounded_channel::<Outbound>();
+    // Register handle in hub
+    hub.s2s_peer_handles.write().unwrap().push(S2SPeerHandle {
+        peer_name: cfg.peer_name.clone(),
+        sender: tx.clone(),
+        filters,
+    });
+    let writer = Arc::new(TokioMutex::new(writer));
+    // Spawn task to forward outgoing packets; it ends the session if a write fails
+    let writer_clone = writer.clone();
+    let latency = hub.latency.peers.clone();
+    let write_timeout = hub.write_timeout;
+    let mut forward = tokio::spawn(async move {
+        while let Some(out) = rx.recv().await {
+            let mut w = writer_clone.lock().await;
+            write_within(&mut *w, out.line.as_bytes(), write_timeout).await?;
+            latency.record(out.received.elapsed());
+        }
+        Ok::<_, io::Error>(())
+    });
+    let _registration = Registration { hub, sender: tx, forward: forward.abort_handle() };
+    relay(cfg, status, hub, &mut reader, &writer, &mut forward).await
+}
+
+/// Logs in on a connected session, then relays until the link drops.
+async fn relay(
+    cfg: &config::S2SPeerConfig,
+    status: &Mutex<hub::S2SPeerStatus>,
+    hub: &hub::Hub,
+    reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: &TokioMutex<tokio::net::tcp::OwnedWriteHalf>,
+    forward: &mut tokio::task::JoinHandle<io::Result<()>>,
+) -> Result<(), String> {
+    let timeout = Duration::from_secs(hub.s2s_timeout_secs);
+    // Send S2S login line (aprsc style)
+    let mut login = format!("# {} s2s {} {} 14579", version::SOFTWARE, hub.server_name, cfg.passcode);
+    if cfg.request_filter
+        && let Some(filter) = &cfg.filter
+    {
+        login.push_str(&format!(" filter {}", filter));
+    }
+    login.push('\n');
+    if let Err(e) = write_within(&mut *writer.lock().await, login.as_bytes(), hub.write_timeout).await {
+        status.lock().unwrap().write_errors += 1;
+        return Err(format!("login send: {}", e));
+    }
+    {
+        let mut s = status.lock().unwrap();
+        s.packets_tx += 1;
+        s.bytes_tx += login.len() as u64;
+        s.last_tx_time = Some(SystemTime::now());
+    }
+    // Wait for peer's login/ack
+    let mut line = String::new();
+    match reader.read_line(&mut line).await {
+        Ok(0) => {
+            status.lock().unwrap().read_errors += 1;
+            return Err("peer closed connection".to_string());
+        }
+        Ok(n) => {
+            let mut s = status.lock().unwrap();
+            s.packets_rx += 1;
+            s.bytes_rx += n as u64;
+            s.last_rx_time = Some(SystemTime::now());
+            s.state = PeerState::Established;
+            s.consecutive_failures = 0;
+            s.last_error = None;
+            if s.alarm {
+                s.alarm = false;
+                info!("S2S peer recovered");
+            }
+            info!(line = line.trim(), "S2S peer login ack");
+        }
+        Err(e) => {
+            status.lock().unwrap().read_errors += 1;
+            return Err(format!("read: {}", e));
+        }
+    }
+    // Main loop: keepalive and relay
+    let mut last_rx = Instant::now();
+    let mut keepalive = tokio::time::interval(keepalive_interval(timeout));
+    keepalive.tick().await;
+    // Kept across iterations: read_until leaves a line cut short by the
+    // keepalive tick in here, where read_line would lose it
+    let mut buf = Vec::new();
+    loop {
+        // Read from peer
+        tokio::select! {
+            read = reader.read_until(b'\n', &mut buf) => {
+                match read {
+                    Ok(0) => return Ok(()), // peer closed
+                    Ok(_) => {
+                        last_rx = Instant::now();
+                        let line = String::from_utf8_lossy(&buf);
+                        let packet = line.trim();
+                        if !packet.starts_with('#') {
+                            pipeline::submit(hub, Origin::S2s(cfg.peer_name.clone()), packet);
+                        }
+                        let mut s = status.lock().unwrap();
+                        s.packets_rx += 1;
+                        s.bytes_rx += buf.len() as u64;
+                        s.last_rx_time = Some(SystemTime::now());
+                        buf.clear();
+                    }
+                    Err(e) => {
+                        status.lock().unwrap().read_errors += 1;
+                        return Err(format!("read: {}", e));
+                    }
+                }
+            }
+            forwarded = &mut *forward => {
+                status.lock().unwrap().write_errors += 1;
+                let e = match forwarded {
+                    Ok(Err(e)) => e.to_string(),
+                    _ => "writer stopped".to_string(),
+                };
+                warn!(error = %e, "S2S peer write failed");
+                return Err(format!("write: {}", e));
+            }
+            _ = keepalive.tick() => {
+                if last_rx.elapsed() >= timeout {
+                    status.lock().unwrap().read_errors += 1;
+                    warn!(silent_secs = last_rx.elapsed().as_secs(), "S2S peer timed out");
+                    return Err("peer timed out".to_string());
+                }
+                let keepalive = version::keepalive(&hub.server_name, Utc::now());
+                let mut w = writer.lock().await;
+                if let Err(e) = write_within(&mut *w, keepalive.as_bytes(), hub.write_timeout).await {
+                    status.lock().unwrap().write_errors += 1;
+                    return Err(format!("keepalive: {}", e));
+                }
+            }
+        }
+    }
+}
+
+pub fn s2s_server_handler(mut stream: std::net::TcpStream, hub: Arc<hub::Hub>) {
+    use std::io::{BufRead, BufReader, ErrorKind, Write};
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "unknown".to_string());
+    info!(%peer, "incoming S2S connection");
+    let timeout = Duration::from_secs(hub.s2s_timeout_secs);
+    // Reads time out periodically so keepalives go out and silence is noticed
+    let _ = stream.set_read_timeout(Some(keepalive_interval(timeout)));
+    let _ = stream.set_write_timeout(Some(hub.write_timeout));
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    let listener = stream.local_addr().ok().and_then(|a| hub.listener(Protocol::Tcp, a.port()).cloned());
+    // Outgoing channel for this peer
+    let (tx, rx) = unbounded_channel::<Outbound>();
+    // Register handle in hub
+    hub.s2s_peer_handles.write().unwrap().push(S2SPeerHandle {
+        peer_name: Some(peer.clone()),
+        sender: tx.clone(),
+        filters: None,
+    });
+    // Spawn thread to forward outgoing packets; a failed write closes the
+    // connection, which ends the read loop below
+    let mut writer = stream.try_clone().unwrap();
+    let tx_listener = listener.clone();
+    let latency = hub.latency.peers.clone();
+    let write_peer = peer.clone();
+    std::thread::spawn(move || {
+        let mut rx = rx;
+        while let Some(out) = rx.blocking_recv() {
+            if let Err(e) = writer.write_all(out.line.as_bytes()) {
+                warn!(peer = %write_peer, error = %e, "S2S write failed, disconnecting");
+                let _ = writer.shutdown(std::net::Shutdown::Both);
+                break;
+            }
+            // Keepalives go through here too but are not packets
+            if !out.line.starts_with('#') {
+                latency.record(out.received.elapsed());
+            }
+            if let Some(listener) = &tx_listener {
+                let (packets, bytes) = count_lines(&out.line);
+                listener.add_tx(packets, bytes);
+            }
+        }
+    });
+    // Wait for S2S login line
+    match reader.read_line(&mut line) {
+        Ok(0) => {
+            info!(%peer, "S2S peer disconnected before login");
+            // Remove handle on disconnect
+            hub.remove_s2s_peer_handle(&tx);
+            return;
+        }
+        Ok(_) => {
+            info!(%peer, line = line.trim(), "S2S peer login");
+            // TODO: validate login line
+            match login_filter(line.trim()) {
+                Some(Ok(filters)) => {
+                    let mut handles = hub.s2s_peer_handles.write().unwrap();
+                    if let Some(handle) = handles.iter_mut().find(|h| h.sender.same_channel(&tx)) {
+                        handle.filters = Some(filters);
+                    }
+                }
+                Some(Err(e)) => warn!(%peer, error = %e, "ignoring invalid S2S peer filter"),
+                None => {}
+            }
+            // Send our own login/ack
+            let login = format!("# {} s2s {} 12345 14579\n", version::SOFTWARE, hub.server_name);
+            if let Err(e) = stream.write_all(login.as_bytes()) {
+                warn!(%peer, error = %e, "S2S send login error");
+                // Remove handle on disconnect
+                hub.remove_s2s_peer_handle(&tx);
+                return;
+            }
+        }
+        Err(e) => {
+            warn!(%peer, error = %e, "S2S read login error");
+            // Remove handle on disconnect
+            hub.remove_s2s_peer_handle(&tx);
+            return;
+        }
+    }
+    // Main loop: keepalive and relay
+    let mut last_rx = Instant::now();
+    line.clear();
+    loop {
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(n) => {
+                last_rx = Instant::now();
+                if let Some(listener) = &listener {
+                    listener.add_rx(count_lines(&line).0, n);
+                }
+                let packet = line.trim();
+                if !packet.starts_with('#') {
+                    pipeline::submit(&hub, Origin::S2s(Some(peer.clone())), packet);
+                }
+                line.clear();
+            }
+            // A partial line stays in `line` until the rest arrives
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if last_rx.elapsed() >= timeout {
+                    warn!(%peer, silent_secs = last_rx.elapsed().as_secs(), "S2S peer timed out");
+                    break;
+                }
+                let _ = tx.send(Outbound::now(version::keepalive(&hub.server_name, Utc::now())));
+            }
+            Err(e) => {
+                warn!(error = %e, "S2S read error");
+                break;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    // Remove handle on disconnect
+    hub.remove_s2s_peer_handle(&tx);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(1, 0.5), Duration::from_secs(5));
+        assert_eq!(backoff_delay(3, 0.5), Duration::from_secs(20));
+        assert_eq!(backoff_delay(50, 0.5), Duration::from_secs(300));
+        assert_eq!(backoff_delay(1, 0.0), Duration::from_secs(4));
+        assert!(backoff_delay(50, 0.999) < Duration::from_secs(360));
+        let jitter = random_jitter();
+        assert!((0.0..1.0).contains(&jitter));
+    }
+    #[tokio::test]
+    async fn test_write_within() {
+        // Nobody reads the other end, so only 16 bytes fit
+        let (mut writer, _reader) = tokio::io::duplex(16);
+        write_within(&mut writer, b"# keepalive\r\n", Duration::from_millis(50)).await.unwrap();
+        let err = write_within(&mut writer, &[b'x'; 64], Duration::from_millis(50)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+    #[test]
+    fn test_inbound_peer_timeout() {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut hub = hub::Hub::new();
+        hub.s2s_timeout_secs = 1;
+        let hub = Arc::new(hub);
+        let handler = std::thread::spawn({
+            let hub = hub.clone();
+            move || s2s_server_handler(stream, hub)
+        });
+        client.write_all(b"# aprsc 2.1.5 s2s T2PEER 12345 14579\n").unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, format!("# {} s2s NOCALL 12345 14579\n", version::SOFTWARE));
+        assert_eq!(hub.s2s_peer_handles.read().unwrap().len(), 1);
+        // Keepalives arrive while we stay silent, then the peer is dropped
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with(&format!("# {} ", version::SOFTWARE)) && line.ends_with(" NOCALL\r\n"));
+        handler.join().unwrap();
+        assert!(hub.s2s_peer_handles.read().unwrap().is_empty());
+    }
+    #[tokio::test]
+    async fn test_line_split_around_keepalive() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut hub = hub::Hub::new();
+        hub.s2s_timeout_secs = 1;
+        let hub = Arc::new(hub);
+        let mut feed = hub.packet_feed.subscribe();
+        let cfg = config::S2SPeerConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            passcode: 12345,
+            peer_name: Some("T2PEER".to_string()),
+            filter: None,
+            request_filter: false,
+            protocol: config::PeerProtocol::Tcp,
+        };
+        let session = tokio::spawn({
+            let hub = hub.clone();
+            async move {
+                let status = Mutex::new(hub::S2SPeerStatus::new(cfg.host.clone(), cfg.port, cfg.peer_name.clone()));
+                run_session(&cfg, None, &status, &hub).await
+            }
+        });
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = tokio::io::BufReader::new(reader);
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        writer.write_all(b"# aprsc 2.1.5 s2s T2PEER 12345 14579\n").await.unwrap();
+        // Half a line, then a keepalive goes out before the rest arrives
+        writer.write_all(b"N0CALL>APRS,TCPIP*:>sp").await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with("# "), "{}", line);
+        writer.write_all(b"lit\n").await.unwrap();
+        let accepted = tokio::time::timeout(Duration::from_secs(5), feed.recv()).await.unwrap().unwrap();
+        assert_eq!(accepted.line, "N0CALL>APRS,TCPIP*,qAS,T2PEER:>split");
+        session.abort();
+    }
+    #[test]
+    fn test_login_filter() {
+        assert!(login_filter("# aprsc 2.1.5 s2s T2PEER 12345 14579").is_none());
+        let filters = login_filter("# aprsc 2.1.5 s2s T2PEER 12345 14579 filter r/60/25/100 t/m").unwrap().unwrap();
+        assert_eq!(filters.len(), 2);
+        assert!(login_filter("# aprsc 2.1.5 s2s T2PEER 12345 14579 filter x/1").unwrap().is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use crate::hook::{HookAction, PacketHook};
+use crate::parser;
+use mlua::{Function, HookTriggers, Lua, LuaOptions, StdLib, Value, VmState};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// Limits applied to every script; a script exceeding them is treated as if it
+// returned nothing, so the packet passes through unchanged.
+const SCRIPT_TIME_BUDGET: Duration = Duration::from_millis(10);
+const SCRIPT_MEMORY_LIMIT: usize = 4 * 1024 * 1024;
+
+/// A packet hook backed by a Lua script. The script must define a global
+/// `filter(p)` function, where `p` is a table with `line`, `source`,
+/// `destination`, `path` and `type` (plus `lat`/`lon` for positions).
+/// Returning `false` drops the packet, returning a string replaces the line,
+/// anything else lets it continue.
+pub struct ScriptHook {
+    name: String,
+    lua: Lua,
+}
+
+impl ScriptHook {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_source(path, &source)
+    }
+
+    pub fn from_source(name: &str, source: &str) -> Result<Self, String> {
+        let lua = Lua::new_with(StdLib::STRING | StdLib::TABLE | StdLib::MATH, LuaOptions::default())
+            .map_err(|e| format!("{}: {}", name, e))?;
+        lua.set_memory_limit(SCRIPT_MEMORY_LIMIT).map_err(|e| format!("{}: {}", name, e))?;
+        run_with_budget(&lua, || lua.load(source).set_name(name).exec()).map_err(|e| format!("{}: {}", name, e))?;
+        lua.globals()
+            .get::<Function>("filter")
+            .map_err(|_| format!("{}: script does not define filter()", name))?;
+        Ok(ScriptHook { name: name.to_string(), lua })
+    }
+
+    fn call(&self, line: &str) -> mlua::Result<HookAction> {
+        let p = self.lua.create_table()?;
+        p.set("line", line)?;
+        if let Ok(parsed) = parser::parse(line) {
+            p.set("source", parsed.source.as_str())?;
+            p.set("destination", parsed.destination.as_str())?;
+            p.set("path", parsed.path.clone())?;
+            p.set("type", parsed.data.type_name())?;
+            if let Some(pos) = parsed.position() {
+                p.set("lat", pos.lat)?;
+                p.set("lon", pos.lon)?;
+            }
+        }
+        let filter: Function = self.lua.globals().get("filter")?;
+        let result = run_with_budget(&self.lua, || filter.call::<Value>(p))?;
+        Ok(match result {
+            Value::Boolean(false) => HookAction::Drop,
+            Value::String(s) => HookAction::Replace(s.to_str()?.to_string()),
+            _ => HookAction::Continue,
+        })
+    }
+}
+
+impl PacketHook for ScriptHook {
+    fn on_ingress(&self, line: &str) -> HookAction {
+        match self.call(line) {
+            Ok(action) => action,
+            Err(e) => {
+                warn!(script = %self.name, error = %e, "script failed");
+                HookAction::Continue
+            }
+        }
+    }
+}
+
+/// Runs `f` with an instruction hook that aborts the script once the time
+/// budget is used up.
+fn run_with_budget<T>(lua: &Lua, f: impl FnOnce() -> mlua::Result<T>) -> mlua::Result<T> {
+    let deadline = Instant::now() + SCRIPT_TIME_BUDGET;
+    lua.set_hook(HookTriggers::new().every_nth_instruction(1000), move |_, _| {
+        if Instant::now() > deadline {
+            Err(mlua::Error::runtime("time budget exceeded"))
+        } else {
+            Ok(VmState::Continue)
+        }
+    })?;
+    let result = f();
+    lua.remove_hook();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_actions() {
+        let hook = ScriptHook::from_source(
+            "test",
+            r#"
+            function filter(p)
+                if p.source == "SPAM" then return false end
+                if p.type == "status" then return p.line .. " via script" end
+            end
+            "#,
+        )
+        .unwrap();
+        assert_eq!(hook.on_ingress("SPAM>APRS:>hello"), HookAction::Drop);
+        assert_eq!(
+            hook.on_ingress("N0CALL>APRS:>hello"),
+            HookAction::Replace("N0CALL>APRS:>hello via script".to_string())
+        );
+        assert_eq!(hook.on_ingress("N0CALL>APRS:!4903.50N/07201.75W-"), HookAction::Continue);
+    }
+
+    #[test]
+    fn test_script_limits() {
+        assert!(ScriptHook::from_source("missing", "x = 1").is_err());
+        assert!(ScriptHook::from_source("load", "while true do end").is_err());
+        let hook = ScriptHook::from_source("loop", "function filter(p) while true do end end").unwrap();
+        assert_eq!(hook.on_ingress("N0CALL>APRS:>hello"), HookAction::Continue);
+        let hook = ScriptHook::from_source("alloc", "function filter(p) local t = {} for i = 1, 1e9 do t[i] = i end end").unwrap();
+        assert_eq!(hook.on_ingress("N0CALL>APRS:>hello"), HookAction::Continue);
+    }
+}
@@ -1,15 +1,31 @@
+use std::collections::VecDeque;
 use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
-use std::collections::{HashSet, VecDeque};
-use std::time::{Instant};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
-use crate::filter::ClientFilter;
-use crate::client::Client;
+use crate::filter::{ClientFilter, FilterSet};
+use crate::client::{Client, ClientConn};
+use crate::client_class::{BandwidthLimiter, ClientClass, RateLimiter, default_filter};
+use crate::auth_callout::Verdict;
+use crate::config::{AuthFallback, InvalidLinesConfig};
+use crate::hook::DropReason;
 use crate::hub::Hub;
+use crate::login::{Login, parse_login_for};
+use crate::login_limit::Failure;
+use crate::parser;
+use crate::pipeline::{self, Source};
+use crate::stats::Protocol;
+use serde::Serialize;
+use tracing::{field, info, info_span, warn};
 
-const DUP_CACHE_SIZE: usize = 100;
+/// The `# logresp` line answering a login, which clients parse to learn
+/// whether they were verified and which server they reached.
+pub fn logresp(callsign: &str, verified: bool, server_id: &str) -> String {
+    format!("# logresp {} {}, server {}\r\n", callsign, if verified { "verified" } else { "unverified" }, server_id)
+}
 
-fn aprs_passcode(callsign: &str) -> u16 {
+pub fn aprs_passcode(callsign: &str) -> u16 {
     // Standard APRS-IS passcode algorithm (from aprsc/javAPRSSrvr)
     let mut hash: u32 = 0x73e2_070a;
     let mut up = callsign.to_uppercase();
@@ -28,16 +44,8 @@ fn aprs_passcode(callsign: &str) -> u16 {
 }
 
 pub fn is_valid_aprs_packet(line: &str) -> bool {
-    // Basic APRS-IS packet validation: must contain '>' and ':'
-    // Example: CALLSIGN>DEST,PATH:payload
-    let line = line.trim();
-    if line.is_empty() { return false; }
-    let gt = line.find('>');
-    let colon = line.find(':');
-    match (gt, colon) {
-        (Some(gt), Some(colon)) if gt > 0 && colon > gt + 1 => true,
-        _ => false,
-    }
+    // Basic APRS-IS packet validation: CALLSIGN>DEST,PATH:payload
+    parser::parse(line.trim()).is_ok()
 }
 
 fn _packet_matches_filter(line: &str, filter: &Option<Vec<String>>) -> bool {
@@ -50,110 +58,345 @@ fn _packet_matches_filter(line: &str, filter: &Option<Vec<String>>) -> bool {
     }
 }
 
-fn extract_message_destination(packet: &str) -> Option<String> {
-    // APRS message format: SRC>DEST,PATH::DEST     :message text
-    // Message payload: :DEST     :message text
-    let colon = packet.find(':')?;
-    let payload = &packet[colon+1..];
-    if !payload.starts_with(':') || payload.len() < 10 {
+/// Packets a session sent us, counted by the session itself.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+struct SessionCounts {
+    received: u64,
+    dropped: u64,
+    duplicated: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ServerTotals {
+    uptime: u64,
+    clients: usize,
+    packets_rx: u64,
+    packets_tx: u64,
+    accepted: u64,
+    duplicates: u64,
+}
+
+/// Reply to `# stats`, or `# stats json` for monitoring clients.
+#[derive(Debug, Serialize)]
+struct StatsReply {
+    uptime: u64,
+    #[serde(flatten)]
+    session: SessionCounts,
+    /// Of our packets sent to the client, those dropped by its filter
+    filtered: u64,
+    sent: u64,
+    verified: bool,
+    filter: Option<String>,
+    server: ServerTotals,
+}
+
+fn stats_reply(hub: &Hub, id: usize, uptime: u64, session: SessionCounts, json: bool) -> String {
+    let (filtered, sent, verified, filter) = match hub.client(id) {
+        Some(client) => {
+            let c = client.lock().unwrap();
+            (c.packets_filtered, c.packets_tx(), c.verified, c.filter_string.clone())
+        }
+        None => (0, 0, false, None),
+    };
+    let reply = StatsReply {
+        uptime,
+        session,
+        filtered,
+        sent,
+        verified,
+        filter,
+        server: ServerTotals {
+            uptime: hub.uptime(),
+            clients: hub.client_count(),
+            packets_rx: hub.counters.packets_rx.load(Ordering::Relaxed),
+            packets_tx: hub.counters.packets_tx.load(Ordering::Relaxed),
+            accepted: hub.counters.accepted.load(Ordering::Relaxed),
+            duplicates: hub.counters.duplicates.load(Ordering::Relaxed),
+        },
+    };
+    if json {
+        return format!("# stats {}\n", serde_json::to_string(&reply).unwrap_or_default());
+    }
+    let s = &reply.server;
+    format!(
+        "# stats: uptime={}s received={} dropped={} duplicated={} filtered={} sent={} verified={} filter={} \
+         server: uptime={}s clients={} rx={} tx={} accepted={} duplicates={}\n",
+        reply.uptime,
+        reply.session.received,
+        reply.session.dropped,
+        reply.session.duplicated,
+        reply.filtered,
+        reply.sent,
+        reply.verified,
+        reply.filter.as_deref().unwrap_or("-"),
+        s.uptime,
+        s.clients,
+        s.packets_rx,
+        s.packets_tx,
+        s.accepted,
+        s.duplicates
+    )
+}
+
+// Offending lines are logged only up to this length
+const MAX_LOGGED_LINE: usize = 200;
+
+/// Invalid lines a client sent within the configured window, with the last
+/// few of them kept for the log.
+#[derive(Debug, Default)]
+struct InvalidLines {
+    times: VecDeque<Instant>,
+    recent: VecDeque<String>,
+}
+
+impl InvalidLines {
+    /// Records an invalid line, returning true once the client has sent
+    /// more than allowed within the window.
+    fn record(&mut self, cfg: &InvalidLinesConfig, line: &str, now: Instant) -> bool {
+        let window = Duration::from_secs(cfg.window_secs);
+        while self.times.front().is_some_and(|t| now.duration_since(*t) > window) {
+            self.times.pop_front();
+        }
+        self.times.push_back(now);
+        self.recent.push_back(line.chars().take(MAX_LOGGED_LINE).collect());
+        while self.recent.len() > cfg.log_lines {
+            self.recent.pop_front();
+        }
+        self.times.len() > cfg.max_invalid
+    }
+}
+
+fn count_client_drop(hub: &Hub, id: usize) {
+    if let Some(client) = hub.client(id) {
+        client.lock().unwrap().inc_dropped();
+    }
+}
+
+/// Parses, compiles and installs a client filter, reporting invalid parts
+/// to the client. Returns None if the filter had no valid part.
+fn apply_filter(hub: &Hub, id: usize, stream: &Mutex<ClientConn>, filter_str: &str) -> Option<Arc<FilterSet>> {
+    let mut filters = Vec::new();
+    for part in filter_str.split_whitespace() {
+        match part.parse::<ClientFilter>() {
+            Ok(f) => filters.push(f),
+            Err(e) => {
+                let _ = stream.lock().unwrap().write_all(format!("# invalid filter: {}\n", e).as_bytes());
+            }
+        }
+    }
+    if filters.is_empty() {
         return None;
     }
-    let dest = &payload[1..10];
-    let dest = dest.trim();
-    if dest.is_empty() || !dest.chars().all(|c| c.is_ascii_alphanumeric()) {
-        None
-    } else {
-        Some(dest.to_string())
+    let filters = Arc::new(FilterSet::new(filters));
+    let Some(client) = hub.client(id) else {
+        return Some(filters);
+    };
+    {
+        let mut c = client.lock().unwrap();
+        c.filter = Some(filters.clone());
+        c.filter_string = Some(filter_str.to_string());
+    }
+    let ack = format!("# filter {} active\n", crate::filter::filter_string(filters.filters()));
+    let _ = stream.lock().unwrap().write_all(ack.as_bytes());
+    Some(filters)
+}
+
+/// Sends a client the buffered history matching its first filter, before
+/// live traffic. The matching packets are collected first, so no hub lock
+/// is held while they are queued. They go through the client's writer
+/// queue like live packets, waiting for room instead of being dropped.
+fn replay_history(hub: &Hub, id: usize, filters: &FilterSet, listener_filter: Option<&FilterSet>, callsign: Option<&str>) {
+    if hub.history_secs == 0 {
+        return;
+    }
+    let lines = hub.history_matching(filters, listener_filter, callsign);
+    let Some(queue) = hub.client(id).map(|c| c.lock().unwrap().queue()) else {
+        return;
+    };
+    for line in lines {
+        let out = format!("{}\r\n", line);
+        if queue.blocking_send((out.clone(), None)).is_err() {
+            break;
+        }
+        hub.notify_egress(id, &out);
     }
 }
 
-pub fn parse_aprs_lat_lon(packet: &str) -> Option<(f64, f64)> {
-    // Very basic APRS position parser: looks for DDMM.hhN/DDDMM.hhE or similar
-    // Example: "N0CALL>APRS,TCPIP*:!4903.50N/07201.75W>..."
-    let payload_start = packet.find(':')? + 1;
-    let payload = &packet[payload_start..];
-    let pos = payload.find('!').or_else(|| payload.find('='))?;
-    let data = &payload[pos+1..];
-    if data.len() < 19 { return None; }
-    // Parse latitude
-    let lat_str = &data[0..8];
-    let ns = &data[7..8];
-    let lon_str = &data[9..18];
-    let ew = &data[17..18];
-    let lat_deg: f64 = lat_str[0..2].parse().ok()?;
-    let lat_min: f64 = lat_str[2..7].parse().ok()?;
-    let mut lat = lat_deg + lat_min / 60.0;
-    if ns == "S" { lat = -lat; }
-    // Parse longitude
-    let lon_deg: f64 = lon_str[0..3].parse().ok()?;
-    let lon_min: f64 = lon_str[3..8].parse().ok()?;
-    let mut lon = lon_deg + lon_min / 60.0;
-    if ew == "W" { lon = -lon; }
-    Some((lat, lon))
+pub fn handle_client(stream: TcpStream, hub: Arc<Hub>) {
+    let addr = stream.peer_addr().ok();
+    info!(peer = ?addr, "new connection");
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    let port = stream.local_addr().ok().map(|a| a.port());
+    let _ = stream.set_write_timeout(Some(hub.write_timeout));
+    run_session(reader, ClientConn::Tcp(stream), addr, port, hub);
+}
+
+/// How a login is let in.
+struct Admission {
+    verified: bool,
+    /// Client class named by the auth callout
+    class: Option<Arc<ClientClass>>,
+}
+
+enum Refusal {
+    /// Wrong credentials, with the comment to answer
+    Denied(String),
+    /// The auth callout failed and is configured to deny
+    Unavailable,
 }
 
-pub fn handle_client(mut stream: TcpStream, hub: Arc<Mutex<Hub>>) {
-    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "unknown".to_string());
-    println!("New connection from {}", peer);
+/// Decides on a login by the auth callout if one is configured, else, or
+/// when the callout fails and falls back, by the passcode policy.
+fn admit(hub: &Hub, login: &Login, ip: Option<IpAddr>) -> Result<Admission, Refusal> {
+    let by_passcode = || match login.admit(hub.passcode_policy) {
+        Some(verified) => Ok(Admission { verified, class: None }),
+        None => Err(Refusal::Denied("invalid passcode".to_string())),
+    };
+    let Some(callout) = &hub.auth_callout else {
+        return by_passcode();
+    };
+    let reply = match callout.check(login, ip) {
+        Ok(reply) => reply,
+        Err(e) => {
+            warn!(error = %e, "auth callout failed");
+            return match callout.fallback() {
+                AuthFallback::Passcode => by_passcode(),
+                AuthFallback::Deny => Err(Refusal::Unavailable),
+            };
+        }
+    };
+    let class = reply.class.as_deref().and_then(|name| {
+        let class = hub.client_classes.iter().find(|c| c.name == name).cloned();
+        if class.is_none() {
+            warn!(class = name, "auth callout named an unknown client class");
+        }
+        class
+    });
+    match reply.result {
+        Verdict::Verified => Ok(Admission { verified: true, class }),
+        Verdict::Unverified => Ok(Admission { verified: false, class }),
+        Verdict::Denied => {
+            // Only the first line, so the endpoint cannot inject packets
+            let message = reply.message.as_deref().and_then(|m| m.lines().next()).map(str::trim).filter(|m| !m.is_empty());
+            Err(Refusal::Denied(message.unwrap_or("login denied").to_string()))
+        }
+    }
+}
 
-    let mut reader = BufReader::new(stream.try_clone().unwrap());
+/// Runs an APRS-IS client session: login, then filter commands and packets
+/// until the connection closes. Used for both TCP and WebSocket clients;
+/// `addr` is the client's address and `port` the local port a TCP client
+/// connected to.
+pub fn run_session(mut reader: impl BufRead, conn: ClientConn, addr: Option<SocketAddr>, port: Option<u16>, hub: Arc<Hub>) {
+    let span = info_span!("client", peer = field::Empty, id = field::Empty, callsign = field::Empty);
+    let _entered = span.enter();
     let mut line = String::new();
-    let mut filters: Option<Vec<ClientFilter>> = None;
-    let callsign: Option<String> = None;
-    let mut dup_cache: HashSet<u64> = HashSet::new();
-    let mut dup_order: VecDeque<u64> = VecDeque::new();
+    let mut filters: Option<Arc<FilterSet>> = None;
+    let mut callsign: Option<String> = None;
+    let mut verified = false;
     let start_time = Instant::now();
-    let mut packets_received = 0u64;
-    let mut packets_dropped = 0u64;
-    let mut packets_duplicated = 0u64;
+    let mut counts = SessionCounts::default();
+    let mut invalid = InvalidLines::default();
+    let mut class: Option<Arc<ClientClass>> = None;
+    let mut rate_limit: Option<RateLimiter> = None;
 
     // Register client in hub
-    let mut hub_lock = hub.lock().unwrap();
-    let id = hub_lock.next_id;
-    let client = Client::new(id, stream.try_clone().unwrap());
-    hub_lock.add_client(client);
-    drop(hub_lock);
+    let mut client = Client::with_conn(0, conn, addr);
+    span.record("peer", client.addr.as_str());
+    let listener = port.and_then(|p| hub.listener(Protocol::Tcp, p)).cloned();
+    if let Some(listener) = &listener {
+        let _ = client.tx.listener.set(listener.clone());
+    }
+    let listener_filter = port.and_then(|p| hub.listener_filters.get(&p));
+    client.listener_filter = listener_filter.map(|(_, f)| f.clone());
+    let stream = client.stream.clone();
+    let id = hub.add_client(client);
+    span.record("id", id);
+    let _ = stream.lock().unwrap().write_all(format!("# {}\r\n", crate::version::SOFTWARE).as_bytes());
 
     // Wait for login line
-    match reader.read_line(&mut line) {
+    let mut login_filter: Option<String> = None;
+    let ip = addr.map(|a| a.ip());
+    let limit = hub.login_limit.as_ref().zip(ip);
+    let logged_in = match reader.read_line(&mut line) {
         Ok(0) => {
-            println!("{} disconnected before login", peer);
-            return;
+            info!("disconnected before login");
+            false
         }
-        Ok(_) => {
-            // Example login: user CALLSIGN pass 12345 vers ...
-            let login = line.trim();
-            let mut callsign: Option<String> = None;
-            let mut passcode: Option<&str> = None;
-            let mut parts = login.split_whitespace();
-            while let Some(part) = parts.next() {
-                if part.eq_ignore_ascii_case("user") {
-                    callsign = parts.next().map(|s| s.to_string());
-                } else if part.eq_ignore_ascii_case("pass") {
-                    passcode = parts.next();
-                }
+        // Example login: user CALLSIGN pass 12345 vers ...
+        Ok(_) => match parse_login_for(&line, hub.passcode_policy) {
+            Ok(login) if hub.bans.read().unwrap().is_callsign_banned(&login.callsign) => {
+                info!(callsign = %login.callsign, "rejected banned callsign");
+                let _ = stream.lock().unwrap().write_all(b"# callsign banned\n");
+                false
             }
-            if let (Some(ref callsign), Some(passcode)) = (callsign.as_ref(), passcode) {
-                if let Ok(passcode_num) = passcode.parse::<u16>() {
-                    if aprs_passcode(callsign) == passcode_num {
-                        println!("{} logged in: {}", peer, login);
-                        let _ = stream.write_all(b"# login ok\n");
-                    } else {
-                        let _ = stream.write_all(b"# invalid passcode\n");
-                        return;
+            Ok(login) => match admit(&hub, &login, ip) {
+                Ok(admission) => {
+                    verified = admission.verified;
+                    let Login { callsign: call, software, filter, .. } = login;
+                    span.record("callsign", call.as_str());
+                    class = admission.class.or_else(|| hub.client_classes.iter().find(|c| c.matches(&call, port)).cloned());
+                    rate_limit = class.as_ref().and_then(|c| c.max_packets_per_minute).map(|n| RateLimiter::new(n, hub.clock.now()));
+                    info!(software = software.as_deref(), class = class.as_ref().map(|c| c.name.as_str()), verified, "login");
+                    if let Some((limit, ip)) = limit {
+                        limit.record_success(ip);
                     }
-                } else {
-                    let _ = stream.write_all(b"# invalid passcode\n");
-                    return;
+                    if let Some(client) = hub.client(id) {
+                        let mut c = client.lock().unwrap();
+                        c.set_login(&call, verified, software, hub.clock.unix_time());
+                        c.class = class.clone();
+                        c.tx_limit = class.as_ref().and_then(|c| c.max_tx_bytes_per_sec).map(|n| BandwidthLimiter::new(n, hub.clock.now()));
+                    }
+                    let _ = stream.lock().unwrap().write_all(logresp(&call, verified, &hub.server_name).as_bytes());
+                    if let Some((filter, _)) = listener_filter {
+                        let notice = format!("# server filter {} applies to this port\n", filter);
+                        let _ = stream.lock().unwrap().write_all(notice.as_bytes());
+                    }
+                    callsign = Some(call);
+                    login_filter = filter;
+                    true
+                }
+                Err(Refusal::Denied(message)) => {
+                    let reply = format!("{}# {}\n", logresp(&login.callsign, false, &hub.server_name), message);
+                    let _ = stream.lock().unwrap().write_all(reply.as_bytes());
+                    if let Some((limit, ip)) = limit {
+                        limit.record_failure(ip, Failure::Passcode, hub.clock.now());
+                    }
+                    false
                 }
-            } else {
-                let _ = stream.write_all(b"# invalid login\n");
-                return;
+                Err(Refusal::Unavailable) => {
+                    let _ = stream.lock().unwrap().write_all(b"# authentication unavailable, try again later\n");
+                    false
+                }
+            },
+            Err(_) => {
+                let _ = stream.lock().unwrap().write_all(b"# invalid login\n");
+                if let Some((limit, ip)) = limit {
+                    limit.record_failure(ip, Failure::Invalid, hub.clock.now());
+                }
+                false
             }
-        }
+        },
         Err(e) => {
-            eprintln!("{} error reading login: {}", peer, e);
-            return;
+            warn!(error = %e, "error reading login");
+            false
+        }
+    };
+    if !logged_in {
+        hub.remove_client(id);
+        return;
+    }
+    // Simple trackers that cannot send a filter get their configured default
+    let login_filter = login_filter.filter(|f| !f.trim().is_empty()).or_else(|| {
+        let default = default_filter(&hub.client_defaults, callsign.as_deref()?)?;
+        info!(filter = default, "default filter applied");
+        Some(default.to_string())
+    });
+    let listener_filter = listener_filter.map(|(_, f)| f.as_ref());
+    if let Some(filter_str) = &login_filter {
+        filters = apply_filter(&hub, id, &stream, filter_str);
+        if let Some(filters) = &filters {
+            replay_history(&hub, id, filters, listener_filter, callsign.as_deref());
         }
     }
 
@@ -162,96 +405,78 @@ pub fn handle_client(mut stream: TcpStream, hub: Arc<Mutex<Hub>>) {
         line.clear();
         match reader.read_line(&mut line) {
             Ok(0) => {
-                println!("{} disconnected", peer);
+                info!("disconnected");
                 break;
             }
             Ok(n) => {
                 let trimmed = line.trim();
-                if trimmed.to_lowercase().starts_with("# filter ") {
+                let filter_command = trimmed.get(..9).filter(|c| c.eq_ignore_ascii_case("# filter ")).map(|_| trimmed[9..].trim());
+                if let Some(filter_str) = filter_command {
                     // Parse filter command(s)
-                    let filter_str = &trimmed[8..].trim();
-                    let mut new_filters = Vec::new();
-                    for part in filter_str.split_whitespace() {
-                        match part.parse::<ClientFilter>() {
-                            Ok(f) => new_filters.push(f),
-                            Err(e) => {
-                                let _ = stream.write_all(format!("# invalid filter: {}\n", e).as_bytes());
-                            }
+                    let first = filters.is_none();
+                    if let Some(new_filters) = apply_filter(&hub, id, &stream, filter_str) {
+                        if first {
+                            replay_history(&hub, id, &new_filters, listener_filter, callsign.as_deref());
                         }
-                    }
-                    if !new_filters.is_empty() {
                         filters = Some(new_filters);
-                        let _ = stream.write_all(b"# filter set\n");
-                        println!("{} set filter: {}", peer, filter_str);
+                        info!(filter = %filter_str, "filter set");
                     }
                     continue;
-                } else if trimmed.to_lowercase() == "# stats" {
-                    let uptime = start_time.elapsed().as_secs();
-                    let stats = format!(
-                        "# stats: uptime={}s received={} dropped={} duplicated={}\n",
-                        uptime, packets_received, packets_dropped, packets_duplicated
-                    );
-                    let _ = stream.write_all(stats.as_bytes());
+                } else if let Some(format) = trimmed.to_lowercase().strip_prefix("# stats")
+                    && (format.is_empty() || format.trim() == "json")
+                {
+                    let stats = stats_reply(&hub, id, start_time.elapsed().as_secs(), counts, !format.is_empty());
+                    let _ = stream.lock().unwrap().write_all(stats.as_bytes());
                     continue;
                 }
-                packets_received += 1;
-                // Increment per-client RX stats
-                if let Some(client) = hub.lock().unwrap().clients.get(&id) {
-                    let mut c = client.lock().unwrap();
-                    c.inc_rx(n);
+                counts.received += 1;
+                if let Some(client) = hub.client(id) {
+                    client.lock().unwrap().inc_rx(n, hub.clock.unix_time());
                 }
-                // Duplicate detection
-                let hash = seahash::hash(trimmed.as_bytes());
-                if dup_cache.contains(&hash) {
-                    packets_duplicated += 1;
-                    continue;
+                if let Some(listener) = &listener {
+                    listener.add_rx(1, n);
                 }
-                dup_cache.insert(hash);
-                dup_order.push_back(hash);
-                if dup_order.len() > DUP_CACHE_SIZE {
-                    if let Some(old) = dup_order.pop_front() {
-                        dup_cache.remove(&old);
+                let limited = rate_limit.as_mut().is_some_and(|r| !r.allow(hub.clock.now()));
+                let result = if class.as_ref().is_some_and(|c| c.read_only) || limited {
+                    hub.notify_drop(trimmed, DropReason::Policy);
+                    Err(DropReason::Policy)
+                } else {
+                    let source = Source::Client {
+                        id,
+                        callsign: callsign.as_deref(),
+                        verified,
+                        class: class.as_deref(),
+                    };
+                    pipeline::ingest(&hub, source, trimmed)
+                };
+                if let Err(reason) = result {
+                    if reason == DropReason::Duplicate {
+                        counts.duplicated += 1;
+                    } else {
+                        counts.dropped += 1;
                     }
-                }
-                // Filtering
-                let mut pass = true;
-                if let Some(ref fs) = filters {
-                    pass = fs.iter().any(|f| f.matches(trimmed));
-                }
-                if pass {
-                    // Broadcast to all other clients and increment their TX stats
-                    let hub_lock = hub.lock().unwrap();
-                    for (other_id, client) in &hub_lock.clients {
-                        if *other_id != id {
-                            let mut c = client.lock().unwrap();
-                            c.inc_tx(n);
-                        }
+                    count_client_drop(&hub, id);
+                    // Comment lines are not packets, but they are not garbage either
+                    if reason == DropReason::Invalid
+                        && !trimmed.starts_with('#')
+                        && let Some(cfg) = &hub.invalid_lines
+                        && invalid.record(cfg, trimmed, hub.clock.now())
+                    {
+                        warn!(count = invalid.times.len(), lines = ?invalid.recent, "too many invalid lines, disconnecting");
+                        let _ = stream.lock().unwrap().write_all(b"# too many invalid packets\n");
+                        break;
                     }
-                    hub_lock.broadcast_packet(id, line.as_str());
-                    drop(hub_lock);
-                } else {
-                    packets_dropped += 1;
                 }
-                // Message routing placeholder
-                if let Some(dest) = extract_message_destination(trimmed) {
-                    println!("Message packet for destination: {}", dest);
-                    // Future: route to correct client
-                }
-                // On filter or login, update client in hub with new filter/callsign
-                let mut hub_lock = hub.lock().unwrap();
-                hub_lock.update_client(id, callsign.clone(), filters.clone());
-                drop(hub_lock);
             }
             Err(e) => {
-                eprintln!("{} error reading: {}", peer, e);
+                warn!(error = %e, "read error");
                 break;
             }
         }
     }
 
     // Remove client from hub on disconnect
-    let mut hub_lock = hub.lock().unwrap();
-    hub_lock.remove_client(id);
+    hub.remove_client(id);
 }
 
 #[cfg(test)]
@@ -268,6 +493,12 @@ mod tests {
         assert_ne!(aprs_passcode("N0CALL"), aprs_passcode("N1CALL"));
     }
 
+    #[test]
+    fn test_logresp() {
+        assert_eq!(logresp("N0CALL", true, "T2TEST"), "# logresp N0CALL verified, server T2TEST\r\n");
+        assert_eq!(logresp("N0CALL", false, "T2TEST"), "# logresp N0CALL unverified, server T2TEST\r\n");
+    }
+
     #[test]
     fn test_is_valid_aprs_packet() {
         assert!(is_valid_aprs_packet("N0CALL>APRS,TCPIP*:payload"));
@@ -278,27 +509,82 @@ mod tests {
     }
 
     #[test]
-    fn test_packet_matches_filter() {
-        let filter = Some(vec!["foo".to_string(), "bar".to_string()]);
-        assert!(packet_matches_filter("this is foo", &filter));
-        assert!(packet_matches_filter("BAR test", &filter));
-        assert!(!packet_matches_filter("baz", &filter));
-        assert!(packet_matches_filter("anything", &None));
+    fn test_stats_reply() {
+        let hub = Hub::new();
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        let mut client = Client::with_conn(0, ClientConn::WebSocket { tx: Some(tx), write_timeout: std::time::Duration::from_secs(1) }, "127.0.0.1:1".parse().ok());
+        client.set_login("N0CALL", true, None, 0);
+        client.filter_string = Some("r/60/25/50".to_string());
+        let id = hub.add_client(client);
+        let session = || SessionCounts { received: 3, dropped: 1, duplicated: 0 };
+        let text = stats_reply(&hub, id, 5, session(), false);
+        assert!(text.starts_with("# stats: uptime=5s received=3 dropped=1 duplicated=0 filtered=0 sent=0 verified=true filter=r/60/25/50 server: "));
+        let json = stats_reply(&hub, id, 5, session(), true);
+        let value: serde_json::Value = serde_json::from_str(json.strip_prefix("# stats ").unwrap()).unwrap();
+        assert_eq!(value["received"], 3);
+        assert_eq!(value["verified"], true);
+        assert_eq!(value["server"]["clients"], 1);
+    }
+
+    #[test]
+    fn test_invalid_lines() {
+        let cfg = InvalidLinesConfig { max_invalid: 3, window_secs: 60, log_lines: 2 };
+        let mut invalid = InvalidLines::default();
+        let start = Instant::now();
+        for i in 0..3 {
+            assert!(!invalid.record(&cfg, &format!("GET /{} HTTP/1.1", i), start));
+        }
+        // Older lines fall out of the window
+        let later = start + Duration::from_secs(61);
+        assert!(!invalid.record(&cfg, "garbage", later));
+        assert!(!invalid.record(&cfg, "garbage", later));
+        assert!(!invalid.record(&cfg, &"x".repeat(500), later));
+        assert!(invalid.record(&cfg, "garbage", later));
+        assert_eq!(invalid.recent.len(), 2);
+        assert_eq!(invalid.recent[0].len(), MAX_LOGGED_LINE);
+    }
+
+    #[test]
+    fn test_websocket_login_limit() {
+        let mut hub = Hub::new();
+        hub.login_limit = Some(crate::login_limit::LoginLimiter::new(crate::config::LoginLimitConfig { max_failures: 1, window_secs: 60, block_secs: 600 }));
+        let hub = Arc::new(hub);
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        let addr: SocketAddr = "192.0.2.1:1234".parse().unwrap();
+        run_session(&b"GET / HTTP/1.1\r\n"[..], ClientConn::WebSocket { tx: Some(tx), write_timeout: Duration::from_secs(1) }, Some(addr), None, hub.clone());
+        let limit = hub.login_limit.as_ref().unwrap();
+        assert_eq!(limit.stats(hub.clock.now()).invalid_logins, 1);
+        assert!(limit.is_blocked(addr.ip(), hub.clock.now()));
     }
 
     #[test]
-    fn test_extract_message_destination() {
-        assert_eq!(extract_message_destination("N0CALL>APRS,TCPIP*::DEST     :Hello"), Some("DEST".to_string()));
-        assert_eq!(extract_message_destination("N0CALL>APRS,TCPIP*::FOO      :Test msg"), Some("FOO".to_string()));
-        assert_eq!(extract_message_destination("N0CALL>APRS,TCPIP*:payload"), None);
-        assert_eq!(extract_message_destination("N0CALL>APRS,TCPIP*::   :No dest"), None);
+    fn test_filter_history() {
+        let mut hub = Hub::new();
+        hub.history_secs = 600;
+        for line in ["N0CALL>APRS:>one", "N1CALL>APRS:>two", "N0CALL-5>APRS:>three"] {
+            hub.notify_accept(line, &Arc::new(parser::parse(line).unwrap()));
+        }
+        let hub = Arc::new(hub);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(64);
+        let conn = ClientConn::WebSocket { tx: Some(tx), write_timeout: Duration::from_secs(1) };
+        let session = format!("user N0CALL pass {} filter p/N0\r\n# filter p/N1\r\n", aprs_passcode("N0CALL"));
+        run_session(session.as_bytes(), conn, None, None, hub.clone());
+        let mut out = Vec::new();
+        while let Some(line) = rx.blocking_recv() {
+            out.push(line);
+        }
+        // History is replayed for the first filter only
+        let history: Vec<&str> = out.iter().filter(|l| !l.starts_with('#')).map(String::as_str).collect();
+        assert_eq!(history, ["N0CALL>APRS:>one", "N0CALL-5>APRS:>three"]);
+        assert!(out.iter().any(|l| l == "# filter p/N1 active"));
     }
 
     #[test]
-    fn test_parse_aprs_lat_lon() {
-        let pkt = "N0CALL>APRS,TCPIP*:!4903.50N/07201.75W>Test";
-        let (lat, lon) = parse_aprs_lat_lon(pkt).unwrap();
-        assert!((lat - 49.0583).abs() < 0.01);
-        assert!((lon + 72.0291).abs() < 0.01);
+    fn test_packet_matches_filter() {
+        let filter = Some(vec!["foo".to_string(), "bar".to_string()]);
+        assert!(_packet_matches_filter("this is foo", &filter));
+        assert!(_packet_matches_filter("BAR test", &filter));
+        assert!(!_packet_matches_filter("baz", &filter));
+        assert!(_packet_matches_filter("anything", &None));
     }
 } 
\ No newline at end of file
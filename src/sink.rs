@@ -0,0 +1,58 @@
+//! The queue between a packet sink's hook and its writer task. Hooks run on
+//! the ingest path and must never wait for a database, so they push into a
+//! bounded queue and count what does not fit; the writer drains it at the
+//! pace of the store. The MQTT sink needs none of this, as its client
+//! brings its own queue.
+
+use std::future::Future;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+pub struct SinkQueue<T> {
+    tx: Sender<T>,
+}
+
+impl<T: Send + 'static> SinkQueue<T> {
+    /// A queue of `size` items, drained by the future `writer` builds from
+    /// its receiving end. The writer is spawned on the current Tokio runtime.
+    pub fn spawn<F, Fut>(size: usize, writer: F) -> Self
+    where
+        F: FnOnce(Receiver<T>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(size);
+        tokio::spawn(writer(rx));
+        SinkQueue { tx }
+    }
+
+    /// Queues an item without waiting. False if the queue is full or the
+    /// writer has stopped; the caller counts it as dropped.
+    pub fn push(&self, item: T) -> bool {
+        self.tx.try_send(item).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sink_queue() {
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+        let (go_tx, go_rx) = tokio::sync::oneshot::channel::<()>();
+        let queue = SinkQueue::spawn(2, |mut rx: Receiver<u32>| async move {
+            let _ = go_rx.await;
+            let mut got = Vec::new();
+            while let Some(n) = rx.recv().await {
+                got.push(n);
+            }
+            let _ = done_tx.send(got);
+        });
+        // The writer is held back, so the third item finds the queue full
+        assert!(queue.push(1));
+        assert!(queue.push(2));
+        assert!(!queue.push(3));
+        go_tx.send(()).unwrap();
+        drop(queue);
+        assert_eq!(done_rx.await.unwrap(), [1, 2]);
+    }
+}
@@ -0,0 +1,145 @@
+//! Counters, link totals and the heard list saved to a file so they survive
+//! restarts. The file is written periodically and on shutdown, and read back
+//! when the server starts.
+
+use crate::hub::{unix_time, HeardStation, Hub, S2SPeerStatus};
+use crate::stats::CounterTotals;
+use crate::uplink::UplinkStatus;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Traffic over the uplink or an S2S peer.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LinkTotals {
+    pub packets_rx: u64,
+    pub packets_tx: u64,
+    pub bytes_rx: u64,
+    pub bytes_tx: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SavedState {
+    pub saved_at: u64,
+    /// Unix time the counters have been running since
+    pub stats_since: u64,
+    pub counters: CounterTotals,
+    #[serde(default)]
+    pub uplink: LinkTotals,
+    /// S2S peers by `host:port`
+    #[serde(default)]
+    pub peers: BTreeMap<String, LinkTotals>,
+    #[serde(default)]
+    pub heard: Vec<HeardStation>,
+}
+
+impl SavedState {
+    pub fn capture(hub: &Hub, uplink: &Mutex<UplinkStatus>) -> Self {
+        let uplink = {
+            let u = uplink.lock().unwrap();
+            LinkTotals { packets_rx: u.packets_rx, packets_tx: u.packets_tx, bytes_rx: u.bytes_rx, bytes_tx: u.bytes_tx }
+        };
+        let peers = hub
+            .s2s_peers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|peer| {
+                let p = peer.lock().unwrap();
+                let totals = LinkTotals { packets_rx: p.packets_rx, packets_tx: p.packets_tx, bytes_rx: p.bytes_rx, bytes_tx: p.bytes_tx };
+                (format!("{}:{}", p.host, p.port), totals)
+            })
+            .collect();
+        SavedState {
+            saved_at: unix_time(),
+            stats_since: hub.stats_since,
+            counters: hub.counters.totals(),
+            uplink,
+            peers,
+            heard: hub.stations.read().unwrap().heard.values().cloned().collect(),
+        }
+    }
+
+    /// Puts the counters and heard list back into a hub that is not shared yet.
+    pub fn restore_hub(&self, hub: &mut Hub) {
+        hub.stats_since = self.stats_since;
+        hub.counters.restore(&self.counters);
+        hub.stats_history.get_mut().unwrap().rebase(&hub.counters);
+        let heard = &mut hub.stations.get_mut().unwrap().heard;
+        heard.extend(self.heard.iter().map(|h| (h.callsign.clone(), h.clone())));
+    }
+
+    pub fn restore_uplink(&self, u: &mut UplinkStatus) {
+        (u.packets_rx, u.packets_tx, u.bytes_rx, u.bytes_tx) =
+            (self.uplink.packets_rx, self.uplink.packets_tx, self.uplink.bytes_rx, self.uplink.bytes_tx);
+    }
+
+    pub fn restore_peer(&self, p: &mut S2SPeerStatus) {
+        if let Some(t) = self.peers.get(&format!("{}:{}", p.host, p.port)) {
+            (p.packets_rx, p.packets_tx, p.bytes_rx, p.bytes_tx) = (t.packets_rx, t.packets_tx, t.bytes_rx, t.bytes_tx);
+        }
+    }
+}
+
+/// Reads the state file, `None` if there is none yet.
+pub fn load(path: &str) -> Result<Option<SavedState>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).map(Some).map_err(|e| format!("{}: {}", path, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {}", path, e)),
+    }
+}
+
+/// Writes the state to a temporary file next to `path` and moves it into
+/// place, so a crash while saving leaves the previous state intact.
+pub fn save(path: &str, state: &SavedState) -> Result<(), String> {
+    let content = serde_json::to_string(state).map_err(|e| e.to_string())?;
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, content).map_err(|e| format!("{}: {}", tmp, e))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UplinkConfig;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_save_and_restore() {
+        let path = std::env::temp_dir().join(format!("aprsserver-state-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let cfg = UplinkConfig { host: "rotate.aprs2.net".to_string(), port: 10152, callsign: "N0CALL".to_string(), passcode: 0, ..Default::default() };
+        let hub = Hub::new();
+        hub.counters.add_rx(50);
+        hub.counters.accepted.fetch_add(1, Ordering::Relaxed);
+        hub.record_heard("N0CALL-9", "uplink");
+        hub.s2s_peers.write().unwrap().push(std::sync::Arc::new(Mutex::new(S2SPeerStatus::new("peer".to_string(), 10152, None))));
+        hub.s2s_peers.read().unwrap()[0].lock().unwrap().packets_rx = 7;
+        let uplink = Mutex::new(UplinkStatus::new(&cfg));
+        uplink.lock().unwrap().bytes_tx = 300;
+
+        assert_eq!(load(path), Ok(None));
+        let saved = SavedState::capture(&hub, &uplink);
+        save(path, &saved).unwrap();
+        let loaded = load(path).unwrap().unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded, saved);
+
+        let mut restored = Hub::new();
+        loaded.restore_hub(&mut restored);
+        assert_eq!(restored.stats_since, hub.stats_since);
+        assert_eq!(restored.counters.totals(), hub.counters.totals());
+        assert!(restored.stations.read().unwrap().heard.contains_key("N0CALL-9"));
+        // Restored totals are not reported as new activity
+        restored.sample_stats();
+        assert_eq!(restored.stats_history.lock().unwrap().samples[0].packets_rx, 0);
+
+        let mut u = UplinkStatus::new(&cfg);
+        loaded.restore_uplink(&mut u);
+        assert_eq!(u.bytes_tx, 300);
+        let mut p = S2SPeerStatus::new("peer".to_string(), 10152, None);
+        loaded.restore_peer(&mut p);
+        assert_eq!(p.packets_rx, 7);
+    }
+}
@@ -0,0 +1,68 @@
+//! Bounds on the per-station maps, which grow with every callsign heard
+//! and would otherwise reach hundreds of thousands of entries on a full
+//! feed. Each map is held to `max_entries` by evicting the entries least
+//! recently updated, and entries older than `max_age_secs` are dropped
+//! when the maps are pruned.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Occupancy of the station maps, for `/status.json`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub positions: usize,
+    pub heard: usize,
+    pub weather: usize,
+    pub telemetry: usize,
+    pub objects: usize,
+    pub talkers: usize,
+    /// The configured limit per map, if any
+    pub max_entries: Option<usize>,
+    /// Entries evicted to stay within `max_entries`
+    pub evicted: u64,
+    /// Entries dropped for being older than `max_age_secs`
+    pub expired: u64,
+}
+
+/// Evicts the least recently updated entries once `map` holds more than
+/// `limit`. A tenth more than needed goes at once, so the sort is not
+/// repeated for every new station. Returns how many were evicted.
+pub fn evict_oldest<V>(map: &mut HashMap<String, V>, limit: usize, updated: impl Fn(&V) -> u64) -> usize {
+    if map.len() <= limit {
+        return 0;
+    }
+    let evict = map.len() - (limit - limit / 10);
+    let mut oldest: Vec<(u64, &String)> = map.iter().map(|(k, v)| (updated(v), k)).collect();
+    oldest.select_nth_unstable(evict - 1);
+    let keys: Vec<String> = oldest[..evict].iter().map(|(_, k)| (*k).clone()).collect();
+    for key in &keys {
+        map.remove(key);
+    }
+    evict
+}
+
+/// Drops entries last updated before `cutoff`. Returns how many went.
+pub fn expire<V>(map: &mut HashMap<String, V>, cutoff: u64, updated: impl Fn(&V) -> u64) -> usize {
+    let before = map.len();
+    map.retain(|_, v| updated(v) >= cutoff);
+    before - map.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evict_oldest() {
+        let mut map: HashMap<String, u64> = (0..25).map(|i| (format!("N{}CALL", i), i)).collect();
+        assert_eq!(evict_oldest(&mut map, 30, |t| *t), 0);
+        assert_eq!(evict_oldest(&mut map, 20, |t| *t), 7);
+        assert_eq!(map.len(), 18);
+        // The newest ones are kept
+        assert!(map.contains_key("N24CALL") && map.contains_key("N7CALL"));
+        assert!(!map.contains_key("N6CALL"));
+
+        assert_eq!(expire(&mut map, 20, |t| *t), 13);
+        assert_eq!(map.len(), 5);
+    }
+}
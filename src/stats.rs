@@ -0,0 +1,444 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// One sample per minute for 24 hours
+pub const STATS_INTERVAL_SECS: u64 = 60;
+const STATS_HISTORY: usize = 24 * 60;
+
+// How often the rolling rate averages are updated
+pub const RATE_INTERVAL_SECS: u64 = 5;
+const RATE_WINDOWS_SECS: [f64; 3] = [60.0, 300.0, 900.0];
+
+/// Exponentially weighted 1, 5 and 15 minute averages of a packet and byte
+/// counter, in units per second, like the load average.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateMeter {
+    pub packets: [f64; 3],
+    pub bytes: [f64; 3],
+    #[serde(skip)]
+    last: Option<(u64, u64)>,
+}
+
+impl RateMeter {
+    /// Feeds the current counter totals, `interval` seconds after the previous call.
+    pub fn update(&mut self, packets: u64, bytes: u64, interval: f64) {
+        if let Some((last_packets, last_bytes)) = self.last {
+            let packet_rate = packets.saturating_sub(last_packets) as f64 / interval;
+            let byte_rate = bytes.saturating_sub(last_bytes) as f64 / interval;
+            for (i, window) in RATE_WINDOWS_SECS.iter().enumerate() {
+                let alpha = 1.0 - (-interval / window).exp();
+                self.packets[i] += alpha * (packet_rate - self.packets[i]);
+                self.bytes[i] += alpha * (byte_rate - self.bytes[i]);
+            }
+        }
+        self.last = Some((packets, bytes));
+    }
+
+    /// Packet rates formatted as `1min / 5min / 15min`.
+    pub fn summary(&self) -> String {
+        format!("{:.2} / {:.2} / {:.2}", self.packets[0], self.packets[1], self.packets[2])
+    }
+}
+
+/// Received and transmitted rate averages of one connection or the server.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrafficRates {
+    pub rx: RateMeter,
+    pub tx: RateMeter,
+}
+
+impl TrafficRates {
+    pub fn update(&mut self, packets_rx: u64, bytes_rx: u64, packets_tx: u64, bytes_tx: u64) {
+        let interval = RATE_INTERVAL_SECS as f64;
+        self.rx.update(packets_rx, bytes_rx, interval);
+        self.tx.update(packets_tx, bytes_tx, interval);
+    }
+}
+
+/// Server-wide packet counters since startup. Unlike the totals summed over
+/// connected clients these never go down, so rates can be derived from them.
+#[derive(Debug, Default)]
+pub struct Counters {
+    pub packets_rx: AtomicU64,
+    pub bytes_rx: AtomicU64,
+    pub packets_tx: AtomicU64,
+    pub bytes_tx: AtomicU64,
+    pub accepted: AtomicU64,
+    pub duplicates: AtomicU64,
+    /// All drops other than duplicates, broken down below
+    pub dropped: AtomicU64,
+    pub invalid: AtomicU64,
+    /// Deliveries to clients held back by their filters
+    pub filtered: AtomicU64,
+    pub hook_drops: AtomicU64,
+    pub loops: AtomicU64,
+    pub policy_drops: AtomicU64,
+    pub bad_positions: AtomicU64,
+    pub tcpxx_drops: AtomicU64,
+    pub nogate_drops: AtomicU64,
+    pub rfonly_drops: AtomicU64,
+}
+
+impl Counters {
+    pub fn add_rx(&self, bytes: usize) {
+        self.packets_rx.fetch_add(1, Ordering::Relaxed);
+        self.bytes_rx.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    pub fn add_tx(&self, bytes: usize) {
+        self.packets_tx.fetch_add(1, Ordering::Relaxed);
+        self.bytes_tx.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    pub fn drop_stats(&self) -> DropStats {
+        DropStats {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            invalid: self.invalid.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            hook: self.hook_drops.load(Ordering::Relaxed),
+            loops: self.loops.load(Ordering::Relaxed),
+            policy: self.policy_drops.load(Ordering::Relaxed),
+            bad_position: self.bad_positions.load(Ordering::Relaxed),
+            tcpxx: self.tcpxx_drops.load(Ordering::Relaxed),
+            nogate: self.nogate_drops.load(Ordering::Relaxed),
+            rfonly: self.rfonly_drops.load(Ordering::Relaxed),
+        }
+    }
+    pub fn totals(&self) -> CounterTotals {
+        CounterTotals {
+            packets_rx: self.packets_rx.load(Ordering::Relaxed),
+            bytes_rx: self.bytes_rx.load(Ordering::Relaxed),
+            packets_tx: self.packets_tx.load(Ordering::Relaxed),
+            bytes_tx: self.bytes_tx.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            decisions: self.drop_stats(),
+        }
+    }
+    /// Sets the counters to totals saved by an earlier run.
+    pub fn restore(&self, t: &CounterTotals) {
+        self.packets_rx.store(t.packets_rx, Ordering::Relaxed);
+        self.bytes_rx.store(t.bytes_rx, Ordering::Relaxed);
+        self.packets_tx.store(t.packets_tx, Ordering::Relaxed);
+        self.bytes_tx.store(t.bytes_tx, Ordering::Relaxed);
+        self.dropped.store(t.dropped, Ordering::Relaxed);
+        let d = &t.decisions;
+        self.accepted.store(d.accepted, Ordering::Relaxed);
+        self.duplicates.store(d.duplicates, Ordering::Relaxed);
+        self.invalid.store(d.invalid, Ordering::Relaxed);
+        self.filtered.store(d.filtered, Ordering::Relaxed);
+        self.hook_drops.store(d.hook, Ordering::Relaxed);
+        self.loops.store(d.loops, Ordering::Relaxed);
+        self.policy_drops.store(d.policy, Ordering::Relaxed);
+        self.bad_positions.store(d.bad_position, Ordering::Relaxed);
+        self.tcpxx_drops.store(d.tcpxx, Ordering::Relaxed);
+        self.nogate_drops.store(d.nogate, Ordering::Relaxed);
+        self.rfonly_drops.store(d.rfonly, Ordering::Relaxed);
+    }
+    fn snapshot(&self) -> [u64; 7] {
+        [
+            self.packets_rx.load(Ordering::Relaxed),
+            self.bytes_rx.load(Ordering::Relaxed),
+            self.packets_tx.load(Ordering::Relaxed),
+            self.bytes_tx.load(Ordering::Relaxed),
+            self.accepted.load(Ordering::Relaxed),
+            self.duplicates.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+        ]
+    }
+}
+
+/// Plain copy of [`Counters`], as kept in the state file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CounterTotals {
+    pub packets_rx: u64,
+    pub bytes_rx: u64,
+    pub packets_tx: u64,
+    pub bytes_tx: u64,
+    pub dropped: u64,
+    pub decisions: DropStats,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "TCP",
+            Protocol::Udp => "UDP",
+        }
+    }
+}
+
+/// Traffic through one listening port, like aprsc's listener table.
+/// Packets are APRS lines; comment lines such as keepalives only count
+/// as bytes.
+#[derive(Debug)]
+pub struct ListenerStats {
+    /// `User`, `Server`, `S2S` or `UDP`
+    pub name: &'static str,
+    pub protocol: Protocol,
+    pub port: u16,
+    /// Connections accepted since startup
+    pub connects: AtomicU64,
+    /// Connections open now
+    pub connections: AtomicU64,
+    pub packets_rx: AtomicU64,
+    pub bytes_rx: AtomicU64,
+    pub packets_tx: AtomicU64,
+    pub bytes_tx: AtomicU64,
+}
+
+impl ListenerStats {
+    pub fn new(name: &'static str, protocol: Protocol, port: u16) -> Self {
+        ListenerStats {
+            name,
+            protocol,
+            port,
+            connects: AtomicU64::new(0),
+            connections: AtomicU64::new(0),
+            packets_rx: AtomicU64::new(0),
+            bytes_rx: AtomicU64::new(0),
+            packets_tx: AtomicU64::new(0),
+            bytes_tx: AtomicU64::new(0),
+        }
+    }
+
+    pub fn connected(&self) {
+        self.connects.fetch_add(1, Ordering::Relaxed);
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn disconnected(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn add_rx(&self, packets: u64, bytes: usize) {
+        self.packets_rx.fetch_add(packets, Ordering::Relaxed);
+        self.bytes_rx.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_tx(&self, packets: u64, bytes: usize) {
+        self.packets_tx.fetch_add(packets, Ordering::Relaxed);
+        self.bytes_tx.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn totals(&self) -> ListenerTotals {
+        ListenerTotals {
+            name: self.name.to_string(),
+            protocol: self.protocol,
+            port: self.port,
+            connects: self.connects.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+            packets_rx: self.packets_rx.load(Ordering::Relaxed),
+            bytes_rx: self.bytes_rx.load(Ordering::Relaxed),
+            packets_tx: self.packets_tx.load(Ordering::Relaxed),
+            bytes_tx: self.bytes_tx.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Plain copy of [`ListenerStats`], for `/status.json`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ListenerTotals {
+    pub name: String,
+    pub protocol: Protocol,
+    pub port: u16,
+    pub connects: u64,
+    pub connections: u64,
+    pub packets_rx: u64,
+    pub bytes_rx: u64,
+    pub packets_tx: u64,
+    pub bytes_tx: u64,
+}
+
+/// Packets and bytes in `lines`, not counting comment lines as packets.
+pub fn count_lines(lines: &str) -> (u64, usize) {
+    let packets = lines.lines().filter(|l| !l.is_empty() && !l.starts_with('#')).count();
+    (packets as u64, lines.len())
+}
+
+/// Occupancy and effectiveness of the duplicate cache, for spotting
+/// packets looping between peers.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DupeStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub shards: usize,
+    pub window_secs: Option<u64>,
+    /// Message ids remembered within the message window
+    pub message_ids: usize,
+    pub lookups: u64,
+    pub hits: u64,
+    /// `hits / lookups`, 0 before the first lookup
+    pub hit_rate: f64,
+    /// Sources sending the most duplicates, most first
+    pub top_sources: Vec<(String, u64)>,
+    /// Packets dropped because their ingest shard's queue was full
+    #[serde(default)]
+    pub ingest_dropped: u64,
+}
+
+/// Packet decisions since startup.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DropStats {
+    pub accepted: u64,
+    pub duplicates: u64,
+    pub invalid: u64,
+    pub filtered: u64,
+    pub hook: u64,
+    pub loops: u64,
+    #[serde(default)]
+    pub policy: u64,
+    #[serde(default)]
+    pub bad_position: u64,
+    #[serde(default)]
+    pub tcpxx: u64,
+    #[serde(default)]
+    pub nogate: u64,
+    #[serde(default)]
+    pub rfonly: u64,
+}
+
+/// Accepted traffic originated by one callsign, across all connections.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SourceStats {
+    pub callsign: String,
+    pub packets: u64,
+    pub bytes: u64,
+    /// Packet counts by type name
+    pub types: BTreeMap<&'static str, u64>,
+    pub last_heard: u64,
+}
+
+impl SourceStats {
+    pub fn add(&mut self, bytes: usize, packet_type: &'static str, time: u64) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+        *self.types.entry(packet_type).or_default() += 1;
+        self.last_heard = time;
+    }
+}
+
+/// Accepted traffic by originating software, as told by the TOCALL.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SoftwareStats {
+    pub software: String,
+    pub packets: u64,
+    pub bytes: u64,
+    /// Distinct source callsigns
+    pub stations: usize,
+    #[serde(skip)]
+    callsigns: HashSet<String>,
+}
+
+impl SoftwareStats {
+    pub fn new(software: &str) -> Self {
+        SoftwareStats { software: software.to_string(), ..Default::default() }
+    }
+    pub fn add(&mut self, bytes: usize, callsign: &str) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+        if !self.callsigns.contains(callsign) {
+            self.callsigns.insert(callsign.to_string());
+            self.stations = self.callsigns.len();
+        }
+    }
+}
+
+/// Activity during one sampling interval, ending at `time`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StatsSample {
+    pub time: u64,
+    pub clients: usize,
+    pub packets_rx: u64,
+    pub bytes_rx: u64,
+    pub packets_tx: u64,
+    pub bytes_tx: u64,
+    pub accepted: u64,
+    pub duplicates: u64,
+    pub dropped: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct StatsHistory {
+    pub samples: VecDeque<StatsSample>,
+    last: [u64; 7],
+}
+
+impl StatsHistory {
+    /// Starts measuring from the current counter values, e.g. after they
+    /// were restored, so the next sample does not count them as activity.
+    pub fn rebase(&mut self, counters: &Counters) {
+        self.last = counters.snapshot();
+    }
+    /// Records the counter increase since the previous call as a new sample.
+    pub fn record(&mut self, time: u64, counters: &Counters, clients: usize) {
+        let now = counters.snapshot();
+        let d: Vec<u64> = now.iter().zip(self.last.iter()).map(|(n, l)| n.saturating_sub(*l)).collect();
+        self.last = now;
+        self.samples.push_back(StatsSample {
+            time,
+            clients,
+            packets_rx: d[0],
+            bytes_rx: d[1],
+            packets_tx: d[2],
+            bytes_tx: d[3],
+            accepted: d[4],
+            duplicates: d[5],
+            dropped: d[6],
+        });
+        if self.samples.len() > STATS_HISTORY {
+            self.samples.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_meter() {
+        let mut meter = RateMeter::default();
+        meter.update(0, 0, 5.0);
+        assert_eq!(meter.packets, [0.0; 3]);
+        // A steady 10 packets/s converges on 10, the 1 minute average fastest
+        for i in 1..=12 {
+            meter.update(i * 50, i * 5000, 5.0);
+        }
+        assert!(meter.packets[0] > meter.packets[1] && meter.packets[1] > meter.packets[2]);
+        assert!((meter.packets[0] - 10.0).abs() < 4.0);
+        assert!((meter.bytes[0] / meter.packets[0] - 100.0).abs() < 1e-6);
+        for i in 13..=1000 {
+            meter.update(i * 50, i * 5000, 5.0);
+        }
+        assert!((meter.packets[2] - 10.0).abs() < 0.1);
+    }
+    #[test]
+    fn test_history_deltas() {
+        let counters = Counters::default();
+        let mut history = StatsHistory::default();
+        counters.add_rx(100);
+        counters.add_rx(50);
+        history.record(60, &counters, 2);
+        counters.add_rx(10);
+        counters.duplicates.fetch_add(1, Ordering::Relaxed);
+        history.record(120, &counters, 3);
+        assert_eq!(history.samples.len(), 2);
+        assert_eq!(history.samples[0].packets_rx, 2);
+        assert_eq!(history.samples[0].bytes_rx, 150);
+        assert_eq!(history.samples[1].packets_rx, 1);
+        assert_eq!(history.samples[1].duplicates, 1);
+        assert_eq!(history.samples[1].clients, 3);
+        for t in 0..STATS_HISTORY {
+            history.record(t as u64, &counters, 0);
+        }
+        assert_eq!(history.samples.len(), STATS_HISTORY);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::clock::Clock;
+use crate::hook::PacketHook;
+use crate::hub::StationPosition;
+use crate::parser::ParsedPacket;
+use rusqlite::{params, Connection};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use tracing::error;
+
+// Position updates buffered for the writer thread before new ones are dropped
+const STORE_QUEUE_SIZE: usize = 10000;
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS stations (
+    callsign TEXT PRIMARY KEY,
+    lat REAL NOT NULL,
+    lon REAL NOT NULL,
+    symbol TEXT NOT NULL,
+    comment TEXT NOT NULL,
+    destination TEXT NOT NULL,
+    updated INTEGER NOT NULL
+)";
+
+/// SQLite file holding the last known position of each station, so the
+/// position cache survives restarts.
+pub struct StationStore {
+    conn: Connection,
+}
+
+impl StationStore {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let conn = Connection::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        conn.execute_batch(CREATE_TABLE).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(StationStore { conn })
+    }
+
+    pub fn load_positions(&self) -> Result<Vec<StationPosition>, String> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT callsign, lat, lon, symbol, comment, destination, updated FROM stations")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(StationPosition {
+                    callsign: row.get(0)?,
+                    lat: row.get(1)?,
+                    lon: row.get(2)?,
+                    symbol: row.get(3)?,
+                    comment: row.get(4)?,
+                    destination: row.get(5)?,
+                    updated: row.get::<_, i64>(6)? as u64,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    pub fn save_positions(&mut self, positions: &[StationPosition]) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        {
+            let mut stmt = tx
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO stations (callsign, lat, lon, symbol, comment, destination, updated)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )
+                .map_err(|e| e.to_string())?;
+            for p in positions {
+                stmt.execute(params![p.callsign, p.lat, p.lon, p.symbol, p.comment, p.destination, p.updated as i64])
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    /// Moves the store to a writer thread and returns a hook feeding it
+    /// position updates, timestamped by `clock`.
+    pub fn into_sink(self, clock: Arc<dyn Clock>) -> StationStoreSink {
+        let (tx, rx) = mpsc::sync_channel(STORE_QUEUE_SIZE);
+        std::thread::spawn(move || run_writer(self, rx));
+        StationStoreSink { queue: tx, clock }
+    }
+}
+
+/// Packet hook persisting station positions. Writes happen on a separate
+/// thread; updates are dropped if it falls behind.
+pub struct StationStoreSink {
+    queue: SyncSender<StationPosition>,
+    clock: Arc<dyn Clock>,
+}
+
+impl PacketHook for StationStoreSink {
+    fn on_accept(&self, _line: &str, packet: &ParsedPacket) {
+        if let Some(pos) = StationPosition::from_packet(packet, self.clock.unix_time()) {
+            let _ = self.queue.try_send(pos);
+        }
+    }
+}
+
+fn run_writer(mut store: StationStore, rx: Receiver<StationPosition>) {
+    while let Ok(first) = rx.recv() {
+        // Write whatever has queued up in one transaction
+        let mut batch = vec![first];
+        batch.extend(rx.try_iter());
+        if let Err(e) = store.save_positions(&batch) {
+            error!(error = %e, "failed to save station positions");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_roundtrip() {
+        let path = "test_stations.db";
+        let _ = std::fs::remove_file(path);
+        let mut store = StationStore::open(path).unwrap();
+        let pos = StationPosition {
+            callsign: "N0CALL".to_string(),
+            lat: 49.0,
+            lon: -72.0,
+            symbol: "/>".to_string(),
+            comment: "test".to_string(),
+            destination: "APRS".to_string(),
+            updated: 1000,
+        };
+        store.save_positions(std::slice::from_ref(&pos)).unwrap();
+        store.save_positions(&[StationPosition { updated: 2000, ..pos.clone() }]).unwrap();
+        drop(store);
+        let store = StationStore::open(path).unwrap();
+        assert_eq!(store.load_positions().unwrap(), vec![StationPosition { updated: 2000, ..pos }]);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,186 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+
+// Samples kept per station
+const TELEMETRY_HISTORY: usize = 100;
+const ANALOG_CHANNELS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TelemetryFrame {
+    pub sequence: String,
+    pub analog: Vec<f64>,
+    pub digital: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelemetryDefinition {
+    Parm(Vec<String>),
+    Unit(Vec<String>),
+    Eqns(Vec<f64>),
+    Bits { sense: String, title: String },
+}
+
+/// Parses a `T#` frame body (`005,199,000,255,073,123,01101001`), after the `T#`.
+pub fn parse_frame(data: &str) -> Option<TelemetryFrame> {
+    let mut fields = data.split(',');
+    let sequence = fields.next()?.trim().to_string();
+    if sequence.is_empty() {
+        return None;
+    }
+    let mut analog = Vec::new();
+    let mut digital = String::new();
+    for field in fields {
+        if analog.len() < ANALOG_CHANNELS {
+            analog.push(field.trim().parse().ok()?);
+        } else {
+            digital = field.chars().take(8).take_while(|c| *c == '0' || *c == '1').collect();
+            break;
+        }
+    }
+    if analog.is_empty() {
+        return None;
+    }
+    Some(TelemetryFrame { sequence, analog, digital })
+}
+
+/// Parses the text of a PARM/UNIT/EQNS/BITS message. The message addressee
+/// is the station the definition applies to.
+pub fn parse_definition(text: &str) -> Option<TelemetryDefinition> {
+    let list = |s: &str| s.split(',').map(|f| f.trim().to_string()).collect::<Vec<_>>();
+    if let Some(rest) = text.strip_prefix("PARM.") {
+        Some(TelemetryDefinition::Parm(list(rest)))
+    } else if let Some(rest) = text.strip_prefix("UNIT.") {
+        Some(TelemetryDefinition::Unit(list(rest)))
+    } else if let Some(rest) = text.strip_prefix("EQNS.") {
+        let coefficients: Option<Vec<f64>> = rest.split(',').map(|f| f.trim().parse().ok()).collect();
+        Some(TelemetryDefinition::Eqns(coefficients?))
+    } else if let Some(rest) = text.strip_prefix("BITS.") {
+        let (sense, title) = rest.split_once(',').unwrap_or((rest, ""));
+        Some(TelemetryDefinition::Bits { sense: sense.trim().to_string(), title: title.trim().to_string() })
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TelemetrySample {
+    pub received: u64,
+    pub sequence: String,
+    pub analog: Vec<f64>,
+    pub digital: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StationTelemetry {
+    pub names: Vec<String>,
+    pub units: Vec<String>,
+    pub eqns: Vec<f64>,
+    pub bits_sense: String,
+    pub title: String,
+    pub history: VecDeque<TelemetrySample>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TelemetryChannel {
+    pub name: Option<String>,
+    pub unit: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TelemetryReport {
+    pub callsign: String,
+    pub title: String,
+    pub channels: Vec<TelemetryChannel>,
+    pub bits_sense: String,
+    pub samples: Vec<TelemetrySample>,
+}
+
+impl StationTelemetry {
+    pub fn add_frame(&mut self, frame: TelemetryFrame, received: u64) {
+        self.history.push_back(TelemetrySample {
+            received,
+            sequence: frame.sequence,
+            analog: frame.analog,
+            digital: frame.digital,
+        });
+        if self.history.len() > TELEMETRY_HISTORY {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn apply_definition(&mut self, def: TelemetryDefinition) {
+        match def {
+            TelemetryDefinition::Parm(names) => self.names = names,
+            TelemetryDefinition::Unit(units) => self.units = units,
+            TelemetryDefinition::Eqns(eqns) => self.eqns = eqns,
+            TelemetryDefinition::Bits { sense, title } => {
+                self.bits_sense = sense;
+                self.title = title;
+            }
+        }
+    }
+
+    /// Applies the station's equations (`a*x^2 + b*x + c`) to a raw channel value.
+    pub fn scale(&self, channel: usize, raw: f64) -> f64 {
+        let coeff = |i: usize, default: f64| self.eqns.get(channel * 3 + i).copied().unwrap_or(default);
+        coeff(0, 0.0) * raw * raw + coeff(1, 1.0) * raw + coeff(2, 0.0)
+    }
+
+    pub fn report(&self, callsign: &str) -> TelemetryReport {
+        let channels = (0..ANALOG_CHANNELS)
+            .map(|i| TelemetryChannel {
+                name: self.names.get(i).cloned(),
+                unit: self.units.get(i).cloned(),
+            })
+            .collect();
+        let samples = self
+            .history
+            .iter()
+            .map(|s| TelemetrySample {
+                analog: s.analog.iter().enumerate().map(|(i, v)| self.scale(i, *v)).collect(),
+                ..s.clone()
+            })
+            .collect();
+        TelemetryReport {
+            callsign: callsign.to_string(),
+            title: self.title.clone(),
+            channels,
+            bits_sense: self.bits_sense.clone(),
+            samples,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_frame() {
+        let f = parse_frame("005,199,000,255,073,123,01101001").unwrap();
+        assert_eq!(f.sequence, "005");
+        assert_eq!(f.analog, vec![199.0, 0.0, 255.0, 73.0, 123.0]);
+        assert_eq!(f.digital, "01101001");
+        let f = parse_frame("MIC,12.5,3").unwrap();
+        assert_eq!(f.analog, vec![12.5, 3.0]);
+        assert!(parse_frame("005,abc").is_none());
+        assert!(parse_frame("").is_none());
+    }
+
+    #[test]
+    fn test_definitions_and_scaling() {
+        let mut station = StationTelemetry::default();
+        station.apply_definition(parse_definition("PARM.Battery,Temp").unwrap());
+        station.apply_definition(parse_definition("UNIT.Volts,deg.F").unwrap());
+        station.apply_definition(parse_definition("EQNS.0,0.1,0,0,1,-40").unwrap());
+        station.apply_definition(parse_definition("BITS.11111111,Weather station").unwrap());
+        station.add_frame(parse_frame("001,138,100").unwrap(), 0);
+        let report = station.report("N0CALL");
+        assert_eq!(report.title, "Weather station");
+        assert_eq!(report.channels[0].name.as_deref(), Some("Battery"));
+        assert_eq!(report.channels[1].unit.as_deref(), Some("deg.F"));
+        assert!((report.samples[0].analog[0] - 13.8).abs() < 1e-9);
+        assert_eq!(report.samples[0].analog[1], 60.0);
+        assert!(parse_definition("hello").is_none());
+    }
+}
@@ -0,0 +1,207 @@
+//! In-process test harness. [`TestServer`] runs the full server on
+//! ephemeral localhost ports with an in-memory config, so end-to-end tests
+//! need neither fixed ports nor config files:
+//!
+//! ```no_run
+//! use aprsserver::testing::TestServer;
+//!
+//! let server = TestServer::start().unwrap();
+//! let mut rx = server.connect_client("N0CALL", Some("p/OH")).unwrap();
+//! let mut tx = server.connect_client("OH2XYZ", None).unwrap();
+//! tx.send("OH2XYZ>APRS,TCPIP*:>hello").unwrap();
+//! assert_eq!(rx.read_packet().unwrap(), "OH2XYZ>APRS,TCPIP*:>hello");
+//! ```
+
+use crate::config::{Config, UplinkConfig};
+use crate::hub::Hub;
+use crate::server::aprs_passcode;
+use crate::{Server, ServerHandle};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// How long reads wait before failing a test instead of hanging it
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A running server with its own Tokio runtime, so it can be used from
+/// plain and async tests alike. Dropping it stops the runtime; the accept
+/// threads stay behind until the test process exits.
+pub struct TestServer {
+    pub handle: ServerHandle,
+    uplink: Option<TcpListener>,
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl TestServer {
+    /// Starts a server with the default config.
+    pub fn start() -> io::Result<Self> {
+        Self::with_config(Config::default())
+    }
+
+    /// Starts a server with `config`. Its listening ports, the web UI and
+    /// the console if set, are replaced by ephemeral ones on 127.0.0.1.
+    pub fn with_config(config: Config) -> io::Result<Self> {
+        Self::spawn(config, None)
+    }
+
+    /// Like [`with_config`](Self::with_config), with the uplink pointed at
+    /// a fake uplink the test accepts with [`accept_uplink`](Self::accept_uplink).
+    pub fn with_uplink(mut config: Config) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let callsign = config.uplink.as_ref().map(|u| u.callsign.clone()).unwrap_or_else(|| "N0CALL".to_string());
+        config.uplink = Some(UplinkConfig {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr()?.port(),
+            passcode: aprs_passcode(&callsign),
+            callsign,
+            ..Default::default()
+        });
+        Self::spawn(config, Some(listener))
+    }
+
+    fn spawn(mut config: Config, uplink: Option<TcpListener>) -> io::Result<Self> {
+        config.user_port = 0;
+        config.server_port = 0;
+        config.s2s_port = Some(0);
+        config.udp_peer_port = Some(0);
+        config.console_port = config.console_port.map(|_| 0);
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(2).enable_all().build()?;
+        let handle = {
+            let _guard = runtime.enter();
+            Server::builder().config(config).bind_host("127.0.0.1").web_addr(Some("127.0.0.1:0")).spawn()?
+        };
+        Ok(TestServer { handle, uplink, runtime: Some(runtime) })
+    }
+
+    pub fn hub(&self) -> &Arc<Hub> {
+        &self.handle.hub
+    }
+
+    /// URL of `path` on the web UI, e.g. `web_url("/status.json")`.
+    pub fn web_url(&self, path: &str) -> String {
+        format!("http://{}{}", self.handle.web_addr.expect("web UI is always started"), path)
+    }
+
+    /// Connects to the user port and logs in as `callsign` with a valid
+    /// passcode and optional filter.
+    pub fn connect_client(&self, callsign: &str, filter: Option<&str>) -> io::Result<TestConnection> {
+        let mut conn = TestConnection::new(TcpStream::connect(self.handle.user_addr)?)?;
+        let mut login = format!("user {} pass {} vers TestClient 1.0", callsign, aprs_passcode(callsign));
+        if let Some(filter) = filter {
+            login.push_str(&format!(" filter {}", filter));
+        }
+        conn.send(&login)?;
+        conn.read_until(|line| line.starts_with("# log"))?;
+        Ok(conn)
+    }
+
+    /// Connects to the user port without logging in.
+    pub fn connect_raw(&self) -> io::Result<TestConnection> {
+        TestConnection::new(TcpStream::connect(self.handle.user_addr)?)
+    }
+
+    /// Accepts the server's uplink connection, reads its login and answers
+    /// it as a verified login. Only for servers made with
+    /// [`with_uplink`](Self::with_uplink).
+    pub fn accept_uplink(&self) -> io::Result<TestConnection> {
+        let listener = self.uplink.as_ref().ok_or_else(|| io::Error::other("no fake uplink configured"))?;
+        listener.set_nonblocking(true)?;
+        let deadline = Instant::now() + READ_TIMEOUT;
+        let stream = loop {
+            match listener.accept() {
+                Ok((stream, _)) => break stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        stream.set_nonblocking(false)?;
+        let mut conn = TestConnection::new(stream)?;
+        let login = conn.read_until(|line| line.starts_with("user "))?;
+        let callsign = login.split_whitespace().nth(1).unwrap_or("N0CALL").to_string();
+        conn.send(&format!("# logresp {} verified, server TESTUPLINK", callsign))?;
+        Ok(conn)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// One end of an APRS-IS connection: a client logged in to the test
+/// server, or the fake uplink the server logged in to.
+pub struct TestConnection {
+    reader: BufReader<TcpStream>,
+}
+
+impl TestConnection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        Ok(TestConnection { reader: BufReader::new(stream) })
+    }
+
+    /// Sends a line, adding the CRLF terminator.
+    pub fn send(&mut self, line: &str) -> io::Result<()> {
+        self.reader.get_mut().write_all(format!("{}\r\n", line).as_bytes())
+    }
+
+    /// Reads the next line without its terminator. Fails after a few
+    /// seconds without one, or when the connection is closed.
+    pub fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Reads lines until one matches, returning it.
+    pub fn read_until(&mut self, matches: impl Fn(&str) -> bool) -> io::Result<String> {
+        loop {
+            let line = self.read_line()?;
+            if matches(&line) {
+                return Ok(line);
+            }
+        }
+    }
+
+    /// Reads the next packet, skipping comment lines such as keepalives.
+    pub fn read_packet(&mut self) -> io::Result<String> {
+        self.read_until(|line| !line.starts_with('#'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clients_exchange_packets() {
+        let server = TestServer::start().unwrap();
+        let mut rx = server.connect_client("N0CALL", Some("p/OH")).unwrap();
+        let mut tx = server.connect_client("OH2XYZ", None).unwrap();
+        assert_eq!(server.hub().client_count(), 2);
+        tx.send("N1CALL>APRS,TCPIP*:>filtered out").unwrap();
+        tx.send("OH2XYZ>APRS,TCPIP*:>hello").unwrap();
+        assert_eq!(rx.read_packet().unwrap(), format!("OH2XYZ>APRS,TCPIP*,qAC,{}:>hello", server.hub().server_name));
+
+        let user = server.hub().listeners[0].totals();
+        assert_eq!((user.name.as_str(), user.connects, user.connections, user.packets_rx), ("User", 2, 2, 2));
+        assert_eq!(server.hub().listeners[1].totals().connects, 0);
+    }
+
+    #[test]
+    fn test_fake_uplink() {
+        let server = TestServer::with_uplink(Config::default()).unwrap();
+        let mut uplink = server.accept_uplink().unwrap();
+        let mut client = server.connect_client("N0CALL", None).unwrap();
+        uplink.send("OH2XYZ>APRS,TCPIP*,qAC,T2TEST:>from the uplink").unwrap();
+        assert_eq!(client.read_packet().unwrap(), "OH2XYZ>APRS,TCPIP*,qAC,T2TEST:>from the uplink");
+    }
+}
@@ -0,0 +1,134 @@
+//! Identifies the software that originated a packet from its destination
+//! callsign (TOCALL), e.g. `APDW16` for Dire Wolf. A few common TOCALLs are
+//! built in; the full list can be loaded from the standard `tocalls.txt`.
+
+use crate::parser::{PacketData, ParsedPacket};
+use std::fs;
+use std::path::Path;
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("APAGW", "AGWtracker"),
+    ("APBPQ", "BPQ32"),
+    ("APDR", "APRSdroid"),
+    ("APDW", "Dire Wolf"),
+    ("APJI", "jAPRSIgate"),
+    ("APJS", "javAPRSSrvr"),
+    ("APK0", "Kenwood TH-D7"),
+    ("APK1", "Kenwood TM-D700"),
+    ("APLRG", "LoRa APRS iGate"),
+    ("APLRT", "LoRa APRS Tracker"),
+    ("APOT", "OpenTracker"),
+    ("APRS", "Generic APRS"),
+    ("APRX", "aprx"),
+    ("APT3", "TinyTrak3"),
+    ("APTT", "TinyTrak"),
+    ("APU25", "UI-View32"),
+    ("APWW", "APRSIS32"),
+    ("APX", "Xastir"),
+    ("APY", "Yaesu"),
+    ("APZ", "Experimental"),
+];
+
+/// Mic-E packets encode the position in the destination, so they are
+/// grouped together instead.
+pub const MIC_E: &str = "Mic-E";
+pub const UNKNOWN: &str = "Unknown";
+
+/// TOCALL patterns and software names, longest pattern first. In patterns,
+/// lowercase `x`, `n`, `?` and `*` match any character.
+#[derive(Debug, Clone)]
+pub struct Tocalls {
+    entries: Vec<(String, String)>,
+}
+
+impl Default for Tocalls {
+    fn default() -> Self {
+        Self::from_entries(BUILTIN.iter().map(|(p, n)| (p.to_string(), n.to_string())).collect())
+    }
+}
+
+fn pattern_matches(pattern: &str, tocall: &str) -> bool {
+    tocall.len() >= pattern.trim_end_matches(['x', 'n', '?', '*']).len()
+        && pattern
+            .chars()
+            .zip(tocall.chars())
+            .all(|(p, c)| matches!(p, 'x' | 'n' | '?' | '*') || p == c)
+}
+
+impl Tocalls {
+    fn from_entries(mut entries: Vec<(String, String)>) -> Self {
+        entries.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+        Tocalls { entries }
+    }
+
+    /// Reads `tocalls.txt` on top of the built-in table. Entries are the
+    /// indented lines starting with an `AP` TOCALL, followed by the name.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        let mut entries: Vec<(String, String)> = BUILTIN.iter().map(|(p, n)| (p.to_string(), n.to_string())).collect();
+        for line in text.lines().filter(|l| l.starts_with(' ')) {
+            let line = line.trim();
+            let Some((tocall, name)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            if tocall.starts_with("AP") && tocall.len() <= 6 && tocall.chars().all(|c| c.is_ascii_alphanumeric() || c == '?' || c == '*') {
+                entries.retain(|(p, _)| p != tocall);
+                entries.push((tocall.to_string(), name.trim().to_string()));
+            }
+        }
+        Ok(Self::from_entries(entries))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Software name for a destination callsign, ignoring any SSID.
+    pub fn lookup(&self, destination: &str) -> Option<&str> {
+        let tocall = destination.split('-').next().unwrap_or(destination).to_uppercase();
+        self.entries.iter().find(|(p, _)| pattern_matches(p, &tocall)).map(|(_, n)| n.as_str())
+    }
+
+    /// Software that originated a packet, `Mic-E` or `Unknown` if it cannot
+    /// be told from the destination.
+    pub fn software(&self, packet: &ParsedPacket) -> &str {
+        if matches!(packet.data, PacketData::MicE(_)) {
+            return MIC_E;
+        }
+        self.lookup(&packet.destination).unwrap_or(UNKNOWN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let tocalls = Tocalls::default();
+        assert_eq!(tocalls.lookup("APDW16"), Some("Dire Wolf"));
+        assert_eq!(tocalls.lookup("apdr15-1"), Some("APRSdroid"));
+        assert_eq!(tocalls.lookup("APRS"), Some("Generic APRS"));
+        assert_eq!(tocalls.lookup("BEACON"), None);
+        let packet = crate::parser::parse("N0CALL>APX219:>status").unwrap();
+        assert_eq!(tocalls.software(&packet), "Xastir");
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!("tocalls-{}.txt", std::process::id()));
+        fs::write(&path, "APRS TOCALLS\n\n APDWxx  Dire Wolf, WB2OSZ\n APN3xx  Kantronics KPC-3 rom versions\n APRS    Older generic\n").unwrap();
+        let tocalls = Tocalls::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(tocalls.lookup("APN391"), Some("Kantronics KPC-3 rom versions"));
+        assert_eq!(tocalls.lookup("APDW16"), Some("Dire Wolf, WB2OSZ"));
+        assert_eq!(tocalls.lookup("APRS"), Some("Older generic"));
+        // Shorter than the fixed part of the pattern
+        assert_eq!(tocalls.lookup("APN"), None);
+        assert!(Tocalls::load("/nonexistent/tocalls.txt").is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! The transmit side of an igate. Messages from APRS-IS to stations heard
+//! on RF recently, within `max_hops` digipeaters, are sent over the air in
+//! third-party format (`}SRC>DEST,TCPIP,IGATE*:...`), followed by the next
+//! position of the sender as a courtesy so the addressee can see where it
+//! is. Everything else stays off the air.
+
+use crate::agwpe;
+use crate::ax25::{Address, Frame};
+use crate::config::{RfInterface, RfTxConfig};
+use crate::kiss;
+use crate::parser::ParsedPacket;
+use crate::rf::RfStatus;
+use crate::rf_heard::RfHeard;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Transmit decisions since startup, one counter per rule.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TxCounters {
+    pub messages: u64,
+    /// Positions of message senders sent as a courtesy
+    pub positions: u64,
+    /// Addressee not heard on RF recently
+    pub not_heard: u64,
+    /// Addressee only heard over more than `max_hops` digipeaters
+    pub too_far: u64,
+    /// Sender heard on RF, so the addressee can hear it directly
+    pub sender_on_rf: u64,
+    /// `TCPXX`, `NOGATE` or `RFONLY` in the path
+    pub flagged: u64,
+    pub rate_limited: u64,
+    /// Would have been sent, but transmitting is switched off
+    pub disabled: u64,
+    /// No TNC connection, or writing to it failed
+    pub errors: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxKind {
+    Message,
+    CourtesyPosition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxSkip {
+    NotHeard,
+    TooFar,
+    SenderOnRf,
+    Flagged,
+    RateLimited,
+    Disabled,
+}
+
+#[derive(Debug, Default)]
+struct TxState {
+    /// Senders of transmitted messages, and when, whose next position goes out too
+    courtesy: HashMap<String, u64>,
+    /// Transmit times within the last minute
+    sent: VecDeque<u64>,
+}
+
+impl TxState {
+    /// Whether `packet` goes on the air; `None` if it is not a candidate.
+    fn decide(&mut self, cfg: &RfTxConfig, heard: &RfHeard, packet: &ParsedPacket, now: u64) -> Option<Result<TxKind, TxSkip>> {
+        let recent = |t: u64| now.saturating_sub(t) < cfg.heard_secs;
+        let source = packet.source.to_uppercase();
+        let kind = if let Some(msg) = packet.message() {
+            if heard.get(&source).is_some_and(|s| recent(s.last_heard)) {
+                return Some(Err(TxSkip::SenderOnRf));
+            }
+            match heard.get(&msg.addressee) {
+                Some(s) if s.last_direct.is_some_and(recent) => TxKind::Message,
+                Some(s) if s.last_digipeated.is_some_and(recent) && s.hops <= cfg.max_hops => TxKind::Message,
+                Some(s) if recent(s.last_heard) => return Some(Err(TxSkip::TooFar)),
+                _ => return Some(Err(TxSkip::NotHeard)),
+            }
+        } else if packet.position().is_some() && self.courtesy.get(&source).is_some_and(|&t| recent(t)) {
+            TxKind::CourtesyPosition
+        } else {
+            return None;
+        };
+        if crate::gating::forbidden_flag(packet).is_some() {
+            return Some(Err(TxSkip::Flagged));
+        }
+        if !cfg.enabled {
+            return Some(Err(TxSkip::Disabled));
+        }
+        while self.sent.front().is_some_and(|&t| now.saturating_sub(t) >= 60) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= cfg.max_per_minute {
+            return Some(Err(TxSkip::RateLimited));
+        }
+        self.sent.push_back(now);
+        match kind {
+            TxKind::Message if cfg.courtesy_position => {
+                self.courtesy.retain(|_, &mut t| recent(t));
+                self.courtesy.insert(source, now);
+            }
+            TxKind::Message => {}
+            TxKind::CourtesyPosition => {
+                self.courtesy.remove(&source);
+            }
+        }
+        Some(Ok(kind))
+    }
+}
+
+pub struct TxIgate {
+    cfg: RfTxConfig,
+    interface: RfInterface,
+    callsign: Address,
+    path: Vec<Address>,
+    state: Mutex<TxState>,
+    /// The TNC connection while there is one
+    writer: Mutex<Option<Box<dyn Write + Send>>>,
+    status: Arc<Mutex<RfStatus>>,
+}
+
+impl TxIgate {
+    /// Fails if the callsign or path cannot be sent over AX.25.
+    pub fn new(cfg: RfTxConfig, interface: RfInterface, callsign: &str, status: Arc<Mutex<RfStatus>>) -> Result<Self, String> {
+        let address = |s: &str| Address::parse(&s.trim().to_uppercase()).map_err(|e| format!("{}: {}", s, e));
+        let path = match &cfg.path {
+            Some(path) => path.split(',').map(address).collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        status.lock().unwrap().tx = Some(TxCounters::default());
+        Ok(TxIgate { callsign: address(callsign)?, path, interface, cfg, state: Mutex::default(), writer: Mutex::new(None), status })
+    }
+
+    /// Uses `writer` to reach the TNC until [`detach`](Self::detach).
+    pub fn attach(&self, writer: Box<dyn Write + Send>) {
+        *self.writer.lock().unwrap() = Some(writer);
+    }
+
+    pub fn detach(&self) {
+        *self.writer.lock().unwrap() = None;
+    }
+
+    /// Transmits `packet`, an accepted APRS-IS line, if the rules allow it
+    /// for the stations `heard` on RF. Returns what was done, `None` if the
+    /// packet is not one that could go out, and `Err(None)` on a TNC error.
+    pub fn consider(&self, heard: &Mutex<RfHeard>, packet: &ParsedPacket, line: &str, now: u64) -> Option<Result<TxKind, Option<TxSkip>>> {
+        let decision = {
+            let heard = heard.lock().unwrap();
+            self.state.lock().unwrap().decide(&self.cfg, &heard, packet, now)
+        }?;
+        let sent = decision.map_err(Some).and_then(|kind| match self.transmit(packet, line) {
+            Ok(()) => Ok(kind),
+            Err(e) => {
+                warn!(error = %e, "failed to transmit to TNC");
+                Err(None)
+            }
+        });
+        let mut status = self.status.lock().unwrap();
+        let counters = status.tx.get_or_insert_default();
+        match sent {
+            Ok(TxKind::Message) => counters.messages += 1,
+            Ok(TxKind::CourtesyPosition) => counters.positions += 1,
+            Err(Some(TxSkip::NotHeard)) => counters.not_heard += 1,
+            Err(Some(TxSkip::TooFar)) => counters.too_far += 1,
+            Err(Some(TxSkip::SenderOnRf)) => counters.sender_on_rf += 1,
+            Err(Some(TxSkip::Flagged)) => counters.flagged += 1,
+            Err(Some(TxSkip::RateLimited)) => counters.rate_limited += 1,
+            Err(Some(TxSkip::Disabled)) => counters.disabled += 1,
+            Err(None) => counters.errors += 1,
+        }
+        Some(sent)
+    }
+
+    /// The third-party frame carrying `line`.
+    fn frame(&self, packet: &ParsedPacket, line: &str) -> Frame {
+        let body = line.split_once(':').map_or("", |(_, body)| body);
+        let info = format!("}}{}>{},TCPIP,{}*:{}", packet.source, packet.destination, self.callsign, body);
+        let destination = Address { callsign: "APRS".to_string(), ssid: 0, repeated: false };
+        Frame::ui(destination, self.callsign.clone(), self.path.clone(), info.into_bytes())
+    }
+
+    fn transmit(&self, packet: &ParsedPacket, line: &str) -> Result<(), String> {
+        let frame = self.frame(packet, line).encode();
+        let bytes = match self.interface {
+            RfInterface::KissTcp | RfInterface::KissSerial => kiss::encode(0, &frame),
+            RfInterface::Agwpe => agwpe::encode(0, agwpe::KIND_RAW, &[&[0x00], frame.as_slice()].concat()),
+            RfInterface::Ax25 => [&[0x00], frame.as_slice()].concat(),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        let stream = writer.as_mut().ok_or("TNC not connected")?;
+        if let Err(e) = stream.write_all(&bytes).and_then(|_| stream.flush()) {
+            // The receive side notices too and reconnects
+            *writer = None;
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> RfTxConfig {
+        RfTxConfig { enabled: true, path: None, heard_secs: 1800, max_hops: 1, max_per_minute: 2, courtesy_position: true }
+    }
+
+    #[test]
+    fn test_decide() {
+        let packet = |line: &str| crate::parser::parse(line).unwrap();
+        let cfg = cfg();
+        let mut state = TxState::default();
+        let mut heard = RfHeard::default();
+        heard.record("N1CALL", 0, 1000);
+        heard.record("N2CALL", 2, 1000);
+        heard.record("N3CALL", 0, 1000);
+        // Heard direct before, so reachable
+        heard.record("N4CALL", 0, 900);
+        heard.record("N4CALL", 3, 1000);
+        let mut decide = |line: &str, now: u64| state.decide(&cfg, &heard, &packet(line), now);
+
+        assert_eq!(decide("N0CALL>APRS,TCPIP*,qAC,T2TEST::N1CALL   :hi{1", 1010), Some(Ok(TxKind::Message)));
+        assert_eq!(decide("N0CALL>APRS,TCPIP*,qAC,T2TEST::N2CALL   :hi{2", 1010), Some(Err(TxSkip::TooFar)));
+        assert_eq!(decide("N0CALL>APRS,TCPIP*,qAC,T2TEST::N9CALL   :hi{3", 1010), Some(Err(TxSkip::NotHeard)));
+        assert_eq!(decide("N3CALL>APRS,qAR,N0GATE::N1CALL   :hi{4", 1010), Some(Err(TxSkip::SenderOnRf)));
+        assert_eq!(decide("N0CALL>APRS,NOGATE,qAR,N0GATE::N1CALL   :hi{5", 1010), Some(Err(TxSkip::Flagged)));
+        assert_eq!(decide("N0CALL>APRS,TCPIP*,qAC,T2TEST:>status", 1010), None);
+        // The sender's next position goes out once
+        assert_eq!(decide("N0CALL>APRS,TCPIP*,qAC,T2TEST:!6000.00N/02500.00E>", 1020), Some(Ok(TxKind::CourtesyPosition)));
+        assert_eq!(decide("N0CALL>APRS,TCPIP*,qAC,T2TEST:!6000.00N/02500.00E>", 1030), None);
+        assert_eq!(decide("N0CALL>APRS,TCPIP*,qAC,T2TEST::N1CALL   :again{6", 1080), Some(Ok(TxKind::Message)));
+        assert_eq!(decide("N0CALL>APRS,TCPIP*,qAC,T2TEST::N4CALL   :hi{9", 1080), Some(Ok(TxKind::Message)));
+        assert_eq!(decide("N0CALL>APRS,TCPIP*,qAC,T2TEST::N1CALL   :more{10", 1090), Some(Err(TxSkip::RateLimited)));
+        // Heard too long ago
+        assert_eq!(decide("N0CALL>APRS,TCPIP*,qAC,T2TEST::N1CALL   :late{7", 1000 + 1800), Some(Err(TxSkip::NotHeard)));
+
+        let off = RfTxConfig { enabled: false, ..cfg.clone() };
+        assert_eq!(state.decide(&off, &heard, &packet("N0CALL>APRS::N1CALL   :hi{8"), 1100), Some(Err(TxSkip::Disabled)));
+    }
+
+    #[test]
+    fn test_frame() {
+        let status = Arc::new(Mutex::new(RfStatus::default()));
+        let cfg = RfTxConfig { path: Some("WIDE1-1".to_string()), ..cfg() };
+        let igate = TxIgate::new(cfg, RfInterface::KissTcp, "n0gate-10", status.clone()).unwrap();
+        let line = "N0CALL>APRS,TCPIP*,qAC,T2TEST::N1CALL   :hi{1";
+        let frame = igate.frame(&crate::parser::parse(line).unwrap(), line);
+        assert_eq!(frame.tnc2_header(), "N0GATE-10>APRS,WIDE1-1");
+        assert_eq!(frame.info, b"}N0CALL>APRS,TCPIP,N0GATE-10*::N1CALL   :hi{1");
+        assert_eq!(status.lock().unwrap().tx, Some(TxCounters::default()));
+        assert!(TxIgate::new(RfTxConfig { path: None, ..igate.cfg.clone() }, RfInterface::KissTcp, "T2FINLAND", status).is_err());
+    }
+}
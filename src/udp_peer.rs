@@ -0,0 +1,249 @@
+//! aprsc-compatible core peering over UDP. Peers exchange datagrams holding
+//! one or more CRLF terminated APRS-IS lines; a `# keepalive` line is sent
+//! when there has been no traffic for a while so peers can tell we are up.
+
+use crate::config::S2SPeerConfig;
+use crate::filter::parse_filter_list;
+use crate::hub::{Hub, PeerState, S2SPeerHandle, S2SPeerStatus};
+use crate::latency::Outbound;
+use crate::pipeline::{self, Origin};
+use crate::stats::{count_lines, ListenerStats, Protocol};
+use crate::version;
+use chrono::Utc;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tracing::{error, info, warn};
+
+// Stay below a typical path MTU so datagrams are not fragmented
+const MAX_DATAGRAM: usize = 1400;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Lines waiting to be sent to a peer in one datagram, with when each
+/// packet was received.
+#[derive(Debug, Default)]
+struct Batch {
+    buf: String,
+    received: Vec<Instant>,
+}
+
+impl Batch {
+    /// Adds a line, returning the previous contents if they had to be sent
+    /// first to keep the datagram under `MAX_DATAGRAM`.
+    fn push(&mut self, line: &str, received: Instant) -> Option<(String, Vec<Instant>)> {
+        let full = if !self.buf.is_empty() && self.buf.len() + line.len() + 2 > MAX_DATAGRAM { self.take() } else { None };
+        self.buf.push_str(line);
+        self.buf.push_str("\r\n");
+        self.received.push(received);
+        full
+    }
+    fn take(&mut self) -> Option<(String, Vec<Instant>)> {
+        if self.buf.is_empty() { None } else { Some((std::mem::take(&mut self.buf), std::mem::take(&mut self.received))) }
+    }
+}
+
+struct UdpPeer {
+    addr: SocketAddr,
+    name: String,
+    status: Arc<Mutex<S2SPeerStatus>>,
+}
+
+/// Runs UDP peering for `peers` on an already bound socket: registers a
+/// handle per peer and relays received lines into the hub.
+pub async fn run(
+    socket: std::net::UdpSocket,
+    peers: Vec<(S2SPeerConfig, Arc<Mutex<S2SPeerStatus>>)>,
+    hub: Arc<Hub>,
+) {
+    let socket = match UdpSocket::from_std(socket) {
+        Ok(s) => Arc::new(s),
+        Err(e) => {
+            error!(error = %e, "failed to set up UDP peer socket");
+            return;
+        }
+    };
+    let listener = socket.local_addr().ok().and_then(|a| hub.listener(Protocol::Udp, a.port()).cloned());
+    let mut udp_peers = Vec::new();
+    for (cfg, status) in peers {
+        let addr = match tokio::net::lookup_host((cfg.host.as_str(), cfg.port)).await.map(|mut a| a.next()) {
+            Ok(Some(addr)) => addr,
+            Ok(None) | Err(_) => {
+                error!(host = %cfg.host, "cannot resolve UDP peer address");
+                status.lock().unwrap().last_error = Some("cannot resolve address".to_string());
+                continue;
+            }
+        };
+        let filters = match cfg.filter.as_deref().map(parse_filter_list).transpose() {
+            Ok(filters) => filters,
+            Err(e) => {
+                error!(%addr, error = %e, "invalid UDP peer filter");
+                status.lock().unwrap().last_error = Some(format!("filter: {}", e));
+                continue;
+            }
+        };
+        let name = cfg.peer_name.clone().unwrap_or_else(|| addr.to_string());
+        let (tx, rx) = unbounded_channel();
+        hub.s2s_peer_handles.write().unwrap().push(S2SPeerHandle {
+            peer_name: Some(name.clone()),
+            sender: tx,
+            filters,
+        });
+        let timeout = Duration::from_secs(hub.s2s_timeout_secs);
+        tokio::spawn(send_loop(socket.clone(), addr, rx, status.clone(), listener.clone(), timeout, hub.clone()));
+        info!(%addr, %name, "UDP core peer configured");
+        udp_peers.push(UdpPeer { addr, name, status });
+    }
+    let mut buf = vec![0u8; 65536];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!(error = %e, "UDP peer receive error");
+                continue;
+            }
+        };
+        let Some(peer) = udp_peers.iter().find(|p| p.addr == from) else {
+            warn!(%from, "datagram from unknown UDP peer ignored");
+            continue;
+        };
+        let data = String::from_utf8_lossy(&buf[..len]);
+        let mut packets = 0;
+        for line in data.split(['\r', '\n']).filter(|l| !l.is_empty()) {
+            if !line.starts_with('#') {
+                pipeline::submit(&hub, Origin::S2s(Some(peer.name.clone())), line);
+                packets += 1;
+            }
+        }
+        if let Some(listener) = &listener {
+            listener.add_rx(packets, len);
+        }
+        let mut s = peer.status.lock().unwrap();
+        if !s.connected {
+            s.last_connect = Some(SystemTime::now());
+        }
+        s.connected = true;
+        s.state = PeerState::Established;
+        s.packets_rx += packets;
+        s.bytes_rx += len as u64;
+        s.last_rx_time = Some(SystemTime::now());
+    }
+}
+
+/// Batches lines queued for one peer into datagrams, sending keepalives
+/// when idle and marking the peer down when it has been silent for `timeout`.
+async fn send_loop(
+    socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+    mut rx: UnboundedReceiver<Outbound>,
+    status: Arc<Mutex<S2SPeerStatus>>,
+    listener: Option<Arc<ListenerStats>>,
+    timeout: Duration,
+    hub: Arc<Hub>,
+) {
+    let mut batch = Batch::default();
+    let mut last_send = tokio::time::Instant::now();
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        let mut datagrams = Vec::new();
+        tokio::select! {
+            line = rx.recv() => match line {
+                Some(out) => datagrams.extend(batch.push(out.line.trim_end(), out.received)),
+                None => return,
+            },
+            _ = flush.tick() => {
+                datagrams.extend(batch.take());
+                if datagrams.is_empty() && last_send.elapsed() >= KEEPALIVE_INTERVAL {
+                    datagrams.push((version::keepalive(&hub.server_name, Utc::now()), Vec::new()));
+                }
+                let mut s = status.lock().unwrap();
+                if s.connected && s.last_rx_time.and_then(|t| t.elapsed().ok()).is_some_and(|e| e > timeout) {
+                    s.connected = false;
+                    s.state = PeerState::Connecting;
+                    s.last_error = Some("peer timed out".to_string());
+                }
+            }
+        }
+        for (datagram, received) in datagrams {
+            last_send = tokio::time::Instant::now();
+            let result = socket.send_to(datagram.as_bytes(), addr).await;
+            let mut s = status.lock().unwrap();
+            match result {
+                Ok(n) => {
+                    for at in received {
+                        hub.latency.peers.record(at.elapsed());
+                    }
+                    if let Some(listener) = &listener {
+                        listener.add_tx(count_lines(&datagram).0, n);
+                    }
+                    s.packets_tx += datagram.lines().filter(|l| !l.starts_with('#')).count() as u64;
+                    s.bytes_tx += n as u64;
+                    s.last_tx_time = Some(SystemTime::now());
+                }
+                Err(e) => {
+                    s.write_errors += 1;
+                    s.last_error = Some(format!("send: {}", e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch() {
+        let mut batch = Batch::default();
+        assert_eq!(batch.take(), None);
+        let line = "N0CALL>APRS,TCPIP*,qAC,T2TEST:>".to_string() + &"x".repeat(600);
+        let now = Instant::now();
+        assert_eq!(batch.push(&line, now), None);
+        assert_eq!(batch.push(&line, now), None);
+        // A third line would not fit, so the first two go out together
+        let (full, received) = batch.push(&line, now).unwrap();
+        assert_eq!(full.matches("\r\n").count(), 2);
+        assert_eq!(received.len(), 2);
+        assert!(full.len() <= MAX_DATAGRAM);
+        assert_eq!(batch.take().unwrap(), (format!("{}\r\n", line), vec![now]));
+    }
+
+    #[tokio::test]
+    async fn test_udp_relay() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let local = socket.local_addr().unwrap();
+        let cfg = S2SPeerConfig {
+            host: "127.0.0.1".to_string(),
+            port: peer_addr.port(),
+            passcode: 0,
+            peer_name: Some("T2PEER".to_string()),
+            filter: None,
+            request_filter: false,
+            protocol: Default::default(),
+        };
+        let status = Arc::new(Mutex::new(S2SPeerStatus::new(cfg.host.clone(), cfg.port, cfg.peer_name.clone())));
+        let hub = Arc::new(Hub::new());
+        tokio::spawn(run(socket, vec![(cfg, status.clone())], hub.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        peer.send_to(b"# keepalive\r\nN0CALL>APRS,TCPIP*:>one\r\nN1CALL>APRS,TCPIP*:>two\r\n", local).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(hub.counters.drop_stats().accepted, 2);
+        assert!(status.lock().unwrap().connected);
+        assert_eq!(status.lock().unwrap().packets_rx, 2);
+
+        let out = "N2CALL>APRS,TCPIP*:>out";
+        hub.broadcast_to_s2s_peers(None, "local", out, Instant::now(), &crate::parser::parse(out).unwrap());
+        let mut buf = [0u8; 1500];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), peer.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"N2CALL>APRS,TCPIP*,NOCALL:>out\r\n");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(hub.latency.peers.snapshot().count, 1);
+    }
+}
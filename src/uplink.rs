@@ -1,15 +1,64 @@
 use crate::config::UplinkConfig;
+use crate::filter::{FilterContext, FilterSet, parse_filter_list};
 use crate::hub::Hub;
+use crate::latency::Outbound;
+use crate::parser::ParsedPacket;
+use crate::pipeline::{self, Origin};
+use crate::s2s::{keepalive_interval, write_within};
+use crate::stats::TrafficRates;
+use crate::version;
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tracing::{debug, info, warn};
+
+/// Where the uplink is in its connection cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum UplinkState {
+    #[default]
+    Resolving,
+    Connecting,
+    /// Login sent, waiting for the server's `# logresp`
+    LoggingIn,
+    Verified,
+    /// Logged in, but the server did not accept the passcode
+    Unverified,
+    /// Waiting before the next connection attempt
+    Backoff,
+}
+
+impl UplinkState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UplinkState::Resolving => "resolving",
+            UplinkState::Connecting => "connecting",
+            UplinkState::LoggingIn => "logging-in",
+            UplinkState::Verified => "verified",
+            UplinkState::Unverified => "unverified",
+            UplinkState::Backoff => "backoff",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct UplinkStatus {
+    /// Network name, the host unless configured
+    pub name: String,
     pub host: String,
     pub port: u16,
-    pub connected: bool,
+    /// Address of the current or last connection attempt; the host name
+    /// may resolve to several
+    pub remote_addr: Option<SocketAddr>,
+    pub state: UplinkState,
+    /// Server ID from the remote's `# logresp` line
+    pub server_id: Option<String>,
     pub last_connect: Option<SystemTime>,
     pub packets_rx: u64,
     pub packets_tx: u64,
@@ -18,17 +67,23 @@ pub struct UplinkStatus {
     pub connect_errors: u64,
     pub read_errors: u64,
     pub write_errors: u64,
+    /// Connections dropped after nothing was received for the timeout
+    pub timeouts: u64,
     pub last_error: Option<String>,
     pub last_rx_time: Option<SystemTime>,
     pub last_tx_time: Option<SystemTime>,
+    pub rates: TrafficRates,
 }
 
 impl UplinkStatus {
     pub fn new(cfg: &UplinkConfig) -> Self {
         Self {
+            name: cfg.name().to_string(),
             host: cfg.host.clone(),
             port: cfg.port,
-            connected: false,
+            remote_addr: None,
+            state: UplinkState::Resolving,
+            server_id: None,
             last_connect: None,
             packets_rx: 0,
             packets_tx: 0,
@@ -37,81 +92,366 @@ impl UplinkStatus {
             connect_errors: 0,
             read_errors: 0,
             write_errors: 0,
+            timeouts: 0,
             last_error: None,
             last_rx_time: None,
             last_tx_time: None,
+            rates: TrafficRates::default(),
         }
     }
+
+    pub fn update_rates(&mut self) {
+        let (prx, brx, ptx, btx) = (self.packets_rx, self.bytes_rx, self.packets_tx, self.bytes_tx);
+        self.rates.update(prx, brx, ptx, btx);
+    }
+}
+
+/// An uplink's outgoing queue and the local packets routed to it.
+pub struct UplinkRoute {
+    /// `None` for `[uplink]`, the name of an `[[uplinks]]` network otherwise
+    pub network: Option<String>,
+    pub tx: UnboundedSender<Outbound>,
+    filter: Option<FilterSet>,
+    exclusive: bool,
+    pub status: Arc<Mutex<UplinkStatus>>,
+}
+
+impl UplinkRoute {
+    pub fn new(cfg: &UplinkConfig, network: Option<String>, tx: UnboundedSender<Outbound>, status: Arc<Mutex<UplinkStatus>>) -> Result<Self, String> {
+        let filter = match &cfg.filter {
+            Some(s) => Some(FilterSet::new(parse_filter_list(s).map_err(|e| format!("uplink {}: {}", cfg.name(), e))?)),
+            None => None,
+        };
+        Ok(UplinkRoute { network, tx, filter, exclusive: cfg.exclusive, status })
+    }
+}
+
+#[cfg(test)]
+/// `[uplink]` taking every local packet, for tests.
+pub fn test_route(tx: UnboundedSender<Outbound>) -> UplinkRoute {
+    let cfg = UplinkConfig::default();
+    UplinkRoute::new(&cfg, None, tx, Arc::new(Mutex::new(UplinkStatus::new(&cfg)))).unwrap()
+}
+
+/// The uplinks a local packet goes to: those whose filter matches it or
+/// that have none, unless an exclusive uplink's filter matches, in which
+/// case only the exclusive uplinks matching it.
+pub fn route<'a>(routes: &'a [UplinkRoute], packet: &ParsedPacket) -> Vec<&'a UplinkRoute> {
+    let ctx = FilterContext::default();
+    let matched: Vec<(&UplinkRoute, Option<bool>)> =
+        routes.iter().map(|r| (r, r.filter.as_ref().map(|f| f.matches(packet, &ctx)))).collect();
+    let exclusive: Vec<&UplinkRoute> = matched.iter().filter(|(r, m)| r.exclusive && *m == Some(true)).map(|(r, _)| *r).collect();
+    if !exclusive.is_empty() {
+        return exclusive;
+    }
+    matched.into_iter().filter(|(_, m)| *m != Some(false)).map(|(r, _)| r).collect()
+}
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// APRS-IS servers send a comment line every 20 seconds or so
+const DEFAULT_TIMEOUT_SECS: u64 = 120;
+
+/// Address for connection attempt number `attempt`. Cycling through all of
+/// them spreads reconnects over the servers behind a rotation name like
+/// `rotate.aprs2.net`.
+fn pick_address(addrs: &[SocketAddr], attempt: usize) -> SocketAddr {
+    addrs[attempt % addrs.len()]
+}
+
+/// Parses a `# logresp CALL verified, server ID` reply into whether the
+/// login was verified and the server ID.
+fn parse_logresp(line: &str) -> Option<(bool, Option<String>)> {
+    let mut words = line.strip_prefix("# logresp ")?.split_whitespace().skip(1);
+    let verified = words.next()?.trim_end_matches(',') == "verified";
+    let server = match (words.next(), words.next()) {
+        (Some("server"), Some(id)) => Some(id.to_string()),
+        _ => None,
+    };
+    Some((verified, server))
+}
+
+fn set_state(status: &Mutex<UplinkStatus>, state: UplinkState) {
+    status.lock().unwrap().state = state;
 }
 
-pub async fn connect_and_run(uplink: UplinkConfig, _hub: Arc<Mutex<Hub>>, status: Arc<Mutex<UplinkStatus>>) {
-    let addr = format!("{}:{}", uplink.host, uplink.port);
+#[tracing::instrument(name = "uplink", skip_all, fields(host = %uplink.host, port = uplink.port))]
+/// Keeps the uplink connected, feeding received packets into the hub and
+/// sending the lines from `outbound`, which must end in CRLF. `network` is
+/// `None` for `[uplink]` and the name of an `[[uplinks]]` network otherwise.
+pub async fn connect_and_run(
+    uplink: UplinkConfig,
+    network: Option<String>,
+    hub: Arc<Hub>,
+    status: Arc<Mutex<UplinkStatus>>,
+    mut outbound: UnboundedReceiver<Outbound>,
+) {
+    let timeout = Duration::from_secs(uplink.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS));
+    // Start at a random address so restarting servers do not all pick the first
+    let mut attempt = RandomState::new().build_hasher().finish() as usize;
     loop {
-        match TcpStream::connect(&addr).await {
+        set_state(&status, UplinkState::Resolving);
+        // Resolved again on every attempt so DNS changes are picked up
+        let addrs: Vec<SocketAddr> = match tokio::net::lookup_host((uplink.host.as_str(), uplink.port)).await {
+            Ok(addrs) => addrs.collect(),
+            Err(e) => {
+                warn!(error = %e, "cannot resolve uplink host");
+                {
+                    let mut s = status.lock().unwrap();
+                    s.connect_errors += 1;
+                    s.last_error = Some(format!("resolve: {}", e));
+                    s.state = UplinkState::Backoff;
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        if addrs.is_empty() {
+            {
+                let mut s = status.lock().unwrap();
+                s.last_error = Some("resolve: no addresses".to_string());
+                s.state = UplinkState::Backoff;
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+            continue;
+        }
+        let addr = pick_address(&addrs, attempt);
+        attempt = attempt.wrapping_add(1);
+        {
+            let mut s = status.lock().unwrap();
+            s.remote_addr = Some(addr);
+            s.server_id = None;
+            s.state = UplinkState::Connecting;
+        }
+        match TcpStream::connect(addr).await {
             Ok(stream) => {
                 {
                     let mut s = status.lock().unwrap();
-                    s.connected = true;
                     s.last_connect = Some(SystemTime::now());
                     s.last_error = None;
                 }
-                println!("Connected to uplink {}", addr);
+                info!(%addr, "connected to uplink");
                 let (reader, mut writer) = stream.into_split();
                 let mut reader = BufReader::new(reader);
-                let login = format!("user {} pass {} vers aprsserver-rust 0.1.0\n", uplink.callsign, uplink.passcode);
-                match writer.write_all(login.as_bytes()).await {
+                let login = format!("user {} pass {} vers {}\n", uplink.callsign, uplink.passcode, version::SOFTWARE);
+                match write_within(&mut writer, login.as_bytes(), hub.write_timeout).await {
                     Ok(_) => {
                         let mut s = status.lock().unwrap();
                         s.packets_tx += 1;
                         s.bytes_tx += login.len() as u64;
                         s.last_tx_time = Some(SystemTime::now());
+                        s.state = UplinkState::LoggingIn;
                     }
                     Err(e) => {
                         let mut s = status.lock().unwrap();
                         s.write_errors += 1;
                         s.last_error = Some(format!("login send: {}", e));
-                        s.connected = false;
                         continue;
                     }
                 }
-                let mut line = String::new();
+                let mut last_rx = Instant::now();
+                let mut keepalive = tokio::time::interval(keepalive_interval(timeout));
+                keepalive.tick().await;
+                // Kept across iterations: read_until leaves a line cut short
+                // by another branch in here, where read_line would lose it
+                let mut buf = Vec::new();
                 loop {
-                    line.clear();
-                    match reader.read_line(&mut line).await {
-                        Ok(0) => {
-                            println!("Uplink disconnected");
-                            let mut s = status.lock().unwrap();
-                            s.connected = false;
-                            break;
-                        }
-                        Ok(n) => {
+                    tokio::select! {
+                        read = reader.read_until(b'\n', &mut buf) => match read {
+                            Ok(0) => {
+                                info!("uplink disconnected");
+                                break;
+                            }
+                            Ok(_) => {
+                                last_rx = Instant::now();
+                                let line = String::from_utf8_lossy(&buf);
+                                let mut s = status.lock().unwrap();
+                                s.packets_rx += 1;
+                                s.bytes_rx += buf.len() as u64;
+                                s.last_rx_time = Some(SystemTime::now());
+                                debug!(line = line.trim_end(), "uplink rx");
+                                let packet = line.trim_end();
+                                if let Some((verified, server_id)) = parse_logresp(packet) {
+                                    info!(verified, server = server_id.as_deref().unwrap_or(""), "uplink login accepted");
+                                    s.state = if verified { UplinkState::Verified } else { UplinkState::Unverified };
+                                    s.server_id = server_id;
+                                }
+                                drop(s);
+                                if !packet.is_empty() && !packet.starts_with('#') {
+                                    let origin = match &network {
+                                        Some(name) => Origin::Network(name.clone()),
+                                        None => Origin::Uplink,
+                                    };
+                                    pipeline::submit(&hub, origin, packet);
+                                }
+                                buf.clear();
+                            }
+                            Err(e) => {
+                                warn!(error = %e, "uplink read error");
+                                let mut s = status.lock().unwrap();
+                                s.read_errors += 1;
+                                s.last_error = Some(format!("read: {}", e));
+                                break;
+                            }
+                        },
+                        Some(out) = outbound.recv() => {
+                            let result = write_within(&mut writer, out.line.as_bytes(), hub.write_timeout).await;
                             let mut s = status.lock().unwrap();
-                            s.packets_rx += 1;
-                            s.bytes_rx += n as u64;
-                            s.last_rx_time = Some(SystemTime::now());
-                            print!("Uplink RX: {}", line);
+                            match result {
+                                Ok(()) => {
+                                    hub.latency.uplinks.record(out.received.elapsed());
+                                    s.packets_tx += 1;
+                                    s.bytes_tx += out.line.len() as u64;
+                                    s.last_tx_time = Some(SystemTime::now());
+                                }
+                                Err(e) => {
+                                    warn!(error = %e, "uplink write error");
+                                    s.write_errors += 1;
+                                    s.last_error = Some(format!("write: {}", e));
+                                    break;
+                                }
+                            }
                         }
-                        Err(e) => {
-                            eprintln!("Uplink read error: {}", e);
-                            let mut s = status.lock().unwrap();
-                            s.connected = false;
-                            s.read_errors += 1;
-                            s.last_error = Some(format!("read: {}", e));
-                            break;
+                        _ = keepalive.tick() => {
+                            // A connection can die without being closed, leaving reads blocked
+                            if last_rx.elapsed() >= timeout {
+                                warn!(silent_secs = last_rx.elapsed().as_secs(), "uplink timed out");
+                                let mut s = status.lock().unwrap();
+                                s.timeouts += 1;
+                                s.last_error = Some("timed out".to_string());
+                                break;
+                            }
+                            let keepalive = version::keepalive(&hub.server_name, Utc::now());
+                            if let Err(e) = write_within(&mut writer, keepalive.as_bytes(), hub.write_timeout).await {
+                                warn!(error = %e, "uplink keepalive failed");
+                                let mut s = status.lock().unwrap();
+                                s.write_errors += 1;
+                                s.last_error = Some(format!("keepalive: {}", e));
+                                break;
+                            }
                         }
                     }
                 }
             }
             Err(e) => {
-                eprintln!("Uplink connect error: {}", e);
+                warn!(%addr, error = %e, "uplink connect error");
                 {
                     let mut s = status.lock().unwrap();
-                    s.connected = false;
+                    s.state = UplinkState::Backoff;
                     s.connect_errors += 1;
                     s.last_error = Some(format!("connect: {}", e));
                 }
-                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                tokio::time::sleep(RECONNECT_DELAY).await;
             }
         }
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_pick_address() {
+        let addrs: Vec<SocketAddr> = ["192.0.2.1:14580", "192.0.2.2:14580", "[2001:db8::1]:14580"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let picked: Vec<SocketAddr> = (5..11).map(|i| pick_address(&addrs, i)).collect();
+        assert_eq!(picked[..3], [addrs[2], addrs[0], addrs[1]]);
+        assert_eq!(picked[..3], picked[3..]);
+    }
+    #[test]
+    fn test_parse_logresp() {
+        assert_eq!(parse_logresp("# logresp N0CALL verified, server T2FINLAND"), Some((true, Some("T2FINLAND".to_string()))));
+        assert_eq!(parse_logresp("# logresp N0CALL unverified, server T2TEST"), Some((false, Some("T2TEST".to_string()))));
+        assert_eq!(parse_logresp("# logresp N0CALL verified"), Some((true, None)));
+        assert_eq!(parse_logresp("# aprsc 2.1.14-g5e22b37"), None);
+    }
+    #[test]
+    fn test_route() {
+        let network = |name: &str, filter: Option<&str>, exclusive: bool| {
+            let cfg = UplinkConfig { name: Some(name.to_string()), filter: filter.map(str::to_string), exclusive, ..Default::default() };
+            let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+            UplinkRoute::new(&cfg, Some(name.to_string()), tx, Arc::new(Mutex::new(UplinkStatus::new(&cfg)))).unwrap()
+        };
+        let routes = vec![network("aprs-is", None, false), network("cwop", Some("t/w"), true), network("club", Some("p/N0"), false)];
+        let names = |line: &str| -> Vec<String> {
+            route(&routes, &crate::parser::parse(line).unwrap()).iter().map(|r| r.network.clone().unwrap()).collect()
+        };
+        assert_eq!(names("N0CALL>APRS:!4903.50N/07201.75W>"), ["aprs-is", "club"]);
+        assert_eq!(names("K1ABC>APRS:!4903.50N/07201.75W>"), ["aprs-is"]);
+        // Weather goes to CWOP only
+        assert_eq!(names("N0CALL>APRS:_10090556c220s004g005t077r000p000P000h50b09900wRSW"), ["cwop"]);
+
+        let cfg = UplinkConfig { filter: Some("x/".to_string()), ..Default::default() };
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        assert!(UplinkRoute::new(&cfg, None, tx, Arc::new(Mutex::new(UplinkStatus::new(&cfg)))).is_err());
+    }
+    #[tokio::test]
+    async fn test_silent_uplink_reconnect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let cfg = UplinkConfig {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            callsign: "N0CALL".to_string(),
+            passcode: 0,
+            timeout_secs: Some(1),
+            ..Default::default()
+        };
+        let status = Arc::new(Mutex::new(UplinkStatus::new(&cfg)));
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(connect_and_run(cfg, None, Arc::new(Hub::new()), status.clone(), rx));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with("user N0CALL "));
+        assert_eq!(status.lock().unwrap().state, UplinkState::LoggingIn);
+        reader.get_mut().write_all(b"# logresp N0CALL unverified, server T2TEST\r\n").await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with(&format!("# {} ", version::SOFTWARE)) && line.ends_with(" NOCALL\r\n"));
+        assert_eq!(status.lock().unwrap().state, UplinkState::Unverified);
+        assert_eq!(status.lock().unwrap().server_id.as_deref(), Some("T2TEST"));
+        tx.send(Outbound::now("N0CALL>APRS,TCPIP*:>out\r\n".to_string())).unwrap();
+        while line.starts_with('#') {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+        }
+        assert_eq!(line, "N0CALL>APRS,TCPIP*:>out\r\n");
+        // We never answer, so the uplink gives up and connects again
+        let accept = tokio::time::timeout(Duration::from_secs(3), listener.accept()).await;
+        assert!(accept.is_ok());
+        assert_eq!(status.lock().unwrap().timeouts, 1);
+    }
+    #[tokio::test]
+    async fn test_line_split_around_send() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let cfg = UplinkConfig {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            callsign: "N0CALL".to_string(),
+            passcode: 0,
+            ..Default::default()
+        };
+        let hub = Arc::new(Hub::new());
+        let mut feed = hub.packet_feed.subscribe();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(connect_and_run(cfg.clone(), None, hub, Arc::new(Mutex::new(UplinkStatus::new(&cfg))), rx));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        // Half a line, then a packet goes out before the rest arrives
+        reader.get_mut().write_all(b"N1CALL>APRS,TCPIP*,qAC,T2TEST:>sp").await.unwrap();
+        tx.send(Outbound::now("N0CALL>APRS,TCPIP*:>out\r\n".to_string())).unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "N0CALL>APRS,TCPIP*:>out\r\n");
+        reader.get_mut().write_all(b"lit\r\n").await.unwrap();
+        let accepted = tokio::time::timeout(Duration::from_secs(5), feed.recv()).await.unwrap().unwrap();
+        assert_eq!(accepted.line, "N1CALL>APRS,TCPIP*,qAC,T2TEST:>split");
+    }
+}
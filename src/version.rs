@@ -0,0 +1,45 @@
+//! Name and version of this build, used in login lines, greetings,
+//! keepalives and on the status pages. The git commit and build time come
+//! from `build.rs`.
+
+use chrono::{DateTime, Utc};
+
+pub const NAME: &str = "aprsserver-rust";
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Software name and version as the two words APRS-IS logins expect
+pub const SOFTWARE: &str = concat!("aprsserver-rust ", env!("CARGO_PKG_VERSION"));
+/// Short commit hash, empty when built outside a git checkout
+pub const GIT_COMMIT: &str = env!("APRSSERVER_GIT_COMMIT");
+
+/// Unix time of the build.
+pub fn build_time() -> u64 {
+    env!("APRSSERVER_BUILD_TIME").parse().unwrap_or(0)
+}
+
+/// `aprsserver-rust 0.1.0 (1a2b3c4d5, built 2024-05-07 18:30 UTC)`
+pub fn long_version() -> String {
+    let built = DateTime::from_timestamp(build_time() as i64, 0).map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string()).unwrap_or_default();
+    match GIT_COMMIT {
+        "" => format!("{} (built {})", SOFTWARE, built),
+        commit => format!("{} ({}, built {})", SOFTWARE, commit, built),
+    }
+}
+
+/// Keepalive comment in the aprsc style, with the software, time and server ID.
+pub fn keepalive(server_id: &str, now: DateTime<Utc>) -> String {
+    format!("# {} {} {}\r\n", SOFTWARE, now.format("%d %b %Y %H:%M:%S GMT"), server_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_version_strings() {
+        assert_eq!(SOFTWARE, format!("{} {}", NAME, VERSION));
+        assert!(long_version().starts_with(SOFTWARE) && long_version().contains(" UTC)"));
+        let now = Utc.with_ymd_and_hms(2024, 5, 7, 18, 30, 0).unwrap();
+        assert_eq!(keepalive("T2TEST", now), format!("# {} 07 May 2024 18:30:00 GMT T2TEST\r\n", SOFTWARE));
+    }
+}
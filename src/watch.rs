@@ -0,0 +1,112 @@
+//! Watch mode for answering "why didn't my packet get through". Packets
+//! from a watched callsign have every pipeline decision reported: drops,
+//! the q construct, each client's filter and where the packet was sent.
+//! Events are logged and streamed to subscribers of the admin WebSocket.
+
+use crate::parser::ParsedPacket;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::RwLock;
+use tokio::sync::broadcast;
+use tracing::info;
+
+const EVENT_FEED_SIZE: usize = 256;
+
+/// One decision about a watched station's packet.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WatchEvent {
+    /// Unix time of the decision
+    pub time: u64,
+    pub callsign: String,
+    pub packet: String,
+    /// Pipeline step, e.g. `received`, `drop`, `client` or `uplink`
+    pub step: &'static str,
+    pub detail: String,
+}
+
+pub struct Watch {
+    callsigns: RwLock<BTreeSet<String>>,
+    events: broadcast::Sender<WatchEvent>,
+}
+
+impl Default for Watch {
+    fn default() -> Self {
+        Watch { callsigns: RwLock::new(BTreeSet::new()), events: broadcast::channel(EVENT_FEED_SIZE).0 }
+    }
+}
+
+/// `N0CALL` watches every SSID of the station, `N0CALL-9` only that one.
+fn matches(watched: &str, callsign: &str) -> bool {
+    watched.eq_ignore_ascii_case(callsign)
+        || (!watched.contains('-') && callsign.split('-').next().is_some_and(|base| base.eq_ignore_ascii_case(watched)))
+}
+
+impl Watch {
+    /// Starts watching `callsign`. Returns false if it already was.
+    pub fn add(&self, callsign: &str) -> bool {
+        self.callsigns.write().unwrap().insert(callsign.to_uppercase())
+    }
+    /// Stops watching `callsign`. Returns false if it was not watched.
+    pub fn remove(&self, callsign: &str) -> bool {
+        self.callsigns.write().unwrap().remove(&callsign.to_uppercase())
+    }
+    pub fn list(&self) -> Vec<String> {
+        self.callsigns.read().unwrap().iter().cloned().collect()
+    }
+    /// Whether packets from `callsign` are watched.
+    pub fn watching(&self, callsign: &str) -> bool {
+        let callsigns = self.callsigns.read().unwrap();
+        !callsigns.is_empty() && callsigns.iter().any(|w| matches(w, callsign))
+    }
+    pub fn subscribe(&self) -> broadcast::Receiver<WatchEvent> {
+        self.events.subscribe()
+    }
+    /// Logs a decision about `packet` and sends it to the subscribers. The
+    /// caller has checked the source is watched.
+    pub fn report(&self, callsign: &str, packet: &str, step: &'static str, detail: impl Into<String>) {
+        let detail = detail.into();
+        info!(target: "aprsserver::watch", %callsign, step, %detail, %packet, "watch");
+        let event = WatchEvent { time: crate::hub::unix_time(), callsign: callsign.to_string(), packet: packet.to_string(), step, detail };
+        let _ = self.events.send(event);
+    }
+}
+
+/// The q construct in a packet's path and the hop after it, for reporting.
+pub fn q_construct(packet: &ParsedPacket) -> String {
+    let Some(q) = &packet.qconstruct else {
+        return "no q construct".to_string();
+    };
+    match packet.path.iter().skip_while(|hop| *hop != q).nth(1) {
+        Some(via) => format!("{} via {}", q, via),
+        None => q.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watching() {
+        let watch = Watch::default();
+        assert!(!watch.watching("N0CALL"));
+        assert!(watch.add("n0call"));
+        assert!(!watch.add("N0CALL"));
+        assert!(watch.add("N1CALL-9"));
+        assert!(watch.watching("N0CALL-7"));
+        assert!(watch.watching("N1CALL-9"));
+        assert!(!watch.watching("N1CALL"));
+        assert!(!watch.watching("N0CALLX"));
+        assert_eq!(watch.list(), ["N0CALL", "N1CALL-9"]);
+        assert!(watch.remove("N0CALL"));
+        assert!(!watch.remove("N0CALL"));
+
+        let mut events = watch.subscribe();
+        watch.report("N1CALL-9", "N1CALL-9>APRS:>hi", "received", "from client:N1CALL-9");
+        assert_eq!(events.try_recv().unwrap().step, "received");
+
+        let parse = |line| crate::parser::parse(line).unwrap();
+        assert_eq!(q_construct(&parse("N0CALL>APRS,TCPIP*,qAC,T2TEST:>hi")), "qAC via T2TEST");
+        assert_eq!(q_construct(&parse("N0CALL>APRS,WIDE2-1:>hi")), "no q construct");
+    }
+}
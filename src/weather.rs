@@ -0,0 +1,110 @@
+use serde::Serialize;
+
+/// Decoded weather report. Values are kept in the units APRS transmits (mph,
+/// degrees Fahrenheit, hundredths of an inch of rain, percent humidity), except
+/// pressure which is converted to hPa.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Weather {
+    pub wind_direction: Option<u16>,
+    pub wind_speed: Option<u16>,
+    pub wind_gust: Option<u16>,
+    pub temperature: Option<i16>,
+    pub rain_1h: Option<u16>,
+    pub rain_24h: Option<u16>,
+    pub rain_since_midnight: Option<u16>,
+    pub humidity: Option<u8>,
+    pub pressure: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WeatherObservation {
+    pub callsign: String,
+    pub received: u64,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub weather: Weather,
+}
+
+/// Parses a positionless report body after the `_` (`MMDDHHMMc220s004g005t077...`).
+pub fn parse_positionless(data: &str) -> Option<Weather> {
+    parse_fields(data.get(8..)?, Weather::default())
+}
+
+/// Parses the comment of a position report with the weather symbol, which
+/// starts with wind direction and speed (`220/004g005t077...`).
+pub fn parse_complete(comment: &str) -> Option<Weather> {
+    let dir = comment.get(0..3)?;
+    let speed = comment.get(4..7)?;
+    if comment.as_bytes().get(3) != Some(&b'/') {
+        return None;
+    }
+    let wx = Weather {
+        wind_direction: dir.parse().ok(),
+        wind_speed: speed.parse().ok(),
+        ..Weather::default()
+    };
+    parse_fields(&comment[7..], wx)
+}
+
+fn parse_fields(mut data: &str, mut wx: Weather) -> Option<Weather> {
+    let mut found = wx.wind_direction.is_some() || wx.wind_speed.is_some();
+    while let Some(letter) = data.chars().next() {
+        let width = match letter {
+            'c' | 's' | 'g' | 't' | 'r' | 'p' | 'P' => 3,
+            'h' => 2,
+            'b' => 5,
+            _ => break,
+        };
+        let Some(value) = data.get(1..1 + width) else {
+            break;
+        };
+        // Missing readings are sent as dots or spaces
+        if !value.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            data = &data[1 + width..];
+            continue;
+        }
+        match letter {
+            'c' => wx.wind_direction = value.parse().ok(),
+            's' if wx.wind_speed.is_none() => wx.wind_speed = value.parse().ok(),
+            'g' => wx.wind_gust = value.parse().ok(),
+            't' => wx.temperature = value.parse().ok(),
+            'r' => wx.rain_1h = value.parse().ok(),
+            'p' => wx.rain_24h = value.parse().ok(),
+            'P' => wx.rain_since_midnight = value.parse().ok(),
+            'h' => wx.humidity = value.parse::<u8>().ok().map(|h| if h == 0 { 100 } else { h }),
+            'b' => wx.pressure = value.parse::<u32>().ok().map(|b| b as f64 / 10.0),
+            _ => {}
+        }
+        found = true;
+        data = &data[1 + width..];
+    }
+    if found { Some(wx) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_positionless() {
+        let wx = parse_positionless("10090556c220s004g005t077r000p000P000h50b09900wRSW").unwrap();
+        assert_eq!(wx.wind_direction, Some(220));
+        assert_eq!(wx.wind_speed, Some(4));
+        assert_eq!(wx.wind_gust, Some(5));
+        assert_eq!(wx.temperature, Some(77));
+        assert_eq!(wx.rain_1h, Some(0));
+        assert_eq!(wx.humidity, Some(50));
+        assert_eq!(wx.pressure, Some(990.0));
+    }
+
+    #[test]
+    fn test_parse_complete() {
+        let wx = parse_complete("220/004g...t-05h00b10132").unwrap();
+        assert_eq!(wx.wind_direction, Some(220));
+        assert_eq!(wx.wind_gust, None);
+        assert_eq!(wx.temperature, Some(-5));
+        assert_eq!(wx.humidity, Some(100));
+        assert_eq!(wx.pressure, Some(1013.2));
+        assert!(parse_complete("Just a comment").is_none());
+    }
+}
@@ -1,18 +1,90 @@
+use arc_swap::ArcSwap;
 use axum::{Router, routing::get, response::{Html, IntoResponse}, Json, extract::State, serve, extract::ws::{WebSocketUpgrade, Message}};
 use serde::{Serialize, Deserialize};
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
-use crate::hub::Hub;
-use serde_json;
+use crate::client::Client;
+use crate::hub::{unix_time, FeedPacket, HeardStation, Hub, ObjectEntry, StationPosition};
+use crate::mqtt::MqttStatus;
+use crate::latency::LatencyStats;
+use crate::process::ProcessStats;
+use crate::station_cache::CacheStats;
+use crate::rf::RfStatus;
+use crate::rf_heard::RfStation;
+use crate::archive::ArchiveStatus;
+use crate::redis_sink::RedisStatus;
 use crate::uplink::UplinkStatus;
+use crate::stats::{DropStats, DupeStats, ListenerTotals, SoftwareStats, SourceStats, StatsSample, TrafficRates};
+use crate::config::ServerConfig;
+use crate::message_tracker::MessageCounters;
+use crate::position_check::RejectedPositions;
+use std::collections::BTreeMap;
+use crate::telemetry::TelemetryReport;
+use crate::weather::WeatherObservation;
+use axum::extract::{Path, Query};
+use axum::extract::ws::WebSocket;
+use axum::extract::ConnectInfo;
+use crate::client::{ChannelReader, ClientConn};
+use crate::filter::{parse_filter_list, ClientFilter, FilterContext};
+use crate::geoip::Location;
+use tokio::sync::broadcast::error::RecvError;
+use axum::response::Response;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::http::StatusCode;
 use serde_json::json;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
+use tracing::{debug, info};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Status {
     pub server_name: String,
+    /// Sysop, email, location and QTH from `[server]`
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// Name, version, git commit and build time of the running binary
+    #[serde(default)]
+    pub software: String,
     pub uptime: u64,
+    /// Unix time the counters have been running since, across restarts
+    #[serde(default)]
+    pub stats_since: u64,
     pub clients: usize,
+    pub mqtt: Option<MqttStatus>,
+    pub archive: Option<ArchiveStatus>,
+    pub redis: Option<RedisStatus>,
+    #[serde(default)]
+    pub rf: Option<RfStatus>,
+    /// Rolling 1, 5 and 15 minute rate averages
+    pub rates: TrafficRates,
+    pub uplink_rates: TrafficRates,
+    pub client_rates: BTreeMap<usize, TrafficRates>,
+    /// S2S peer rates keyed by `host:port`
+    pub s2s_rates: BTreeMap<String, TrafficRates>,
+    /// Hub-wide packet decisions
+    pub drops: DropStats,
+    /// Traffic per listening port
+    #[serde(default)]
+    pub listeners: Vec<ListenerTotals>,
+    /// APRS message delivery counters
+    #[serde(default)]
+    pub messages: MessageCounters,
+    pub client_drops: BTreeMap<usize, ClientDrops>,
+    /// Memory, CPU and task use of the server process
+    #[serde(default)]
+    pub process: ProcessStats,
+    /// Size of the per-station maps
+    #[serde(default)]
+    pub caches: CacheStats,
+    /// Time from ingress until packets were written out, per egress
+    #[serde(default)]
+    pub latency: LatencyStats,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClientDrops {
+    pub dropped: u64,
+    pub filtered: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -20,33 +92,178 @@ pub struct ClientInfo {
     pub id: usize,
     pub callsign: Option<String>,
     pub filter: Option<Vec<crate::filter::ClientFilter>>,
+    /// Packets matched by each filter in `filter`
+    pub filter_matches: Vec<u64>,
+    pub addr: String,
+    pub location: Option<Location>,
+    pub login_time: Option<u64>,
+    pub verified: bool,
+    pub software: Option<String>,
+    pub class: Option<String>,
+    pub filter_string: Option<String>,
+    pub connected_secs: u64,
+    pub packets_rx: u64,
+    pub packets_tx: u64,
+    pub packets_dropped: u64,
+    pub packets_filtered: u64,
+    /// Packets not delivered because the client's queue was full
+    pub tx_overflows: u64,
+    /// Packets held back by the class bandwidth cap
+    pub tx_shed: u64,
+    pub tx_shed_bytes: u64,
+    pub tx_write_errors: u64,
+    pub queue_depth: usize,
+    pub queue_high_water: u64,
+    pub bytes_rx: u64,
+    pub bytes_tx: u64,
+    pub last_activity: u64,
+    pub rates: TrafficRates,
+}
+
+impl ClientInfo {
+    fn new(id: usize, c: &Client) -> Self {
+        ClientInfo {
+            id,
+            callsign: c.callsign.clone(),
+            filter: c.filter.as_ref().map(|f| f.filters().to_vec()),
+            filter_matches: c.filter.as_ref().map(|f| f.match_counts()).unwrap_or_default(),
+            addr: c.addr.clone(),
+            location: c.location.clone(),
+            login_time: c.login_time,
+            verified: c.verified,
+            software: c.software.clone(),
+            class: c.class.as_ref().map(|class| class.name.clone()),
+            filter_string: c.filter_string.clone(),
+            connected_secs: c.connect_time.elapsed().as_secs(),
+            packets_rx: c.packets_rx,
+            packets_tx: c.packets_tx(),
+            packets_dropped: c.packets_dropped,
+            packets_filtered: c.packets_filtered,
+            tx_overflows: c.tx.overflows.load(Ordering::Relaxed),
+            tx_shed: c.tx.shed.load(Ordering::Relaxed),
+            tx_shed_bytes: c.tx.shed_bytes.load(Ordering::Relaxed),
+            tx_write_errors: c.tx.write_errors.load(Ordering::Relaxed),
+            queue_depth: c.queue_depth(),
+            queue_high_water: c.tx.queue_high_water.load(Ordering::Relaxed),
+            bytes_rx: c.bytes_rx,
+            bytes_tx: c.bytes_tx(),
+            last_activity: c.last_activity,
+            rates: c.rates.clone(),
+        }
+    }
+}
+
+// How often the web snapshot is refreshed
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Copy of the statistics the web pages show, so handlers never lock the
+/// clients or stations while rendering.
+#[derive(Default)]
+pub struct Snapshot {
+    pub status: Status,
+    pub clients: Vec<ClientInfo>,
+    /// `(packets_rx, packets_tx, bytes_rx, bytes_tx)` over the clients
+    pub totals: (u64, u64, u64, u64),
+    pub objects: Vec<ObjectEntry>,
+    pub top_talkers: Vec<SourceStats>,
+    pub software: Vec<SoftwareStats>,
+}
+
+impl Snapshot {
+    pub fn capture(hub: &Hub, uplink_status: &Mutex<UplinkStatus>) -> Self {
+        let mut clients: Vec<ClientInfo> =
+            hub.clients.read().unwrap().iter().map(|(id, c)| ClientInfo::new(*id, &c.lock().unwrap())).collect();
+        clients.sort_by_key(|c| c.id);
+        let totals = clients.iter().fold((0, 0, 0, 0), |t, c| {
+            (t.0 + c.packets_rx, t.1 + c.packets_tx, t.2 + c.bytes_rx, t.3 + c.bytes_tx)
+        });
+        let status = Status {
+            server_name: hub.server_name.clone(),
+            server: hub.server.clone(),
+            software: crate::version::long_version(),
+            uptime: hub.uptime(),
+            stats_since: hub.stats_since,
+            clients: clients.len(),
+            mqtt: hub.mqtt_status.as_ref().map(|s| s.lock().unwrap().clone()),
+            archive: hub.archive_status.as_ref().map(|s| s.lock().unwrap().clone()),
+            redis: hub.redis_status.as_ref().map(|s| s.lock().unwrap().clone()),
+            rf: hub.rf_status.as_ref().map(|s| s.lock().unwrap().clone()),
+            process: hub.process.lock().unwrap().stats.clone(),
+            caches: hub.cache_stats(),
+            latency: hub.latency.stats(),
+            rates: hub.rates.lock().unwrap().clone(),
+            uplink_rates: uplink_status.lock().unwrap().rates.clone(),
+            client_rates: clients.iter().map(|c| (c.id, c.rates.clone())).collect(),
+            drops: hub.counters.drop_stats(),
+            listeners: hub.listeners.iter().map(|l| l.totals()).collect(),
+            messages: {
+                let mut messages = hub.messages.lock().unwrap();
+                messages.expire(hub.clock.unix_time());
+                messages.counters.clone()
+            },
+            client_drops: clients
+                .iter()
+                .map(|c| (c.id, ClientDrops { dropped: c.packets_dropped, filtered: c.packets_filtered }))
+                .collect(),
+            s2s_rates: hub
+                .s2s_peers
+                .read()
+                .unwrap()
+                .iter()
+                .map(|peer| {
+                    let p = peer.lock().unwrap();
+                    (format!("{}:{}", p.host, p.port), p.rates.clone())
+                })
+                .collect(),
+        };
+        let mut objects: Vec<ObjectEntry> = hub.stations.read().unwrap().objects.values().cloned().collect();
+        objects.sort_by(|a, b| a.name.cmp(&b.name));
+        Snapshot {
+            status,
+            clients,
+            totals,
+            objects,
+            top_talkers: hub.top_talkers(TOP_TALKERS),
+            software: hub.software_breakdown(),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct AppState {
-    pub hub: Arc<Mutex<Hub>>,
+    pub hub: Arc<Hub>,
     pub uplink_status: Arc<Mutex<UplinkStatus>>,
+    /// Bearer token for the admin API; admin endpoints are disabled without one
+    pub admin_token: Option<String>,
+    /// Refreshed every `SNAPSHOT_INTERVAL` while the web UI is served
+    pub snapshot: Arc<ArcSwap<Snapshot>>,
 }
 
-fn filter_summary(filters: &Option<Vec<crate::filter::ClientFilter>>) -> String {
-    match filters {
-        Some(fs) => fs.iter().map(|f| format!("{:?}", f)).collect::<Vec<_>>().join(", "),
-        None => String::new(),
+impl AppState {
+    pub fn new(hub: Arc<Hub>, uplink_status: Arc<Mutex<UplinkStatus>>, admin_token: Option<String>) -> Self {
+        AppState { hub, uplink_status, admin_token, snapshot: Arc::default() }
+    }
+
+    pub fn refresh_snapshot(&self) {
+        self.snapshot.store(Arc::new(Snapshot::capture(&self.hub, &self.uplink_status)));
     }
 }
 
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('\'', "&#39;").replace('"', "&quot;")
+}
+
+fn filter_summary(filters: &Option<Vec<crate::filter::ClientFilter>>) -> String {
+    filters.as_deref().map(crate::filter::filter_string).unwrap_or_default()
+}
+
 async fn root(State(state): State<AppState>) -> impl IntoResponse {
-    let mut hub_guard = state.hub.lock().unwrap();
-    hub_guard.update_totals();
-    let started = hub_guard.start_time;
-    let _uptime = hub_guard.uptime();
-    let _server_id = "aprsserver-rust";
-    let _admin = "admin@example.com";
-    let _email = "admin@example.com";
-    let _software = "aprsserver-rust";
-    let _version = "0.1.0";
-    let _os = std::env::consts::OS;
-    let _started_str = format!("{:?}", started);
+    let hub = &state.hub;
+    let snapshot = state.snapshot.load();
+    let started = hub.start_time;
+    let uptime = hub.uptime();
+    let os = std::env::consts::OS;
+    let started_str = format!("{:?}", started);
     let uplink = state.uplink_status.lock().unwrap();
     let uplink_table = format!(r#"
     <table class="min-w-full bg-white rounded shadow overflow-hidden mb-4">
@@ -54,15 +271,20 @@ async fn root(State(state): State<AppState>) -> impl IntoResponse {
       <tbody>
         <tr><td class="px-4 py-2 font-semibold">Host</td><td class="px-4 py-2" id="uplink-host">{}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Port</td><td class="px-4 py-2" id="uplink-port">{}</td></tr>
-        <tr><td class="px-4 py-2 font-semibold">Connected</td><td class="px-4 py-2" id="uplink-connected">{}</td></tr>
+        <tr><td class="px-4 py-2 font-semibold">Address</td><td class="px-4 py-2" id="uplink-remote-addr">{}</td></tr>
+        <tr><td class="px-4 py-2 font-semibold">State</td><td class="px-4 py-2" id="uplink-state">{}</td></tr>
+        <tr><td class="px-4 py-2 font-semibold">Server ID</td><td class="px-4 py-2" id="uplink-server-id">{}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Last Connect</td><td class="px-4 py-2" id="uplink-last-connect">{:?}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Packets RX</td><td class="px-4 py-2" id="uplink-packets-rx">{}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Packets TX</td><td class="px-4 py-2" id="uplink-packets-tx">{}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Bytes RX</td><td class="px-4 py-2" id="uplink-bytes-rx">{}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Bytes TX</td><td class="px-4 py-2" id="uplink-bytes-tx">{}</td></tr>
+        <tr><td class="px-4 py-2 font-semibold">RX Packets/s (1/5/15 min)</td><td class="px-4 py-2" id="uplink-rx-rate">{}</td></tr>
+        <tr><td class="px-4 py-2 font-semibold">TX Packets/s (1/5/15 min)</td><td class="px-4 py-2" id="uplink-tx-rate">{}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Connect Errors</td><td class="px-4 py-2" id="uplink-connect-errors">{}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Read Errors</td><td class="px-4 py-2" id="uplink-read-errors">{}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Write Errors</td><td class="px-4 py-2" id="uplink-write-errors">{}</td></tr>
+        <tr><td class="px-4 py-2 font-semibold">Timeouts</td><td class="px-4 py-2" id="uplink-timeouts">{}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Last Error</td><td class="px-4 py-2" id="uplink-last-error">{}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Last RX Time</td><td class="px-4 py-2" id="uplink-last-rx-time">{:?}</td></tr>
         <tr><td class="px-4 py-2 font-semibold">Last TX Time</td><td class="px-4 py-2" id="uplink-last-tx-time">{:?}</td></tr>
@@ -71,26 +293,155 @@ async fn root(State(state): State<AppState>) -> impl IntoResponse {
     "#,
     uplink.host,
     uplink.port,
-    uplink.connected,
+    uplink.remote_addr.map(|a| a.to_string()).unwrap_or_default(),
+    uplink.state.name(),
+    html_escape(uplink.server_id.as_deref().unwrap_or("")),
     uplink.last_connect,
     uplink.packets_rx,
     uplink.packets_tx,
     uplink.bytes_rx,
     uplink.bytes_tx,
+    uplink.rates.rx.summary(),
+    uplink.rates.tx.summary(),
     uplink.connect_errors,
     uplink.read_errors,
     uplink.write_errors,
+    uplink.timeouts,
     uplink.last_error.as_deref().unwrap_or(""),
     uplink.last_rx_time,
     uplink.last_tx_time
     );
+    let networks_table = {
+        let rows: String = hub
+            .uplinks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|route| route.network.is_some())
+            .map(|route| {
+                let u = route.status.lock().unwrap();
+                format!("<tr><td>{}</td><td>{}:{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>", html_escape(&u.name), u.host, u.port, u.state.name(), u.packets_rx, u.packets_tx, html_escape(u.last_error.as_deref().unwrap_or("")))
+            })
+            .collect();
+        if rows.is_empty() {
+            String::new()
+        } else {
+            format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-green-100 px-4 py-2 text-left' colspan='6'>Uplink Networks</th></tr><tr><th>Name</th><th>Server</th><th>State</th><th>Packets RX</th><th>Packets TX</th><th>Last Error</th></tr></thead><tbody>{}</tbody></table>", rows)
+        }
+    };
     let s2s_peers_table = {
         let mut rows = String::new();
-        for peer in &hub_guard.s2s_peers {
+        for peer in hub.s2s_peers.read().unwrap().iter() {
             let p = peer.lock().unwrap();
-            rows.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{:?}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:?}</td><td>{:?}</td></tr>", p.host, p.port, p.peer_name, p.connected, p.packets_rx, p.packets_tx, p.bytes_rx, p.bytes_tx, p.connect_errors, p.read_errors, p.write_errors, p.last_error, p.last_connect));
+            rows.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{:?}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:?}</td><td>{:?}</td></tr>", p.host, p.port, p.peer_name, p.connected, p.state_summary(), p.packets_rx, p.packets_tx, p.bytes_rx, p.bytes_tx, p.rates.rx.summary(), p.rates.tx.summary(), p.connect_errors, p.read_errors, p.write_errors, p.last_error, p.last_connect));
+        }
+        format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-yellow-100 px-4 py-2 text-left' colspan='16'>S2S Peers</th></tr><tr><th>Host</th><th>Port</th><th>Peer Name</th><th>Connected</th><th>State</th><th>Packets RX</th><th>Packets TX</th><th>Bytes RX</th><th>Bytes TX</th><th>RX Packets/s</th><th>TX Packets/s</th><th>Connect Errors</th><th>Read Errors</th><th>Write Errors</th><th>Last Error</th><th>Last Connect</th></tr></thead><tbody id='s2s-peers-tbody'>{}</tbody></table>", rows)
+    };
+    let process_table = {
+        let p = &snapshot.status.process;
+        let or_dash = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+        format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-gray-100 px-4 py-2 text-left' colspan='6'>Process</th></tr><tr><th>Memory (RSS)</th><th>Open Files</th><th>CPU</th><th>CPU Time</th><th>Tokio Tasks</th><th>Tokio Workers</th></tr></thead><tbody><tr><td>{}</td><td>{}</td><td>{}</td><td>{:.1} s</td><td>{}</td><td>{}</td></tr></tbody></table>",
+            or_dash(p.rss_bytes.map(|b| format!("{:.1} MiB", b as f64 / (1024.0 * 1024.0)))),
+            or_dash(p.open_fds.map(|n| n.to_string())),
+            or_dash(p.cpu_percent.map(|c| format!("{:.1}%", c))),
+            p.cpu_secs,
+            or_dash(p.tokio_tasks.map(|n| n.to_string())),
+            or_dash(p.tokio_workers.map(|n| n.to_string())))
+    };
+    let caches_table = {
+        let c = &snapshot.status.caches;
+        format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-gray-100 px-4 py-2 text-left' colspan='9'>Station Caches</th></tr><tr><th>Positions</th><th>Heard</th><th>Weather</th><th>Telemetry</th><th>Objects</th><th>Talkers</th><th>Limit</th><th>Evicted</th><th>Expired</th></tr></thead><tbody><tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr></tbody></table>",
+            c.positions, c.heard, c.weather, c.telemetry, c.objects, c.talkers, c.max_entries.map_or_else(|| "-".to_string(), |n| n.to_string()), c.evicted, c.expired)
+    };
+    let latency_table = {
+        let l = &snapshot.status.latency;
+        let rows: String = [("Clients", &l.clients), ("Uplinks", &l.uplinks), ("Peers", &l.peers)]
+            .iter()
+            .map(|(name, h)| format!("<tr><td>{}</td><td>{}</td><td>{:.2}</td><td>{}</td><td>{}</td></tr>", name, h.count, h.mean_ms, h.p50_ms, h.p99_ms))
+            .collect();
+        format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-gray-100 px-4 py-2 text-left' colspan='5'>Latency (ms)</th></tr><tr><th>Egress</th><th>Packets</th><th>Mean</th><th>p50</th><th>p99</th></tr></thead><tbody>{}</tbody></table>", rows)
+    };
+    let listeners_table = {
+        let rows: String = snapshot
+            .status
+            .listeners
+            .iter()
+            .map(|l| {
+                format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    l.name, l.protocol.name(), l.port, l.connections, l.connects, l.packets_rx, l.packets_tx, l.bytes_rx, l.bytes_tx
+                )
+            })
+            .collect();
+        format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-green-100 px-4 py-2 text-left' colspan='9'>Listeners</th></tr><tr><th>Name</th><th>Protocol</th><th>Port</th><th>Connections</th><th>Total Connects</th><th>Packets RX</th><th>Packets TX</th><th>Bytes RX</th><th>Bytes TX</th></tr></thead><tbody>{}</tbody></table>", rows)
+    };
+    let mqtt_table = match &hub.mqtt_status {
+        Some(status) => {
+            let m = status.lock().unwrap();
+            format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-indigo-100 px-4 py-2 text-left' colspan='7'>MQTT</th></tr><tr><th>Host</th><th>Port</th><th>Connected</th><th>Published</th><th>Dropped</th><th>Connect Errors</th><th>Last Error</th></tr></thead><tbody><tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr></tbody></table>", html_escape(&m.host), m.port, m.connected, m.published, m.dropped, m.connect_errors, html_escape(m.last_error.as_deref().unwrap_or("")))
         }
-        format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-yellow-100 px-4 py-2 text-left' colspan='13'>S2S Peers</th></tr><tr><th>Host</th><th>Port</th><th>Peer Name</th><th>Connected</th><th>Packets RX</th><th>Packets TX</th><th>Bytes RX</th><th>Bytes TX</th><th>Connect Errors</th><th>Read Errors</th><th>Write Errors</th><th>Last Error</th><th>Last Connect</th></tr></thead><tbody id='s2s-peers-tbody'>{}</tbody></table>", rows)
+        None => String::new(),
+    };
+    let rf_table = match &hub.rf_status {
+        Some(status) => {
+            let r = status.lock().unwrap();
+            format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-indigo-100 px-4 py-2 text-left' colspan='8'>RF</th></tr><tr><th>Interface</th><th>Connected</th><th>Frames</th><th>Gated</th><th>Not Gated</th><th>Invalid</th><th>Connect Errors</th><th>Last Error</th></tr></thead><tbody><tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr></tbody></table>", html_escape(&r.interface), r.connected, r.frames, r.gated, r.not_gated, r.invalid, r.connect_errors, html_escape(r.last_error.as_deref().unwrap_or("")))
+                + &r.tx.as_ref().map(|t| format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-indigo-100 px-4 py-2 text-left' colspan='9'>RF Transmit</th></tr><tr><th>Messages</th><th>Positions</th><th>Not Heard</th><th>Too Far</th><th>Sender on RF</th><th>Flagged</th><th>Rate Limited</th><th>Disabled</th><th>Errors</th></tr></thead><tbody><tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr></tbody></table>", t.messages, t.positions, t.not_heard, t.too_far, t.sender_on_rf, t.flagged, t.rate_limited, t.disabled, t.errors)).unwrap_or_default()
+        }
+        None => String::new(),
+    };
+    // Stations heard on RF, direct ones first
+    let rf_heard_table = if hub.rf_status.is_some() {
+        let now = hub.clock.unix_time();
+        let mut stations = hub.rf_heard.lock().unwrap().all();
+        stations.sort_by_key(|s| !s.is_direct(now));
+        let rows: String = stations
+            .iter()
+            .take(50)
+            .map(|s| {
+                let heard = if s.is_direct(now) { "direct".to_string() } else { format!("{} hops", s.hops) };
+                format!("<tr><td>{}</td><td>{}</td><td>{} s ago</td><td>{}</td></tr>", html_escape(&s.callsign), heard, now.saturating_sub(s.last_heard), s.packets)
+            })
+            .collect();
+        format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-indigo-100 px-4 py-2 text-left' colspan='4'>Heard on RF</th></tr><tr><th>Callsign</th><th>Heard</th><th>Last Heard</th><th>Packets</th></tr></thead><tbody>{}</tbody></table>", rows)
+    } else {
+        String::new()
+    };
+    let archive_table = match &hub.archive_status {
+        Some(status) => {
+            let a = status.lock().unwrap();
+            format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-indigo-100 px-4 py-2 text-left' colspan='5'>Archive</th></tr><tr><th>Connected</th><th>Written</th><th>Dropped</th><th>Errors</th><th>Last Error</th></tr></thead><tbody><tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr></tbody></table>", a.connected, a.written, a.dropped, a.errors, html_escape(a.last_error.as_deref().unwrap_or("")))
+        }
+        None => String::new(),
+    };
+    let redis_table = match &hub.redis_status {
+        Some(status) => {
+            let r = status.lock().unwrap();
+            format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-indigo-100 px-4 py-2 text-left' colspan='5'>Redis</th></tr><tr><th>Connected</th><th>Published</th><th>Dropped</th><th>Errors</th><th>Last Error</th></tr></thead><tbody><tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr></tbody></table>", r.connected, r.published, r.dropped, r.errors, html_escape(r.last_error.as_deref().unwrap_or("")))
+        }
+        None => String::new(),
+    };
+    let objects_table = {
+        let mut rows = String::new();
+        for o in &snapshot.objects {
+            rows.push_str(&format!("<tr><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{:.4}</td><td class='px-2 py-1 border'>{:.4}</td><td class='px-2 py-1 border'>{}</td></tr>", html_escape(&o.name), if o.item { "item" } else { "object" }, html_escape(&o.owner), o.lat, o.lon, html_escape(&o.comment)));
+        }
+        format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-red-100 px-4 py-2 text-left' colspan='6'>Objects</th></tr><tr><th>Name</th><th>Type</th><th>Owner</th><th>Lat</th><th>Lon</th><th>Comment</th></tr></thead><tbody id='objects-tbody'>{}</tbody></table>", rows)
+    };
+    let talkers_table = {
+        let mut rows = String::new();
+        for t in &snapshot.top_talkers {
+            let types: Vec<String> = t.types.iter().map(|(name, count)| format!("{} {}", name, count)).collect();
+            rows.push_str(&format!("<tr><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td></tr>", html_escape(&t.callsign), t.packets, t.bytes, types.join(", ")));
+        }
+        format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-orange-100 px-4 py-2 text-left' colspan='4'>Top Talkers</th></tr><tr><th>Callsign</th><th>Packets</th><th>Bytes</th><th>Types</th></tr></thead><tbody>{}</tbody></table>", rows)
+    };
+    let software_table = {
+        let mut rows = String::new();
+        for s in &snapshot.software {
+            rows.push_str(&format!("<tr><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td></tr>", html_escape(&s.software), s.stations, s.packets, s.bytes));
+        }
+        format!("<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'><thead><tr><th class='bg-orange-100 px-4 py-2 text-left' colspan='4'>Software</th></tr><tr><th>Software</th><th>Stations</th><th>Packets</th><th>Bytes</th></tr></thead><tbody>{}</tbody></table>", rows)
     };
     let mut html = String::from(r#"<!DOCTYPE html>
 <html lang="en">
@@ -110,12 +461,18 @@ ws.onmessage = function(event) {
     const data = JSON.parse(event.data);
     if (data.server_name) {
       document.getElementById('uptime').textContent = data.uptime + ' seconds';
-    } else if (Array.isArray(data)) {
-      let tbody = '';
-      for (const c of data) {
-        tbody += `<tr class='hover:bg-gray-100'><td class='px-2 py-1 border'>${c.id}</td><td class='px-2 py-1 border'>${c.callsign ?? ''}</td><td class='px-2 py-1 border'>${c.filter ?? ''}</td></tr>`;
+    } else if (data.clients) {
+      const td = v => `<td class='px-2 py-1 border'>${esc(v)}</td>`;
+      document.getElementById('clients-tbody').innerHTML = data.clients.map(c =>
+        `<tr class='hover:bg-gray-100'><td class='px-2 py-1 border'><a class='underline text-blue-600' href='/clients/${c.id}'>${c.id}</a></td>` +
+        [c.callsign, c.addr, c.location, c.software, c.filter, c.packets_rx, c.packets_tx, c.packets_dropped, c.packets_filtered, c.bytes_rx, c.bytes_tx, c.rx_rate, c.connected_secs].map(td).join('') +
+        `<td class='px-2 py-1 border'><button class='text-red-600 underline' onclick='kick(${c.id})'>Kick</button></td></tr>`
+      ).join('');
+    } else if (data.totals) {
+      for (const [k, v] of Object.entries(data.totals)) {
+        const el = document.getElementById('totals-' + k.replace(/_/g, '-'));
+        if (el) el.textContent = v;
       }
-      document.getElementById('clients-tbody').innerHTML = tbody;
     } else if (data.uplink) {
       for (const [k, v] of Object.entries(data.uplink)) {
         const el = document.getElementById('uplink-' + k.replace(/_/g, '-'));
@@ -123,25 +480,60 @@ ws.onmessage = function(event) {
       }
     } else if (data.s2s_peers) {
       let tbody = data.s2s_peers.map(p =>
-        `<tr><td class='px-2 py-1 border'>${p.host}</td><td class='px-2 py-1 border'>${p.port}</td><td class='px-2 py-1 border'>${p.peer_name ?? ''}</td><td class='px-2 py-1 border'>${p.connected}</td><td class='px-2 py-1 border'>${p.packets_rx}</td><td class='px-2 py-1 border'>${p.packets_tx}</td><td class='px-2 py-1 border'>${p.bytes_rx}</td><td class='px-2 py-1 border'>${p.bytes_tx}</td><td class='px-2 py-1 border'>${p.connect_errors}</td><td class='px-2 py-1 border'>${p.read_errors}</td><td class='px-2 py-1 border'>${p.write_errors}</td><td class='px-2 py-1 border'>${p.last_error ?? ''}</td><td class='px-2 py-1 border'>${p.last_connect ?? ''}</td></tr>`
+        `<tr><td class='px-2 py-1 border'>${p.host}</td><td class='px-2 py-1 border'>${p.port}</td><td class='px-2 py-1 border'>${p.peer_name ?? ''}</td><td class='px-2 py-1 border'>${p.connected}</td><td class='px-2 py-1 border'>${p.state}</td><td class='px-2 py-1 border'>${p.packets_rx}</td><td class='px-2 py-1 border'>${p.packets_tx}</td><td class='px-2 py-1 border'>${p.bytes_rx}</td><td class='px-2 py-1 border'>${p.bytes_tx}</td><td class='px-2 py-1 border'>${p.rx_rate}</td><td class='px-2 py-1 border'>${p.tx_rate}</td><td class='px-2 py-1 border'>${p.connect_errors}</td><td class='px-2 py-1 border'>${p.read_errors}</td><td class='px-2 py-1 border'>${p.write_errors}</td><td class='px-2 py-1 border'>${p.last_error ?? ''}</td><td class='px-2 py-1 border'>${p.last_connect ?? ''}</td></tr>`
       ).join('');
       document.getElementById('s2s-peers-tbody').innerHTML = tbody;
     }
   } catch (e) {}
 };
+function esc(v) {
+  return String(v ?? '').replace(/[&<>'"]/g, c => ({'&': '&amp;', '<': '&lt;', '>': '&gt;', "'": '&#39;', '"': '&quot;'})[c]);
+}
+async function kick(id) {
+  let token = sessionStorage.getItem('adminToken') || prompt('Admin token');
+  if (!token) return;
+  const resp = await fetch(`/admin/clients/${id}/kick`, {method: 'POST', headers: {'Authorization': 'Bearer ' + token}});
+  if (resp.ok) {
+    sessionStorage.setItem('adminToken', token);
+  } else {
+    if (resp.status === 401) sessionStorage.removeItem('adminToken');
+    alert('Kick failed: ' + resp.status);
+  }
+}
 </script>
+"#);
+    html.push_str(&process_table);
+    html.push_str(&caches_table);
+    html.push_str(&latency_table);
+    html.push_str(&listeners_table);
     html.push_str(&uplink_table);
+    html.push_str(&networks_table);
     html.push_str(&s2s_peers_table);
-    html.push_str("<div class='mb-6'>
+    html.push_str(&rf_table);
+    html.push_str(&rf_heard_table);
+    html.push_str(&mqtt_table);
+    html.push_str(&archive_table);
+    html.push_str(&redis_table);
+    html.push_str(&objects_table);
+    html.push_str(&talkers_table);
+    html.push_str(&software_table);
+    let (packets_rx, packets_tx, bytes_rx, bytes_tx) = snapshot.totals;
+    let drops = &snapshot.status.drops;
+    let rates = &snapshot.status.rates;
+    let server = &snapshot.status.server;
+    html.push_str(&format!("<div class='mb-6'>
 <table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'>
   <thead><tr><th class='bg-blue-100 px-4 py-2 text-left' colspan='2'>Server Info</th></tr></thead>
   <tbody>
-    <tr><td class='px-4 py-2 font-semibold'>Server ID</td><td class='px-4 py-2'>aprsserver-rust</td></tr>
-    <tr><td class='px-4 py-2 font-semibold'>Admin</td><td class='px-4 py-2'>admin@example.com</td></tr>
-    <tr><td class='px-4 py-2 font-semibold'>Email</td><td class='px-4 py-2'>admin@example.com</td></tr>
-    <tr><td class='px-4 py-2 font-semibold'>Software</td><td class='px-4 py-2'>aprsserver-rust 0.1.0</td></tr>
+    <tr><td class='px-4 py-2 font-semibold'>Server ID</td><td class='px-4 py-2'>{}</td></tr>
+    <tr><td class='px-4 py-2 font-semibold'>Sysop</td><td class='px-4 py-2'>{}</td></tr>
+    <tr><td class='px-4 py-2 font-semibold'>Email</td><td class='px-4 py-2'>{}</td></tr>
+    <tr><td class='px-4 py-2 font-semibold'>Location</td><td class='px-4 py-2'>{}</td></tr>
+    <tr><td class='px-4 py-2 font-semibold'>QTH</td><td class='px-4 py-2'>{}</td></tr>
+    <tr><td class='px-4 py-2 font-semibold'>Software</td><td class='px-4 py-2'>{}</td></tr>
     <tr><td class='px-4 py-2 font-semibold'>Uptime</td><td class='px-4 py-2' id='uptime'>{} seconds</td></tr>
     <tr><td class='px-4 py-2 font-semibold'>Started</td><td class='px-4 py-2'>{}</td></tr>
+    <tr><td class='px-4 py-2 font-semibold'>Counting Since</td><td class='px-4 py-2'>{}</td></tr>
     <tr><td class='px-4 py-2 font-semibold'>OS</td><td class='px-4 py-2'>{}</td></tr>
   </tbody>
 </table>
@@ -150,7 +542,17 @@ ws.onmessage = function(event) {
   <thead><tr><th class='bg-green-100 px-4 py-2 text-left' colspan='4'>Totals</th></tr></thead>
   <tbody>
     <tr><th class='px-4 py-2'>Packets RX</th><th class='px-4 py-2'>Packets TX</th><th class='px-4 py-2'>Bytes RX</th><th class='px-4 py-2'>Bytes TX</th></tr>
-    <tr><td class='px-4 py-2'>{}</td><td class='px-4 py-2'>{}</td><td class='px-4 py-2'>{}</td><td class='px-4 py-2'>{}</td></tr>
+    <tr><td class='px-4 py-2' id='totals-packets-rx'>{}</td><td class='px-4 py-2' id='totals-packets-tx'>{}</td><td class='px-4 py-2' id='totals-bytes-rx'>{}</td><td class='px-4 py-2' id='totals-bytes-tx'>{}</td></tr>
+    <tr><th class='px-4 py-2' colspan='2'>RX Packets/s (1/5/15 min)</th><th class='px-4 py-2' colspan='2'>TX Packets/s (1/5/15 min)</th></tr>
+    <tr><td class='px-4 py-2' colspan='2' id='totals-rx-rate'>{}</td><td class='px-4 py-2' colspan='2' id='totals-tx-rate'>{}</td></tr>
+  </tbody>
+</table>
+
+<table class='min-w-full bg-white rounded shadow overflow-hidden mb-4'>
+  <thead><tr><th class='bg-green-100 px-4 py-2 text-left' colspan='11'>Packet Decisions</th></tr></thead>
+  <tbody>
+    <tr><th class='px-4 py-2'>Accepted</th><th class='px-4 py-2'>Duplicates</th><th class='px-4 py-2'>Invalid</th><th class='px-4 py-2'>Filtered</th><th class='px-4 py-2'>Dropped by Hooks</th><th class='px-4 py-2'>Loops</th><th class='px-4 py-2'>Class Policy</th><th class='px-4 py-2'>Bad Positions</th><th class='px-4 py-2'>TCPXX</th><th class='px-4 py-2'>NOGATE</th><th class='px-4 py-2'>RFONLY</th></tr>
+    <tr><td class='px-4 py-2' id='totals-accepted'>{}</td><td class='px-4 py-2' id='totals-duplicates'>{}</td><td class='px-4 py-2' id='totals-invalid'>{}</td><td class='px-4 py-2' id='totals-filtered'>{}</td><td class='px-4 py-2' id='totals-hook'>{}</td><td class='px-4 py-2' id='totals-loops'>{}</td><td class='px-4 py-2' id='totals-policy'>{}</td><td class='px-4 py-2' id='totals-bad-position'>{}</td><td class='px-4 py-2' id='totals-tcpxx'>{}</td><td class='px-4 py-2' id='totals-nogate'>{}</td><td class='px-4 py-2' id='totals-rfonly'>{}</td></tr>
   </tbody>
 </table>
 
@@ -158,47 +560,385 @@ ws.onmessage = function(event) {
   <thead><tr class='bg-gray-200'>
     <th class='px-2 py-1'>ID</th>
     <th class='px-2 py-1'>Callsign</th>
+    <th class='px-2 py-1'>Address</th>
+    <th class='px-2 py-1'>Location</th>
+    <th class='px-2 py-1'>Software</th>
     <th class='px-2 py-1'>Filter</th>
     <th class='px-2 py-1'>Packets RX</th>
     <th class='px-2 py-1'>Packets TX</th>
+    <th class='px-2 py-1'>Dropped</th>
+    <th class='px-2 py-1'>Filtered</th>
     <th class='px-2 py-1'>Bytes RX</th>
     <th class='px-2 py-1'>Bytes TX</th>
+    <th class='px-2 py-1'>RX Packets/s</th>
     <th class='px-2 py-1'>Connect Time (s)</th>
+    <th class='px-2 py-1'></th>
   </tr></thead>
   <tbody id='clients-tbody'>
-"#);
-    for (id, client) in &hub_guard.clients {
-        let c = client.lock().unwrap();
-        let connect_secs = c.connect_time.elapsed().as_secs();
-        html.push_str(&format!("<tr class='hover:bg-gray-100'><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{:?}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td></tr>", id, c.callsign, filter_summary(&c.filter), c.packets_rx, c.packets_tx, c.bytes_rx, c.bytes_tx, connect_secs));
+", html_escape(&snapshot.status.server_name), html_escape(server.sysop.as_deref().unwrap_or("")), html_escape(server.email.as_deref().unwrap_or("")), html_escape(server.location.as_deref().unwrap_or("")), server.qth().map(|(lat, lon)| format!("{:.4}, {:.4}", lat, lon)).unwrap_or_default(), html_escape(&snapshot.status.software), uptime, started_str, snapshot.status.stats_since, os, packets_rx, packets_tx, bytes_rx, bytes_tx, rates.rx.summary(), rates.tx.summary(), drops.accepted, drops.duplicates, drops.invalid, drops.filtered, drops.hook, drops.loops, drops.policy, drops.bad_position, drops.tcpxx, drops.nogate, drops.rfonly));
+    for c in &snapshot.clients {
+        let id = c.id;
+        html.push_str(&format!("<tr class='hover:bg-gray-100'><td class='px-2 py-1 border'><a class='underline text-blue-600' href='/clients/{}'>{}</a></td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'>{}</td><td class='px-2 py-1 border'><button class='text-red-600 underline' onclick='kick({})'>Kick</button></td></tr>", id, id, html_escape(c.callsign.as_deref().unwrap_or("")), html_escape(&c.addr), html_escape(&c.location.as_ref().map(|l| l.summary()).unwrap_or_default()), html_escape(c.software.as_deref().unwrap_or("")), html_escape(&filter_summary(&c.filter)), c.packets_rx, c.packets_tx, c.packets_dropped, c.packets_filtered, c.bytes_rx, c.bytes_tx, c.rates.rx.summary(), c.connected_secs, id));
     }
     html.push_str("</tbody></table>");
-    html.push_str("<div class='mt-4 text-sm text-gray-500'>See <a class='underline text-blue-600' href='/status.json'>/status.json</a> and <a class='underline text-blue-600' href='/clients.json'>/clients.json</a></div>");
+    html.push_str("<div class='mt-4 text-sm text-gray-500'>See <a class='underline text-blue-600' href='/status.json'>/status.json</a>, <a class='underline text-blue-600' href='/clients.json'>/clients.json</a>, <a class='underline text-blue-600' href='/objects.json'>/objects.json</a>, <a class='underline text-blue-600' href='/heard.json'>/heard.json</a>, <a class='underline text-blue-600' href='/talkers.json'>/talkers.json</a>, <a class='underline text-blue-600' href='/software.json'>/software.json</a> and <a class='underline text-blue-600' href='/weather.json'>/weather.json</a>, or watch the <a class='underline text-blue-600' href='/packets'>packet console</a>, <a class='underline text-blue-600' href='/map'>station map</a> and <a class='underline text-blue-600' href='/graphs'>statistics graphs</a></div>");
     html.push_str("</div></body></html>");
     Html(html)
 }
 
-async fn status(State(state): State<AppState>) -> Json<Status> {
-    let hub = state.hub.lock().unwrap();
-    Json(Status {
-        server_name: "aprsserver-rust".to_string(),
-        uptime: hub.uptime(),
-        clients: hub.client_count(),
-    })
+async fn status(State(state): State<AppState>) -> axum::response::Response {
+    Json(&state.snapshot.load().status).into_response()
 }
 
-async fn clients(State(state): State<AppState>) -> Json<Vec<ClientInfo>> {
-    let hub = state.hub.lock().unwrap();
-    let mut out = Vec::new();
-    for (id, client) in &hub.clients {
-        let c = client.lock().unwrap();
-        out.push(ClientInfo {
-            id: *id,
-            callsign: c.callsign.clone(),
-            filter: c.filter.clone(),
-        });
+async fn clients(State(state): State<AppState>) -> axum::response::Response {
+    Json(&state.snapshot.load().clients).into_response()
+}
+
+/// `/clients/{id}.json` returns the client as JSON, `/clients/{id}` a detail page.
+async fn client_detail(State(state): State<AppState>, Path(file): Path<String>) -> Result<axum::response::Response, StatusCode> {
+    let (id, json) = match file.strip_suffix(".json") {
+        Some(id) => (id, true),
+        None => (file.as_str(), false),
+    };
+    let id: usize = id.parse().map_err(|_| StatusCode::NOT_FOUND)?;
+    let snapshot = state.snapshot.load();
+    let info = snapshot.clients.iter().find(|c| c.id == id).ok_or(StatusCode::NOT_FOUND)?;
+    if json {
+        return Ok(Json(info).into_response());
     }
-    Json(out)
+    let row = |name: &str, value: String| {
+        format!("<tr><td class='px-4 py-2 font-semibold'>{}</td><td class='px-4 py-2'>{}</td></tr>", name, html_escape(&value))
+    };
+    let rows = [
+        row("Callsign", info.callsign.clone().unwrap_or_default()),
+        row("Address", info.addr.clone()),
+        row("Location", info.location.as_ref().map(|l| l.summary()).unwrap_or_default()),
+        row("Verified", info.verified.to_string()),
+        row("Software", info.software.clone().unwrap_or_default()),
+        row("Class", info.class.clone().unwrap_or_default()),
+        row("Login Time", info.login_time.map(|t| t.to_string()).unwrap_or_default()),
+        row("Connected (s)", info.connected_secs.to_string()),
+        row("Filter", filter_summary(&info.filter)),
+        row("Filter Matches", info.filter_matches.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(" / ")),
+        row("Packets RX", info.packets_rx.to_string()),
+        row("Packets TX", info.packets_tx.to_string()),
+        row("Packets Dropped", info.packets_dropped.to_string()),
+        row("Dropped by Filter", info.packets_filtered.to_string()),
+        row("TX Queue Overflows", info.tx_overflows.to_string()),
+        row("Shed by Bandwidth Cap", format!("{} ({} bytes)", info.tx_shed, info.tx_shed_bytes)),
+        row("TX Write Errors", info.tx_write_errors.to_string()),
+        row("TX Queue Depth", format!("{} (high water {})", info.queue_depth, info.queue_high_water)),
+        row("Bytes RX", info.bytes_rx.to_string()),
+        row("Bytes TX", info.bytes_tx.to_string()),
+        row("RX Packets/s (1/5/15 min)", info.rates.rx.summary()),
+        row("TX Packets/s (1/5/15 min)", info.rates.tx.summary()),
+        row("Last Activity", info.last_activity.to_string()),
+    ]
+    .concat();
+    Ok(Html(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="UTF-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1.0">
+  <title>Client {id}</title>
+  <script src="https://cdn.tailwindcss.com"></script>
+</head>
+<body class="bg-gray-50 text-gray-900">
+<div class="max-w-4xl mx-auto p-4">
+<h1 class="text-3xl font-bold mb-4">Client {id}</h1>
+<table class="min-w-full bg-white rounded shadow overflow-hidden mb-4"><tbody>{rows}</tbody></table>
+<div class="text-sm text-gray-500"><a class="underline text-blue-600" href="/">Back</a> · <a class="underline text-blue-600" href="/clients/{id}.json">JSON</a></div>
+</div>
+</body></html>
+"#)).into_response())
+}
+
+// Observations older than this are left out of /weather.json
+const WEATHER_MAX_AGE: u64 = 2 * 3600;
+
+async fn weather(State(state): State<AppState>) -> Json<Vec<WeatherObservation>> {
+    Json(state.hub.recent_weather(WEATHER_MAX_AGE))
+}
+
+// Stations not heard for this long are left off the map
+const MAP_MAX_AGE: u64 = 2 * 3600;
+// Rows in the top talkers table
+const TOP_TALKERS: usize = 20;
+
+async fn positions(State(state): State<AppState>) -> Json<Vec<StationPosition>> {
+    Json(state.hub.recent_positions(MAP_MAX_AGE))
+}
+
+#[derive(Deserialize)]
+struct TalkersQuery {
+    limit: Option<usize>,
+}
+
+/// Callsigns originating the most accepted packets, with a breakdown by type.
+async fn talkers(State(state): State<AppState>, Query(query): Query<TalkersQuery>) -> Json<Vec<SourceStats>> {
+    Json(state.hub.top_talkers(query.limit.unwrap_or(TOP_TALKERS)))
+}
+
+/// Accepted traffic by originating software, busiest first.
+async fn software(State(state): State<AppState>) -> axum::response::Response {
+    Json(&state.snapshot.load().software).into_response()
+}
+
+/// Stations whose positions were dropped by the sanity checks in the last
+/// day, most rejections first.
+async fn rejected_positions(State(state): State<AppState>) -> Json<Vec<RejectedPositions>> {
+    let mut rejected: Vec<_> = state.hub.stations.read().unwrap().rejected_positions.values().cloned().collect();
+    rejected.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.callsign.cmp(&b.callsign)));
+    Json(rejected)
+}
+
+#[derive(Serialize)]
+struct RfHeardResponse {
+    /// Heard direct within the last 30 minutes, as answered to `?APRSD`
+    directs: Vec<String>,
+    stations: Vec<RfStation>,
+}
+
+/// Duplicate cache occupancy and hit rate, with the sources sending the
+/// most duplicates.
+async fn dupes(State(state): State<AppState>, Query(query): Query<TalkersQuery>) -> Json<DupeStats> {
+    Json(state.hub.dupe_stats(query.limit.unwrap_or(TOP_TALKERS)))
+}
+
+async fn rf_heard(State(state): State<AppState>) -> Json<RfHeardResponse> {
+    let heard = state.hub.rf_heard.lock().unwrap();
+    Json(RfHeardResponse { directs: heard.directs(state.hub.clock.unix_time()), stations: heard.all() })
+}
+
+#[derive(Deserialize)]
+struct MessagesQuery {
+    callsign: Option<String>,
+}
+
+/// Message counters and the messages of the last hour by addressee, with
+/// whether each was delivered here, forwarded to the uplink and acked.
+/// `callsign` limits the list to messages to that station.
+async fn messages(State(state): State<AppState>, Query(query): Query<MessagesQuery>) -> Json<serde_json::Value> {
+    let mut messages = state.hub.messages.lock().unwrap();
+    messages.expire(state.hub.clock.unix_time());
+    let addressees = match &query.callsign {
+        Some(callsign) => json!({ callsign.to_uppercase(): messages.to(callsign) }),
+        None => json!(messages.all()),
+    };
+    Json(json!({ "counters": messages.counters, "addressees": addressees }))
+}
+
+#[derive(Deserialize)]
+struct HeardQuery {
+    prefix: Option<String>,
+    since: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct HeardEntry<'a> {
+    #[serde(flatten)]
+    station: &'a HeardStation,
+    position: Option<StationPosition>,
+}
+
+/// Stations heard in the last day, most recent first. `prefix` limits the
+/// list to callsigns starting with it and `since` to stations heard at or
+/// after that Unix time.
+async fn heard(State(state): State<AppState>, Query(query): Query<HeardQuery>) -> Json<serde_json::Value> {
+    let stations = state.hub.stations.read().unwrap();
+    let hub_positions = &state.hub.positions;
+    let prefix = query.prefix.unwrap_or_default().to_uppercase();
+    let since = query.since.unwrap_or(0);
+    let mut heard: Vec<_> = stations
+        .heard
+        .values()
+        .filter(|h| h.callsign.starts_with(&prefix) && h.last_heard >= since)
+        .collect();
+    heard.sort_by(|a, b| b.last_heard.cmp(&a.last_heard).then_with(|| a.callsign.cmp(&b.callsign)));
+    let entries: Vec<_> = heard
+        .into_iter()
+        .map(|station| HeardEntry { station, position: hub_positions.get(&station.callsign) })
+        .collect();
+    Json(json!(entries))
+}
+
+async fn map_page() -> Html<&'static str> {
+    Html(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="UTF-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1.0">
+  <title>APRS Station Map</title>
+  <link rel="stylesheet" href="https://unpkg.com/leaflet@1.9.4/dist/leaflet.css">
+  <script src="https://unpkg.com/leaflet@1.9.4/dist/leaflet.js"></script>
+  <style>
+    html, body, #map { height: 100%; margin: 0; }
+    .aprs-symbol { width: 24px; height: 24px; background-size: 384px 144px; }
+  </style>
+</head>
+<body>
+<div id="map"></div>
+<script>
+const SYMBOLS = 'https://raw.githubusercontent.com/hessu/aprs-symbols/master/png/aprs-symbols-24-';
+const map = L.map('map').setView([20, 0], 2);
+L.tileLayer('https://tile.openstreetmap.org/{z}/{x}/{y}.png', {
+  maxZoom: 19,
+  attribution: '&copy; OpenStreetMap contributors'
+}).addTo(map);
+const markers = {};
+
+function escapeHtml(s) {
+  return s.replace(/[&<>"']/g, c => ({'&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;', "'": '&#39;'}[c]));
+}
+
+function icon(symbol) {
+  // Sprite sheets have 16 symbols per row, starting at '!'
+  const table = symbol[0] === '/' ? 0 : 1;
+  const index = Math.max(0, symbol.charCodeAt(1) - 33);
+  const x = (index % 16) * 24, y = Math.floor(index / 16) * 24;
+  return L.divIcon({
+    className: '',
+    html: `<div class="aprs-symbol" style="background-image: url(${SYMBOLS}${table}.png); background-position: -${x}px -${y}px"></div>`,
+    iconSize: [24, 24],
+    iconAnchor: [12, 12]
+  });
+}
+
+function popup(p) {
+  const age = Math.round(Date.now() / 1000 - p.updated);
+  return `<b>${escapeHtml(p.callsign)}</b><br>${escapeHtml(p.comment)}<br><small>${age} s ago</small>`;
+}
+
+function update(p) {
+  let m = markers[p.callsign];
+  if (m) {
+    m.setLatLng([p.lat, p.lon]).setIcon(icon(p.symbol));
+  } else {
+    m = markers[p.callsign] = L.marker([p.lat, p.lon], {icon: icon(p.symbol), title: p.callsign}).addTo(map);
+  }
+  m.station = p;
+  m.bindPopup(() => popup(m.station));
+}
+
+fetch('/positions.json').then(r => r.json()).then(list => list.forEach(update));
+const ws = new WebSocket(`ws://${location.host}/ws`);
+ws.onmessage = function(event) {
+  const data = JSON.parse(event.data);
+  if (data.positions) data.positions.forEach(update);
+};
+</script>
+</body></html>
+"#)
+}
+
+async fn graphs(State(state): State<AppState>) -> Json<Vec<StatsSample>> {
+    Json(state.hub.stats_history.lock().unwrap().samples.iter().cloned().collect())
+}
+
+async fn openapi() -> Json<serde_json::Value> {
+    Json(crate::openapi::document())
+}
+
+async fn graphs_page() -> Html<&'static str> {
+    Html(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="UTF-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1.0">
+  <title>APRS Server Statistics</title>
+  <script src="https://cdn.tailwindcss.com"></script>
+  <script src="https://cdn.jsdelivr.net/npm/chart.js@4"></script>
+</head>
+<body class="bg-gray-50 text-gray-900">
+<div class="max-w-4xl mx-auto p-4">
+<h1 class="text-3xl font-bold mb-4">Statistics (last 24 hours)</h1>
+<div class="bg-white rounded shadow p-2 mb-4"><canvas id="packets"></canvas></div>
+<div class="bg-white rounded shadow p-2 mb-4"><canvas id="bytes"></canvas></div>
+<div class="bg-white rounded shadow p-2 mb-4"><canvas id="clients"></canvas></div>
+</div>
+<script>
+function chart(id, labels, title, datasets) {
+  new Chart(document.getElementById(id), {
+    type: 'line',
+    data: {labels, datasets: datasets.map(([label, data]) => ({label, data, pointRadius: 0, borderWidth: 1}))},
+    options: {animation: false, plugins: {title: {display: true, text: title}}}
+  });
+}
+fetch('/graphs.json').then(r => r.json()).then(samples => {
+  const labels = samples.map(s => new Date(s.time * 1000).toLocaleTimeString([], {hour: '2-digit', minute: '2-digit'}));
+  const series = key => samples.map(s => s[key]);
+  chart('packets', labels, 'Packets per minute', [['RX', series('packets_rx')], ['TX', series('packets_tx')], ['Accepted', series('accepted')], ['Duplicates', series('duplicates')], ['Dropped', series('dropped')]]);
+  chart('bytes', labels, 'Bytes per minute', [['RX', series('bytes_rx')], ['TX', series('bytes_tx')]]);
+  chart('clients', labels, 'Connected clients', [['Clients', series('clients')]]);
+});
+</script>
+</body></html>
+"#)
+}
+
+async fn objects(State(state): State<AppState>) -> axum::response::Response {
+    Json(&state.snapshot.load().objects).into_response()
+}
+
+async fn telemetry(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+) -> Result<Json<TelemetryReport>, StatusCode> {
+    let call = file.strip_suffix(".json").ok_or(StatusCode::NOT_FOUND)?.to_uppercase();
+    let stations = state.hub.stations.read().unwrap();
+    let station = stations.telemetry.get(&call).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(station.report(&call)))
+}
+
+/// The client table and the totals of the status page, as sent on `/ws`
+/// with each snapshot so the page stays current without reloading.
+fn clients_update(snapshot: &Snapshot) -> (serde_json::Value, serde_json::Value) {
+    let clients: Vec<_> = snapshot
+        .clients
+        .iter()
+        .map(|c| {
+            json!({
+                "id": c.id,
+                "callsign": c.callsign,
+                "addr": c.addr,
+                "location": c.location.as_ref().map(|l| l.summary()),
+                "software": c.software,
+                "filter": filter_summary(&c.filter),
+                "packets_rx": c.packets_rx,
+                "packets_tx": c.packets_tx,
+                "packets_dropped": c.packets_dropped,
+                "packets_filtered": c.packets_filtered,
+                "bytes_rx": c.bytes_rx,
+                "bytes_tx": c.bytes_tx,
+                "rx_rate": c.rates.rx.summary(),
+                "connected_secs": c.connected_secs,
+            })
+        })
+        .collect();
+    let (packets_rx, packets_tx, bytes_rx, bytes_tx) = snapshot.totals;
+    let drops = &snapshot.status.drops;
+    let totals = json!({
+        "totals": {
+            "packets_rx": packets_rx,
+            "packets_tx": packets_tx,
+            "bytes_rx": bytes_rx,
+            "bytes_tx": bytes_tx,
+            "rx_rate": snapshot.status.rates.rx.summary(),
+            "tx_rate": snapshot.status.rates.tx.summary(),
+            "accepted": drops.accepted,
+            "duplicates": drops.duplicates,
+            "invalid": drops.invalid,
+            "filtered": drops.filtered,
+            "hook": drops.hook,
+            "loops": drops.loops,
+            "policy": drops.policy,
+            "bad_position": drops.bad_position,
+            "tcpxx": drops.tcpxx,
+            "nogate": drops.nogate,
+            "rfonly": drops.rfonly,
+        }
+    });
+    (json!({"clients": clients}), totals)
 }
 
 async fn ws_handler(
@@ -208,21 +948,30 @@ async fn ws_handler(
     let hub = state.hub.clone();
     let uplink_status = state.uplink_status.clone();
     ws.on_upgrade(move |mut socket| async move {
+        let mut positions_since = unix_time();
         loop {
-            let (uptime, s2s_peers_json, uplink_json) = {
-                let hub_guard = hub.lock().unwrap();
-                let uptime = hub_guard.uptime();
-                let s2s_peers: Vec<_> = hub_guard.s2s_peers.iter().map(|peer| {
+            let (uptime, s2s_peers_json, uplink_json, positions_json) = {
+                // Positions changed since the previous tick, for the map page
+                let now = unix_time();
+                let positions_json = json!({"positions": hub.positions.updated_since(positions_since)});
+                positions_since = now;
+                let uptime = hub.uptime();
+                let s2s_peers: Vec<_> = hub.s2s_peers.read().unwrap().iter().map(|peer| {
                     let p = peer.lock().unwrap();
                     json!({
                         "host": p.host,
                         "port": p.port,
                         "peer_name": p.peer_name,
                         "connected": p.connected,
+                        "state": p.state_summary(),
+                        "consecutive_failures": p.consecutive_failures,
+                        "alarm": p.alarm,
                         "packets_rx": p.packets_rx,
                         "packets_tx": p.packets_tx,
                         "bytes_rx": p.bytes_rx,
                         "bytes_tx": p.bytes_tx,
+                        "rx_rate": p.rates.rx.summary(),
+                        "tx_rate": p.rates.tx.summary(),
                         "connect_errors": p.connect_errors,
                         "read_errors": p.read_errors,
                         "write_errors": p.write_errors,
@@ -236,84 +985,362 @@ async fn ws_handler(
                     "uplink": {
                         "host": uplink.host,
                         "port": uplink.port,
-                        "connected": uplink.connected,
+                        "remote_addr": uplink.remote_addr.map(|a| a.to_string()),
+                        "state": uplink.state.name(),
+                        "server_id": uplink.server_id,
                         "last_connect": uplink.last_connect.map(|t| format!("{:?}", t)),
                         "packets_rx": uplink.packets_rx,
                         "packets_tx": uplink.packets_tx,
                         "bytes_rx": uplink.bytes_rx,
                         "bytes_tx": uplink.bytes_tx,
+                        "rx_rate": uplink.rates.rx.summary(),
+                        "tx_rate": uplink.rates.tx.summary(),
                         "connect_errors": uplink.connect_errors,
                         "read_errors": uplink.read_errors,
                         "write_errors": uplink.write_errors,
+                        "timeouts": uplink.timeouts,
                         "last_error": uplink.last_error,
                         "last_rx_time": uplink.last_rx_time.map(|t| format!("{:?}", t)),
                         "last_tx_time": uplink.last_tx_time.map(|t| format!("{:?}", t)),
                     }
                 });
-                (uptime, s2s_json, uplink_json)
+                (uptime, s2s_json, uplink_json, positions_json)
             };
             let stats = json!({
-                "server_name": "aprsserver-rust",
+                "server_name": hub.server_name,
                 "uptime": uptime,
             });
-            if socket.send(Message::Text(stats.to_string())).await.is_err() {
-                break;
+            let (clients_json, totals_json) = clients_update(&state.snapshot.load());
+            for msg in [stats, uplink_json, s2s_peers_json, positions_json, clients_json, totals_json] {
+                if socket.send(Message::Text(msg.to_string())).await.is_err() {
+                    return;
+                }
             }
-            if socket.send(Message::Text(uplink_json.to_string())).await.is_err() {
-                break;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct PacketConsoleQuery {
+    filter: Option<String>,
+}
+
+/// Live feed of accepted packets as JSON. The filter comes from the `filter`
+/// query parameter and can be replaced by sending a new filter string; an
+/// empty filter passes everything.
+async fn packets_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(query): Query<PacketConsoleQuery>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| packet_console(socket, state.hub, query.filter.unwrap_or_default()))
+}
+
+/// Whether a fed packet passes `filters`; no filters pass everything.
+pub fn feed_matches(hub: &Hub, filters: &[ClientFilter], p: &FeedPacket) -> bool {
+    filters.is_empty() || {
+        let ctx = FilterContext { callsign: None, positions: Some(&hub.positions), zones: hub.nws_zones.as_ref() };
+        filters.iter().any(|f| f.matches_with(&p.packet, &ctx))
+    }
+}
+
+#[derive(Deserialize)]
+struct StreamQuery {
+    filter: Option<String>,
+    /// `raw` (the default) or `json`
+    format: Option<String>,
+}
+
+/// Accepted packets matching `filter` as Server-Sent Events, one packet per
+/// event: the raw line, or with `format=json` the same objects as the
+/// packet console. Packets missed by a slow reader are counted in a
+/// `skipped` event.
+async fn stream(State(state): State<AppState>, Query(query): Query<StreamQuery>) -> Response {
+    let filters = match parse_filter_list(query.filter.as_deref().unwrap_or("")) {
+        Ok(filters) => filters,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let json = match query.format.as_deref() {
+        None | Some("raw") => false,
+        Some("json") => true,
+        Some(other) => return (StatusCode::BAD_REQUEST, format!("unknown format {}", other)).into_response(),
+    };
+    let feed = state.hub.packet_feed.subscribe();
+    let events = futures_util::stream::unfold((feed, state.hub, filters), move |(mut feed, hub, filters)| async move {
+        let event = loop {
+            match feed.recv().await {
+                Ok(p) if feed_matches(&hub, &filters, &p) => {
+                    break if json { Event::default().json_data(&*p) } else { Ok(Event::default().data(&p.line)) };
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(n)) => break Ok(Event::default().event("skipped").data(n.to_string())),
+                Err(RecvError::Closed) => return None,
             }
-            if socket.send(Message::Text(s2s_peers_json.to_string())).await.is_err() {
+        };
+        Some((event, (feed, hub, filters)))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+async fn packet_console(mut socket: WebSocket, hub: Arc<Hub>, filter: String) {
+    let mut feed = hub.packet_feed.subscribe();
+    let mut filters = Vec::new();
+    let mut pending = Some(filter);
+    loop {
+        if let Some(filter) = pending.take() {
+            let reply = match parse_filter_list(&filter) {
+                Ok(f) => {
+                    filters = f;
+                    json!({"filter": crate::filter::filter_string(&filters)})
+                }
+                Err(e) => json!({"error": e.to_string()}),
+            };
+            if socket.send(Message::Text(reply.to_string())).await.is_err() {
                 break;
             }
-            tokio::time::sleep(Duration::from_secs(1)).await;
         }
-    })
+        tokio::select! {
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(text))) => pending = Some(text),
+                Some(Ok(_)) => {}
+                _ => break,
+            },
+            received = feed.recv() => {
+                let text = match received {
+                    Ok(p) => {
+                        if !feed_matches(&hub, &filters, &p) {
+                            continue;
+                        }
+                        serde_json::to_string(&*p).unwrap_or_default()
+                    }
+                    Err(RecvError::Lagged(n)) => json!({"skipped": n}).to_string(),
+                    Err(RecvError::Closed) => break,
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn packets_page() -> Html<&'static str> {
+    Html(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="UTF-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1.0">
+  <title>APRS Packet Console</title>
+  <script src="https://cdn.tailwindcss.com"></script>
+</head>
+<body class="bg-gray-50 text-gray-900">
+<div class="max-w-6xl mx-auto p-4">
+<h1 class="text-3xl font-bold mb-4">Packet Console</h1>
+<form id="filter-form" class="mb-4 flex gap-2">
+  <input id="filter" class="flex-1 border rounded px-2 py-1 font-mono" placeholder="r/60/25/100 t/m (empty for all)">
+  <button class="bg-blue-600 text-white rounded px-4 py-1">Apply</button>
+</form>
+<div id="state" class="text-sm text-gray-500 mb-2"></div>
+<pre id="packets" class="bg-white rounded shadow p-2 text-xs overflow-x-auto h-[70vh]"></pre>
+</div>
+<script>
+const MAX_LINES = 500;
+const out = document.getElementById('packets');
+const state = document.getElementById('state');
+const ws = new WebSocket(`ws://${location.host}/ws/packets`);
+ws.onmessage = function(event) {
+  const data = JSON.parse(event.data);
+  if (data.error) {
+    state.textContent = 'Invalid filter: ' + data.error;
+  } else if (data.filter !== undefined) {
+    state.textContent = 'Filter: ' + (data.filter || '(all packets)');
+  } else if (data.skipped) {
+    out.prepend(`-- ${data.skipped} packets skipped --
+`);
+  } else if (data.line) {
+    const time = new Date(data.received * 1000).toLocaleTimeString();
+    out.prepend(`${time} ${data.line}
+`);
+    while (out.childNodes.length > MAX_LINES) out.removeChild(out.lastChild);
+  }
+};
+ws.onclose = () => state.textContent = 'Disconnected';
+document.getElementById('filter-form').onsubmit = function(e) {
+  e.preventDefault();
+  ws.send(document.getElementById('filter').value);
+};
+</script>
+</body></html>
+"#)
 }
 
 async fn live_reload(State(state): State<AppState>) -> String {
-    let hub = state.hub.lock().unwrap();
-    hub.start_time.elapsed().as_secs().to_string()
+    state.hub.start_time.elapsed().as_secs().to_string()
+}
+
+// Lines waiting for a WebSocket client's socket task
+const WEBSOCKET_BUFFER: usize = 256;
+
+/// APRS-IS over WebSocket: each text message carries one or more protocol
+/// lines and every line sent to the client arrives as its own message. The
+/// session runs on a thread like a TCP client and is a regular hub client.
+async fn aprs_is_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if state.hub.bans.read().unwrap().is_ip_banned(addr.ip()) {
+        info!(%addr, "websocket rejected banned address");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if let Some(limit) = &state.hub.login_limit
+        && limit.is_blocked(addr.ip(), state.hub.clock.now())
+    {
+        debug!(%addr, "websocket rejected address blocked after failed logins");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(ws.on_upgrade(move |socket| aprs_is_session(socket, state.hub, addr)))
 }
 
-pub async fn serve_web_ui(addr: &str, hub: Arc<Mutex<Hub>>, uplink_status: Arc<Mutex<UplinkStatus>>) {
+async fn aprs_is_session(mut socket: WebSocket, hub: Arc<Hub>, addr: SocketAddr) {
+    let (line_tx, line_rx) = std::sync::mpsc::channel();
+    let (out_tx, mut out_rx) = tokio::sync::mpsc::channel(WEBSOCKET_BUFFER);
+    let conn = ClientConn::WebSocket { tx: Some(out_tx), write_timeout: hub.write_timeout };
+    info!(%addr, "new websocket connection");
+    std::thread::spawn(move || {
+        let reader = std::io::BufReader::new(ChannelReader::new(line_rx));
+        crate::server::run_session(reader, conn, Some(addr), None, hub);
+    });
+    // Dropping line_tx ends the session; the session dropping out_tx closes the socket
+    loop {
+        tokio::select! {
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    if line_tx.send(text).is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(_)) => {}
+                _ => break,
+            },
+            out = out_rx.recv() => match out {
+                Some(line) => {
+                    if socket.send(Message::Text(line)).await.is_err() {
+                        break;
+                    }
+                }
+                None => {
+                    let _ = socket.send(Message::Close(None)).await;
+                    break;
+                }
+            },
+        }
+    }
+}
+
+pub async fn serve_web_ui(addr: &str, state: AppState) {
+    let addr: SocketAddr = addr.parse().unwrap();
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    serve_web_listener(listener, state).await;
+}
+
+pub async fn serve_web_listener(listener: tokio::net::TcpListener, state: AppState) {
+    state.refresh_snapshot();
+    let refresher = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+        loop {
+            interval.tick().await;
+            refresher.refresh_snapshot();
+        }
+    });
     let app = Router::new()
         .route("/", get(root))
         .route("/status.json", get(status))
         .route("/clients.json", get(clients))
+        .route("/clients/:file", get(client_detail))
+        .route("/weather.json", get(weather))
+        .route("/objects.json", get(objects))
+        .route("/positions.json", get(positions))
+        .route("/heard.json", get(heard))
+        .route("/messages.json", get(messages))
+        .route("/rejected-positions.json", get(rejected_positions))
+        .route("/rf-heard.json", get(rf_heard))
+        .route("/dupes.json", get(dupes))
+        .route("/talkers.json", get(talkers))
+        .route("/software.json", get(software))
+        .route("/map", get(map_page))
+        .route("/graphs", get(graphs_page))
+        .route("/graphs.json", get(graphs))
+        .route("/telemetry/:file", get(telemetry))
         .route("/ws", get(ws_handler))
         .route("/live-reload", get(live_reload))
-        .with_state(AppState { hub, uplink_status });
-    let addr: SocketAddr = addr.parse().unwrap();
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    serve(listener, app.into_make_service()).await.unwrap();
+        .route("/packets", get(packets_page))
+        .route("/ws/packets", get(packets_ws))
+        .route("/stream", get(stream))
+        .route("/ws/aprs-is", get(aprs_is_ws))
+        .route("/api/openapi.json", get(openapi))
+        .merge(crate::admin::routes())
+        .merge(crate::export::routes())
+        .with_state(state);
+    serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::task;
-    use reqwest;
-    use crate::hub::Hub;
-    use std::sync::{Arc, Mutex};
+    use crate::config::{ServerConfig, UplinkConfig};
 
     #[tokio::test]
     async fn test_status_endpoint() {
-        let addr = "127.0.0.1:3001";
-        let hub = Arc::new(Mutex::new(Hub::new()));
-        let hub2 = hub.clone();
-        let dummy_cfg = UplinkConfig {
-            host: "dummy".to_string(),
-            port: 0,
-            callsign: "dummy".to_string(),
-            passcode: 0,
-        };
-        task::spawn(async move {
-            serve_web_ui(addr, hub2, Arc::new(Mutex::new(UplinkStatus::new(&dummy_cfg)))).await;
-        });
-        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-        let resp = reqwest::get(&format!("http://{}/status.json", addr)).await.unwrap();
+        let server = crate::testing::TestServer::start().unwrap();
+        let resp = reqwest::get(server.web_url("/status.json")).await.unwrap();
         assert!(resp.status().is_success());
         let status: Status = resp.json().await.unwrap();
-        assert_eq!(status.server_name, "aprsserver-rust");
+        assert_eq!(status.server_name, "NOCALL");
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let server = crate::testing::TestServer::start().unwrap();
+        assert_eq!(reqwest::get(server.web_url("/stream?format=xml")).await.unwrap().status(), 400);
+        let mut resp = reqwest::get(server.web_url("/stream?filter=p/N1")).await.unwrap();
+        assert_eq!(resp.headers()["content-type"], "text/event-stream");
+        let hub = server.hub();
+        crate::pipeline::ingest(hub, crate::pipeline::Source::Uplink, "N0CALL>APRS,TCPIP*:>skipped").unwrap();
+        crate::pipeline::ingest(hub, crate::pipeline::Source::Uplink, "N1CALL>APRS,TCPIP*:>streamed").unwrap();
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(2), resp.chunk()).await.unwrap().unwrap().unwrap();
+        assert_eq!(String::from_utf8_lossy(&chunk), format!("data: N1CALL>APRS,TCPIP*,qAS,{}:>streamed\n\n", hub.server_name));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let cfg = UplinkConfig { host: "dummy".to_string(), port: 0, callsign: "dummy".to_string(), passcode: 0, ..Default::default() };
+        let mut hub = Hub::new();
+        hub.server_name = "T2TEST".to_string();
+        hub.server = ServerConfig { id: Some("T2TEST".to_string()), sysop: Some("OH2XYZ".to_string()), ..Default::default() };
+        let hub = Arc::new(hub);
+        let state = AppState::new(hub.clone(), Arc::new(Mutex::new(UplinkStatus::new(&cfg))), None);
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        let mut client = Client::with_conn(0, ClientConn::WebSocket { tx: Some(tx), write_timeout: std::time::Duration::from_secs(1) }, "127.0.0.1:1".parse().ok());
+        client.packets_rx = 3;
+        let id = hub.add_client(client);
+
+        // Handlers see the client only once the snapshot is refreshed
+        assert!(state.snapshot.load().clients.is_empty());
+        state.refresh_snapshot();
+        let snapshot = state.snapshot.load();
+        assert_eq!(snapshot.clients.len(), 1);
+        assert_eq!(snapshot.clients[0].id, id);
+        assert_eq!(snapshot.status.clients, 1);
+        assert_eq!(snapshot.status.server_name, "T2TEST");
+        assert_eq!(snapshot.status.server.sysop.as_deref(), Some("OH2XYZ"));
+        assert_eq!(snapshot.totals.0, 3);
+
+        let (clients, totals) = clients_update(&snapshot);
+        assert_eq!(clients["clients"][0]["id"], id);
+        assert_eq!(clients["clients"][0]["addr"], "ws:127.0.0.1:1");
+        assert_eq!(totals["totals"]["packets_rx"], 3);
     }
 } 
\ No newline at end of file